@@ -0,0 +1,41 @@
+//! Look the collections up with the cached prepared statement of `erc721_db::get_collection`, and with a statement
+//! prepared for each lookup as before. Run it with `cargo bench --bench erc721_db`.
+use nft_events::erc721_db;
+use rusqlite::{params, Connection};
+use std::time::{Duration, Instant};
+
+const COLLECTIONS: u64 = 1_000;
+const LOOKUPS: usize = 100_000;
+
+fn lookups(addresses: &[String], mut lookup: impl FnMut(&str)) -> Duration {
+    let started = Instant::now();
+    for address in addresses.iter().cycle().take(LOOKUPS) {
+        lookup(address);
+    }
+    started.elapsed()
+}
+
+fn main() {
+    let conn = Connection::open_in_memory().unwrap();
+    erc721_db::create_tables_if_not_exist(&conn).unwrap();
+    let addresses: Vec<String> = (0..COLLECTIONS).map(|i| format!("0x{:040x}", i)).collect();
+    for address in &addresses {
+        let (name, symbol) = (Some("Mock".to_owned()), Some("MOCK".to_owned()));
+        erc721_db::add_collection_to_db(&conn, "Mock", address.clone(), name, symbol).unwrap();
+    }
+
+    let prepared = lookups(&addresses, |address| {
+        let mut stmt = conn
+            .prepare("SELECT id, address, name, symbol from erc721_collections where chain=?1 and address=?2")
+            .unwrap();
+        stmt.query_row(params!["Mock", address], |row| row.get::<_, i64>(0)).unwrap();
+    });
+    let cached = lookups(&addresses, |address| {
+        erc721_db::get_collection(&conn, "Mock", address).unwrap().unwrap();
+    });
+
+    println!("{} lookups of {} collections:", LOOKUPS, COLLECTIONS);
+    println!("  prepared for each lookup: {:?}", prepared);
+    println!("  cached:                   {:?}", cached);
+    println!("  speedup:                  {:.2}x", prepared.as_secs_f64() / cached.as_secs_f64());
+}
@@ -0,0 +1,1652 @@
+//! This module contains the configuration of the trackers and the options used to tune the tracking loops.
+use crate::{
+    erc721_evm::{CRYPTOKITTIES_ADDRESS, CRYPTOPUNKS_ADDRESS, MAX_ADDRESSES_PER_FILTER},
+    evm_client::block_number_at_timestamp,
+    Error, EvmClientApi, Result, TrackerMetrics,
+};
+use std::{borrow::Cow, fmt, sync::Arc, time::Duration};
+use tokio_util::sync::CancellationToken;
+use web3::types::H160;
+
+/// Options controlling how the tracking loops wait between requests.
+#[derive(Debug, Clone)]
+pub struct ScanOptions {
+    /// How long to wait after a block range has been scanned successfully
+    pub range_interval: Duration,
+    /// Skip `range_interval` while the tracker is far behind the confirmed blocks,
+    /// so that the history is caught up with the ranges scanned back-to-back.
+    pub backfill: bool,
+    /// The tracker is backfilling while more confirmed blocks than this remain after a range.
+    /// Defaults to twice the current step.
+    pub backfill_threshold: Option<u64>,
+    /// How long to wait when the tracker has caught up with the latest block
+    pub idle_interval: Duration,
+    /// How long to wait after an error. The delay grows after each consecutive error.
+    pub error_interval: Duration,
+    /// The delay after an error is multiplied by this factor after each consecutive error
+    pub error_backoff_multiplier: u32,
+    /// The delay after an error never grows beyond this value
+    pub max_error_interval: Duration,
+    /// Randomize the delays after errors between half and all of their value,
+    /// so that several trackers do not retry at the same time.
+    pub error_jitter: bool,
+    /// Give up and return the error once the delays after consecutive errors add up to more than this.
+    /// None means retrying forever.
+    pub retry_budget: Option<Duration>,
+    /// How many blocks behind the latest block are considered confirmed.
+    /// Use 0 for chains with instant finality.
+    pub confirmations: u64,
+    /// After the step has been halved, it is multiplied by this factor again
+    /// once `step_growth_threshold` consecutive ranges have been scanned successfully.
+    pub step_growth_factor: u64,
+    /// How many consecutive successful ranges are needed before the step grows
+    pub step_growth_threshold: u32,
+    /// The step never grows beyond this value. Defaults to the step the tracker was started with.
+    pub max_step: Option<u64>,
+    /// How many fetched ranges can wait for their events to be processed.
+    /// Only the ERC721 tracker fetches the next ranges while it processes the current one.
+    pub pipeline_depth: usize,
+    /// How many ranges of `step` blocks are fetched concurrently, their events are still delivered in order.
+    /// Only the ERC721 tracker fetches in parallel, which is mostly useful for backfills.
+    pub parallel_ranges: usize,
+    /// How many events of a range have their metadata fetched concurrently, they are still delivered in order.
+    /// Only the ERC721 tracker fetches the metadata concurrently.
+    pub metadata_concurrency: usize,
+    /// Wake up on the new blocks notified by the client instead of polling the latest block,
+    /// when the client supports subscriptions. Polling is used again if the subscription drops.
+    /// Only the ERC721 tracker subscribes to the new blocks.
+    pub live: bool,
+    /// Persist the last scanned block in the database and resume from it on the next start.
+    /// The ERC1155 tracker stores its progress under `erc1155::progress_key`, so that it can share the database
+    /// of the ERC721 tracker.
+    pub resume: bool,
+    /// Record the delivered events in the database and skip them when they are scanned again.
+    /// Only the ERC721 tracker deduplicates its events.
+    pub dedup: bool,
+    /// Store the hash of the last block of each range and rescan the blocks removed by a reorg.
+    /// Only the ERC721 tracker detects reorgs.
+    pub detect_reorgs: bool,
+    /// Fetch the hash of each block of a range first and query its logs by that hash, so that a reorg between
+    /// the two requests can not mix the logs of two forks. The hashes of all the blocks are stored to detect
+    /// the reorgs, as with `detect_reorgs`. It costs one more request per block. Only the ERC721 tracker has it.
+    pub strict_block_hashes: bool,
+    /// Never write to the database: the metadata which is not cached yet is fetched from the chain
+    /// for every event, and neither the progress, the delivered events nor the scanned blocks are saved.
+    /// Only the ERC721 tracker has a dry-run mode.
+    pub dry_run: bool,
+    /// When this token is cancelled, the tracker finishes the event it is processing and returns.
+    /// The ERC721 tracker delivers the events of a range at once, so it finishes the range instead.
+    pub cancellation_token: Option<CancellationToken>,
+}
+
+impl Default for ScanOptions {
+    fn default() -> Self {
+        ScanOptions {
+            range_interval: Duration::from_secs(5),
+            backfill: true,
+            backfill_threshold: None,
+            idle_interval: Duration::from_secs(30),
+            error_interval: Duration::from_secs(1),
+            error_backoff_multiplier: 2,
+            max_error_interval: Duration::from_secs(60),
+            error_jitter: true,
+            retry_budget: None,
+            confirmations: 6,
+            step_growth_factor: 2,
+            step_growth_threshold: 10,
+            max_step: None,
+            pipeline_depth: 2,
+            parallel_ranges: 1,
+            metadata_concurrency: 1,
+            live: true,
+            resume: false,
+            dedup: false,
+            detect_reorgs: false,
+            strict_block_hashes: false,
+            dry_run: false,
+            cancellation_token: None,
+        }
+    }
+}
+
+impl ScanOptions {
+    /// Check if the tracker has been asked to stop
+    pub fn is_cancelled(&self) -> bool {
+        self.cancellation_token
+            .as_ref()
+            .map_or(false, |token| token.is_cancelled())
+    }
+
+    /// Check if the stored block hashes are compared with the chain to detect the reorgs
+    pub(crate) fn detects_reorgs(&self) -> bool {
+        self.detect_reorgs || self.strict_block_hashes
+    }
+
+    /// Check if the range ending at `to` is far enough behind the confirmed blocks
+    /// to scan the next one without waiting for `range_interval`.
+    pub(crate) fn is_backfilling(&self, to: u64, latest_block_number: u64, step: u64) -> bool {
+        if !self.backfill {
+            return false;
+        }
+        let confirmed = latest_block_number.saturating_sub(self.confirmations);
+        let threshold = self.backfill_threshold.unwrap_or_else(|| step.saturating_mul(2));
+        confirmed.saturating_sub(to) > threshold
+    }
+
+    /// Sleep for `duration`, waking up early if the tracker is cancelled.
+    pub(crate) async fn sleep(&self, duration: Duration) {
+        match &self.cancellation_token {
+            Some(token) => {
+                tokio::select! {
+                    _ = token.cancelled() => {}
+                    _ = tokio::time::sleep(duration) => {}
+                }
+            }
+            None => tokio::time::sleep(duration).await,
+        }
+    }
+}
+
+/// Decides which errors make a tracker stop instead of retrying in the loop,
+/// and which ones make it retry with a smaller step.
+#[derive(Clone)]
+pub struct ErrorPolicy {
+    is_terminal: Arc<dyn Fn(&Error) -> bool + Send + Sync>,
+    is_range_limit: Option<Arc<dyn Fn(&web3::Error) -> bool + Send + Sync>>,
+    /// Stop after this many consecutive failed requests, even if none of the errors is terminal.
+    /// None means retrying forever.
+    pub max_consecutive_errors: Option<u32>,
+}
+
+impl ErrorPolicy {
+    /// Create a policy treating the errors matched by `is_terminal` as terminal
+    pub fn new<F>(is_terminal: F) -> Self
+    where
+        F: Fn(&Error) -> bool + Send + Sync + 'static,
+    {
+        ErrorPolicy {
+            is_terminal: Arc::new(is_terminal),
+            is_range_limit: None,
+            max_consecutive_errors: None,
+        }
+    }
+
+    /// Also treat the errors matched by `is_range_limit` as range-limit errors,
+    /// for the providers not known by `is_range_limit_error`
+    pub fn with_range_limit_errors<F>(mut self, is_range_limit: F) -> Self
+    where
+        F: Fn(&web3::Error) -> bool + Send + Sync + 'static,
+    {
+        self.is_range_limit = Some(Arc::new(is_range_limit));
+        self
+    }
+
+    /// Stop after `max` consecutive failed requests
+    pub fn with_max_consecutive_errors(mut self, max: u32) -> Self {
+        self.max_consecutive_errors = Some(max);
+        self
+    }
+
+    /// Check if `err` is terminal
+    pub fn is_terminal(&self, err: &Error) -> bool {
+        (self.is_terminal)(err)
+    }
+
+    /// Check if the RPC rejected a block range because of its size, so that it should be scanned again with a smaller step
+    pub fn is_range_limit(&self, err: &Error) -> bool {
+        match err {
+            Error::Web3Error(err) => {
+                is_range_limit_error(err)
+                    || self
+                        .is_range_limit
+                        .as_ref()
+                        .map_or(false, |is_range_limit| is_range_limit(err))
+            }
+            _ => false,
+        }
+    }
+
+    /// Check if the tracker should stop after `err`, the `consecutive_errors`th error in a row
+    pub(crate) fn should_stop(&self, err: &Error, consecutive_errors: u32) -> bool {
+        self.is_terminal(err)
+            || self
+                .max_consecutive_errors
+                .map_or(false, |max| consecutive_errors >= max)
+    }
+}
+
+impl Default for ErrorPolicy {
+    fn default() -> Self {
+        ErrorPolicy::new(is_terminal_error)
+    }
+}
+
+impl fmt::Debug for ErrorPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ErrorPolicy")
+            .field("max_consecutive_errors", &self.max_consecutive_errors)
+            .field("is_range_limit", &self.is_range_limit.is_some())
+            .finish()
+    }
+}
+
+/// The default terminal errors: authentication failures, invalid configurations and nodes for another chain.
+/// Retrying them would never succeed.
+pub fn is_terminal_error(err: &Error) -> bool {
+    fn is_auth_failure(message: &str) -> bool {
+        let message = message.to_lowercase();
+        // the status codes as words of an HTTP status, not the digits of a hash or a block number echoed by the node
+        let is_auth_status = message.contains("status")
+            && message
+                .split(|c: char| !c.is_ascii_alphanumeric())
+                .any(|word| word == "401" || word == "403");
+        is_auth_status
+            || ["unauthorized", "forbidden", "invalid api key"]
+                .iter()
+                .any(|pattern| message.contains(pattern))
+    }
+
+    match err {
+        Error::Web3Error(web3::Error::Transport(message)) => is_auth_failure(message),
+        Error::Web3Error(web3::Error::Rpc(e)) => is_auth_failure(&e.message),
+        Error::InvalidConfig(_) | Error::ChainIdMismatch { .. } => true,
+        _ => false,
+    }
+}
+
+/// The error code of Infura for the requests exceeding one of its limits
+const LIMIT_EXCEEDED_CODE: i64 = -32005;
+
+/// Check if the RPC rejected a block range because it has too many blocks or too many results.
+/// It knows the errors of Infura, Alchemy, QuickNode, Ankr and the BSC and geth-based nodes.
+pub fn is_range_limit_error(err: &web3::Error) -> bool {
+    fn is_range_limit_message(message: &str) -> bool {
+        let message = message.to_lowercase();
+        [
+            // Infura, erigon
+            "query returned more than",
+            // Alchemy
+            "log response size exceeded",
+            // BSC
+            "exceed maximum block range",
+            // QuickNode
+            "eth_getlogs is limited to",
+            // Ankr
+            "block range is too wide",
+            "block range too large",
+            "range limit exceeded",
+        ]
+        .iter()
+        .any(|pattern| message.contains(pattern))
+    }
+
+    match err {
+        web3::Error::Rpc(e) => {
+            // Infura also uses this code when the request rate is limited
+            (e.code.code() == LIMIT_EXCEEDED_CODE && !e.message.to_lowercase().contains("rate"))
+                || is_range_limit_message(&e.message)
+        }
+        web3::Error::Transport(message) => is_range_limit_message(message),
+        _ => false,
+    }
+}
+
+/// The well-known `0x000000000000000000000000000000000000dEaD` address, the tokens sent to it are burnt
+/// as nobody holds its key
+pub const DEAD_ADDRESS: H160 = H160([0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0xde, 0xad]);
+
+/// The kind of a transfer event, derived from the zero address
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum EventKind {
+    /// Transferred from the zero address
+    Mint,
+    /// Transferred to the zero address, or to a burn address for the ERC721 events
+    Burn,
+    /// Transferred between two accounts
+    Transfer,
+}
+
+impl EventKind {
+    /// Classify a transfer from `from` to `to`
+    pub fn of(from: &H160, to: &H160) -> EventKind {
+        if from.is_zero() {
+            EventKind::Mint
+        } else if to.is_zero() {
+            EventKind::Burn
+        } else {
+            EventKind::Transfer
+        }
+    }
+
+    /// Classify a transfer from `from` to `to` like `of`, the transfers to one of `burn_addresses` are burns too
+    pub fn classify(from: &H160, to: &H160, burn_addresses: &[H160]) -> EventKind {
+        match EventKind::of(from, to) {
+            EventKind::Transfer if burn_addresses.contains(to) => EventKind::Burn,
+            kind => kind,
+        }
+    }
+}
+
+/// Which kinds of events are passed to the callback
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EventKindFilter {
+    /// All the events
+    All,
+    /// Only the mints
+    MintsOnly,
+    /// Only the burns
+    BurnsOnly,
+    /// Only the transfers between two accounts
+    TransfersOnly,
+}
+
+impl Default for EventKindFilter {
+    fn default() -> Self {
+        EventKindFilter::All
+    }
+}
+
+impl EventKindFilter {
+    /// Check if the events of `kind` pass this filter
+    pub fn accepts(&self, kind: EventKind) -> bool {
+        match self {
+            EventKindFilter::All => true,
+            EventKindFilter::MintsOnly => kind == EventKind::Mint,
+            EventKindFilter::BurnsOnly => kind == EventKind::Burn,
+            EventKindFilter::TransfersOnly => kind == EventKind::Transfer,
+        }
+    }
+}
+
+/// What a tracker does when the callback returns an error
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CallbackErrorPolicy {
+    /// Log the error and go on with the next event
+    Skip,
+    /// Call the callback again, waiting `base_delay` and doubling the delay after each attempt.
+    /// When all the attempts fail, the block of the event is scanned again after the error interval,
+    /// so the tracker never moves past an event that has not been accepted.
+    RetryWithBackoff {
+        /// How many times the callback is called for an event, including the first time
+        max_attempts: u32,
+        /// How long to wait before the second attempt
+        base_delay: Duration,
+    },
+    /// Stop tracking and return the error
+    Abort,
+}
+
+impl Default for CallbackErrorPolicy {
+    fn default() -> Self {
+        CallbackErrorPolicy::Skip
+    }
+}
+
+/// When the token uri saved in the database is fetched again, for the collections whose
+/// metadata changes after the mint, like the reveals and the dynamic NFTs
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MetadataRefresh {
+    /// Keep the token uri fetched the first time
+    Never,
+    /// Fetch the token uri again for every event of the token
+    EveryEvent,
+    /// Fetch the token uri again when it was fetched longer than this ago
+    After(Duration),
+}
+
+impl Default for MetadataRefresh {
+    fn default() -> Self {
+        MetadataRefresh::Never
+    }
+}
+
+impl MetadataRefresh {
+    /// Check if the token uri fetched at `fetched_at` has to be fetched again at `now`, in unix seconds.
+    /// The token uris saved before their fetch time was recorded are always fetched again.
+    pub(crate) fn is_due(&self, fetched_at: Option<u64>, now: u64) -> bool {
+        match self {
+            MetadataRefresh::Never => false,
+            MetadataRefresh::EveryEvent => true,
+            MetadataRefresh::After(max_age) => fetched_at
+                .map_or(true, |fetched_at| now.saturating_sub(fetched_at) >= max_age.as_secs()),
+        }
+    }
+}
+
+/// How the metadata lookups which failed are retried by the next events of the same collection or token.
+/// The metadata which the contract does not provide, according to ERC165, is never looked up again.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MetadataRetry {
+    /// Give up after this many failed lookups, including the first one
+    pub max_attempts: u32,
+    /// The delay after the first failed lookup, it doubles after each of the next ones
+    pub base_delay: Duration,
+}
+
+impl Default for MetadataRetry {
+    fn default() -> Self {
+        MetadataRetry {
+            max_attempts: 5,
+            base_delay: Duration::from_secs(60),
+        }
+    }
+}
+
+impl MetadataRetry {
+    /// Check if the lookup which failed `attempts` times, the last time at `last_attempt_at`,
+    /// can be retried at `now`, in unix seconds.
+    pub(crate) fn is_due(&self, attempts: u32, last_attempt_at: u64, now: u64) -> bool {
+        if attempts >= self.max_attempts {
+            return false;
+        }
+        let delay = self
+            .base_delay
+            .as_secs()
+            .saturating_mul(2u64.saturating_pow(attempts.saturating_sub(1)));
+        now >= last_attempt_at.saturating_add(delay)
+    }
+}
+
+/// Where a tracker starts, relatively to the latest block or not
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StartBlock {
+    /// Start from this block
+    Number(u64),
+    /// Start from the latest confirmed block, `confirmations` blocks behind the latest block
+    Latest,
+    /// Start this many blocks before the latest confirmed block
+    LatestMinus(u64),
+}
+
+/// The configuration of a tracker.
+/// Both the ERC721 and the ERC1155 trackers are configured by this type.
+#[derive(Debug, Clone)]
+pub struct TrackerConfig {
+    /// The block to start tracking from
+    pub start_from: u64,
+    /// Where to start instead of `start_from`, resolved to a block when the tracker starts.
+    /// A tracker resuming from its saved progress does not resolve it.
+    pub start_block: Option<StartBlock>,
+    /// How many blocks are scanned at once
+    pub step: u64,
+    /// The tracker returns after this block has been processed
+    pub end_block: Option<u64>,
+    /// The tracker returns after the last block at or before this time, in unix seconds.
+    /// It is resolved to a block when the tracker starts, so it has to be in the past.
+    /// The earliest of `end_block` and `end_time` applies.
+    pub end_time: Option<u64>,
+    /// Options controlling how the tracking loop waits between requests
+    pub options: ScanOptions,
+    /// Which errors make the tracker stop
+    pub error_policy: ErrorPolicy,
+    /// Only track the events of these contracts. The addresses are passed to the node,
+    /// so the logs of other contracts are never returned. Only the ERC721 tracker filters its events.
+    pub address_allowlist: Option<Vec<H160>>,
+    /// How many addresses of the allowlist are sent in one request
+    pub max_addresses_per_request: usize,
+    /// Ignore the events of these contracts, typically known spam
+    pub denylist: Vec<H160>,
+    /// Which kinds of events are passed to the callback
+    pub event_kinds: EventKindFilter,
+    /// The addresses whose incoming transfers are burns, besides the zero address, `DEAD_ADDRESS` by default.
+    /// Only the ERC721 events are classified with them.
+    pub burn_addresses: Vec<H160>,
+    /// What to do when the callback returns an error
+    pub callback_error_policy: CallbackErrorPolicy,
+    /// When the saved token uris are fetched again. Only the ERC721 tracker refreshes its metadata.
+    pub metadata_refresh: MetadataRefresh,
+    /// How the failed metadata lookups are retried. Only the ERC721 tracker retries them.
+    pub metadata_retry: MetadataRetry,
+    /// Drop the events of the contracts which report with ERC165 not supporting ERC721, like the ERC20 contracts
+    /// sharing the Transfer topic, also for the raw callbacks. The contracts without ERC165 keep their events.
+    /// Only the ERC721 tracker checks the contracts.
+    pub skip_non_erc721_contracts: bool,
+    /// Fetch the owner of each token at the last block of its range, with one more call per token.
+    /// Only the ERC721 tracker fetches the owners.
+    pub fetch_owners: bool,
+    /// Fetch the timestamp of the block of each event, with one more call per block of the range.
+    /// Only the ERC721 tracker fetches the timestamps.
+    pub fetch_block_timestamps: bool,
+    /// Fetch the sender of the transaction of each event, with one more call per block of the range
+    pub fetch_transaction_senders: bool,
+    /// Find the Seaport, LooksRare and X2Y2 sale of each event in the receipt of its transaction,
+    /// with one more call per transaction. Only the ERC721 tracker finds the sales.
+    pub fetch_sales: bool,
+    /// Save the owner of each token after its last transfer, so that the holdings of an account can be compared
+    /// with its balance by `erc721::verify_owner_holdings` or queried by `erc721_db::get_tokens_by_owner`.
+    /// Scanning older blocks again does not change the saved owners. Only the ERC721 tracker saves the owners.
+    pub save_owners: bool,
+    /// Save the delivered transfers in the `transfers` table, with the metadata of their range, so that the history
+    /// of a token or the activity of a collection can be queried by `erc721_db::get_token_transfers` and
+    /// `erc721_db::get_collection_transfers`. Only the ERC721 tracker saves the transfers.
+    pub persist_events: bool,
+    /// Resolve the EIP-1967 implementation of each collection when it is first seen, and of each proxy again
+    /// at the last block of every range with its events, with one more call or two per collection.
+    /// The interfaces of a proxy are checked again with ERC165 when its implementation changes.
+    /// Only the ERC721 tracker resolves the proxies.
+    pub resolve_proxies: bool,
+    /// Request the ERC721 `Approval` events too, and deliver them to `on_erc721_approval`.
+    /// Only the ERC721 tracker tracks the approvals.
+    pub track_approvals: bool,
+    /// Request the ERC721 `ApprovalForAll` events too, and deliver them to `on_erc721_approval_for_all`.
+    /// Only the ERC721 tracker tracks the approvals.
+    pub track_approvals_for_all: bool,
+    /// Decode the events of the CryptoPunks contract, which predates ERC721, as transfers of its punks.
+    /// They are decoded too when the contract is in `address_allowlist`. Only the ERC721 tracker decodes them.
+    pub track_cryptopunks: bool,
+    /// The widest ERC2309 `ConsecutiveTransfer` delivered as the transfers of its tokens one by one, with their
+    /// metadata. The wider ones are delivered to `on_erc721_consecutive_transfer` without the metadata of their tokens,
+    /// which would take a call per token. Only the ERC721 tracker decodes them.
+    pub max_expanded_consecutive_transfer: u64,
+    /// The contracts whose `Transfer` events index nothing, as the 2017 ones, decoded from the data of the events.
+    /// `CRYPTOKITTIES_ADDRESS` by default. Only the ERC721 tracker decodes them.
+    pub non_indexed_erc721_contracts: Vec<H160>,
+    /// Keep the log decoded as each transfer event in `Erc721Event::raw`, for the consumers needing its topics,
+    /// its data or its removed flag. Off by default as it holds the logs in memory until they are delivered.
+    /// Only the ERC721 tracker keeps them.
+    pub keep_raw_logs: bool,
+    /// Fetch the ERC2981 royalty of each collection of a range, with one more call or two per collection,
+    /// and save it with the collection. Only the ERC721 tracker fetches the royalties.
+    pub fetch_royalties: bool,
+    /// How many collections the ERC721 tracker keeps in memory, so that the events of the collections seen
+    /// recently do not query the database. 0 disables the cache.
+    pub collection_cache_capacity: usize,
+    /// How many tokens with their token uris the ERC721 tracker keeps in memory. 0 disables the cache.
+    pub token_cache_capacity: usize,
+    /// Get the name, symbol and token uri of each event at the block of the event rather than at the latest block,
+    /// so that they are the ones of the time of the transfer, before a reveal or a migration of the base uri.
+    /// The nodes which do not keep the old states answer at the latest block. Only the ERC721 tracker pins them.
+    pub historical_metadata: bool,
+    /// The metrics updated by the tracker, shared with the host application
+    pub metrics: Option<Arc<TrackerMetrics>>,
+}
+
+/// The configuration of the ERC721 tracker
+pub type Erc721TrackerConfig = TrackerConfig;
+/// The configuration of the ERC1155 tracker
+pub type Erc1155TrackerConfig = TrackerConfig;
+
+impl Default for TrackerConfig {
+    fn default() -> Self {
+        TrackerConfig {
+            start_from: 0,
+            start_block: None,
+            step: 6,
+            end_block: None,
+            end_time: None,
+            options: ScanOptions::default(),
+            error_policy: ErrorPolicy::default(),
+            address_allowlist: None,
+            max_addresses_per_request: MAX_ADDRESSES_PER_FILTER,
+            denylist: vec![],
+            event_kinds: EventKindFilter::All,
+            burn_addresses: vec![DEAD_ADDRESS],
+            callback_error_policy: CallbackErrorPolicy::Skip,
+            metadata_refresh: MetadataRefresh::Never,
+            metadata_retry: MetadataRetry::default(),
+            skip_non_erc721_contracts: false,
+            fetch_owners: false,
+            fetch_block_timestamps: false,
+            fetch_transaction_senders: false,
+            fetch_sales: false,
+            save_owners: false,
+            persist_events: false,
+            resolve_proxies: false,
+            track_approvals: false,
+            track_approvals_for_all: false,
+            track_cryptopunks: false,
+            max_expanded_consecutive_transfer: 10_000,
+            non_indexed_erc721_contracts: vec![CRYPTOKITTIES_ADDRESS],
+            keep_raw_logs: false,
+            fetch_royalties: false,
+            collection_cache_capacity: 1024,
+            token_cache_capacity: 256,
+            historical_metadata: false,
+            metrics: None,
+        }
+    }
+}
+
+impl TrackerConfig {
+    /// Create a builder starting from the default configuration
+    pub fn builder() -> TrackerConfigBuilder {
+        TrackerConfigBuilder::default()
+    }
+
+    /// Check the configuration, as `TrackerConfigBuilder::build` does. The trackers check it before they start,
+    /// since a configuration may also be written without the builder.
+    pub fn validate(&self) -> Result<()> {
+        if self.step == 0 {
+            return Err(Error::InvalidConfig("step must be greater than 0".to_owned()));
+        }
+        if let Some(end_block) = self.end_block {
+            if end_block < self.start_from {
+                return Err(Error::InvalidConfig(format!(
+                    "end_block {} is less than start_from {}",
+                    end_block, self.start_from
+                )));
+            }
+        }
+        if self.options.step_growth_factor == 0 {
+            return Err(Error::InvalidConfig(
+                "step_growth_factor must be greater than 0".to_owned(),
+            ));
+        }
+        if self.options.error_backoff_multiplier == 0 {
+            return Err(Error::InvalidConfig(
+                "error_backoff_multiplier must be greater than 0".to_owned(),
+            ));
+        }
+        if self.options.max_step == Some(0) {
+            return Err(Error::InvalidConfig("max_step must be greater than 0".to_owned()));
+        }
+        if self.options.pipeline_depth == 0 {
+            return Err(Error::InvalidConfig("pipeline_depth must be greater than 0".to_owned()));
+        }
+        if self.options.parallel_ranges == 0 {
+            return Err(Error::InvalidConfig("parallel_ranges must be greater than 0".to_owned()));
+        }
+        if self.options.metadata_concurrency == 0 {
+            return Err(Error::InvalidConfig("metadata_concurrency must be greater than 0".to_owned()));
+        }
+        if self.metadata_retry.max_attempts == 0 {
+            return Err(Error::InvalidConfig("metadata_retry.max_attempts must be greater than 0".to_owned()));
+        }
+        if let CallbackErrorPolicy::RetryWithBackoff { max_attempts: 0, .. } =
+            self.callback_error_policy
+        {
+            return Err(Error::InvalidConfig("max_attempts must be greater than 0".to_owned()));
+        }
+        if self.max_addresses_per_request == 0 {
+            return Err(Error::InvalidConfig(
+                "max_addresses_per_request must be greater than 0".to_owned(),
+            ));
+        }
+        if self.error_policy.max_consecutive_errors == Some(0) {
+            return Err(Error::InvalidConfig(
+                "max_consecutive_errors must be greater than 0".to_owned(),
+            ));
+        }
+        Ok(())
+    }
+
+    /// Whether the events of the CryptoPunks contract are decoded, with `track_cryptopunks`
+    /// or with the contract in the allowlist
+    pub(crate) fn tracks_cryptopunks(&self) -> bool {
+        self.track_cryptopunks
+            || self.address_allowlist.as_ref().map_or(false, |addresses| addresses.contains(&CRYPTOPUNKS_ADDRESS))
+    }
+
+    /// Resolve `start_block` to a block, which becomes the `start_from`
+    pub(crate) async fn resolve_start_block(
+        &self,
+        evm_client: &dyn EvmClientApi,
+    ) -> Result<Cow<'_, TrackerConfig>> {
+        let start_block = match self.start_block {
+            Some(start_block) => start_block,
+            None => return Ok(Cow::Borrowed(self)),
+        };
+        let start_from = match start_block {
+            StartBlock::Number(block_number) => block_number,
+            StartBlock::Latest | StartBlock::LatestMinus(_) => {
+                let latest_block_number = evm_client.get_latest_block_number().await?;
+                let confirmed = latest_block_number.saturating_sub(self.options.confirmations);
+                match start_block {
+                    StartBlock::LatestMinus(blocks) => confirmed.saturating_sub(blocks),
+                    _ => confirmed,
+                }
+            }
+        };
+        if let Some(end_block) = self.end_block {
+            if end_block < start_from {
+                return Err(Error::InvalidConfig(format!(
+                    "end_block {} is less than the start block {}",
+                    end_block, start_from
+                )));
+            }
+        }
+        info!(
+            "The start block {:?} of {} is resolved to block {}.",
+            start_block,
+            evm_client.chain_name(),
+            start_from
+        );
+        let mut config = self.clone();
+        config.start_from = start_from;
+        config.start_block = None;
+        Ok(Cow::Owned(config))
+    }
+
+    /// Resolve `end_time` to the last block at or before it, which becomes the `end_block`
+    pub(crate) async fn resolve_end_time(
+        &self,
+        evm_client: &dyn EvmClientApi,
+    ) -> Result<Cow<'_, TrackerConfig>> {
+        let end_time = match self.end_time {
+            Some(end_time) => end_time,
+            None => return Ok(Cow::Borrowed(self)),
+        };
+        let end_block = block_number_at_timestamp(evm_client, end_time)
+            .await?
+            .ok_or_else(|| {
+                Error::InvalidConfig(format!("end_time {} is before the first block", end_time))
+            })?;
+        info!(
+            "The end_time {} of {} is resolved to block {}.",
+            end_time,
+            evm_client.chain_name(),
+            end_block
+        );
+        let mut config = self.clone();
+        config.end_block = Some(self.end_block.map_or(end_block, |block| std::cmp::min(block, end_block)));
+        config.end_time = None;
+        Ok(Cow::Owned(config))
+    }
+}
+
+/// The builder of `TrackerConfig`
+#[derive(Debug, Clone, Default)]
+pub struct TrackerConfigBuilder {
+    config: TrackerConfig,
+}
+
+impl TrackerConfigBuilder {
+    /// The block to start tracking from
+    pub fn start_from(mut self, start_from: u64) -> Self {
+        self.config.start_from = start_from;
+        self
+    }
+
+    /// Where to start, possibly relatively to the latest block, instead of `start_from`
+    pub fn start_block(mut self, start_block: StartBlock) -> Self {
+        self.config.start_block = Some(start_block);
+        self
+    }
+
+    /// How many blocks are scanned at once
+    pub fn step(mut self, step: u64) -> Self {
+        self.config.step = step;
+        self
+    }
+
+    /// The tracker returns after this block has been processed
+    pub fn end_block(mut self, end_block: u64) -> Self {
+        self.config.end_block = Some(end_block);
+        self
+    }
+
+    /// The tracker returns after the last block at or before this time, in unix seconds
+    pub fn end_time(mut self, end_time: u64) -> Self {
+        self.config.end_time = Some(end_time);
+        self
+    }
+
+    /// How many blocks behind the latest block are considered confirmed
+    pub fn confirmations(mut self, confirmations: u64) -> Self {
+        self.config.options.confirmations = confirmations;
+        self
+    }
+
+    /// How long to wait after a block range has been scanned successfully
+    pub fn range_interval(mut self, interval: Duration) -> Self {
+        self.config.options.range_interval = interval;
+        self
+    }
+
+    /// Skip `range_interval` while the tracker is far behind the confirmed blocks
+    pub fn backfill(mut self, backfill: bool) -> Self {
+        self.config.options.backfill = backfill;
+        self
+    }
+
+    /// How many confirmed blocks must remain after a range to skip `range_interval`
+    pub fn backfill_threshold(mut self, threshold: u64) -> Self {
+        self.config.options.backfill_threshold = Some(threshold);
+        self
+    }
+
+    /// How long to wait when the tracker has caught up with the latest block
+    pub fn idle_interval(mut self, interval: Duration) -> Self {
+        self.config.options.idle_interval = interval;
+        self
+    }
+
+    /// How long to wait after an error
+    pub fn error_interval(mut self, interval: Duration) -> Self {
+        self.config.options.error_interval = interval;
+        self
+    }
+
+    /// The multiplier and the cap of the delay after consecutive errors
+    pub fn error_backoff(mut self, multiplier: u32, max_interval: Duration) -> Self {
+        self.config.options.error_backoff_multiplier = multiplier;
+        self.config.options.max_error_interval = max_interval;
+        self
+    }
+
+    /// Randomize the delays after errors
+    pub fn error_jitter(mut self, jitter: bool) -> Self {
+        self.config.options.error_jitter = jitter;
+        self
+    }
+
+    /// Give up once the delays after consecutive errors add up to more than `budget`
+    pub fn retry_budget(mut self, budget: Duration) -> Self {
+        self.config.options.retry_budget = Some(budget);
+        self
+    }
+
+    /// The factor and the number of consecutive successful ranges used to grow the step back
+    pub fn step_growth(mut self, factor: u64, threshold: u32) -> Self {
+        self.config.options.step_growth_factor = factor;
+        self.config.options.step_growth_threshold = threshold;
+        self
+    }
+
+    /// The step never grows beyond this value
+    pub fn max_step(mut self, max_step: u64) -> Self {
+        self.config.options.max_step = Some(max_step);
+        self
+    }
+
+    /// How many fetched ranges can wait for their events to be processed
+    pub fn pipeline_depth(mut self, depth: usize) -> Self {
+        self.config.options.pipeline_depth = depth;
+        self
+    }
+
+    /// How many ranges are fetched concurrently
+    pub fn parallel_ranges(mut self, parallel_ranges: usize) -> Self {
+        self.config.options.parallel_ranges = parallel_ranges;
+        self
+    }
+
+    /// How many events have their metadata fetched concurrently
+    pub fn metadata_concurrency(mut self, metadata_concurrency: usize) -> Self {
+        self.config.options.metadata_concurrency = metadata_concurrency;
+        self
+    }
+
+    /// Subscribe to the new blocks when the client supports it
+    pub fn live(mut self, live: bool) -> Self {
+        self.config.options.live = live;
+        self
+    }
+
+    /// Persist the last scanned block in the database and resume from it
+    pub fn resume(mut self, resume: bool) -> Self {
+        self.config.options.resume = resume;
+        self
+    }
+
+    /// Skip the events which have already been delivered
+    pub fn dedup(mut self, dedup: bool) -> Self {
+        self.config.options.dedup = dedup;
+        self
+    }
+
+    /// Rescan the blocks removed by a reorg
+    pub fn detect_reorgs(mut self, detect_reorgs: bool) -> Self {
+        self.config.options.detect_reorgs = detect_reorgs;
+        self
+    }
+
+    /// Query the logs block by block, by the hashes of the blocks
+    pub fn strict_block_hashes(mut self, strict_block_hashes: bool) -> Self {
+        self.config.options.strict_block_hashes = strict_block_hashes;
+        self
+    }
+
+    /// Never write to the database
+    pub fn dry_run(mut self, dry_run: bool) -> Self {
+        self.config.options.dry_run = dry_run;
+        self
+    }
+
+    /// The tracker returns when this token is cancelled
+    pub fn cancellation_token(mut self, token: CancellationToken) -> Self {
+        self.config.options.cancellation_token = Some(token);
+        self
+    }
+
+    /// Replace all the scan options at once
+    pub fn options(mut self, options: ScanOptions) -> Self {
+        self.config.options = options;
+        self
+    }
+
+    /// Which errors make the tracker stop
+    pub fn error_policy(mut self, error_policy: ErrorPolicy) -> Self {
+        self.config.error_policy = error_policy;
+        self
+    }
+
+    /// Only track the events of these contracts
+    pub fn address_allowlist(mut self, addresses: Vec<H160>) -> Self {
+        self.config.address_allowlist = Some(addresses);
+        self
+    }
+
+    /// How many addresses of the allowlist are sent in one request
+    pub fn max_addresses_per_request(mut self, max: usize) -> Self {
+        self.config.max_addresses_per_request = max;
+        self
+    }
+
+    /// Ignore the events of these contracts
+    pub fn denylist(mut self, addresses: Vec<H160>) -> Self {
+        self.config.denylist = addresses;
+        self
+    }
+
+    /// Which kinds of events are passed to the callback
+    pub fn event_kinds(mut self, event_kinds: EventKindFilter) -> Self {
+        self.config.event_kinds = event_kinds;
+        self
+    }
+
+    /// The addresses whose incoming transfers are burns, besides the zero address
+    pub fn burn_addresses(mut self, addresses: Vec<H160>) -> Self {
+        self.config.burn_addresses = addresses;
+        self
+    }
+
+    /// What to do when the callback returns an error
+    pub fn callback_error_policy(mut self, policy: CallbackErrorPolicy) -> Self {
+        self.config.callback_error_policy = policy;
+        self
+    }
+
+    /// When the saved token uris are fetched again
+    pub fn metadata_refresh(mut self, refresh: MetadataRefresh) -> Self {
+        self.config.metadata_refresh = refresh;
+        self
+    }
+
+    /// How the failed metadata lookups are retried
+    pub fn metadata_retry(mut self, retry: MetadataRetry) -> Self {
+        self.config.metadata_retry = retry;
+        self
+    }
+
+    /// Drop the events of the contracts which report with ERC165 not supporting ERC721
+    pub fn skip_non_erc721_contracts(mut self, skip: bool) -> Self {
+        self.config.skip_non_erc721_contracts = skip;
+        self
+    }
+
+    /// Fetch the owner of each token
+    pub fn fetch_owners(mut self, fetch_owners: bool) -> Self {
+        self.config.fetch_owners = fetch_owners;
+        self
+    }
+
+    /// Fetch the timestamp of the block of each event
+    pub fn fetch_block_timestamps(mut self, fetch_block_timestamps: bool) -> Self {
+        self.config.fetch_block_timestamps = fetch_block_timestamps;
+        self
+    }
+
+    /// Fetch the sender of the transaction of each event
+    pub fn fetch_transaction_senders(mut self, fetch_transaction_senders: bool) -> Self {
+        self.config.fetch_transaction_senders = fetch_transaction_senders;
+        self
+    }
+
+    /// Find the marketplace sale of each event
+    pub fn fetch_sales(mut self, fetch_sales: bool) -> Self {
+        self.config.fetch_sales = fetch_sales;
+        self
+    }
+
+    /// Save the owner of each token
+    pub fn save_owners(mut self, save_owners: bool) -> Self {
+        self.config.save_owners = save_owners;
+        self
+    }
+
+    /// Save the delivered transfers
+    pub fn persist_events(mut self, persist_events: bool) -> Self {
+        self.config.persist_events = persist_events;
+        self
+    }
+
+    /// Resolve the EIP-1967 implementation of each collection
+    pub fn resolve_proxies(mut self, resolve_proxies: bool) -> Self {
+        self.config.resolve_proxies = resolve_proxies;
+        self
+    }
+
+    /// Track the `Approval` events
+    pub fn track_approvals(mut self, track_approvals: bool) -> Self {
+        self.config.track_approvals = track_approvals;
+        self
+    }
+
+    /// Track the `ApprovalForAll` events
+    pub fn track_approvals_for_all(mut self, track_approvals_for_all: bool) -> Self {
+        self.config.track_approvals_for_all = track_approvals_for_all;
+        self
+    }
+
+    /// Decode the events of the CryptoPunks contract as transfers
+    pub fn track_cryptopunks(mut self, track_cryptopunks: bool) -> Self {
+        self.config.track_cryptopunks = track_cryptopunks;
+        self
+    }
+
+    /// The widest ERC2309 `ConsecutiveTransfer` delivered as the transfers of its tokens
+    pub fn max_expanded_consecutive_transfer(mut self, tokens: u64) -> Self {
+        self.config.max_expanded_consecutive_transfer = tokens;
+        self
+    }
+
+    /// The contracts whose `Transfer` events index nothing
+    pub fn non_indexed_erc721_contracts(mut self, addresses: Vec<H160>) -> Self {
+        self.config.non_indexed_erc721_contracts = addresses;
+        self
+    }
+
+    /// Keep the log of each transfer event
+    pub fn keep_raw_logs(mut self, keep_raw_logs: bool) -> Self {
+        self.config.keep_raw_logs = keep_raw_logs;
+        self
+    }
+
+    /// Fetch the ERC2981 royalty of each collection
+    pub fn fetch_royalties(mut self, fetch_royalties: bool) -> Self {
+        self.config.fetch_royalties = fetch_royalties;
+        self
+    }
+
+    /// How many collections are kept in memory
+    pub fn collection_cache_capacity(mut self, capacity: usize) -> Self {
+        self.config.collection_cache_capacity = capacity;
+        self
+    }
+
+    /// How many tokens are kept in memory
+    pub fn token_cache_capacity(mut self, capacity: usize) -> Self {
+        self.config.token_cache_capacity = capacity;
+        self
+    }
+
+    /// Get the metadata of each event at the block of the event
+    pub fn historical_metadata(mut self, historical_metadata: bool) -> Self {
+        self.config.historical_metadata = historical_metadata;
+        self
+    }
+
+    /// The metrics updated by the tracker
+    pub fn metrics(mut self, metrics: Arc<TrackerMetrics>) -> Self {
+        self.config.metrics = Some(metrics);
+        self
+    }
+
+    /// Validate and build the configuration
+    pub fn build(self) -> Result<TrackerConfig> {
+        self.config.validate()?;
+        Ok(self.config)
+    }
+}
+
+/// The step of the tracking loops. It is halved when the RPC complains about too many results,
+/// and grows back after enough consecutive successful ranges.
+pub(crate) struct AdaptiveStep {
+    step: u64,
+    max_step: u64,
+    growth_factor: u64,
+    growth_threshold: u32,
+    successes: u32,
+}
+
+impl AdaptiveStep {
+    pub(crate) fn new(step: u64, options: &ScanOptions) -> AdaptiveStep {
+        AdaptiveStep {
+            step,
+            max_step: options.max_step.unwrap_or(step),
+            growth_factor: options.step_growth_factor,
+            growth_threshold: options.step_growth_threshold,
+            successes: 0,
+        }
+    }
+
+    /// The current step
+    pub(crate) fn get(&self) -> u64 {
+        self.step
+    }
+
+    /// Halve the step after the RPC rejected a range
+    pub(crate) fn shrink(&mut self) {
+        self.step = std::cmp::max(self.step / 2, 1);
+        self.successes = 0;
+    }
+
+    /// Record a successfully scanned range, growing the step if possible
+    pub(crate) fn succeed(&mut self) {
+        if self.step >= self.max_step {
+            return;
+        }
+        self.successes += 1;
+        if self.successes >= self.growth_threshold {
+            self.step = std::cmp::min(self.step.saturating_mul(self.growth_factor), self.max_step);
+            self.successes = 0;
+        }
+    }
+}
+
+/// The delays after consecutive errors
+pub(crate) struct Backoff {
+    initial: Duration,
+    multiplier: u32,
+    max: Duration,
+    jitter: bool,
+    budget: Option<Duration>,
+    next: Duration,
+    spent: Duration,
+}
+
+impl Backoff {
+    pub(crate) fn new(options: &ScanOptions) -> Backoff {
+        Backoff {
+            initial: options.error_interval,
+            multiplier: options.error_backoff_multiplier,
+            max: options.max_error_interval,
+            jitter: options.error_jitter,
+            budget: options.retry_budget,
+            next: options.error_interval,
+            spent: Duration::from_secs(0),
+        }
+    }
+
+    /// The delay before retrying, None if the retry budget is exhausted
+    pub(crate) fn next_delay(&mut self) -> Option<Duration> {
+        self.next_delay_with(random_fraction())
+    }
+
+    /// The delay before retrying, jittered by `random` which is between 0 and 1
+    pub(crate) fn next_delay_with(&mut self, random: f64) -> Option<Duration> {
+        let delay = std::cmp::min(self.next, self.max);
+        let delay = if self.jitter {
+            delay.mul_f64(0.5 + random / 2.0)
+        } else {
+            delay
+        };
+        self.spent += delay;
+        if let Some(budget) = self.budget {
+            if self.spent > budget {
+                return None;
+            }
+        }
+        self.next = std::cmp::min(self.next * self.multiplier, self.max);
+        Some(delay)
+    }
+
+    /// Start again from the first delay after a success
+    pub(crate) fn reset(&mut self) {
+        self.next = self.initial;
+        self.spent = Duration::from_secs(0);
+    }
+}
+
+/// A pseudo-random number between 0 and 1, good enough for jitter
+pub(crate) fn random_fraction() -> f64 {
+    use std::collections::hash_map::RandomState;
+    use std::hash::{BuildHasher, Hasher};
+
+    let mut hasher = RandomState::new().build_hasher();
+    hasher.write_u128(
+        std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map_or(0, |elapsed| elapsed.as_nanos()),
+    );
+    (hasher.finish() as f64) / (u64::MAX as f64)
+}
+
+/// The last block of the next range to scan.
+/// It returns None if no block has been confirmed yet.
+pub(crate) fn range_end(
+    from: u64,
+    step: u64,
+    latest_block_number: u64,
+    confirmations: u64,
+) -> Option<u64> {
+    latest_block_number
+        .checked_sub(confirmations)
+        .map(|confirmed| std::cmp::min(from.saturating_add(step).saturating_sub(1), confirmed))
+}
+
+/// The last fully processed block, given the first block that has not been processed yet.
+pub(crate) fn last_processed_block(start_from: u64, next_block: u64) -> Option<u64> {
+    if next_block > start_from {
+        Some(next_block - 1)
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::MockEvmClient;
+
+    #[test]
+    fn test_range_end() {
+        // limited by the step
+        assert_eq!(Some(109), range_end(100, 10, 1000, 6));
+        // limited by the confirmations
+        assert_eq!(Some(94), range_end(90, 10, 100, 6));
+        assert_eq!(Some(88), range_end(80, 10, 100, 12));
+        // instant finality
+        assert_eq!(Some(100), range_end(95, 10, 100, 0));
+        // a step as wide as the chain, as the span of many parallel ranges
+        assert_eq!(Some(994), range_end(100, u64::MAX, 1000, 6));
+        assert_eq!(Some(u64::MAX), range_end(u64::MAX, u64::MAX, u64::MAX, 0));
+    }
+
+    #[test]
+    fn test_adaptive_step() {
+        let options = ScanOptions {
+            step_growth_factor: 2,
+            step_growth_threshold: 3,
+            ..Default::default()
+        };
+        let mut step = AdaptiveStep::new(100, &options);
+
+        step.shrink();
+        step.shrink();
+        assert_eq!(25, step.get());
+
+        // grows after 3 consecutive successes
+        step.succeed();
+        step.succeed();
+        assert_eq!(25, step.get());
+        step.succeed();
+        assert_eq!(50, step.get());
+
+        // a failure resets the successes
+        step.succeed();
+        step.succeed();
+        step.shrink();
+        assert_eq!(25, step.get());
+        for _ in 0..6 {
+            step.succeed();
+        }
+        assert_eq!(100, step.get());
+
+        // capped at the initial step
+        for _ in 0..10 {
+            step.succeed();
+        }
+        assert_eq!(100, step.get());
+    }
+
+    #[test]
+    fn test_adaptive_step_with_a_large_growth_factor() {
+        let options = ScanOptions {
+            max_step: Some(u64::MAX),
+            step_growth_factor: u64::MAX,
+            step_growth_threshold: 1,
+            ..Default::default()
+        };
+        let mut step = AdaptiveStep::new(10, &options);
+        step.succeed();
+        assert_eq!(u64::MAX, step.get());
+    }
+
+    #[test]
+    fn test_adaptive_step_never_below_one() {
+        let mut step = AdaptiveStep::new(1, &ScanOptions::default());
+        step.shrink();
+        assert_eq!(1, step.get());
+    }
+
+    #[test]
+    fn test_build_tracker_config() {
+        let config = TrackerConfig::builder()
+            .start_from(13015344)
+            .step(10)
+            .end_block(13015346)
+            .confirmations(12)
+            .build()
+            .unwrap();
+        assert_eq!(13015344, config.start_from);
+        assert_eq!(10, config.step);
+        assert_eq!(Some(13015346), config.end_block);
+        assert_eq!(12, config.options.confirmations);
+        assert_eq!(Duration::from_secs(5), config.options.range_interval);
+    }
+
+    #[test]
+    fn test_build_invalid_tracker_config() {
+        let result = TrackerConfig::builder().step(0).build();
+        assert!(matches!(result, Err(Error::InvalidConfig(_))));
+
+        let result = TrackerConfig::builder().start_from(100).end_block(99).build();
+        assert!(matches!(result, Err(Error::InvalidConfig(_))));
+
+        let result = TrackerConfig::builder().step_growth(0, 10).build();
+        assert!(matches!(result, Err(Error::InvalidConfig(_))));
+
+        let result = TrackerConfig::builder()
+            .error_backoff(0, Duration::from_secs(60))
+            .build();
+        assert!(matches!(result, Err(Error::InvalidConfig(_))));
+
+        let result = TrackerConfig::builder().max_step(0).build();
+        assert!(matches!(result, Err(Error::InvalidConfig(_))));
+
+        let result = TrackerConfig::builder().pipeline_depth(0).build();
+        assert!(matches!(result, Err(Error::InvalidConfig(_))));
+
+        let result = TrackerConfig::builder().parallel_ranges(0).build();
+        assert!(matches!(result, Err(Error::InvalidConfig(_))));
+
+        let result = TrackerConfig::builder().metadata_concurrency(0).build();
+        assert!(matches!(result, Err(Error::InvalidConfig(_))));
+
+        let result = TrackerConfig::builder()
+            .metadata_retry(MetadataRetry {
+                max_attempts: 0,
+                base_delay: Duration::from_secs(1),
+            })
+            .build();
+        assert!(matches!(result, Err(Error::InvalidConfig(_))));
+
+        let result = TrackerConfig::builder()
+            .callback_error_policy(CallbackErrorPolicy::RetryWithBackoff {
+                max_attempts: 0,
+                base_delay: Duration::from_secs(1),
+            })
+            .build();
+        assert!(matches!(result, Err(Error::InvalidConfig(_))));
+
+        let result = TrackerConfig::builder().max_addresses_per_request(0).build();
+        assert!(matches!(result, Err(Error::InvalidConfig(_))));
+    }
+
+    #[test]
+    fn test_error_policy() {
+        let policy = ErrorPolicy::default();
+        let unauthorized = Error::Web3Error(web3::Error::Transport(
+            "response status code is not success: 401".to_owned(),
+        ));
+        assert!(policy.is_terminal(&unauthorized));
+        assert!(!policy.is_terminal(&Error::Other("timeout".to_owned())));
+        assert!(!policy.should_stop(&Error::Other("timeout".to_owned()), 100));
+        assert!(!policy.is_terminal(&Error::Timeout(Duration::from_secs(30))));
+        assert!(policy.is_terminal(&Error::ChainIdMismatch { expected: 1, actual: 137 }));
+        let forbidden = Error::Web3Error(web3::Error::Transport(
+            "Server responded with a non-success status code: 403 Forbidden".to_owned(),
+        ));
+        assert!(policy.is_terminal(&forbidden));
+        // the digits of a hash or of a block number are not a status
+        let pruned = Error::Web3Error(web3::Error::Rpc(web3::rpc::Error {
+            code: web3::rpc::ErrorCode::ServerError(-32000),
+            message: "missing trie node 4013ab403f (path ) state 0x403ab not available".to_owned(),
+            data: None,
+        }));
+        assert!(!policy.is_terminal(&pruned));
+        let behind = Error::Web3Error(web3::Error::Transport(
+            "header not found for block 14031401, state 0x4030 is syncing".to_owned(),
+        ));
+        assert!(!policy.is_terminal(&behind));
+
+        let policy = ErrorPolicy::new(|err| matches!(err, Error::Other(_)))
+            .with_max_consecutive_errors(3);
+        assert!(policy.is_terminal(&Error::Other("decode".to_owned())));
+        assert!(!policy.is_terminal(&unauthorized));
+        assert!(!policy.should_stop(&unauthorized, 2));
+        assert!(policy.should_stop(&unauthorized, 3));
+    }
+
+    fn rpc_error(code: i64, message: &str) -> web3::Error {
+        web3::Error::Rpc(web3::rpc::Error {
+            code: web3::rpc::ErrorCode::ServerError(code),
+            message: message.to_owned(),
+            data: None,
+        })
+    }
+
+    #[test]
+    fn test_is_range_limit_error() {
+        // Infura
+        assert!(is_range_limit_error(&rpc_error(-32005, "query returned more than 10000 results")));
+        assert!(!is_range_limit_error(&rpc_error(-32005, "daily request count exceeded, request rate limited")));
+        // Alchemy
+        assert!(is_range_limit_error(&rpc_error(
+            -32602,
+            "Log response size exceeded. You can make eth_getLogs requests with up to a 2K block range and no limit on the response size, or you can request any block range with a cap of 10K logs in the response. Based on your parameters, this block range should work: [0x0, 0x1f3f]"
+        )));
+        // BSC
+        assert!(is_range_limit_error(&rpc_error(-32000, "exceed maximum block range: 5000")));
+        // QuickNode
+        assert!(is_range_limit_error(&rpc_error(-32614, "eth_getLogs is limited to a 10,000 range")));
+        // Ankr
+        assert!(is_range_limit_error(&rpc_error(-32600, "block range is too wide")));
+        // a gateway relaying the error of the node in its body
+        assert!(is_range_limit_error(&web3::Error::Transport(
+            "response status code is not success: 400, {\"error\":{\"code\":-32000,\"message\":\"block range too large\"}}".to_owned()
+        )));
+
+        assert!(!is_range_limit_error(&rpc_error(-32000, "header not found")));
+        assert!(!is_range_limit_error(&web3::Error::Transport("connection refused".to_owned())));
+    }
+
+    #[test]
+    fn test_error_policy_with_range_limit_errors() {
+        let custom = rpc_error(-32000, "too many blocks requested");
+        let policy = ErrorPolicy::default();
+        assert!(policy.is_range_limit(&Error::Web3Error(rpc_error(-32000, "exceed maximum block range: 5000"))));
+        assert!(!policy.is_range_limit(&Error::Web3Error(rpc_error(-32000, "too many blocks requested"))));
+        assert!(!policy.is_range_limit(&Error::Other("block range too large".to_owned())));
+
+        let policy = ErrorPolicy::default().with_range_limit_errors(|err| {
+            matches!(err, web3::Error::Rpc(e) if e.message.contains("too many blocks"))
+        });
+        assert!(policy.is_range_limit(&Error::Web3Error(custom)));
+        assert!(policy.is_range_limit(&Error::Web3Error(rpc_error(-32000, "exceed maximum block range: 5000"))));
+    }
+
+    #[test]
+    fn test_event_kind_filter() {
+        let zero = H160::zero();
+        let account = H160::from_low_u64_be(1);
+        let mint = EventKind::of(&zero, &account);
+        let burn = EventKind::of(&account, &zero);
+        let transfer = EventKind::of(&account, &H160::from_low_u64_be(2));
+        assert_eq!(EventKind::Mint, mint);
+        assert_eq!(EventKind::Burn, burn);
+        assert_eq!(EventKind::Transfer, transfer);
+
+        let accepted = |filter: EventKindFilter| {
+            vec![mint, burn, transfer]
+                .into_iter()
+                .filter(|kind| filter.accepts(*kind))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(vec![mint, burn, transfer], accepted(EventKindFilter::All));
+        assert_eq!(vec![mint], accepted(EventKindFilter::MintsOnly));
+        assert_eq!(vec![burn], accepted(EventKindFilter::BurnsOnly));
+        assert_eq!(vec![transfer], accepted(EventKindFilter::TransfersOnly));
+    }
+
+    #[test]
+    fn test_backoff() {
+        let options = ScanOptions {
+            error_interval: Duration::from_secs(1),
+            error_backoff_multiplier: 2,
+            max_error_interval: Duration::from_secs(8),
+            error_jitter: false,
+            ..Default::default()
+        };
+        let mut backoff = Backoff::new(&options);
+        let delays: Vec<u64> = (0..6)
+            .map(|_| backoff.next_delay_with(0.0).unwrap().as_secs())
+            .collect();
+        assert_eq!(vec![1, 2, 4, 8, 8, 8], delays);
+
+        // reset after a success
+        backoff.reset();
+        assert_eq!(Some(Duration::from_secs(1)), backoff.next_delay_with(0.0));
+    }
+
+    #[test]
+    fn test_backoff_jitter() {
+        let options = ScanOptions {
+            error_interval: Duration::from_secs(4),
+            ..Default::default()
+        };
+        let mut backoff = Backoff::new(&options);
+        assert_eq!(Some(Duration::from_secs(2)), backoff.next_delay_with(0.0));
+        assert_eq!(Some(Duration::from_secs(8)), backoff.next_delay_with(1.0));
+        assert_eq!(Some(Duration::from_secs(12)), backoff.next_delay_with(0.5));
+
+        let delay = Backoff::new(&options).next_delay().unwrap();
+        assert!(delay >= Duration::from_secs(2) && delay <= Duration::from_secs(4));
+    }
+
+    #[test]
+    fn test_backoff_budget() {
+        let options = ScanOptions {
+            error_interval: Duration::from_secs(1),
+            error_backoff_multiplier: 2,
+            error_jitter: false,
+            retry_budget: Some(Duration::from_secs(10)),
+            ..Default::default()
+        };
+        let mut backoff = Backoff::new(&options);
+        // 1 + 2 + 4 = 7, the next delay would exceed the budget
+        assert_eq!(Some(Duration::from_secs(1)), backoff.next_delay_with(0.0));
+        assert_eq!(Some(Duration::from_secs(2)), backoff.next_delay_with(0.0));
+        assert_eq!(Some(Duration::from_secs(4)), backoff.next_delay_with(0.0));
+        assert_eq!(None, backoff.next_delay_with(0.0));
+
+        // the budget is restored after a success
+        backoff.reset();
+        assert_eq!(Some(Duration::from_secs(1)), backoff.next_delay_with(0.0));
+    }
+
+    #[test]
+    fn test_is_backfilling() {
+        let options = ScanOptions::default();
+        // 94 is the last confirmed block
+        assert!(options.is_backfilling(73, 100, 10));
+        assert!(!options.is_backfilling(74, 100, 10));
+        assert!(!options.is_backfilling(94, 100, 10));
+
+        let options = ScanOptions {
+            backfill_threshold: Some(0),
+            ..Default::default()
+        };
+        assert!(options.is_backfilling(93, 100, 10));
+
+        let options = ScanOptions {
+            backfill: false,
+            ..Default::default()
+        };
+        assert!(!options.is_backfilling(10, 100, 10));
+    }
+
+    #[test]
+    fn test_range_end_before_any_confirmed_block() {
+        assert_eq!(None, range_end(0, 10, 3, 6));
+        assert_eq!(Some(0), range_end(0, 10, 6, 6));
+    }
+
+    #[tokio::test]
+    async fn test_resolve_end_time() {
+        let client = MockEvmClient::new("Mock", 1000).with_block_time(1_000, 10);
+
+        let config = TrackerConfig::builder().end_time(1_000 + 10 * 300 + 5).build().unwrap();
+        let resolved = config.resolve_end_time(&client).await.unwrap();
+        assert_eq!(Some(300), resolved.end_block);
+        assert_eq!(None, resolved.end_time);
+
+        // the earliest applies
+        let config = TrackerConfig::builder()
+            .end_block(200)
+            .end_time(1_000 + 10 * 300)
+            .build()
+            .unwrap();
+        assert_eq!(Some(200), config.resolve_end_time(&client).await.unwrap().end_block);
+
+        let config = TrackerConfig::builder().end_time(999).build().unwrap();
+        let result = config.resolve_end_time(&client).await;
+        assert!(matches!(result, Err(Error::InvalidConfig(_))));
+
+        // nothing to resolve
+        let config = TrackerConfig::builder().end_block(200).build().unwrap();
+        assert!(matches!(config.resolve_end_time(&client).await.unwrap(), Cow::Borrowed(_)));
+        assert_eq!(0, client.call_count("get_block_timestamp"));
+    }
+
+    #[tokio::test]
+    async fn test_resolve_start_block() {
+        let client = MockEvmClient::new("Mock", 100);
+
+        let config = TrackerConfig::builder().start_block(StartBlock::Latest).build().unwrap();
+        let resolved = config.resolve_start_block(&client).await.unwrap();
+        assert_eq!(94, resolved.start_from);
+        assert_eq!(None, resolved.start_block);
+
+        let config = TrackerConfig::builder()
+            .start_block(StartBlock::LatestMinus(10))
+            .confirmations(12)
+            .build()
+            .unwrap();
+        assert_eq!(78, config.resolve_start_block(&client).await.unwrap().start_from);
+
+        let config = TrackerConfig::builder()
+            .start_block(StartBlock::LatestMinus(1000))
+            .build()
+            .unwrap();
+        assert_eq!(0, config.resolve_start_block(&client).await.unwrap().start_from);
+
+        let config = TrackerConfig::builder()
+            .start_block(StartBlock::Latest)
+            .end_block(50)
+            .build()
+            .unwrap();
+        let result = config.resolve_start_block(&client).await;
+        assert!(matches!(result, Err(Error::InvalidConfig(_))));
+        assert_eq!(4, client.call_count("get_latest_block_number"));
+
+        // no need for the latest block
+        let config = TrackerConfig::builder().start_block(StartBlock::Number(7)).build().unwrap();
+        assert_eq!(7, config.resolve_start_block(&client).await.unwrap().start_from);
+        let config = TrackerConfig::builder().start_from(7).build().unwrap();
+        assert!(matches!(config.resolve_start_block(&client).await.unwrap(), Cow::Borrowed(_)));
+        assert_eq!(4, client.call_count("get_latest_block_number"));
+    }
+
+    #[test]
+    fn test_metadata_retry_is_due() {
+        let retry = MetadataRetry {
+            max_attempts: 3,
+            base_delay: Duration::from_secs(60),
+        };
+        assert!(!retry.is_due(1, 1000, 1059));
+        assert!(retry.is_due(1, 1000, 1060));
+        // the delay doubles
+        assert!(!retry.is_due(2, 1000, 1119));
+        assert!(retry.is_due(2, 1000, 1120));
+        // given up
+        assert!(!retry.is_due(3, 1000, u64::MAX));
+    }
+
+    #[test]
+    fn test_metadata_refresh_is_due() {
+        assert!(!MetadataRefresh::Never.is_due(Some(0), 1000));
+        assert!(!MetadataRefresh::Never.is_due(None, 1000));
+        assert!(MetadataRefresh::EveryEvent.is_due(Some(1000), 1000));
+
+        let refresh = MetadataRefresh::After(Duration::from_secs(60));
+        assert!(!refresh.is_due(Some(1000), 1059));
+        assert!(refresh.is_due(Some(1000), 1060));
+        assert!(refresh.is_due(None, 1000));
+        // the clock went back
+        assert!(!refresh.is_due(Some(1000), 900));
+    }
+}
@@ -1,11 +1,17 @@
 //! This module is the entry point for tracking ERC1155.
-use crate::{erc1155_db, erc1155_evm, erc1155_evm::Erc1155Event, Error, EvmClient, Result};
-use std::time::Duration;
-use tokio::time::sleep;
+use crate::{
+    config::{last_processed_block, range_end, AdaptiveStep, Backoff},
+    erc1155_evm,
+    erc1155_evm::Erc1155Event,
+    erc721::resume_from,
+    error::ProcessError,
+    evm_client::TransactionSenders,
+    Erc1155TrackerConfig, EvmClientApi, NftStore, Result, ScanOptions, ScanProgress, ScanReport,
+    TrackerConfig, TrackerMetrics,
+};
+use std::{borrow::Cow, time::Instant};
 use web3::types::{H160, U256};
 
-use rusqlite::Connection;
-
 /// When the ERC1155 event is fetched, the event will be exposed to the caller through this trait.
 /// The caller needs to implement this trait and write the code on how to use the event.
 /// The metadata is also passed along with it.
@@ -13,146 +19,345 @@ use rusqlite::Connection;
 pub trait Erc1155EventCallback: Send {
     /// The callback function
     async fn on_erc1155_event(&mut self, event: Erc1155Event, token_uri: String) -> Result<()>;
+
+    /// Called after each scanned block range, including the ranges without any event
+    async fn on_progress(&mut self, _progress: ScanProgress) {}
+}
+
+/// The key of the progress of the ERC1155 tracker of a chain in the `scan_progress` table, so that it does not
+/// overwrite the progress of the ERC721 tracker sharing its database, saved under the name of the chain.
+pub fn progress_key(chain_name: &str) -> String {
+    format!("{}:erc1155", chain_name)
 }
 
 /// Entry function for tracking ERC1155.
 /// If you only need to track ERC1155, you can use this function directly.
+/// It returns a report when `end_block` is reached or the tracker is cancelled.
 pub async fn track_erc1155_events(
-    evm_client: &EvmClient,
-    db_conn: &Connection,
+    evm_client: &dyn EvmClientApi,
+    store: &dyn NftStore,
     start_from: u64,
     step: u64,
     end_block: Option<u64>,
+    options: &ScanOptions,
     callback: &mut dyn Erc1155EventCallback,
-) {
-    let mut step = step;
+) -> Result<ScanReport> {
+    let config = TrackerConfig {
+        start_from,
+        step,
+        end_block,
+        options: options.clone(),
+        ..Default::default()
+    };
+    track_erc1155_events_with_config(evm_client, store, &config, callback).await
+}
+
+/// Track ERC1155 events as configured by `config`.
+/// It returns a report when `end_block` is reached or the tracker is cancelled,
+/// or the error which made the tracker stop according to the error policy.
+pub async fn track_erc1155_events_with_config(
+    evm_client: &dyn EvmClientApi,
+    store: &dyn NftStore,
+    config: &Erc1155TrackerConfig,
+    callback: &mut dyn Erc1155EventCallback,
+) -> Result<ScanReport> {
+    config.validate()?;
+    let started = Instant::now();
+    evm_client.verify().await?;
+    let progress_key = progress_key(evm_client.chain_name());
+    // a saved progress takes precedence over the start block
+    let resumes = config.options.resume && store.get_scan_progress(&progress_key).await?.is_some();
+    let started_config = if resumes {
+        Cow::Borrowed(config)
+    } else {
+        config.resolve_start_block(evm_client).await?
+    };
+    let resolved_config = started_config.resolve_end_time(evm_client).await?;
+    let config: &Erc1155TrackerConfig = &resolved_config;
+    let options = &config.options;
+    let chain_name = evm_client.chain_name();
+    let start_from = if options.resume {
+        resume_from(store, &progress_key, config.start_from).await?
+    } else {
+        config.start_from
+    };
+    let mut step = AdaptiveStep::new(config.step, options);
     let mut from = start_from;
+    let mut report = ScanReport::default();
+    let mut consecutive_errors = 0;
+    let mut backoff = Backoff::new(options);
+    let metrics = config.metrics.clone().unwrap_or_default();
     loop {
-        match evm_client.get_latest_block_number().await {
+        metrics.set_position(from, step.get());
+        if options.is_cancelled() {
+            info!("Tracking {} ERC1155 events is cancelled.", chain_name);
+            break;
+        }
+        if let Some(end_block) = config.end_block {
+            if from > end_block {
+                break;
+            }
+        }
+
+        let latest_block_number = match evm_client.get_latest_block_number().await {
             Ok(latest_block_number) => {
-                let to = std::cmp::min(from + step - 1, latest_block_number - 6);
-                if let Some(end_block) = end_block {
-                    if to > end_block {
-                        break;
+                metrics.set_latest_block(latest_block_number);
+                latest_block_number
+            }
+            Err(err) => {
+                report.record_rpc_error();
+                metrics.record_rpc_error();
+                consecutive_errors += 1;
+                if config.error_policy.should_stop(&err, consecutive_errors) {
+                    error!("Stop tracking {} ERC1155 events because of error: {:?}.", chain_name, err);
+                    return Err(err);
+                }
+                let delay = match backoff.next_delay() {
+                    Some(delay) => delay,
+                    None => {
+                        error!("The retry budget of {} is exhausted.", chain_name);
+                        return Err(err);
                     }
+                };
+                error!("Encountered an error when get latest_block_number from {}: {:?}, wait for {:?}.", chain_name, err, delay);
+                options.sleep(delay).await;
+                continue;
+            }
+        };
+
+        let to = match range_end(from, step.get(), latest_block_number, options.confirmations) {
+            // the last range may be shorter than the step
+            Some(to) => config.end_block.map_or(to, |end_block| std::cmp::min(to, end_block)),
+            None => {
+                debug!(
+                    "No {} block is confirmed yet, wait for {:?}.",
+                    chain_name, options.idle_interval
+                );
+                options.sleep(options.idle_interval).await;
+                continue;
+            }
+        };
+        if to < from {
+            debug!(
+                "Track {} ERC1155 events too fast, wait for {:?}.",
+                chain_name, options.idle_interval
+            );
+            options.sleep(options.idle_interval).await;
+            continue;
+        }
+
+        debug!(
+            "Scan for {} ERC1155 events in block range of {} - {}({})",
+            chain_name,
+            from,
+            to,
+            to - from + 1
+        );
+        let start = Instant::now();
+        let mut events = match erc1155_evm::get_erc1155_events(evm_client, from, to).await {
+            Ok(events) => events,
+            Err(err) => {
+                report.record_rpc_error();
+                metrics.record_rpc_error();
+                if config.error_policy.is_range_limit(&err) {
+                    // the client splits the rejected ranges up to its split depth, the next ranges are smaller
+                    error!("{:?}", err);
+                    step.shrink();
+                    continue;
+                }
+                consecutive_errors += 1;
+                if config.error_policy.should_stop(&err, consecutive_errors) {
+                    error!("Stop tracking {} ERC1155 events because of error: {:?}.", chain_name, err);
+                    return Err(err);
                 }
+                let delay = match backoff.next_delay() {
+                    Some(delay) => delay,
+                    None => {
+                        error!("The retry budget of {} is exhausted.", chain_name);
+                        return Err(err);
+                    }
+                };
+                error!("Encountered an error when get ERC1155 events from {}: {:?}, wait for {:?}.", chain_name, err, delay);
+                options.sleep(delay).await;
+                continue;
+            }
+        };
+        info!(
+            "{} {} ERC1155 events were scanned in block range of {} - {}({})",
+            events.len(),
+            chain_name,
+            from,
+            to,
+            to - from + 1
+        );
 
-                if to >= from {
-                    debug!(
-                        "Scan for {} ERC1155 events in block range of {} - {}({})",
-                        evm_client.chain_name,
-                        from,
-                        to,
-                        to - from + 1
-                    );
-                    match erc1155_evm::get_erc1155_events(&evm_client, from, to).await {
-                        Ok(events) => {
-                            info!(
-                                "{} {} ERC1155 events were scanned in block range of {} - {}({})",
-                                events.len(),
-                                evm_client.chain_name,
-                                from,
-                                to,
-                                to - from + 1
-                            );
-                            for event in events {
-                                // PROCESS AN EVENT
-                                // ******************************************************
-                                match get_token_uri(evm_client, db_conn, &event).await {
-                                    Ok(token_uri) => {
-                                        if let Err(err) = callback
-                                            .on_erc1155_event(event.clone(), token_uri)
-                                            .await
-                                        {
-                                            error!("Encountered an error when process ERC1155 event {:?} from {}: {:?}.", event, evm_client.chain_name, err);
-                                        }
-                                    }
-                                    Err(err) => {
-                                        error!("Encountered an error when get metadata for ERC1155 event {:?} from {}: {:?}.", event, evm_client.chain_name, err);
-                                    }
-                                }
-                                // ******************************************************
-                            }
-
-                            from = to + 1;
-
-                            sleep(Duration::from_secs(5)).await;
+        let events_found = events.len();
+        // the denied contracts never reach the database
+        events.retain(|event| !config.denylist.contains(&event.address));
+        // the sort is stable, the events of a batch transfer stay in the order of its ids
+        events.sort_by_key(|event| (event.block_number, event.transaction_index, event.log_index));
+        if config.fetch_transaction_senders {
+            fill_transaction_senders(evm_client, &mut events, &mut report, &metrics).await;
+        }
+
+        // the metadata is saved as it is looked up, the progress once the range is processed
+        for event in events {
+            if options.is_cancelled() {
+                info!("Tracking {} ERC1155 events is cancelled.", chain_name);
+                let next_block = event.block_number.unwrap_or(from);
+                report.last_processed_block = last_processed_block(start_from, next_block);
+                report.duration = started.elapsed();
+                return Ok(report);
+            }
+
+            // PROCESS AN EVENT
+            match process_event(evm_client, store, event.clone(), config, callback, &mut report).await {
+                Ok(true) => {
+                    report.events_delivered += 1;
+                    metrics.record_events_delivered(1);
+                }
+                Ok(false) => {}
+                Err(err) => {
+                    let err = match err {
+                        ProcessError::Callback(err) => {
+                            report.errors += 1;
+                            metrics.record_callback_error();
+                            err
+                        }
+                        ProcessError::Metadata(err) => {
+                            report.record_rpc_error();
+                            metrics.record_rpc_error();
+                            err
                         }
-                        Err(err) => match err {
-                            Error::Web3Error(web3::Error::Rpc(e)) => {
-                                if e.message == "query returned more than 10000 results" {
-                                    error!("{}", e.message);
-                                    step = std::cmp::max(step / 2, 1);
-                                } else {
-                                    error!("Encountered an error when get ERC1155 events from {}: {:?}, wait for 30 seconds.", evm_client.chain_name, e);
-                                    sleep(Duration::from_secs(30)).await;
-                                }
-                            }
-                            _ => {
-                                error!("Encountered an error when get ERC1155 events from {}: {:?}, wait for 30 seconds.", evm_client.chain_name, err);
-                                sleep(Duration::from_secs(30)).await;
-                            }
-                        },
+                    };
+                    error!("Encountered an error when process ERC1155 event {:?} from {}: {:?}.", event, chain_name, err);
+                    if config.error_policy.is_terminal(&err) {
+                        return Err(err);
                     }
-                } else {
-                    debug!(
-                        "Track {} ERC1155 events too fast, wait for 30 seconds.",
-                        evm_client.chain_name
-                    );
-                    sleep(Duration::from_secs(30)).await;
                 }
             }
-            Err(err) => {
-                error!("Encountered an error when get latest_block_number from {}: {:?}, wait for 30 seconds.", evm_client.chain_name, err);
-                sleep(Duration::from_secs(30)).await;
-            }
+        }
+
+        if options.resume {
+            store.save_scan_progress(&progress_key, to).await?;
+        }
+
+        report.blocks_scanned += to - from + 1;
+        report.events_decoded += events_found as u64;
+        callback
+            .on_progress(ScanProgress {
+                chain: chain_name.to_owned(),
+                from,
+                to,
+                latest_block: latest_block_number,
+                events_found,
+                elapsed: start.elapsed(),
+            })
+            .await;
+
+        from = to + 1;
+        consecutive_errors = 0;
+        backoff.reset();
+        step.succeed();
+        if options.is_backfilling(to, latest_block_number, step.get()) {
+            debug!("Backfilling {} ERC1155 events, scan the next range right away.", chain_name);
+        } else {
+            options.sleep(options.range_interval).await;
         }
     }
+
+    report.last_processed_block = last_processed_block(start_from, from);
+    report.duration = started.elapsed();
+    Ok(report)
+}
+
+/// Fill the senders of the transactions of the events of a range, each block is fetched once.
+/// The sender stays None if it can not be fetched, the events are delivered anyway.
+async fn fill_transaction_senders(
+    evm_client: &dyn EvmClientApi,
+    events: &mut [Erc1155Event],
+    report: &mut ScanReport,
+    metrics: &TrackerMetrics,
+) {
+    let mut senders = TransactionSenders::default();
+    for event in events.iter_mut() {
+        if let (Some(block_number), Some(transaction_hash)) = (event.block_number, event.transaction_hash) {
+            event.tx_sender = match senders.sender(evm_client, block_number, &transaction_hash).await {
+                Ok(sender) => sender,
+                Err(err) => {
+                    report.record_rpc_error();
+                    metrics.record_rpc_error();
+                    warn!("Encountered an error when get the sender of transaction {:?}: {:?}.", transaction_hash, err);
+                    None
+                }
+            };
+        }
+    }
+}
+
+/// Process an event, it returns whether the event was delivered to the callback.
+async fn process_event(
+    evm_client: &dyn EvmClientApi,
+    store: &dyn NftStore,
+    event: Erc1155Event,
+    config: &Erc1155TrackerConfig,
+    callback: &mut dyn Erc1155EventCallback,
+    report: &mut ScanReport,
+) -> std::result::Result<bool, ProcessError> {
+    if !config.event_kinds.accepts(event.kind()) {
+        return Ok(false);
+    }
+
+    let token_uri = get_token_uri(evm_client, store, &event, report).await?;
+    callback
+        .on_erc1155_event(event, token_uri)
+        .await
+        .map_err(ProcessError::Callback)?;
+    Ok(true)
 }
 
 async fn get_token_uri(
-    evm_client: &EvmClient,
-    db_conn: &Connection,
+    evm_client: &dyn EvmClientApi,
+    store: &dyn NftStore,
     event: &Erc1155Event,
+    report: &mut ScanReport,
 ) -> Result<String> {
-    save_metadata_to_db_if_not_exists(evm_client, db_conn, &event.address, &event.token_id).await?;
-    let collection =
-        erc1155_db::get_collection_from_db(db_conn, &format!("{:?}", event.address))?.unwrap();
-    let token =
-        erc1155_db::get_token_from_db(db_conn, collection.0, &event.token_id.to_string())?.unwrap();
-    Ok(token.3.unwrap())
+    let cached =
+        save_metadata_to_db_if_not_exists(evm_client, store, &event.address, &event.token_id).await?;
+    report.record_metadata_lookup(cached);
+    let collection_id =
+        store.get_erc1155_collection(&format!("{:?}", event.address)).await?.unwrap();
+    let token_uri =
+        store.get_erc1155_token_uri(collection_id, &event.token_id.to_string()).await?.unwrap();
+    Ok(token_uri.unwrap())
 }
 
+/// Save the uri of a token to the database, it returns whether it was already saved
 async fn save_metadata_to_db_if_not_exists(
-    evm_client: &EvmClient,
-    db_conn: &Connection,
+    evm_client: &dyn EvmClientApi,
+    store: &dyn NftStore,
     address: &H160,
     token_id: &U256,
-) -> Result<()> {
+) -> Result<bool> {
     let address_string = format!("{:?}", address);
     let collection_id =
-        if let Some(collection) = erc1155_db::get_collection_from_db(db_conn, &address_string)? {
-            collection.0
+        if let Some(collection_id) = store.get_erc1155_collection(&address_string).await? {
+            collection_id
         } else {
-            erc1155_db::add_collection_to_db(db_conn, address_string.clone())?
+            store.add_erc1155_collection(&address_string).await?
         };
 
-    let token = erc1155_db::get_token_from_db(db_conn, collection_id, &token_id.to_string())?;
+    let token = store.get_erc1155_token_uri(collection_id, &token_id.to_string()).await?;
     if token.is_none() {
         let token_uri = evm_client.get_erc1155_token_uri(address, token_id).await?;
-        erc1155_db::add_token_to_db(
-            db_conn,
-            token_id.to_string(),
-            collection_id,
-            Some(token_uri),
-        )?;
+        store.add_erc1155_token(collection_id, &token_id.to_string(), Some(token_uri)).await?;
         // if remove_whitespace(&token_uri).as_str() == "" {
         //     return Err(Error::Other("Blank token uri".to_owned()));
         // } else {
         //     erc1155_db::add_token_to_db(db_conn, token_id.to_string(), collection_id, Some(token_uri))?;
         // }
     }
-    Ok(())
+    Ok(token.is_some())
 }
 
 // fn remove_whitespace(s: &str) -> String {
@@ -162,7 +367,10 @@ async fn save_metadata_to_db_if_not_exists(
 #[cfg(test)]
 mod tests {
     use super::*;
-    use web3::{transports::http::Http, Web3};
+    use crate::test_support::{address, erc1155_transfer_single_log, erc721_transfer_log, MockEvmClient};
+    use crate::{erc721, erc721_db, Erc721Event, Erc721EventCallback, Error, EvmClient, SqliteStore};
+    use std::time::Duration;
+    use web3::{transports::http::Http, types::H256, Web3};
 
     struct EthereumErc1155EventCallback {
         events: Vec<Erc1155Event>,
@@ -186,14 +394,215 @@ mod tests {
         let client = EvmClient::new("Ethereum".to_owned(), web3);
 
         //
-        let conn = Connection::open("./test6.db").unwrap();
-        erc1155_db::create_tables_if_not_exist(&conn).unwrap();
+        let store = SqliteStore::open("./test6.db").await.unwrap();
 
         //
         let mut callback = EthereumErc1155EventCallback { events: vec![] };
-        track_erc1155_events(&client, &conn, 13015344, 1, Some(13015346), &mut callback).await;
+        track_erc1155_events(
+            &client,
+            &store,
+            13015344,
+            1,
+            Some(13015346),
+            &ScanOptions::default(),
+            &mut callback,
+        )
+        .await
+        .unwrap();
         assert_eq!(5, callback.events.len());
 
+        drop(store);
         std::fs::remove_file("./test6.db").unwrap();
     }
+
+    #[tokio::test]
+    async fn test_track_erc1155_events_final_partial_range() {
+        let client = MockEvmClient::new("Mock", 1000);
+        let store = SqliteStore::open_in_memory().await.unwrap();
+
+        let options = ScanOptions {
+            range_interval: Duration::from_millis(1),
+            ..Default::default()
+        };
+        let mut callback = EthereumErc1155EventCallback { events: vec![] };
+        let last_processed =
+            track_erc1155_events(&client, &store, 100, 10, Some(105), &options, &mut callback)
+                .await
+                .unwrap()
+                .last_processed_block;
+
+        assert_eq!(Some(105), last_processed);
+        assert_eq!(vec![(100, 105)], client.scanned_ranges());
+    }
+
+    #[tokio::test]
+    async fn test_track_erc1155_events_with_a_zero_step() {
+        let client = MockEvmClient::new("Mock", 1000);
+        let store = SqliteStore::open_in_memory().await.unwrap();
+
+        let mut callback = EthereumErc1155EventCallback { events: vec![] };
+        let options = ScanOptions::default();
+        let result = track_erc1155_events(&client, &store, 100, 0, Some(105), &options, &mut callback).await;
+
+        assert!(matches!(result, Err(Error::InvalidConfig(_))));
+        assert!(client.scanned_ranges().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_track_erc1155_events_with_denylist() {
+        let spam = address(2);
+        let client = MockEvmClient::new("Mock", 100)
+            .with_erc1155_token_uri(address(1), 1, "https://mock/1")
+            .with_erc1155_token_uri(spam, 1, "https://spam/1")
+            .with_log(erc1155_transfer_single_log(address(1), address(0), address(9), 1, 5, 11, 0))
+            .with_log(erc1155_transfer_single_log(spam, address(0), address(9), 1, 5, 11, 1));
+        let store = SqliteStore::open_in_memory().await.unwrap();
+
+        let config = Erc1155TrackerConfig::builder()
+            .start_from(10)
+            .step(2)
+            .end_block(11)
+            .range_interval(Duration::from_millis(1))
+            .denylist(vec![spam])
+            .build()
+            .unwrap();
+        let mut callback = EthereumErc1155EventCallback { events: vec![] };
+        track_erc1155_events_with_config(&client, &store, &config, &mut callback)
+            .await
+            .unwrap();
+
+        assert_eq!(1, callback.events.len());
+        assert_eq!(address(1), callback.events[0].address);
+        assert_eq!(1, client.call_count("get_erc1155_token_uri"));
+        let spam = format!("{:?}", spam);
+        assert!(store.get_erc1155_collection(&spam).await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_track_erc1155_events_with_transaction_senders() {
+        let client = MockEvmClient::new("Mock", 100)
+            .with_erc1155_token_uri(address(1), 1, "https://mock/1")
+            .with_log(erc1155_transfer_single_log(address(1), address(0), address(9), 1, 5, 11, 0))
+            .with_log(erc1155_transfer_single_log(address(1), address(9), address(3), 1, 2, 11, 1))
+            .with_transaction_sender(11, H256::from_low_u64_be(11_000), address(7))
+            .with_transaction_sender(11, H256::from_low_u64_be(11_001), address(8));
+        let store = SqliteStore::open_in_memory().await.unwrap();
+
+        let config = Erc1155TrackerConfig::builder()
+            .start_from(10)
+            .step(2)
+            .end_block(11)
+            .range_interval(Duration::from_millis(1))
+            .fetch_transaction_senders(true)
+            .build()
+            .unwrap();
+        let mut callback = EthereumErc1155EventCallback { events: vec![] };
+        track_erc1155_events_with_config(&client, &store, &config, &mut callback)
+            .await
+            .unwrap();
+
+        let senders: Vec<Option<H160>> = callback.events.iter().map(|event| event.tx_sender).collect();
+        assert_eq!(vec![Some(address(7)), Some(address(8))], senders);
+        assert_eq!(1, client.call_count("get_transaction_senders"));
+    }
+
+    #[tokio::test]
+    async fn test_track_erc1155_events_in_block_order() {
+        let client = MockEvmClient::new("Mock", 100)
+            .with_erc1155_token_uri(address(1), 1, "https://mock/1")
+            .with_log(erc1155_transfer_single_log(address(1), address(9), address(3), 1, 2, 11, 1))
+            .with_log(erc1155_transfer_single_log(address(1), address(0), address(9), 1, 5, 11, 0));
+        let store = SqliteStore::open_in_memory().await.unwrap();
+
+        let config = Erc1155TrackerConfig::builder()
+            .start_from(10)
+            .step(2)
+            .end_block(11)
+            .range_interval(Duration::from_millis(1))
+            .build()
+            .unwrap();
+        let mut callback = EthereumErc1155EventCallback { events: vec![] };
+        track_erc1155_events_with_config(&client, &store, &config, &mut callback)
+            .await
+            .unwrap();
+
+        let order: Vec<(Option<u64>, Option<u64>)> =
+            callback.events.iter().map(|event| (event.transaction_index, event.log_index)).collect();
+        assert_eq!(vec![(Some(0), Some(0)), (Some(1), Some(1))], order);
+    }
+
+    struct Erc721EventCounter {
+        events: usize,
+    }
+
+    #[async_trait]
+    impl Erc721EventCallback for Erc721EventCounter {
+        async fn on_erc721_event(
+            &mut self,
+            _event: Erc721Event,
+            _name: String,
+            _symbol: String,
+            _total_supply: Option<u128>,
+            _token_uri: String,
+        ) -> Result<()> {
+            self.events += 1;
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_track_erc721_and_erc1155_progress_in_one_database() {
+        let path = std::env::temp_dir().join("erc1155_progress.db");
+        let remove_database = || {
+            for suffix in &["", "-wal", "-shm"] {
+                let _ = std::fs::remove_file(format!("{}{}", path.display(), suffix));
+            }
+        };
+        remove_database();
+        let (store721, store1155) = (SqliteStore::open(&path).await.unwrap(), SqliteStore::open(&path).await.unwrap());
+
+        let collection = address(1);
+        let mut client721 = MockEvmClient::new("Mock", 100).with_erc721_collection(collection, "Mock", "MOCK");
+        let mut client1155 = MockEvmClient::new("Mock", 100).with_erc1155_token_uri(collection, 1, "https://mock/1");
+        for block_number in 10..18 {
+            client721 = client721
+                .with_erc721_token_uri(collection, block_number, "https://mock")
+                .with_log(erc721_transfer_log(collection, address(0), address(2), block_number, block_number, 0));
+            client1155 = client1155
+                .with_log(erc1155_transfer_single_log(collection, address(0), address(9), 1, 5, block_number, 0));
+        }
+        let options = ScanOptions {
+            range_interval: Duration::from_millis(1),
+            resume: true,
+            ..Default::default()
+        };
+
+        // the trackers of the same chain save their progress concurrently, the ERC1155 one stops earlier
+        let mut callback721 = Erc721EventCounter { events: 0 };
+        let mut callback1155 = EthereumErc1155EventCallback { events: vec![] };
+        let (report721, report1155) = tokio::join!(
+            erc721::track_erc721_events(&client721, &store721, 10, 2, Some(17), &options, &mut callback721),
+            track_erc1155_events(&client1155, &store1155, 10, 2, Some(13), &options, &mut callback1155),
+        );
+        assert_eq!(Some(17), report721.unwrap().last_processed_block);
+        assert_eq!(Some(13), report1155.unwrap().last_processed_block);
+        let progress: Vec<_> = store721
+            .call(|conn| erc721_db::status(conn))
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|progress| (progress.chain, progress.last_scanned_block))
+            .collect();
+        assert_eq!(vec![("Mock".to_owned(), 17), (progress_key("Mock"), 13)], progress);
+
+        // each tracker resumes from its own progress
+        track_erc1155_events(&client1155, &store1155, 10, 2, Some(17), &options, &mut callback1155).await.unwrap();
+        assert_eq!(vec![(10, 11), (12, 13), (14, 15), (16, 17)], client1155.scanned_ranges());
+        assert_eq!((8, 8), (callback721.events, callback1155.events.len()));
+        assert_eq!(Some(17), store1155.get_scan_progress("Mock").await.unwrap());
+        assert_eq!(Some(17), store1155.get_scan_progress(&progress_key("Mock")).await.unwrap());
+
+        drop((store721, store1155));
+        remove_database();
+    }
 }
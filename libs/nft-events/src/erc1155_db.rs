@@ -21,6 +21,15 @@ pub fn create_tables_if_not_exist(conn: &Connection) -> Result<()> {
          )",
         [],
     )?;
+    // the table of `erc721_db`, shared by the trackers of a database
+    conn.execute(
+        "create table if not exists scan_progress (
+             chain text primary key,
+             last_scanned_block integer not null,
+             updated_at integer
+         )",
+        [],
+    )?;
 
     Ok(())
 }
@@ -1,10 +1,11 @@
 //! This module is a library to get ERC1155 transfer events.
-use crate::{EvmClient, Result};
+use crate::{config::EventKind, EvmClientApi, Result};
 use array_bytes::hex2bytes_unchecked as bytes;
 use web3::types::{Bytes, Log, H160, H256, U256};
 
 /// The Erc721 Transfer Event Wrapper
 #[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Erc1155Event {
     /// The block to which this event belongs
     pub block_number: Option<u64>,
@@ -12,26 +13,43 @@ pub struct Erc1155Event {
     pub address: H160,
     /// The transaction that issued this event
     pub transaction_hash: Option<H256>,
+    /// The account which sent the transaction, only with `TrackerConfig::fetch_transaction_senders`
+    pub tx_sender: Option<H160>,
+    /// The index of the transaction in its block
+    pub transaction_index: Option<u64>,
+    /// The index of this event in its block, the events of a batch transfer share it
+    pub log_index: Option<u64>,
     /// The address of an account/contract that is approved to make the transfer
     pub operator: H160,
     /// Transfer from
     pub from: H160,
     /// Balance of from
+    #[cfg_attr(feature = "serde", serde(with = "crate::serde_decimal"))]
     pub balance_of_from: U256,
     /// Transfer to
     pub to: H160,
     /// Balance of to
+    #[cfg_attr(feature = "serde", serde(with = "crate::serde_decimal"))]
     pub balance_of_to: U256,
     /// The token type being transferred
+    #[cfg_attr(feature = "serde", serde(with = "crate::serde_decimal"))]
     pub token_id: U256,
     /// Number of the token transferred
+    #[cfg_attr(feature = "serde", serde(with = "crate::serde_decimal"))]
     pub amount: U256,
 }
 
+impl Erc1155Event {
+    /// Whether this event is a mint, a burn or a transfer
+    pub fn kind(&self) -> EventKind {
+        EventKind::of(&self.from, &self.to)
+    }
+}
+
 /// Get all erc1155 events between `from` and `to`.
 /// the `from` and `to` blocks are included.
 pub async fn get_erc1155_events(
-    client: &EvmClient,
+    client: &dyn EvmClientApi,
     from: u64,
     to: u64,
 ) -> Result<Vec<Erc1155Event>> {
@@ -61,12 +79,14 @@ pub async fn get_erc1155_events(
     Ok(result)
 }
 
-async fn build_event(client: &EvmClient, log: &Log) -> Result<Erc1155Event> {
+async fn build_event(client: &dyn EvmClientApi, log: &Log) -> Result<Erc1155Event> {
     let token_id = U256::from_big_endian(&log.data.0[0..32]);
     let amount = U256::from_big_endian(&log.data.0[32..64]);
     let block_number = log.block_number.map(|b| b.as_u64());
     let address = log.address;
     let transaction_hash = log.transaction_hash;
+    let transaction_index = log.transaction_index.map(|i| i.as_u64());
+    let log_index = log.log_index.map(|i| i.as_u64());
     let operator = H160::from(log.topics[1]);
     let from = H160::from(log.topics[2]);
     let to = H160::from(log.topics[3]);
@@ -81,6 +101,9 @@ async fn build_event(client: &EvmClient, log: &Log) -> Result<Erc1155Event> {
             block_number,
             address,
             transaction_hash,
+            tx_sender: None,
+            transaction_index,
+            log_index,
             operator,
             from,
             balance_of_from,
@@ -92,10 +115,12 @@ async fn build_event(client: &EvmClient, log: &Log) -> Result<Erc1155Event> {
     )
 }
 
-async fn build_events(client: &EvmClient, log: &Log) -> Result<Vec<Erc1155Event>> {
+async fn build_events(client: &dyn EvmClientApi, log: &Log) -> Result<Vec<Erc1155Event>> {
     let block_number = log.block_number.map(|b| b.as_u64());
     let address = log.address;
     let transaction_hash = log.transaction_hash;
+    let transaction_index = log.transaction_index.map(|i| i.as_u64());
+    let log_index = log.log_index.map(|i| i.as_u64());
     let operator = H160::from(log.topics[1]);
     let from = H160::from(log.topics[2]);
     let to = H160::from(log.topics[3]);
@@ -135,6 +160,9 @@ async fn build_events(client: &EvmClient, log: &Log) -> Result<Vec<Erc1155Event>
             block_number,
             address,
             transaction_hash,
+            tx_sender: None,
+            transaction_index,
+            log_index,
             operator,
             from,
             balance_of_from,
@@ -173,8 +201,41 @@ fn get_ids_and_amounts(data: &Bytes) -> (Vec<U256>, Vec<U256>) {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::EvmClient;
     use web3::{transports::http::Http, Web3};
 
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_erc1155_event_json() {
+        let account = H160::from_low_u64_be(2);
+        let event = Erc1155Event {
+            block_number: Some(12),
+            address: H160::from_low_u64_be(1),
+            transaction_hash: Some(H256::from_low_u64_be(12003)),
+            tx_sender: None,
+            transaction_index: Some(3),
+            log_index: Some(3),
+            operator: account,
+            from: H160::zero(),
+            balance_of_from: U256::zero(),
+            to: account,
+            balance_of_to: U256::max_value(),
+            token_id: U256::exp10(30),
+            amount: U256::from(5),
+        };
+        let json = serde_json::to_value(&event).unwrap();
+        assert_eq!("0x0000000000000000000000000000000000000002", json["operator"]);
+        assert_eq!(
+            "115792089237316195423570985008687907853269984665640564039457584007913129639935",
+            json["balance_of_to"]
+        );
+        assert_eq!("1000000000000000000000000000000", json["token_id"]);
+        assert_eq!("5", json["amount"]);
+
+        let decoded: Erc1155Event = serde_json::from_value(json.clone()).unwrap();
+        assert_eq!(json, serde_json::to_value(&decoded).unwrap());
+    }
+
     #[tokio::test]
     async fn test_get_erc1155_events() {
         let web3 = Web3::new(Http::new("https://main-light.eth.linkpool.io").unwrap());
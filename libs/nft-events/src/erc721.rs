@@ -1,10 +1,67 @@
 //! This module is the entry point for tracking ERC721.
-use crate::{erc721_db, erc721_evm, erc721_evm::Erc721Event, Error, EvmClient, Result};
-use std::time::{Duration, Instant};
-use tokio::time::sleep;
-use web3::types::{H160, U256};
+use crate::{
+    config::{last_processed_block, range_end, AdaptiveStep, Backoff},
+    erc721_db::{self, CollectionCode},
+    erc721_evm,
+    erc721_evm::{
+        Erc721ApprovalEvent, Erc721ApprovalForAllEvent, Erc721ConsecutiveTransfer, Erc721Event, Erc721Logs,
+        Erc721MetadataUpdate, LogKinds, CRYPTOPUNKS_ADDRESS,
+    },
+    evm_client::TransactionSenders,
+    handle::{tracker_handle, TrackerControl, TrackerHandle, TrackerState},
+    marketplace,
+    metadata_cache::{CachedCollection, CachedToken, MetadataCache},
+    store::{OwnerWrite, RangeWrites, TransferWrite},
+    CallbackErrorPolicy, Erc721TrackerConfig, Error, EventKind, EvmClientApi, HeadStream, MetadataRefresh,
+    MetadataRetry, NftStore, ERC721_ENUMERABLE_INTERFACE_ID, ERC721_INTERFACE_ID,
+    Result, ScanOptions,
+    ScanProgress, ScanReport, TrackerConfig, TrackerMetrics,
+};
+use std::{
+    borrow::Cow,
+    collections::{HashMap, HashSet},
+    ops::RangeInclusive,
+    sync::{Arc, Mutex},
+    time::{Instant, SystemTime, UNIX_EPOCH},
+};
+use futures::{stream, StreamExt};
+use tokio::sync::mpsc;
+use web3::types::{Log, H160, H256, U256};
 
-use rusqlite::Connection;
+/// The metadata of an ERC721 token, delivered along with its events
+#[derive(Debug, Clone, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Erc721Metadata {
+    /// The name of the collection
+    pub name: String,
+    /// The symbol of the collection
+    pub symbol: String,
+    /// The total supply of the collection, if it is enumerable
+    #[cfg_attr(feature = "serde", serde(with = "crate::serde_decimal::option"))]
+    pub total_supply: Option<u128>,
+    /// The uri of the token, empty if the contract has none for the token, as for some unrevealed tokens
+    pub token_uri: String,
+    /// The owner of the token at the last block of the range, with `fetch_owners`.
+    /// None if the token does not exist anymore, or if the contract has no `ownerOf`.
+    /// It is only delivered to `on_erc721_events`.
+    pub owner: Option<H160>,
+    /// The ERC2981 royalty receiver of the collection and its royalty in basis points, with `fetch_royalties`.
+    /// It is only delivered to `on_erc721_events`.
+    pub royalty: Option<(H160, u64)>,
+    /// The uri of the collection-level metadata, from the `contractURI` of the collection when it was first seen.
+    /// It is only delivered to `on_erc721_events`.
+    pub contract_uri: Option<String>,
+    /// The EIP-1967 implementation of the collection if it is a proxy, with `resolve_proxies`.
+    /// It is only delivered to `on_erc721_events`.
+    pub implementation: Option<H160>,
+}
+
+impl Erc721Metadata {
+    /// Whether the collection is an EIP-1967 proxy, as far as `resolve_proxies` found
+    pub fn is_proxy(&self) -> bool {
+        self.implementation.is_some()
+    }
+}
 
 /// When the ERC721 event is fetched, the event will be exposed to the caller through this trait.
 /// The caller needs to implement this trait and write the code on how to use the event.
@@ -20,202 +77,6219 @@ pub trait Erc721EventCallback: Send {
         total_supply: Option<u128>,
         token_uri: String,
     ) -> Result<()>;
+
+    /// Called once per scanned block range with its events to deliver, in log order.
+    /// The ranges without any event to deliver are skipped, and the batch is retried as a whole.
+    /// The default implementation calls `on_erc721_event` for each event, the events after
+    /// a failing one are still delivered and the first error is returned.
+    async fn on_erc721_events(&mut self, events: Vec<(Erc721Event, Erc721Metadata)>) -> Result<()> {
+        let mut result = Ok(());
+        for (event, metadata) in events {
+            let Erc721Metadata {
+                name,
+                symbol,
+                total_supply,
+                token_uri,
+                ..
+            } = metadata;
+            let delivered = self
+                .on_erc721_event(event, name, symbol, total_supply, token_uri)
+                .await;
+            if let Err(err) = delivered {
+                if result.is_ok() {
+                    result = Err(err);
+                }
+            }
+        }
+        result
+    }
+
+    /// Called when a reorg removed the blocks of `block_range`, whose events have been delivered.
+    /// The events of the new canonical blocks are delivered again after it.
+    async fn on_erc721_events_removed(&mut self, _block_range: RangeInclusive<u64>) -> Result<()> {
+        Ok(())
+    }
+
+    /// Called when an ERC4906 event of the collection `address` updated the metadata of `token_ids`,
+    /// after the events of its block range, with the token uri fetched again for each token.
+    /// The tokens of a wide `BatchMetadataUpdate` are not fetched again at once: it is called once
+    /// for the whole range without a token uri, and the next events of the tokens carry the new ones.
+    async fn on_erc721_metadata_update(
+        &mut self,
+        _address: H160,
+        _token_ids: RangeInclusive<U256>,
+        _token_uri: Option<String>,
+    ) -> Result<()> {
+        Ok(())
+    }
+
+    /// Called for each ERC2309 `ConsecutiveTransfer` event wider than `max_expanded_consecutive_transfer`,
+    /// in log order after the events of its block range. The narrower ones are delivered as the events of their tokens.
+    async fn on_erc721_consecutive_transfer(&mut self, _consecutive_transfer: Erc721ConsecutiveTransfer) -> Result<()> {
+        Ok(())
+    }
+
+    /// Called for each `Approval` event with `track_approvals`, in log order after the events of its block range
+    async fn on_erc721_approval(&mut self, _approval: Erc721ApprovalEvent) -> Result<()> {
+        Ok(())
+    }
+
+    /// Called for each `ApprovalForAll` event with `track_approvals_for_all`,
+    /// in log order after the events of its block range
+    async fn on_erc721_approval_for_all(&mut self, _approval_for_all: Erc721ApprovalForAllEvent) -> Result<()> {
+        Ok(())
+    }
+
+    /// Called after each scanned block range, including the ranges without any event
+    async fn on_progress(&mut self, _progress: ScanProgress) {}
+
+    /// Called after each block range without any event to deliver, with the last block processed so far.
+    /// It tells that the tracker is alive during the quiet periods.
+    async fn on_idle(&mut self, _chain: &str, _up_to_block: u64) {}
+}
+
+/// Receives the ERC721 events without their metadata, see `track_erc721_raw_events`.
+#[async_trait]
+pub trait Erc721RawEventCallback: Send {
+    /// The callback function
+    async fn on_erc721_raw_event(&mut self, event: Erc721Event) -> Result<()>;
+
+    /// Called once per scanned block range with its events to deliver, in log order.
+    /// The default implementation calls `on_erc721_raw_event` for each event, the events after
+    /// a failing one are still delivered and the first error is returned.
+    async fn on_erc721_raw_events(&mut self, events: Vec<Erc721Event>) -> Result<()> {
+        let mut result = Ok(());
+        for event in events {
+            if let Err(err) = self.on_erc721_raw_event(event).await {
+                if result.is_ok() {
+                    result = Err(err);
+                }
+            }
+        }
+        result
+    }
+
+    /// Called when a reorg removed the blocks of `block_range`, whose events have been delivered
+    async fn on_erc721_events_removed(&mut self, _block_range: RangeInclusive<u64>) -> Result<()> {
+        Ok(())
+    }
+}
+
+/// Delivers the events to an `Erc721RawEventCallback`, dropping their empty metadata
+struct RawErc721EventCallback<'a> {
+    callback: &'a mut dyn Erc721RawEventCallback,
+}
+
+#[async_trait]
+impl<'a> Erc721EventCallback for RawErc721EventCallback<'a> {
+    async fn on_erc721_event(
+        &mut self,
+        event: Erc721Event,
+        _name: String,
+        _symbol: String,
+        _total_supply: Option<u128>,
+        _token_uri: String,
+    ) -> Result<()> {
+        self.callback.on_erc721_raw_event(event).await
+    }
+
+    async fn on_erc721_events(&mut self, events: Vec<(Erc721Event, Erc721Metadata)>) -> Result<()> {
+        let events = events.into_iter().map(|(event, _)| event).collect();
+        self.callback.on_erc721_raw_events(events).await
+    }
+
+    async fn on_erc721_events_removed(&mut self, block_range: RangeInclusive<u64>) -> Result<()> {
+        self.callback.on_erc721_events_removed(block_range).await
+    }
 }
 
 /// Entry function for tracking ERC721.
 /// If you only need to track ERC721, you can use this function directly.
+/// It returns a report when `end_block` is reached or the tracker is cancelled.
 pub async fn track_erc721_events(
-    evm_client: &EvmClient,
-    db_conn: &Connection,
+    evm_client: &dyn EvmClientApi,
+    store: &dyn NftStore,
     start_from: u64,
     step: u64,
     end_block: Option<u64>,
+    options: &ScanOptions,
     callback: &mut dyn Erc721EventCallback,
-) {
-    let mut step = step;
+) -> Result<ScanReport> {
+    let config = TrackerConfig {
+        start_from,
+        step,
+        end_block,
+        options: options.clone(),
+        ..Default::default()
+    };
+    track_erc721_events_with_config(evm_client, store, &config, callback).await
+}
+
+/// Track ERC721 events as configured by `config`.
+/// It returns a report when `end_block` is reached or the tracker is cancelled,
+/// or the error which made the tracker stop according to the error policy.
+pub async fn track_erc721_events_with_config(
+    evm_client: &dyn EvmClientApi,
+    store: &dyn NftStore,
+    config: &Erc721TrackerConfig,
+    callback: &mut dyn Erc721EventCallback,
+) -> Result<ScanReport> {
+    let mut control = TrackerControl::detached(config.start_from, config.step);
+    track_erc721_events_with_control(evm_client, store, config, callback, &mut control, true).await
+}
+
+/// Track ERC721 events as configured by `config`, without their metadata.
+/// The events are delivered without checking whether their contracts are visual ERC721 contracts,
+/// so the node is only asked for the logs and nothing is saved except what `config.options` enables.
+/// With `config.skip_non_erc721_contracts`, the contracts are checked with ERC165 and the answers are saved.
+pub async fn track_erc721_raw_events(
+    evm_client: &dyn EvmClientApi,
+    store: &dyn NftStore,
+    config: &Erc721TrackerConfig,
+    callback: &mut dyn Erc721RawEventCallback,
+) -> Result<ScanReport> {
+    let mut control = TrackerControl::detached(config.start_from, config.step);
+    let mut callback = RawErc721EventCallback { callback };
+    track_erc721_events_with_control(evm_client, store, config, &mut callback, &mut control, false).await
+}
+
+/// Track the whole history of the ERC721 collection `contract`.
+/// The scan starts at the block where the contract was created, unless `config.start_from` is later.
+/// The creation block is searched once and cached in the database, and the allowlist of `config`
+/// is replaced by the contract.
+pub async fn track_erc721_collection(
+    evm_client: &dyn EvmClientApi,
+    store: &dyn NftStore,
+    contract: H160,
+    config: &Erc721TrackerConfig,
+    callback: &mut dyn Erc721EventCallback,
+) -> Result<ScanReport> {
+    let address = format!("{:?}", contract);
+    let creation_block = match store.get_collection_creation_block(evm_client.chain_name(), &address).await? {
+        Some(creation_block) => creation_block,
+        None => {
+            let latest_block_number = evm_client.get_latest_block_number().await?;
+            let creation_block = find_creation_block(evm_client, contract, latest_block_number)
+                .await?
+                .ok_or_else(|| Error::Other(format!("{} is not a contract", address)))?;
+            info!("The ERC721 collection {} was created at block {}.", address, creation_block);
+            let collection_id = save_collection_if_not_exists(
+                evm_client,
+                store,
+                &contract,
+                &config.metadata_retry,
+                None,
+                &MetadataSources::default(),
+            )
+            .await?;
+            store.save_collection_creation_block(collection_id, creation_block).await?;
+            creation_block
+        }
+    };
+
+    let config = TrackerConfig {
+        start_from: std::cmp::max(config.start_from, creation_block),
+        address_allowlist: Some(vec![contract]),
+        ..config.clone()
+    };
+    track_erc721_events_with_config(evm_client, store, &config, callback).await
+}
+
+/// Save all the tokens of the ERC721Enumerable collection `contract` at `block_number` with their token uris,
+/// without scanning its transfer logs: the tokens are enumerated with `totalSupply` and `tokenByIndex`.
+/// The index of the next token is saved with the collection, so an interrupted snapshot at the same block
+/// resumes where it stopped. It returns the number of tokens enumerated by this call.
+pub async fn snapshot_erc721_collection(
+    evm_client: &dyn EvmClientApi,
+    store: &dyn NftStore,
+    contract: H160,
+    block_number: u64,
+) -> Result<u64> {
+    if evm_client.supports_interface(contract, ERC721_ENUMERABLE_INTERFACE_ID).await? == Some(false) {
+        return Err(Error::NotEnumerable(contract));
+    }
+    let total_supply = evm_client
+        .get_erc721_total_supply(&contract, Some(block_number))
+        .await?
+        .ok_or(Error::NotEnumerable(contract))? as u64;
+
+    let config = Erc721TrackerConfig::default();
+    let sources = MetadataSources::default();
+    let collection_id =
+        save_collection_if_not_exists(evm_client, store, &contract, &config.metadata_retry, None, &sources).await?;
+    let address = format!("{:?}", contract);
+    let start_index = match store.get_collection_snapshot(evm_client.chain_name(), &address).await? {
+        Some((snapshot_block, index)) if snapshot_block == block_number => index,
+        _ => 0,
+    };
+    if start_index < total_supply {
+        info!("Snapshot the ERC721 collection {:?} at block {} from its token {}.", contract, block_number, start_index);
+    }
+    for index in start_index..total_supply {
+        let token_id = evm_client
+            .get_erc721_token_by_index(&contract, index, Some(block_number))
+            .await?
+            .ok_or(Error::NotEnumerable(contract))?;
+        save_metadata_to_db_if_not_exists(evm_client, store, &contract, &token_id, None, &config, &sources).await?;
+        store.save_collection_snapshot(collection_id, block_number, index + 1).await?;
+    }
+    Ok(total_supply.saturating_sub(start_index))
+}
+
+/// The tokens of a collection held by an account, as saved by the tracker and as told by the contract
+#[derive(Debug, Clone, PartialEq)]
+pub struct HoldingsReport {
+    /// The ERC721 contract
+    pub collection: H160,
+    /// The account holding the tokens
+    pub owner: H160,
+    /// The block of the balance, the last one scanned by the tracker if its progress is saved
+    pub block_number: Option<u64>,
+    /// How many tokens the database says the owner holds
+    pub indexed: u64,
+    /// The `balanceOf` of the owner, None if the contract has no `balanceOf`
+    pub on_chain: Option<U256>,
+}
+
+impl HoldingsReport {
+    /// Whether the contract holds another balance than the saved tokens, which tells a gap of the indexing:
+    /// a range which was skipped, a reorg, or tokens moved without a Transfer event.
+    /// A contract without `balanceOf` has no discrepancy.
+    pub fn has_discrepancy(&self) -> bool {
+        self.on_chain.map_or(false, |balance| balance != U256::from(self.indexed))
+    }
+}
+
+/// Compare the tokens of `collection` held by `owner` as saved by a tracker with `save_owners`, with the
+/// `balanceOf` of the owner at the last block scanned by the tracker, or at the latest block if the scan progress
+/// is not saved.
+pub async fn verify_owner_holdings(
+    store: &dyn NftStore,
+    evm_client: &dyn EvmClientApi,
+    collection: H160,
+    owner: H160,
+) -> Result<HoldingsReport> {
+    let block_number = store.get_scan_progress(evm_client.chain_name()).await?;
+    let (address, owner_string) = (format!("{:?}", collection), format!("{:?}", owner));
+    let indexed = store.count_tokens_of_owner(evm_client.chain_name(), &address, &owner_string).await?;
+    let on_chain = evm_client.get_erc721_balance_of(&collection, &owner, block_number).await?;
+    Ok(HoldingsReport {
+        collection,
+        owner,
+        block_number,
+        indexed,
+        on_chain,
+    })
+}
+
+/// The metadata saved before a refresh, and the one fetched by the refresh which replaced it
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MetadataChange<T> {
+    /// The metadata saved before the refresh
+    pub old: T,
+    /// The metadata fetched by the refresh
+    pub new: T,
+}
+
+impl<T: PartialEq> MetadataChange<T> {
+    /// Whether the refresh fetched another metadata than the saved one
+    pub fn is_changed(&self) -> bool {
+        self.old != self.new
+    }
+}
+
+/// Fetch again the token uri of a saved token at the latest block, as after a reveal, and save it in place of the
+/// saved one, forgetting the failed lookups of the token. It returns the old and the new token uris, None if the
+/// token is not saved. A running tracker delivers the token uri it cached until the token is evicted from its cache.
+pub async fn refresh_token_metadata(
+    store: &dyn NftStore,
+    evm_client: &dyn EvmClientApi,
+    collection: H160,
+    token_id: U256,
+) -> Result<Option<MetadataChange<Option<String>>>> {
+    let address = format!("{:?}", collection);
+    let collection_id = match store.get_collection(evm_client.chain_name(), &address).await? {
+        Some(saved) => saved.id,
+        None => return Ok(None),
+    };
+    let token = match store.get_token(collection_id, &token_id.to_string()).await? {
+        Some(token) => token,
+        None => return Ok(None),
+    };
+    let token_uri = evm_client.get_erc721_token_uri(&collection, &token_id, None).await?;
+    store.update_token_uri(token.id, token_uri.clone(), now()).await?;
+    Ok(Some(MetadataChange {
+        old: token.token_uri,
+        new: token_uri,
+    }))
+}
+
+/// Fetch again the name and the symbol of a saved collection at the latest block, and save them in place of the
+/// saved ones, forgetting the failed lookups of the collection. It returns the old and the new names and symbols,
+/// None if the collection is not saved. A running tracker delivers the ones it cached until the collection is evicted
+/// from its cache.
+pub async fn refresh_collection_metadata(
+    store: &dyn NftStore,
+    evm_client: &dyn EvmClientApi,
+    collection: H160,
+) -> Result<Option<MetadataChange<Option<(String, String)>>>> {
+    let saved = match store.get_collection(evm_client.chain_name(), &format!("{:?}", collection)).await? {
+        Some(saved) => saved,
+        None => return Ok(None),
+    };
+    let name_symbol = evm_client.get_erc721_name_symbol(&collection, None).await?;
+    let (name, symbol) = match name_symbol.clone() {
+        Some((name, symbol)) => (Some(name), Some(symbol)),
+        None => (None, None),
+    };
+    store.update_collection_metadata(saved.id, name, symbol).await?;
+    Ok(Some(MetadataChange {
+        old: saved.name_symbol(),
+        new: name_symbol,
+    }))
+}
+
+/// Find the first block where `contract` has some code, with a binary search up to `latest_block_number`.
+/// None if the contract has no code at the latest block. A contract which was destroyed and
+/// deployed again at the same address is found at one of its creation blocks.
+async fn find_creation_block(
+    evm_client: &dyn EvmClientApi,
+    contract: H160,
+    latest_block_number: u64,
+) -> Result<Option<u64>> {
+    if evm_client.get_code(contract, latest_block_number).await?.0.is_empty() {
+        return Ok(None);
+    }
+    // the contract has some code at `high`, and no code before `low`
+    let (mut low, mut high) = (0, latest_block_number);
+    while low < high {
+        let middle = low + (high - low) / 2;
+        if evm_client.get_code(contract, middle).await?.0.is_empty() {
+            low = middle + 1;
+        } else {
+            high = middle;
+        }
+    }
+    Ok(Some(high))
+}
+
+/// Spawn an ERC721 tracker, which is controlled through the returned handle.
+/// The tracker is a task of the runtime, it shares the store with the other trackers.
+pub fn spawn_erc721_tracker(
+    evm_client: Arc<dyn EvmClientApi>,
+    store: Arc<dyn NftStore>,
+    config: Erc721TrackerConfig,
+    mut callback: Box<dyn Erc721EventCallback>,
+) -> TrackerHandle {
+    tracker_handle(config.start_from, config.step, move |mut control| {
+        tokio::spawn(async move {
+            let result =
+                track_erc721_events_with_control(&*evm_client, &*store, &config, &mut *callback, &mut control, true)
+                    .await;
+            control.set_state(TrackerState::Stopped);
+            result
+        })
+    })
+}
+
+/// Track the ERC721 events with a two-stage pipeline: the fetcher gets the logs of the next ranges
+/// while the processor gets the metadata of the current one and delivers its events.
+/// A bounded channel between them keeps the fetcher at most `pipeline_depth` ranges ahead.
+/// Without `fetch_metadata`, the events are delivered with an empty metadata.
+async fn track_erc721_events_with_control(
+    evm_client: &dyn EvmClientApi,
+    store: &dyn NftStore,
+    config: &Erc721TrackerConfig,
+    callback: &mut dyn Erc721EventCallback,
+    control: &mut TrackerControl,
+    fetch_metadata: bool,
+) -> Result<ScanReport> {
+    // the configs written without the builder, as by the compatibility wrappers, are checked here
+    config.validate()?;
+    let started = Instant::now();
+    evm_client.verify().await?;
+    // a saved progress takes precedence over the start block
+    let resumes = config.options.resume && store.get_scan_progress(evm_client.chain_name()).await?.is_some();
+    let started_config = if resumes {
+        Cow::Borrowed(config)
+    } else {
+        config.resolve_start_block(evm_client).await?
+    };
+    let resolved_config = started_config.resolve_end_time(evm_client).await?;
+    let config: &Erc721TrackerConfig = &resolved_config;
+    let options = &config.options;
+    let chain_name = evm_client.chain_name();
+    let start_from = if options.resume {
+        resume_from(store, chain_name, config.start_from).await?
+    } else {
+        config.start_from
+    };
+    let mut step = AdaptiveStep::new(config.step, options);
     let mut from = start_from;
-    loop {
-        match evm_client.get_latest_block_number().await {
-            Ok(latest_block_number) => {
-                let to = std::cmp::min(from + step - 1, latest_block_number - 6);
-                if let Some(end_block) = end_block {
-                    if to > end_block {
-                        break;
+    let mut report = ScanReport::default();
+    let mut fetch_errors = 0;
+    let metrics = config.metrics.clone().unwrap_or_default();
+    // the cached metadata outlives the pipelines too
+    let cache = MetadataCache::new(config.collection_cache_capacity, config.token_cache_capacity, metrics.clone());
+    // the subscription outlives the pipelines, which are restarted after a reorg
+    let mut heads = if options.live {
+        subscribe_new_heads(evm_client).await
+    } else {
+        None
+    };
+    let result = loop {
+        let (sender, receiver) = mpsc::channel(std::cmp::max(options.pipeline_depth, 1));
+        let mut fetcher = Fetcher {
+            evm_client,
+            config,
+            step: &mut step,
+            metrics: &metrics,
+            errors: &mut fetch_errors,
+            heads: &mut heads,
+            fetch_metadata,
+        };
+        let mut processor = Processor {
+            evm_client,
+            store,
+            config,
+            callback: &mut *callback,
+            control: &mut *control,
+            metrics: &metrics,
+            cache: &cache,
+            report: &mut report,
+            start_from,
+            from,
+            fetch_metadata,
+        };
+
+        // the fetcher is dropped as soon as the processor returns,
+        // but the processor drains the fetched ranges after the fetcher returned
+        let (fetched, processed) = {
+            let fetch = fetcher.run(from, sender);
+            let process = processor.run(receiver);
+            tokio::pin!(fetch);
+            tokio::pin!(process);
+            let mut fetched = None;
+            loop {
+                tokio::select! {
+                    result = &mut fetch, if fetched.is_none() => fetched = Some(result),
+                    processed = &mut process => break (fetched, processed),
+                }
+            }
+        };
+        from = processor.from;
+        match (processed, fetched) {
+            (Ok(Processed::Rescan(rescan_from)), _) => from = rescan_from,
+            (Ok(Processed::Done), Some(Err(err))) | (Err(err), _) => break Err(err),
+            (Ok(Processed::Done), _) => break Ok(()),
+        }
+    };
+    // all the errors of the fetcher are failed requests
+    report.errors += fetch_errors;
+    report.rpc_errors += fetch_errors;
+    result?;
+
+    report.last_processed_block = last_processed_block(start_from, from);
+    report.duration = started.elapsed();
+    Ok(report)
+}
+
+/// A block range fetched by the first stage of the pipeline
+struct FetchedRange {
+    from: u64,
+    to: u64,
+    /// The step when the range was fetched
+    step: u64,
+    latest_block_number: u64,
+    /// The events which are not denied
+    events: Vec<Erc721Event>,
+    /// How many events were found, including the denied ones
+    events_found: usize,
+    /// The ERC4906 metadata updates which are not denied, when the metadata is fetched
+    metadata_updates: Vec<Erc721MetadataUpdate>,
+    /// The approvals which are not denied, with `track_approvals`
+    approvals: Vec<Erc721ApprovalEvent>,
+    /// The approvals for all which are not denied, with `track_approvals_for_all`
+    approvals_for_all: Vec<Erc721ApprovalForAllEvent>,
+    /// The ERC2309 batches wider than `max_expanded_consecutive_transfer` which are not denied,
+    /// the narrower ones are in the events
+    consecutive_transfers: Vec<Erc721ConsecutiveTransfer>,
+    /// How many `Transfer` logs were not ERC721 ones
+    transfers_rejected: u64,
+    /// The hashes of the blocks stored to detect the reorgs: the hash of the last block,
+    /// or the hashes of all the blocks with `strict_block_hashes`
+    block_hashes: Vec<(u64, H256)>,
+    started: Instant,
+}
+
+/// The first stage of the pipeline, it fetches the logs of the ranges one after another.
+struct Fetcher<'a> {
+    evm_client: &'a dyn EvmClientApi,
+    config: &'a Erc721TrackerConfig,
+    step: &'a mut AdaptiveStep,
+    metrics: &'a TrackerMetrics,
+    errors: &'a mut u64,
+    /// The new blocks in live mode, None when the latest block is polled
+    heads: &'a mut Option<HeadStream>,
+    /// Whether the metadata updates are fetched with the events, for refreshing the metadata
+    fetch_metadata: bool,
+}
+
+impl Fetcher<'_> {
+    /// Fetch the ranges from `from` until the end block, the cancellation or a terminal error.
+    async fn run(&mut self, mut from: u64, sender: mpsc::Sender<FetchedRange>) -> Result<()> {
+        let evm_client = self.evm_client;
+        let config = self.config;
+        let options = &config.options;
+        let chain_name = evm_client.chain_name();
+        let kinds = LogKinds {
+            metadata_updates: self.fetch_metadata,
+            approvals: config.track_approvals,
+            approvals_for_all: config.track_approvals_for_all,
+            consecutive_transfers: true,
+            cryptopunks: config.tracks_cryptopunks(),
+            non_indexed_erc721_contracts: &config.non_indexed_erc721_contracts,
+            raw_logs: config.keep_raw_logs,
+        };
+        let mut consecutive_errors = 0;
+        let mut backoff = Backoff::new(options);
+        loop {
+            self.metrics.set_position(from, self.step.get());
+            if options.is_cancelled() {
+                info!("Tracking {} ERC721 events is cancelled.", chain_name);
+                return Ok(());
+            }
+            if let Some(end_block) = config.end_block {
+                if from > end_block {
+                    return Ok(());
+                }
+            }
+
+            let latest_block_number = match evm_client.get_latest_block_number().await {
+                Ok(latest_block_number) => {
+                    self.metrics.set_latest_block(latest_block_number);
+                    latest_block_number
+                }
+                Err(err) => {
+                    *self.errors += 1;
+                    self.metrics.record_rpc_error();
+                    consecutive_errors += 1;
+                    if config.error_policy.should_stop(&err, consecutive_errors) {
+                        error!("Stop tracking {} ERC721 events because of error: {:?}.", chain_name, err);
+                        return Err(err);
                     }
+                    let delay = match backoff.next_delay() {
+                        Some(delay) => delay,
+                        None => {
+                            error!("The retry budget of {} is exhausted.", chain_name);
+                            return Err(err);
+                        }
+                    };
+                    error!("Encountered an error when get latest_block_number from {}: {:?}, wait for {:?}.", chain_name, err, delay);
+                    options.sleep(delay).await;
+                    continue;
+                }
+            };
+
+            // the sub-ranges of a step are fetched concurrently in parallel mode
+            let parallel_ranges = std::cmp::max(options.parallel_ranges, 1);
+            let span = self.step.get().saturating_mul(parallel_ranges as u64);
+            let to = match range_end(from, span, latest_block_number, options.confirmations) {
+                // the last range may be shorter than the step
+                Some(to) => config.end_block.map_or(to, |end_block| std::cmp::min(to, end_block)),
+                None => {
+                    debug!("No {} block is confirmed yet, wait for the next one.", chain_name);
+                    self.wait_for_new_block().await;
+                    continue;
                 }
+            };
+            if to < from {
+                debug!("Track {} ERC721 events too fast, wait for the next block.", chain_name);
+                self.wait_for_new_block().await;
+                continue;
+            }
 
-                if to >= from {
+            let step = self.step.get();
+            let started = Instant::now();
+            let mut scans = stream::iter(split_range(from, to, step))
+                .map(|(from, to)| async move {
                     debug!(
                         "Scan for {} ERC721 events in block range of {} - {}({})",
-                        evm_client.chain_name,
+                        chain_name,
                         from,
                         to,
                         to - from + 1
                     );
-                    let start = Instant::now();
-                    match erc721_evm::get_erc721_events(&evm_client, from, to).await {
-                        Ok(events) => {
-                            info!(
-                                "{} {} ERC721 events were scanned in block range of {} - {}({})",
-                                events.len(),
-                                evm_client.chain_name,
-                                from,
-                                to,
-                                to - from + 1
-                            );
-                            for event in events {
-                                // PROCESS AN EVENT
-                                if let Err(err) = process_event(evm_client, db_conn, event.clone(), callback).await {
-                                    error!("Encountered an error when process ERC721 event {:?} from {}: {:?}.", event, evm_client.chain_name, err);
-                                }
-                            }
-
-                            from = to + 1;
-                            let duration = start.elapsed();
-                            debug!("Time elapsed is: {:?}", duration);
-                            sleep(Duration::from_secs(5)).await;
-                        }
-                        Err(err) => match err {
-                            Error::Web3Error(web3::Error::Rpc(e)) => {
-                                if e.message.contains("more than") {
-                                    error!("{}", e.message);
-                                    step = std::cmp::max(step / 2, 1);
-                                } else {
-                                    error!("Encountered an error when get ERC721 events from {}: {:?}, wait for 30 seconds.", evm_client.chain_name, e);
-                                    sleep(Duration::from_secs(30)).await;
-                                }
-                            }
-                            _ => {
-                                error!("Encountered an error when get ERC721 events from {}: {:?}, wait for 30 seconds.", evm_client.chain_name, err);
-                                sleep(Duration::from_secs(30)).await;
-                            }
-                        },
+                    (from, to, scan_range(evm_client, config, kinds, from, to).await)
+                })
+                // the results come in the order of the sub-ranges
+                .buffered(parallel_ranges);
+            let mut failure = None;
+            while let Some((sub_from, sub_to, scanned)) = scans.next().await {
+                let (logs, block_hashes) = match scanned {
+                    Ok(scanned) => scanned,
+                    Err(err) => {
+                        // the next sub-ranges are fetched again
+                        failure = Some(err);
+                        break;
                     }
-                } else {
-                    debug!(
-                        "Track {} ERC721 events too fast, wait for 30 seconds.",
-                        evm_client.chain_name
-                    );
-                    sleep(Duration::from_secs(30)).await;
+                };
+                let Erc721Logs {
+                    mut events,
+                    mut metadata_updates,
+                    mut approvals,
+                    mut approvals_for_all,
+                    mut consecutive_transfers,
+                    transfers_rejected,
+                } = logs;
+                info!(
+                    "{} {} ERC721 events were scanned in block range of {} - {}({})",
+                    events.len(),
+                    chain_name,
+                    sub_from,
+                    sub_to,
+                    sub_to - sub_from + 1
+                );
+
+                let events_found = events.len();
+                // the denied contracts never reach the database
+                events.retain(|event| !config.denylist.contains(&event.address));
+                metadata_updates.retain(|metadata_update| !config.denylist.contains(&metadata_update.address));
+                approvals.retain(|approval| !config.denylist.contains(&approval.address));
+                approvals_for_all.retain(|approval_for_all| !config.denylist.contains(&approval_for_all.address));
+                consecutive_transfers.retain(|consecutive_transfer| !config.denylist.contains(&consecutive_transfer.address));
+                // the events are decoded with the default burn addresses
+                for event in &mut events {
+                    event.kind = EventKind::classify(&event.from, &event.to, &config.burn_addresses);
+                }
+
+                let range = FetchedRange {
+                    from: sub_from,
+                    to: sub_to,
+                    step,
+                    latest_block_number,
+                    events,
+                    events_found,
+                    metadata_updates,
+                    approvals,
+                    approvals_for_all,
+                    consecutive_transfers,
+                    transfers_rejected,
+                    block_hashes,
+                    started,
+                };
+                // waits while the processor is `pipeline_depth` ranges behind
+                if sender.send(range).await.is_err() {
+                    // the processor has returned
+                    return Ok(());
+                }
+
+                from = sub_to + 1;
+                consecutive_errors = 0;
+                backoff.reset();
+                self.step.succeed();
+            }
+
+            if let Some(err) = failure {
+                *self.errors += 1;
+                self.metrics.record_rpc_error();
+                if config.error_policy.is_range_limit(&err) {
+                    // the client splits the rejected ranges up to its split depth, the next ranges are smaller
+                    error!("{:?}", err);
+                    self.step.shrink();
+                    continue;
+                }
+                consecutive_errors += 1;
+                if config.error_policy.should_stop(&err, consecutive_errors) {
+                    error!("Stop tracking {} ERC721 events because of error: {:?}.", chain_name, err);
+                    return Err(err);
                 }
+                let delay = match backoff.next_delay() {
+                    Some(delay) => delay,
+                    None => {
+                        error!("The retry budget of {} is exhausted.", chain_name);
+                        return Err(err);
+                    }
+                };
+                error!("Encountered an error when get ERC721 events from {}: {:?}, wait for {:?}.", chain_name, err, delay);
+                options.sleep(delay).await;
+                continue;
             }
-            Err(err) => {
-                error!("Encountered an error when get latest_block_number from {}: {:?}, wait for 30 seconds.", evm_client.chain_name, err);
-                sleep(Duration::from_secs(30)).await;
+
+            if options.is_backfilling(to, latest_block_number, self.step.get()) {
+                debug!("Backfilling {} ERC721 events, scan the next range right away.", chain_name);
+            } else if self.heads.is_none() {
+                options.sleep(options.range_interval).await;
             }
         }
     }
-}
 
-async fn process_event(evm_client: &EvmClient, db_conn: &Connection, event: Erc721Event, callback: &mut dyn Erc721EventCallback) -> Result<()> {
-    let metadata = get_metadata(evm_client, db_conn, &event).await?;
-    if let Some((name, symbol, token_uri)) = metadata {
-        // get total supply
-        // let total_supply = evm_client.get_erc721_total_supply(&event.address, event.block_number).await?;
-        let total_supply = Some(0);
+    /// Wait for `idle_interval`, or only until the next block is notified in live mode.
+    /// The confirmations still apply, the new blocks are scanned once they are confirmed.
+    async fn wait_for_new_block(&mut self) {
+        let config = self.config;
+        let options = &config.options;
+        let chain_name = self.evm_client.chain_name();
+        let heads = match self.heads.as_mut() {
+            Some(heads) => heads,
+            None => return options.sleep(options.idle_interval).await,
+        };
+        let subscription_dropped = tokio::select! {
+            head = heads.next() => match head {
+                Some(Ok(block_number)) => {
+                    debug!("The {} block {} is notified.", chain_name, block_number);
+                    false
+                }
+                Some(Err(err)) => {
+                    error!("The subscription to the new {} blocks failed: {:?}, poll the latest block instead.", chain_name, err);
+                    true
+                }
+                None => {
+                    error!("The subscription to the new {} blocks is closed, poll the latest block instead.", chain_name);
+                    true
+                }
+            },
+            _ = options.sleep(options.idle_interval) => false,
+        };
+        if subscription_dropped {
+            *self.heads = None;
+        }
+    }
+}
 
-        // callback
-        callback.on_erc721_event(
-            event,
-            name,
-            symbol,
-            total_supply,
-            token_uri,
-        )
-        .await?;
+/// Subscribe to the new blocks for the live mode, the latest block is polled if it fails
+async fn subscribe_new_heads(evm_client: &dyn EvmClientApi) -> Option<HeadStream> {
+    match evm_client.subscribe_new_heads().await {
+        Ok(heads) => heads,
+        Err(err) => {
+            error!(
+                "Failed to subscribe to the new {} blocks: {:?}, poll the latest block instead.",
+                evm_client.chain_name(),
+                err
+            );
+            None
+        }
     }
+}
 
-    Ok(())
+/// Why the processor returned
+enum Processed {
+    /// All the fetched ranges have been processed, or the tracker is cancelled
+    Done,
+    /// The ranges have to be fetched again from this block
+    Rescan(u64),
 }
 
-async fn get_metadata(
-    evm_client: &EvmClient,
-    db_conn: &Connection,
-    event: &Erc721Event,
-) -> Result<Option<(String, String, String)>> {
-    save_metadata_to_db_if_not_exists(evm_client, db_conn, &event.address, &event.token_id).await?;
-    let collection =
-        erc721_db::get_collection_from_db(db_conn, &format!("{:?}", event.address))?.unwrap();
-    let token =
-        erc721_db::get_token_from_db(db_conn, collection.0, &event.token_id.to_string())?.unwrap();
-
-    if collection.2.is_some() && collection.3.is_some() && token.3.is_some() {
-        Ok(Some((collection.2.unwrap(), collection.3.unwrap(), token.3.unwrap())))
-    } else {
-        Ok(None)
-    }
+/// The second stage of the pipeline, it processes the fetched ranges in order.
+/// The status reported through the handle is the one of the processor.
+struct Processor<'a> {
+    evm_client: &'a dyn EvmClientApi,
+    store: &'a dyn NftStore,
+    config: &'a Erc721TrackerConfig,
+    callback: &'a mut dyn Erc721EventCallback,
+    control: &'a mut TrackerControl,
+    metrics: &'a TrackerMetrics,
+    cache: &'a MetadataCache,
+    report: &'a mut ScanReport,
+    start_from: u64,
+    /// The first block of the next range to process
+    from: u64,
+    fetch_metadata: bool,
 }
 
-async fn save_metadata_to_db_if_not_exists(
-    evm_client: &EvmClient,
-    db_conn: &Connection,
-    address: &H160,
-    token_id: &U256,
-) -> Result<()> {
-    let address_string = format!("{:?}", address);
-    let collection_id =
-        if let Some(collection) = erc721_db::get_collection_from_db(db_conn, &address_string)? {
-            collection.0
-        } else {
-            if let Some((name, symbol)) = evm_client.get_erc721_name_symbol(address).await? {
-                erc721_db::add_collection_to_db(
-                    db_conn,
-                    address_string.clone(),
-                    Some(name),
-                    Some(symbol),
-                )?
+impl Processor<'_> {
+    async fn run(&mut self, mut receiver: mpsc::Receiver<FetchedRange>) -> Result<Processed> {
+        let evm_client = self.evm_client;
+        let store = self.store;
+        let config = self.config;
+        let options = &config.options;
+        let chain_name = evm_client.chain_name();
+        let start_from = self.start_from;
+        let fetch_metadata = self.fetch_metadata;
+        let mut backoff = Backoff::new(options);
+        loop {
+            if options.is_cancelled() {
+                info!("Tracking {} ERC721 events is cancelled.", chain_name);
+                return Ok(Processed::Done);
+            }
+            let range = match receiver.recv().await {
+                Some(range) => range,
+                None => return Ok(Processed::Done),
+            };
+            let from = range.from;
+            let to = range.to;
+            self.control.update(from, range.step, TrackerState::Scanning);
+            self.control.wait_while_paused(options).await;
+            if options.is_cancelled() {
+                info!("Tracking {} ERC721 events is cancelled.", chain_name);
+                return Ok(Processed::Done);
+            }
+
+            if options.detects_reorgs() {
+                let rescan_from = match find_reorg(evm_client, store, start_from, from).await {
+                    Ok(rescan_from) => rescan_from,
+                    Err(err) => {
+                        self.report.record_rpc_error();
+                        self.metrics.record_rpc_error();
+                        let delay = match backoff.next_delay() {
+                            Some(delay) => delay,
+                            None => {
+                                error!("The retry budget of {} is exhausted.", chain_name);
+                                return Err(err);
+                            }
+                        };
+                        error!("Encountered an error when check the {} blocks for reorgs: {:?}, wait for {:?}.", chain_name, err, delay);
+                        self.control.sleep(options, delay).await;
+                        return Ok(Processed::Rescan(from));
+                    }
+                };
+                if let Some(rescan_from) = rescan_from {
+                    warn!("A reorg of {} is detected, rescan from block {}.", chain_name, rescan_from);
+                    if let Err(err) = rewind(store, chain_name, rescan_from, from, options, &mut *self.callback).await {
+                        self.report.errors += 1;
+                        self.metrics.record_error();
+                        let delay = match backoff.next_delay() {
+                            Some(delay) => delay,
+                            None => {
+                                error!("The retry budget of {} is exhausted.", chain_name);
+                                return Err(err);
+                            }
+                        };
+                        error!("Encountered an error when rewind the {} ERC721 events: {:?}, wait for {:?}.", chain_name, err, delay);
+                        self.control.sleep(options, delay).await;
+                        return Ok(Processed::Rescan(from));
+                    }
+                    self.from = rescan_from;
+                    return Ok(Processed::Rescan(rescan_from));
+                }
+            }
+
+            // the writes of a range and its progress are committed together once it is processed,
+            // the metadata is saved as it is looked up
+            let mut writes = RangeWrites {
+                chain: chain_name.to_owned(),
+                ..Default::default()
+            };
+
+            // the owners follow all the transfers of the range, the ones not delivered included
+            if config.save_owners {
+                writes.owners = owner_writes(&range.events);
+            }
+
+            // PREPARE THE EVENTS
+            // the metadata missing from the database is fetched for the whole range if the client batches the lookups,
+            // at the latest block, so not when the metadata is pinned to the blocks of the events
+            let batched = evm_client.batches_metadata_lookups() && !config.historical_metadata;
+            let prefetched = if fetch_metadata && !options.dry_run && batched {
+                match prefetch_metadata(evm_client, store, &range.events, config).await {
+                    Ok(prefetched) => prefetched,
+                    Err(err) => {
+                        // the events look their metadata up one at a time instead
+                        self.report.record_rpc_error();
+                        self.metrics.record_rpc_error();
+                        warn!("Encountered an error when batch the ERC721 metadata lookups of {}: {:?}.", chain_name, err);
+                        PrefetchedMetadata::default()
+                    }
+                }
             } else {
-                erc721_db::add_collection_to_db(db_conn, address_string.clone(), None, None)?
+                PrefetchedMetadata::default()
+            };
+            let sources = &MetadataSources {
+                prefetched,
+                cache: Some(self.cache),
+            };
+            // an upgraded proxy is checked again before the metadata of its events is looked up
+            if config.resolve_proxies && !options.dry_run {
+                if let Err(err) = check_proxies(evm_client, store, &range.events, to, sources).await {
+                    self.report.record_rpc_error();
+                    self.metrics.record_rpc_error();
+                    warn!("Encountered an error when resolve the proxies of the {} ERC721 collections: {:?}.", chain_name, err);
+                }
+            }
+
+            // the first event of each collection missing from the cache is prepared before the others, which would
+            // look the collection up again concurrently, or find it saved without its metadata while it is looked up
+            let mut prepared_first = HashMap::new();
+            if options.metadata_concurrency > 1 {
+                let mut seen_addresses = HashSet::new();
+                for (index, event) in range.events.iter().enumerate() {
+                    let uncached = sources.collection(&event.address).is_none();
+                    if uncached && config.event_kinds.accepts(event.kind()) && seen_addresses.insert(event.address) {
+                        let mut report = ScanReport::default();
+                        let prepared =
+                            prepare_event(evm_client, store, event, config, fetch_metadata, sources, &mut report)
+                                .await;
+                        prepared_first.insert(index, (prepared, report));
+                    }
+                }
+            }
+
+            // the metadata of several events is fetched concurrently, `buffered` yields them in order.
+            // Their saves interleave, the saves of a collection or a token saved meanwhile are upserts.
+            let mut batch = Vec::with_capacity(range.events.len());
+            let mut dedup_keys = vec![];
+            let mut prepared_events = stream::iter(range.events.into_iter().enumerate())
+                .map(move |(index, event)| {
+                    let prepared_first = prepared_first.remove(&index);
+                    async move {
+                        let (prepared, report) = match prepared_first {
+                            Some(prepared_first) => prepared_first,
+                            None => {
+                                let mut report = ScanReport::default();
+                                let prepared = prepare_event(
+                                    evm_client,
+                                    store,
+                                    &event,
+                                    config,
+                                    fetch_metadata,
+                                    sources,
+                                    &mut report,
+                                )
+                                .await;
+                                (prepared, report)
+                            }
+                        };
+                        (event, prepared, report)
+                    }
+                })
+                .buffered(options.metadata_concurrency);
+            while let Some((event, prepared, report)) = prepared_events.next().await {
+                self.report.add_metadata_lookups(&report);
+                match prepared {
+                    Ok(Some((metadata, dedup_key))) => {
+                        batch.push((event, metadata));
+                        dedup_keys.extend(dedup_key);
+                    }
+                    Ok(None) => {}
+                    Err(err) => {
+                        self.report.record_rpc_error();
+                        self.metrics.record_rpc_error();
+                        error!("Encountered an error when process ERC721 event {:?} from {}: {:?}.", event, chain_name, err);
+                        if config.error_policy.is_terminal(&err) {
+                            // nothing of the range has been delivered yet, its writes are not committed,
+                            // the progress before it is saved already
+                            return Err(err);
+                        }
+                    }
+                }
+            }
+
+            if fetch_metadata {
+                fill_total_supplies(evm_client, &mut batch, to, &mut *self.report, self.metrics).await;
+                if config.fetch_owners {
+                    fill_owners(evm_client, &mut batch, to, &mut *self.report, self.metrics).await;
+                }
+                if config.fetch_royalties {
+                    fill_royalties(evm_client, store, &mut batch, options, &mut *self.report, self.metrics).await;
+                }
+            }
+            if config.fetch_block_timestamps {
+                fill_block_timestamps(evm_client, &mut batch, &mut *self.report, self.metrics).await;
+            }
+            if config.fetch_transaction_senders {
+                fill_transaction_senders(evm_client, &mut batch, &mut *self.report, self.metrics).await;
+            }
+            if config.fetch_sales {
+                fill_sales(evm_client, &mut batch, &mut *self.report, self.metrics).await;
+            }
+            if config.persist_events && !options.dry_run {
+                writes.transfers = transfer_writes(&batch);
+            }
+
+            // DELIVER THE EVENTS OF THE RANGE
+            let idle = batch.is_empty();
+            if !idle {
+                let batch_len = batch.len() as u64;
+                match deliver_events(chain_name, batch, config, &mut *self.callback).await {
+                    Ok(()) => {
+                        self.report.events_delivered += batch_len;
+                        self.metrics.record_events_delivered(batch_len);
+                        // marked after the callback, a crash in between re-delivers the events instead of losing them
+                        writes.delivered_events = dedup_keys;
+                    }
+                    Err(err) => {
+                        self.report.errors += 1;
+                        self.metrics.record_callback_error();
+                        error!("The callback failed to process the {} ERC721 events of block range {} - {}: {:?}.", chain_name, from, to, err);
+                        let terminal = config.error_policy.is_terminal(&err);
+                        match config.callback_error_policy {
+                            CallbackErrorPolicy::Skip if !terminal => {}
+                            CallbackErrorPolicy::RetryWithBackoff { .. } if !terminal => {
+                                // the range is scanned again, the owners and the transfers of its rejected events
+                                // are not committed
+                                let delay = match backoff.next_delay() {
+                                    Some(delay) => delay,
+                                    None => {
+                                        error!("The retry budget of {} is exhausted.", chain_name);
+                                        return Err(err);
+                                    }
+                                };
+                                self.control.sleep(options, delay).await;
+                                return Ok(Processed::Rescan(from));
+                            }
+                            _ => return Err(err),
+                        }
+                    }
+                }
+            }
+            // the updated metadata is saved before the progress of the range
+            if fetch_metadata {
+                self.process_metadata_updates(&range.metadata_updates).await;
+            }
+            self.deliver_consecutive_transfers(range.consecutive_transfers).await;
+            self.deliver_approvals(range.approvals, range.approvals_for_all).await;
+            // the events of the range are delivered already, scanning it again would deliver them twice:
+            // the same writes are committed again until they are saved
+            let writes = range_writes(writes, to, &range.block_hashes, options);
+            while let Err(err) = commit_range(store, &writes, options).await {
+                self.report.errors += 1;
+                self.metrics.record_error();
+                let delay = match backoff.next_delay() {
+                    Some(delay) if !config.error_policy.is_terminal(&err) => delay,
+                    _ => {
+                        error!("Stop tracking {} ERC721 events because of error: {:?}.", chain_name, err);
+                        return Err(err);
+                    }
+                };
+                error!("Encountered an error when commit the ERC721 range of {}: {:?}, wait for {:?}.", chain_name, err, delay);
+                self.control.sleep(options, delay).await;
+                if options.is_cancelled() {
+                    // the range is delivered again by the next run, its delivered events are not saved
+                    info!("Tracking {} ERC721 events is cancelled.", chain_name);
+                    return Ok(Processed::Done);
+                }
+            }
+            self.report.blocks_scanned += to - from + 1;
+            self.report.events_decoded += range.events_found as u64;
+            self.report.transfers_rejected += range.transfers_rejected;
+
+            self.callback
+                .on_progress(ScanProgress {
+                    chain: chain_name.to_owned(),
+                    from,
+                    to,
+                    latest_block: range.latest_block_number,
+                    events_found: range.events_found,
+                    elapsed: range.started.elapsed(),
+                })
+                .await;
+            if idle {
+                self.callback.on_idle(chain_name, to).await;
             }
-        };
 
-    let token = erc721_db::get_token_from_db(db_conn, collection_id, &token_id.to_string())?;
-    if token.is_none() {
-        let token_uri = evm_client.get_erc721_token_uri(address, token_id).await?;
-        erc721_db::add_token_to_db(db_conn, token_id.to_string(), collection_id, token_uri)?;
+            self.from = to + 1;
+            backoff.reset();
+            debug!("Time elapsed is: {:?}", range.started.elapsed());
+        }
     }
-    Ok(())
-}
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use web3::{transports::http::Http, Web3};
+    /// Tell the callback about the wide ERC2309 batches of a range.
+    /// A failed delivery is logged and the next batches are still delivered.
+    async fn deliver_consecutive_transfers(&mut self, consecutive_transfers: Vec<Erc721ConsecutiveTransfer>) {
+        let chain_name = self.evm_client.chain_name();
+        for consecutive_transfer in consecutive_transfers {
+            let description = format!("{:?}", consecutive_transfer);
+            if let Err(err) = self.callback.on_erc721_consecutive_transfer(consecutive_transfer).await {
+                self.report.errors += 1;
+                self.metrics.record_callback_error();
+                error!("The callback failed to process the {} ERC721 consecutive transfer {}: {:?}.", chain_name, description, err);
+            }
+        }
+    }
 
-    struct EthereumErc721EventCallback {
-        events: Vec<Erc721Event>,
+    /// Tell the callback about the approvals of a range, the approvals for all after the other ones.
+    /// A failed delivery is logged and the next approvals are still delivered.
+    async fn deliver_approvals(
+        &mut self,
+        approvals: Vec<Erc721ApprovalEvent>,
+        approvals_for_all: Vec<Erc721ApprovalForAllEvent>,
+    ) {
+        let chain_name = self.evm_client.chain_name();
+        for approval in approvals {
+            let description = format!("{:?}", approval);
+            if let Err(err) = self.callback.on_erc721_approval(approval).await {
+                self.report.errors += 1;
+                self.metrics.record_callback_error();
+                error!("The callback failed to process the {} ERC721 approval {}: {:?}.", chain_name, description, err);
+            }
+        }
+        for approval_for_all in approvals_for_all {
+            let description = format!("{:?}", approval_for_all);
+            if let Err(err) = self.callback.on_erc721_approval_for_all(approval_for_all).await {
+                self.report.errors += 1;
+                self.metrics.record_callback_error();
+                error!("The callback failed to process the {} ERC721 approval for all {}: {:?}.", chain_name, description, err);
+            }
+        }
     }
 
-    #[async_trait]
-    impl Erc721EventCallback for EthereumErc721EventCallback {
-        async fn on_erc721_event(
-            &mut self,
-            event: Erc721Event,
-            _name: String,
-            _symbol: String,
-            _total_supply: Option<u128>,
-            _token_uri: String,
-        ) -> Result<()> {
-            self.events.push(event);
-            Ok(())
+    /// Refresh the token uris of the ERC4906 metadata updates and tell the callback about them.
+    /// A failed refresh is logged and the next updates are still processed.
+    async fn process_metadata_updates(&mut self, metadata_updates: &[Erc721MetadataUpdate]) {
+        let chain_name = self.evm_client.chain_name();
+        for metadata_update in metadata_updates {
+            let refreshed = match self.refresh_token_uris(metadata_update).await {
+                Ok(refreshed) => refreshed,
+                Err(err) => {
+                    self.report.record_rpc_error();
+                    self.metrics.record_rpc_error();
+                    error!("Encountered an error when refresh the {} ERC721 metadata of {:?}: {:?}.", chain_name, metadata_update, err);
+                    continue;
+                }
+            };
+            for (token_ids, token_uri) in refreshed {
+                let address = metadata_update.address;
+                if let Err(err) = self.callback.on_erc721_metadata_update(address, token_ids, token_uri).await {
+                    self.report.errors += 1;
+                    self.metrics.record_callback_error();
+                    error!("The callback failed to process the {} ERC721 metadata update {:?}: {:?}.", chain_name, metadata_update, err);
+                }
+            }
         }
     }
 
-    #[tokio::test]
-    async fn test_track_erc721_events() {
-        //
-        let web3 = Web3::new(Http::new("https://main-light.eth.linkpool.io").unwrap());
-        let client = EvmClient::new("Ethereum".to_owned(), web3);
+    /// Fetch again the token uris of a metadata update, and save the ones of the saved tokens.
+    /// The tokens of a `BatchMetadataUpdate` wider than `EAGER_METADATA_UPDATE_TOKENS` are marked stale instead,
+    /// their token uris are fetched again by their next events. It returns the refreshed token uris,
+    /// or the whole range without a token uri when the tokens are marked stale.
+    async fn refresh_token_uris(
+        &self,
+        metadata_update: &Erc721MetadataUpdate,
+    ) -> Result<Vec<(RangeInclusive<U256>, Option<String>)>> {
+        let address = &metadata_update.address;
+        let (first, last) = (*metadata_update.token_ids.start(), *metadata_update.token_ids.end());
+        if first > last {
+            return Ok(vec![]);
+        }
+        let chain_name = self.evm_client.chain_name();
+        let collection = self.store.get_collection(chain_name, &format!("{:?}", address)).await?;
+        // the token uris are only fetched in dry-run mode
+        let collection_id = collection.filter(|_| !self.config.options.dry_run).map(|collection| collection.id);
 
-        //
-        let conn = Connection::open("./test7.db").unwrap();
-        erc721_db::create_tables_if_not_exist(&conn).unwrap();
+        if last - first >= U256::from(EAGER_METADATA_UPDATE_TOKENS) {
+            if let Some(collection_id) = collection_id {
+                self.store.mark_tokens_stale(collection_id, &first.to_string(), &last.to_string()).await?;
+                self.cache.remove_tokens(address, &metadata_update.token_ids);
+            }
+            return Ok(vec![(metadata_update.token_ids.clone(), None)]);
+        }
 
-        //
-        let mut callback = EthereumErc721EventCallback { events: vec![] };
-        track_erc721_events(&client, &conn, 13015344, 1, Some(13015346), &mut callback).await;
-        assert_eq!(15, callback.events.len());
+        let at_block = if self.config.historical_metadata { metadata_update.block_number } else { None };
+        let mut refreshed = vec![];
+        let mut token_id = first;
+        loop {
+            let token_uri = self.evm_client.get_erc721_token_uri(address, &token_id, at_block).await?;
+            if let Some(collection_id) = collection_id {
+                if let Some(token) = self.store.get_token(collection_id, &token_id.to_string()).await? {
+                    self.store.update_token_uri(token.id, token_uri.clone(), now()).await?;
+                    self.cache.remove_token(address, &token_id);
+                }
+            }
+            refreshed.push((token_id..=token_id, token_uri));
+            if token_id == last {
+                return Ok(refreshed);
+            }
+            token_id += U256::one();
+        }
+    }
+}
+
+/// The widest `BatchMetadataUpdate` whose token uris are fetched again right away, the tokens of the wider ones
+/// are only marked stale, as a collection may update the metadata of millions of tokens at once
+const EAGER_METADATA_UPDATE_TOKENS: u64 = 16;
+
+/// The block to start from, considering the progress stored in the database under `progress_key`.
+/// A failure to read the progress is returned, the tracker would scan again from `start_from` otherwise.
+pub(crate) async fn resume_from(store: &dyn NftStore, progress_key: &str, start_from: u64) -> Result<u64> {
+    match store.get_scan_progress(progress_key).await? {
+        Some(last_scanned_block) => {
+            let from = std::cmp::max(last_scanned_block + 1, start_from);
+            info!("Resume tracking the events of {} from block {}.", progress_key, from);
+            Ok(from)
+        }
+        None => Ok(start_from),
+    }
+}
+
+/// Split the range `from` - `to` into consecutive sub-ranges of `step` blocks, the last one may be shorter.
+fn split_range(from: u64, to: u64, step: u64) -> Vec<(u64, u64)> {
+    let mut sub_ranges = vec![];
+    let mut sub_from = from;
+    while sub_from <= to {
+        let sub_to = std::cmp::min(sub_from.saturating_add(step - 1), to);
+        sub_ranges.push((sub_from, sub_to));
+        sub_from = sub_to + 1;
+    }
+    sub_ranges
+}
+
+/// Get the events of a range with the other logs of `kinds`,
+/// and the hashes of its blocks to store if reorgs are detected.
+async fn scan_range(
+    evm_client: &dyn EvmClientApi,
+    config: &Erc721TrackerConfig,
+    kinds: LogKinds<'_>,
+    from: u64,
+    to: u64,
+) -> Result<(Erc721Logs, Vec<(u64, H256)>)> {
+    let (mut logs, block_hashes) = if config.options.strict_block_hashes {
+        scan_range_by_block_hash(evm_client, config, kinds, from, to).await?
+    } else {
+        let addresses = config.address_allowlist.as_deref();
+        let chunk_size = config.max_addresses_per_request;
+        let logs = erc721_evm::get_erc721_logs(evm_client, addresses, chunk_size, kinds, from, to).await?;
+        let block_hashes = if config.options.detect_reorgs {
+            evm_client.get_block_hash(to).await?.map(|block_hash| (to, block_hash)).into_iter().collect()
+        } else {
+            vec![]
+        };
+        (logs, block_hashes)
+    };
+    // the narrow ERC2309 batches are delivered as the transfers of their tokens
+    let max_expanded = U256::from(config.max_expanded_consecutive_transfer);
+    let (expanded, consecutive_transfers): (Vec<_>, Vec<_>) = std::mem::take(&mut logs.consecutive_transfers)
+        .into_iter()
+        .partition(|consecutive_transfer| consecutive_transfer.token_count() <= max_expanded);
+    logs.consecutive_transfers = consecutive_transfers;
+    for consecutive_transfer in expanded {
+        logs.events.append(&mut consecutive_transfer.events());
+    }
+    // the events are delivered in the order of the chain, a token can be transferred several times in a block
+    logs.events.sort_by_key(|event| (event.block_number, event.transaction_index, event.log_index));
+    Ok((logs, block_hashes))
+}
+
+/// Get the events of a range block after block, by the hash of each block fetched first,
+/// so that the events are the ones of the blocks whose hashes are returned even if a reorg happens meanwhile.
+async fn scan_range_by_block_hash(
+    evm_client: &dyn EvmClientApi,
+    config: &Erc721TrackerConfig,
+    kinds: LogKinds<'_>,
+    from: u64,
+    to: u64,
+) -> Result<(Erc721Logs, Vec<(u64, H256)>)> {
+    let mut logs = Erc721Logs::default();
+    let mut block_hashes = vec![];
+    for block_number in from..=to {
+        let block_hash = evm_client.get_block_hash(block_number).await?.ok_or_else(|| {
+            Error::Other(format!("The block {} of {} does not exist yet", block_number, evm_client.chain_name()))
+        })?;
+        let addresses = config.address_allowlist.as_deref();
+        let mut block_logs = erc721_evm::get_erc721_logs_by_block_hash(
+            evm_client,
+            block_hash,
+            addresses,
+            config.max_addresses_per_request,
+            kinds,
+        )
+        .await?;
+        logs.events.append(&mut block_logs.events);
+        logs.metadata_updates.append(&mut block_logs.metadata_updates);
+        logs.approvals.append(&mut block_logs.approvals);
+        logs.approvals_for_all.append(&mut block_logs.approvals_for_all);
+        logs.consecutive_transfers.append(&mut block_logs.consecutive_transfers);
+        logs.transfers_rejected += block_logs.transfers_rejected;
+        block_hashes.push((block_number, block_hash));
+    }
+    Ok((logs, block_hashes))
+}
+
+/// The writes of a range, together with the scan progress if resuming is enabled,
+/// and the hashes of its blocks if reorgs are detected
+fn range_writes(
+    writes: RangeWrites,
+    last_processed_block: u64,
+    block_hashes: &[(u64, H256)],
+    options: &ScanOptions,
+) -> RangeWrites {
+    RangeWrites {
+        last_scanned_block: options.resume.then(|| last_processed_block),
+        scanned_blocks: block_hashes
+            .iter()
+            .map(|(block_number, block_hash)| (*block_number, format!("{:?}", block_hash)))
+            .collect(),
+        ..writes
+    }
+}
+
+/// Commit the writes of a range, nothing is committed in dry-run mode
+async fn commit_range(store: &dyn NftStore, writes: &RangeWrites, options: &ScanOptions) -> Result<()> {
+    if options.dry_run {
+        return Ok(());
+    }
+    store.commit_range(writes).await
+}
+
+/// Compare the stored range boundaries before `from` with the chain, from the latest one.
+/// It returns the block to rescan from if some of them are not in the canonical chain anymore.
+async fn find_reorg(
+    evm_client: &dyn EvmClientApi,
+    store: &dyn NftStore,
+    start_from: u64,
+    from: u64,
+) -> Result<Option<u64>> {
+    let boundaries = store.get_scanned_blocks_before(evm_client.chain_name(), from).await?;
+    let mut orphaned = false;
+    for (block_number, block_hash) in boundaries {
+        let canonical_hash = evm_client.get_block_hash(block_number).await?;
+        if canonical_hash.map(|hash| format!("{:?}", hash)) == Some(block_hash) {
+            return Ok(if orphaned { Some(block_number + 1) } else { None });
+        }
+        orphaned = true;
+    }
+    // the fork is older than all the stored boundaries
+    Ok(if orphaned { Some(start_from) } else { None })
+}
+
+/// Forget what was scanned from `rescan_from`, and tell the callback about the removed blocks.
+/// The owners saved from the orphaned blocks are forgotten too, the tokens transferred again by the canonical ones
+/// get their owners back when they are scanned again, the others have no saved owner until their next transfer.
+async fn rewind(
+    store: &dyn NftStore,
+    chain_name: &str,
+    rescan_from: u64,
+    from: u64,
+    options: &ScanOptions,
+    callback: &mut dyn Erc721EventCallback,
+) -> Result<()> {
+    let last_scanned_block = rescan_from.checked_sub(1).filter(|_| options.resume);
+    store.rewind(chain_name, rescan_from, last_scanned_block).await?;
+    if from > rescan_from {
+        callback.on_erc721_events_removed(rescan_from..=from - 1).await?;
+    }
+    Ok(())
+}
+
+/// The dedup key of an event: its transaction hash, log index and block number
+type DedupKey = (String, u64, u64);
+
+/// Prepare an event for the delivery, it returns None if the event is not delivered.
+async fn prepare_event(
+    evm_client: &dyn EvmClientApi,
+    store: &dyn NftStore,
+    event: &Erc721Event,
+    config: &Erc721TrackerConfig,
+    fetch_metadata: bool,
+    sources: &MetadataSources<'_>,
+    report: &mut ScanReport,
+) -> Result<Option<(Erc721Metadata, Option<DedupKey>)>> {
+    let chain_name = evm_client.chain_name();
+    if !config.event_kinds.accepts(event.kind()) {
+        return Ok(None);
+    }
+    if config.skip_non_erc721_contracts {
+        let non_erc721 = if config.options.dry_run {
+            is_known_non_erc721(store, evm_client.chain_name(), &event.address).await?
+        } else {
+            check_erc721_support(evm_client, store, &event.address, sources).await?.1 == Some(false)
+        };
+        if non_erc721 {
+            debug!("Skip the ERC721 event {:?} of a contract which is not ERC721 from {}.", event, chain_name);
+            return Ok(None);
+        }
+    }
+
+    // events without a transaction hash or a log index can not be deduplicated
+    let block_number = event.block_number.unwrap_or_default();
+    let dedup_key = match (config.options.dedup, event.transaction_hash, event.log_index) {
+        (true, Some(transaction_hash), Some(log_index)) => {
+            Some((format!("{:?}", transaction_hash), log_index, block_number))
+        }
+        _ => None,
+    };
+    if let Some((transaction_hash, log_index, _)) = &dedup_key {
+        if store.is_event_delivered(chain_name, transaction_hash, *log_index).await? {
+            debug!("Skip the delivered ERC721 event {:?} from {}.", event, chain_name);
+            return Ok(None);
+        }
+    }
+
+    if !fetch_metadata {
+        return Ok(Some((Erc721Metadata::default(), dedup_key)));
+    }
+    let metadata = if config.options.dry_run {
+        get_metadata_read_only(evm_client, store, event, config, report).await?
+    } else {
+        get_metadata(evm_client, store, event, config, sources, report).await?
+    };
+    let (name, symbol, token_uri) = match metadata {
+        Some(metadata) => metadata,
+        None => return Ok(None),
+    };
+    // the total supply, the owner and the royalty are filled for the whole range, see `fill_total_supplies`
+    let metadata = Erc721Metadata {
+        name,
+        symbol,
+        total_supply: None,
+        token_uri,
+        owner: None,
+        royalty: None,
+        contract_uri: None,
+        implementation: None,
+    };
+    let metadata = match sources.collection(&event.address) {
+        Some(collection) => Erc721Metadata {
+            contract_uri: collection.contract_uri,
+            implementation: collection.implementation,
+            ..metadata
+        },
+        None => {
+            let address_string = format!("{:?}", event.address);
+            let chain_name = evm_client.chain_name();
+            Erc721Metadata {
+                contract_uri: store.get_collection_contract_uri(chain_name, &address_string).await?,
+                implementation: get_implementation(store, chain_name, &address_string).await?,
+                ..metadata
+            }
+        }
+    };
+    Ok(Some((metadata, dedup_key)))
+}
+
+/// Fill the total supplies of the events of a range, fetched once per collection at the last block of the range.
+/// The total supply stays None if it can not be fetched, the events are delivered anyway.
+async fn fill_total_supplies(
+    evm_client: &dyn EvmClientApi,
+    batch: &mut [(Erc721Event, Erc721Metadata)],
+    block_number: u64,
+    report: &mut ScanReport,
+    metrics: &TrackerMetrics,
+) {
+    let mut total_supplies: HashMap<H160, Option<u128>> = HashMap::new();
+    for (event, metadata) in batch.iter_mut() {
+        let total_supply = match total_supplies.get(&event.address) {
+            Some(total_supply) => *total_supply,
+            None => {
+                let total_supply = match evm_client
+                    .get_erc721_total_supply(&event.address, Some(block_number))
+                    .await
+                {
+                    Ok(total_supply) => total_supply,
+                    Err(err) => {
+                        report.record_rpc_error();
+                        metrics.record_rpc_error();
+                        warn!("Encountered an error when get the total supply of {:?}: {:?}.", event.address, err);
+                        None
+                    }
+                };
+                total_supplies.insert(event.address, total_supply);
+                total_supply
+            }
+        };
+        metadata.total_supply = total_supply;
+    }
+}
+
+/// Fill the owners of the tokens of a range, fetched once per token at the last block of the range.
+/// The owner stays None if it can not be fetched, the events are delivered anyway.
+async fn fill_owners(
+    evm_client: &dyn EvmClientApi,
+    batch: &mut [(Erc721Event, Erc721Metadata)],
+    block_number: u64,
+    report: &mut ScanReport,
+    metrics: &TrackerMetrics,
+) {
+    let mut owners: HashMap<(H160, U256), Option<H160>> = HashMap::new();
+    for (event, metadata) in batch.iter_mut() {
+        let token = (event.address, event.token_id);
+        let owner = match owners.get(&token) {
+            Some(owner) => *owner,
+            None => {
+                let owner = match evm_client
+                    .get_erc721_owner_of(&event.address, &event.token_id, Some(block_number))
+                    .await
+                {
+                    Ok(owner) => owner,
+                    Err(err) => {
+                        report.record_rpc_error();
+                        metrics.record_rpc_error();
+                        warn!("Encountered an error when get the owner of {:?} {}: {:?}.", event.address, event.token_id, err);
+                        None
+                    }
+                };
+                owners.insert(token, owner);
+                owner
+            }
+        };
+        metadata.owner = owner;
+    }
+}
+
+/// The sale price the royalties are asked for, so that the royalty amounts are in basis points
+const ROYALTY_SALE_PRICE: u64 = 10_000;
+
+/// Fill the royalties of the events of a range, fetched once per collection with the token of its first event,
+/// and save them with the collections. The royalty stays None if it can not be fetched, the events are delivered anyway.
+async fn fill_royalties(
+    evm_client: &dyn EvmClientApi,
+    store: &dyn NftStore,
+    batch: &mut [(Erc721Event, Erc721Metadata)],
+    options: &ScanOptions,
+    report: &mut ScanReport,
+    metrics: &TrackerMetrics,
+) {
+    let mut royalties: HashMap<H160, Option<(H160, u64)>> = HashMap::new();
+    for (event, metadata) in batch.iter_mut() {
+        let royalty = match royalties.get(&event.address) {
+            Some(royalty) => *royalty,
+            None => {
+                let sale_price = U256::from(ROYALTY_SALE_PRICE);
+                let royalty = match evm_client.get_royalty_info(&event.address, &event.token_id, &sale_price).await {
+                    Ok(royalty) => {
+                        // a royalty over the sale price is bogus
+                        let royalty = royalty
+                            .filter(|(_, amount)| *amount <= sale_price)
+                            .map(|(receiver, amount)| (receiver, amount.as_u64()));
+                        if !options.dry_run {
+                            let saved = save_royalty(store, evm_client.chain_name(), &event.address, royalty).await;
+                            if let Err(err) = saved {
+                                warn!("Encountered an error when save the royalty of {:?}: {:?}.", event.address, err);
+                            }
+                        }
+                        royalty
+                    }
+                    Err(err) => {
+                        report.record_rpc_error();
+                        metrics.record_rpc_error();
+                        warn!("Encountered an error when get the royalty of {:?}: {:?}.", event.address, err);
+                        None
+                    }
+                };
+                royalties.insert(event.address, royalty);
+                royalty
+            }
+        };
+        metadata.royalty = royalty;
+    }
+}
+
+/// Save the royalty of a collection already saved with its metadata
+async fn save_royalty(store: &dyn NftStore, chain: &str, address: &H160, royalty: Option<(H160, u64)>) -> Result<()> {
+    if let Some(collection) = store.get_collection(chain, &format!("{:?}", address)).await? {
+        let royalty = royalty.map(|(receiver, bps)| (format!("{:?}", receiver), bps));
+        let royalty = royalty.as_ref().map(|(receiver, bps)| (receiver.as_str(), *bps));
+        store.save_collection_royalty(collection.id, royalty).await?;
+    }
+    Ok(())
+}
+
+/// Fill the timestamps of the blocks of the events of a range, fetched once per block.
+/// The timestamp stays None if it can not be fetched, the events are delivered anyway.
+async fn fill_block_timestamps(
+    evm_client: &dyn EvmClientApi,
+    batch: &mut [(Erc721Event, Erc721Metadata)],
+    report: &mut ScanReport,
+    metrics: &TrackerMetrics,
+) {
+    let mut timestamps: HashMap<u64, Option<u64>> = HashMap::new();
+    for (event, _) in batch.iter_mut() {
+        let block_number = match event.block_number {
+            Some(block_number) => block_number,
+            None => continue,
+        };
+        let timestamp = match timestamps.get(&block_number) {
+            Some(timestamp) => *timestamp,
+            None => {
+                let timestamp = match evm_client.get_block_timestamp(block_number).await {
+                    Ok(timestamp) => timestamp,
+                    Err(err) => {
+                        report.record_rpc_error();
+                        metrics.record_rpc_error();
+                        warn!("Encountered an error when get the timestamp of block {}: {:?}.", block_number, err);
+                        None
+                    }
+                };
+                timestamps.insert(block_number, timestamp);
+                timestamp
+            }
+        };
+        event.block_timestamp = timestamp;
+    }
+}
+
+/// Fill the senders of the transactions of the events of a range, each block is fetched once.
+/// The sender stays None if it can not be fetched, the events are delivered anyway.
+async fn fill_transaction_senders(
+    evm_client: &dyn EvmClientApi,
+    batch: &mut [(Erc721Event, Erc721Metadata)],
+    report: &mut ScanReport,
+    metrics: &TrackerMetrics,
+) {
+    let mut senders = TransactionSenders::default();
+    for (event, _) in batch.iter_mut() {
+        if let (Some(block_number), Some(transaction_hash)) = (event.block_number, event.transaction_hash) {
+            event.tx_sender = match senders.sender(evm_client, block_number, &transaction_hash).await {
+                Ok(sender) => sender,
+                Err(err) => {
+                    report.record_rpc_error();
+                    metrics.record_rpc_error();
+                    warn!("Encountered an error when get the sender of transaction {:?}: {:?}.", transaction_hash, err);
+                    None
+                }
+            };
+        }
+    }
+}
+
+/// The transfers of the events to deliver, saved with their collection if it is not saved yet.
+/// The events without a transaction hash or a log index are not saved, they could be saved twice.
+fn transfer_writes(batch: &[(Erc721Event, Erc721Metadata)]) -> Vec<TransferWrite> {
+    let mut transfers = vec![];
+    for (event, _) in batch {
+        let (transaction_hash, log_index) = match (event.transaction_hash, event.log_index) {
+            (Some(transaction_hash), Some(log_index)) => (transaction_hash, log_index),
+            _ => continue,
+        };
+        transfers.push(TransferWrite {
+            address: format!("{:?}", event.address),
+            token_id: event.token_id.to_string(),
+            from: format!("{:?}", event.from),
+            to: format!("{:?}", event.to),
+            block_number: event.block_number.unwrap_or_default(),
+            tx_hash: format!("{:?}", transaction_hash),
+            log_index,
+            timestamp: event.block_timestamp,
+        });
+    }
+    transfers
+}
+
+/// The owner of the token of each event in order, the last transfer of a token wins, even when the blocks
+/// of an older one are scanned again. A burnt token has no owner anymore.
+fn owner_writes(events: &[Erc721Event]) -> Vec<OwnerWrite> {
+    events
+        .iter()
+        .map(|event| OwnerWrite {
+            address: format!("{:?}", event.address),
+            token_id: event.token_id.to_string(),
+            owner: format!("{:?}", event.to),
+            burnt: event.kind() == EventKind::Burn,
+            block_number: event.block_number.unwrap_or_default(),
+            log_index: event.log_index.unwrap_or_default(),
+        })
+        .collect()
+}
+
+/// Fill the marketplace sales of the events of a range, the receipt of each transaction is fetched once.
+/// The sale stays None if the receipt can not be fetched, the events are delivered anyway.
+async fn fill_sales(
+    evm_client: &dyn EvmClientApi,
+    batch: &mut [(Erc721Event, Erc721Metadata)],
+    report: &mut ScanReport,
+    metrics: &TrackerMetrics,
+) {
+    let mut receipts: HashMap<H256, Vec<Log>> = HashMap::new();
+    for (event, _) in batch.iter_mut() {
+        let transaction_hash = match event.transaction_hash {
+            Some(transaction_hash) => transaction_hash,
+            None => continue,
+        };
+        if !receipts.contains_key(&transaction_hash) {
+            let logs = match evm_client.get_transaction_logs(transaction_hash).await {
+                Ok(logs) => logs.unwrap_or_default(),
+                Err(err) => {
+                    report.record_rpc_error();
+                    metrics.record_rpc_error();
+                    warn!("Encountered an error when get the receipt of transaction {:?}: {:?}.", transaction_hash, err);
+                    vec![]
+                }
+            };
+            receipts.insert(transaction_hash, logs);
+        }
+        event.sale = marketplace::find_sale(&receipts[&transaction_hash], &event.address, &event.token_id);
+    }
+}
+
+/// Deliver the events of a range to the callback, retrying the whole batch as configured.
+async fn deliver_events(
+    chain_name: &str,
+    events: Vec<(Erc721Event, Erc721Metadata)>,
+    config: &Erc721TrackerConfig,
+    callback: &mut dyn Erc721EventCallback,
+) -> Result<()> {
+    let mut attempt = 1;
+    loop {
+        let result = callback.on_erc721_events(events.clone()).await;
+        match (result, &config.callback_error_policy) {
+            (Ok(()), _) => return Ok(()),
+            (Err(err), CallbackErrorPolicy::RetryWithBackoff { max_attempts, base_delay })
+                if attempt < *max_attempts =>
+            {
+                let delay = *base_delay * 2u32.saturating_pow(attempt - 1);
+                warn!("The callback failed to process {} ERC721 events from {}: {:?}, retry in {:?}.", events.len(), chain_name, err, delay);
+                config.options.sleep(delay).await;
+                attempt += 1;
+            }
+            (Err(err), _) => return Err(err),
+        }
+    }
+}
+
+async fn get_metadata(
+    evm_client: &dyn EvmClientApi,
+    store: &dyn NftStore,
+    event: &Erc721Event,
+    config: &Erc721TrackerConfig,
+    sources: &MetadataSources<'_>,
+    report: &mut ScanReport,
+) -> Result<Option<(String, String, String)>> {
+    if let Some(metadata) = cached_metadata(sources, event, &config.metadata_refresh) {
+        sources.record_cache_lookup(true);
+        report.record_metadata_lookup(true);
+        return Ok(metadata);
+    }
+    sources.record_cache_lookup(false);
+    let at_block = if config.historical_metadata { event.block_number } else { None };
+    let (token, cached) = save_metadata_to_db_if_not_exists(
+        evm_client,
+        store,
+        &event.address,
+        &event.token_id,
+        at_block,
+        config,
+        sources,
+    )
+    .await?;
+    report.record_metadata_lookup(cached);
+    let collection = match sources.collection(&event.address) {
+        Some(collection) => collection,
+        None => {
+            let collection = cache_collection(store, evm_client.chain_name(), sources, &event.address).await?;
+            // deleted meanwhile, as by another process sharing the database
+            collection.ok_or(Error::DbInconsistent {
+                address: event.address,
+                token_id: event.token_id,
+            })?
+        }
+    };
+    cache_token(store, sources, event, &token).await?;
+
+    // a token without a token uri is delivered with an empty one
+    match collection.name_symbol {
+        Some((name, symbol)) => Ok(Some((name, symbol, token.token_uri.unwrap_or_default()))),
+        None => Ok(None),
+    }
+}
+
+/// The metadata of an event from the cache, without the database. None if the collection or the token
+/// is not cached, or if the token uri is due for a refresh. Some(None) if the collection has no metadata.
+fn cached_metadata(
+    sources: &MetadataSources<'_>,
+    event: &Erc721Event,
+    refresh: &MetadataRefresh,
+) -> Option<Option<(String, String, String)>> {
+    let collection = sources.collection(&event.address)?;
+    let token = sources.token(&event.address, &event.token_id)?;
+    if refresh.is_due(token.fetched_at, now()) {
+        // the token uri is fetched again and its row rewritten, the cached one is stale
+        sources.remove_token(&event.address, &event.token_id);
+        return None;
+    }
+    Some(collection.name_symbol.map(|(name, symbol)| (name, symbol, token.token_uri.unwrap_or_default())))
+}
+
+/// Read a saved collection, and cache it if its lookup is settled: the collections whose last lookup failed
+/// may be looked up again. None if the collection is not saved.
+async fn cache_collection(
+    store: &dyn NftStore,
+    chain: &str,
+    sources: &MetadataSources<'_>,
+    address: &H160,
+) -> Result<Option<CachedCollection>> {
+    let address_string = format!("{:?}", address);
+    let saved = match store.get_collection(chain, &address_string).await? {
+        Some(saved) => saved,
+        None => return Ok(None),
+    };
+    let id = saved.id;
+    let collection = CachedCollection {
+        id,
+        name_symbol: saved.name_symbol(),
+        supports_erc721: store.get_collection_erc721_support(id).await?,
+        contract_uri: store.get_collection_contract_uri(chain, &address_string).await?,
+        implementation: get_implementation(store, chain, &address_string).await?,
+    };
+    if store.get_collection_lookup_failures(id).await?.is_none() {
+        sources.save_collection(*address, collection.clone());
+    }
+    Ok(Some(collection))
+}
+
+/// Cache a saved token, unless its last lookup failed so that it may be looked up again
+async fn cache_token(
+    store: &dyn NftStore,
+    sources: &MetadataSources<'_>,
+    event: &Erc721Event,
+    token: &erc721_db::Token,
+) -> Result<()> {
+    if store.get_token_lookup_failures(token.id).await?.is_some() {
+        sources.remove_token(&event.address, &event.token_id);
+        return Ok(());
+    }
+    let token = CachedToken {
+        token_uri: token.token_uri.clone(),
+        fetched_at: store.get_token_fetched_at(token.id).await?,
+    };
+    sources.save_token(event.address, event.token_id, token);
+    Ok(())
+}
+
+/// Get the metadata from the database if it is cached, or from the chain without saving it.
+/// The failed lookups are retried right away, since they are not recorded.
+async fn get_metadata_read_only(
+    evm_client: &dyn EvmClientApi,
+    store: &dyn NftStore,
+    event: &Erc721Event,
+    config: &Erc721TrackerConfig,
+    report: &mut ScanReport,
+) -> Result<Option<(String, String, String)>> {
+    if is_known_non_erc721(store, evm_client.chain_name(), &event.address).await? {
+        report.record_metadata_lookup(true);
+        return Ok(None);
+    }
+    let at_block = if config.historical_metadata { event.block_number } else { None };
+    let collection = store.get_collection(evm_client.chain_name(), &format!("{:?}", event.address)).await?;
+    let name_symbol = match &collection {
+        Some(collection) if collection.name_symbol().is_some() => collection.name_symbol(),
+        Some(collection) if store.get_collection_lookup_failures(collection.id).await?.is_none() => None,
+        _ => evm_client.get_erc721_name_symbol(&event.address, at_block).await?,
+    };
+    let (name, symbol) = match name_symbol {
+        Some(name_symbol) => name_symbol,
+        None => return Ok(None),
+    };
+
+    let token = match &collection {
+        Some(collection) => store.get_token(collection.id, &event.token_id.to_string()).await?,
+        None => None,
+    };
+    let token = match token {
+        Some(token) => {
+            let fetched_at = store.get_token_fetched_at(token.id).await?;
+            let failed = store.get_token_lookup_failures(token.id).await?.is_some();
+            if failed || config.metadata_refresh.is_due(fetched_at, now()) {
+                None
+            } else {
+                Some(token.token_uri)
+            }
+        }
+        None => None,
+    };
+    report.record_metadata_lookup(token.is_some());
+    let token_uri = match token {
+        Some(token_uri) => token_uri,
+        None => match constructed_token_uri(config, &event.address, &event.token_id) {
+            Some(token_uri) => Some(token_uri),
+            None => evm_client.get_erc721_token_uri(&event.address, &event.token_id, at_block).await?,
+        },
+    };
+    Ok(Some((name, symbol, token_uri.unwrap_or_default())))
+}
+
+/// The metadata fetched in a few batched requests for the events of a range.
+/// Each fetched value is taken by the first event which needs it, the others find it in the database.
+#[derive(Default)]
+struct PrefetchedMetadata {
+    name_symbols: Mutex<HashMap<H160, Result<Option<(String, String)>>>>,
+    token_uris: Mutex<HashMap<(H160, U256), Result<Option<String>>>>,
+}
+
+impl PrefetchedMetadata {
+    fn take_name_symbol(&self, address: &H160) -> Option<Result<Option<(String, String)>>> {
+        self.name_symbols.lock().unwrap().remove(address)
+    }
+
+    fn take_token_uri(&self, address: &H160, token_id: &U256) -> Option<Result<Option<String>>> {
+        self.token_uris.lock().unwrap().remove(&(*address, *token_id))
+    }
+}
+
+/// Where the metadata of the events is found before the database and the chain:
+/// the metadata prefetched for the range, and the metadata cached by the tracker
+#[derive(Default)]
+struct MetadataSources<'a> {
+    prefetched: PrefetchedMetadata,
+    cache: Option<&'a MetadataCache>,
+}
+
+impl MetadataSources<'_> {
+    fn collection(&self, address: &H160) -> Option<CachedCollection> {
+        self.cache.and_then(|cache| cache.collection(address))
+    }
+
+    fn save_collection(&self, address: H160, collection: CachedCollection) {
+        if let Some(cache) = self.cache {
+            cache.save_collection(address, collection);
+        }
+    }
+
+    fn remove_collection(&self, address: &H160) {
+        if let Some(cache) = self.cache {
+            cache.remove_collection(address);
+        }
+    }
+
+    fn token(&self, address: &H160, token_id: &U256) -> Option<CachedToken> {
+        self.cache.and_then(|cache| cache.token(address, token_id))
+    }
+
+    fn save_token(&self, address: H160, token_id: U256, token: CachedToken) {
+        if let Some(cache) = self.cache {
+            cache.save_token(address, token_id, token);
+        }
+    }
+
+    fn remove_token(&self, address: &H160, token_id: &U256) {
+        if let Some(cache) = self.cache {
+            cache.remove_token(address, token_id);
+        }
+    }
+
+    fn record_cache_lookup(&self, hit: bool) {
+        if let Some(cache) = self.cache {
+            cache.record_lookup(hit);
+        }
+    }
+}
+
+/// Fetch the metadata the events of a range have to look up, with a batched request for the collections
+/// and another one for the tokens. The lookups are the ones `save_metadata_to_db_if_not_exists` would do.
+async fn prefetch_metadata(
+    evm_client: &dyn EvmClientApi,
+    store: &dyn NftStore,
+    events: &[Erc721Event],
+    config: &Erc721TrackerConfig,
+) -> Result<PrefetchedMetadata> {
+    // the lookups are in the order of the events, the database is read once per collection and per token
+    let (mut addresses, mut tokens) = (vec![], vec![]);
+    // the saved id of each collection, if any, None if it is known not to be ERC721
+    let mut collections: HashMap<H160, Option<Option<usize>>> = HashMap::new();
+    let mut seen_tokens = HashSet::new();
+    for event in events.iter().filter(|event| config.event_kinds.accepts(event.kind())) {
+        if !seen_tokens.insert((event.address, event.token_id)) {
+            continue;
+        }
+        let collection_id = match collections.get(&event.address) {
+            Some(collection_id) => *collection_id,
+            None => {
+                let collection_id = if is_known_non_erc721(store, evm_client.chain_name(), &event.address).await? {
+                    None
+                } else {
+                    let address = format!("{:?}", event.address);
+                    let collection = store.get_collection(evm_client.chain_name(), &address).await?;
+                    let collection_due = match &collection {
+                        Some(collection) => {
+                            is_collection_lookup_due(store, collection.id, &config.metadata_retry).await?
+                        }
+                        None => true,
+                    };
+                    if collection_due {
+                        addresses.push(event.address);
+                    }
+                    Some(collection.map(|collection| collection.id))
+                };
+                collections.insert(event.address, collection_id);
+                collection_id
+            }
+        };
+        let token_due = match collection_id {
+            None => continue,
+            Some(Some(collection_id)) => {
+                match store.get_token(collection_id, &event.token_id.to_string()).await? {
+                    Some(token) => is_token_lookup_due(store, token.id, config).await?,
+                    None => true,
+                }
+            }
+            Some(None) => true,
+        };
+        if token_due && constructed_token_uri(config, &event.address, &event.token_id).is_none() {
+            tokens.push((event.address, event.token_id));
+        }
+    }
+
+    let name_symbols = if addresses.is_empty() {
+        vec![]
+    } else {
+        evm_client.get_erc721_name_symbols(&addresses).await?
+    };
+    let token_uris = if tokens.is_empty() {
+        vec![]
+    } else {
+        evm_client.get_erc721_token_uris(&tokens).await?
+    };
+    Ok(PrefetchedMetadata {
+        name_symbols: Mutex::new(addresses.into_iter().zip(name_symbols).collect()),
+        token_uris: Mutex::new(tokens.into_iter().zip(token_uris).collect()),
+    })
+}
+
+/// Whether the token uri of a saved token has to be fetched again, a stale one is fetched again by its next event
+async fn is_token_lookup_due(store: &dyn NftStore, id: usize, config: &Erc721TrackerConfig) -> Result<bool> {
+    Ok(match store.get_token_lookup_failures(id).await? {
+        Some((attempts, last_attempt_at)) => config.metadata_retry.is_due(attempts, last_attempt_at, now()),
+        None if store.is_token_stale(id).await? => true,
+        None => config
+            .metadata_refresh
+            .is_due(store.get_token_fetched_at(id).await?, now()),
+    })
+}
+
+/// Whether the name and symbol of a saved collection have to be fetched again
+async fn is_collection_lookup_due(store: &dyn NftStore, id: usize, retry: &MetadataRetry) -> Result<bool> {
+    Ok(match store.get_collection_lookup_failures(id).await? {
+        Some((attempts, last_attempt_at)) => retry.is_due(attempts, last_attempt_at, now()),
+        None => false,
+    })
+}
+
+/// Save the metadata of a token to the database. Its token uri is fetched again when `metadata_refresh` says so,
+/// or when its last lookup failed and `metadata_retry` says so. It returns the saved token, with the id of its
+/// collection, and whether the saved metadata was used.
+/// The metadata in `sources` is used instead of looking it up, the metadata looked up is the one at `at_block` if any.
+async fn save_metadata_to_db_if_not_exists(
+    evm_client: &dyn EvmClientApi,
+    store: &dyn NftStore,
+    address: &H160,
+    token_id: &U256,
+    at_block: Option<u64>,
+    config: &Erc721TrackerConfig,
+    sources: &MetadataSources<'_>,
+) -> Result<(erc721_db::Token, bool)> {
+    let collection_id =
+        save_collection_if_not_exists(evm_client, store, address, &config.metadata_retry, at_block, sources).await?;
+
+    let token = store.get_token(collection_id, &token_id.to_string()).await?;
+    let supports_erc721 = match sources.collection(address) {
+        Some(collection) => collection.supports_erc721,
+        None => store.get_collection_erc721_support(collection_id).await?,
+    };
+    if supports_erc721 == Some(Some(false)) {
+        // not an ERC721 token, its token uri is not looked up
+        let token = match token {
+            Some(token) => token,
+            None => erc721_db::Token {
+                id: store.put_token(collection_id, &token_id.to_string(), None).await?,
+                token_id: token_id.to_string(),
+                collection_id,
+                token_uri: None,
+            },
+        };
+        return Ok((token, true));
+    }
+    if let Some(token) = &token {
+        if !is_token_lookup_due(store, token.id, config).await? {
+            return Ok((token.clone(), true));
+        }
+    }
+
+    let fetched = match constructed_token_uri(config, address, token_id) {
+        Some(token_uri) => Ok(Some(token_uri)),
+        None => match sources.prefetched.take_token_uri(address, token_id) {
+            Some(fetched) => fetched,
+            None => evm_client.get_erc721_token_uri(address, token_id, at_block).await,
+        },
+    };
+    // another event of the token may have saved it while its uri was fetched
+    let id = match store.get_token(collection_id, &token_id.to_string()).await? {
+        Some(saved) => saved.id,
+        None => store.put_token(collection_id, &token_id.to_string(), None).await?,
+    };
+    match fetched {
+        Ok(token_uri) => {
+            if let Some(saved_token_uri) = token.and_then(|token| token.token_uri) {
+                if token_uri.as_ref() != Some(&saved_token_uri) {
+                    info!("The token uri of {:?} {} changed to {:?}.", address, token_id, token_uri);
+                }
+            }
+            let unknown = token_uri.is_none();
+            store.update_token_uri(id, token_uri.clone(), now()).await?;
+            let collection = store.get_collection(evm_client.chain_name(), &format!("{:?}", address)).await?;
+            if unknown && collection.map_or(false, |collection| collection.name_symbol().is_some()) {
+                // the contract has metadata, but `tokenURI` reverted or returned nothing for the token,
+                // as for an unrevealed token: the next events of the token retry the lookup,
+                // unless the contract was destroyed
+                if !check_code_removed(evm_client, store, collection_id, address, sources).await? {
+                    store.record_token_lookup_failure(id, now()).await?;
+                }
+            }
+            let token = erc721_db::Token {
+                id,
+                token_id: token_id.to_string(),
+                collection_id,
+                token_uri,
+            };
+            Ok((token, false))
+        }
+        Err(err) => {
+            // the next events of the token retry the lookup
+            store.record_token_lookup_failure(id, now()).await?;
+            Err(err)
+        }
+    }
+}
+
+/// The token uri of a punk, constructed as the CryptoPunks contract has no `tokenURI`.
+/// None for the other tokens, whose token uri is fetched.
+fn constructed_token_uri(config: &Erc721TrackerConfig, address: &H160, token_id: &U256) -> Option<String> {
+    if *address == CRYPTOPUNKS_ADDRESS && config.tracks_cryptopunks() {
+        Some(erc721_evm::cryptopunk_token_uri(token_id))
+    } else {
+        None
+    }
+}
+
+/// Check with ERC165 if a contract supports ERC721, None if it does not implement ERC165.
+/// An address without code, as an externally owned account, is saved as not supporting ERC721
+/// so that its metadata is never looked up.
+/// The answer is cached with the collection, a new collection is saved with its metadata to look up.
+/// It returns the database id of the collection.
+async fn check_erc721_support(
+    evm_client: &dyn EvmClientApi,
+    store: &dyn NftStore,
+    address: &H160,
+    sources: &MetadataSources<'_>,
+) -> Result<(usize, Option<bool>)> {
+    if let Some(CachedCollection { id, supports_erc721: Some(supports_erc721), .. }) = sources.collection(address) {
+        return Ok((id, supports_erc721));
+    }
+    let address_string = format!("{:?}", address);
+    if let Some(collection) = store.get_collection(evm_client.chain_name(), &address_string).await? {
+        if let Some(supports_erc721) = store.get_collection_erc721_support(collection.id).await? {
+            return Ok((collection.id, supports_erc721));
+        }
+    }
+    let code = if evm_client.is_contract(*address).await? {
+        CollectionCode::Live
+    } else {
+        CollectionCode::Missing
+    };
+    let supports_erc721 = match code {
+        CollectionCode::Live => evm_client.supports_interface(*address, ERC721_INTERFACE_ID).await?,
+        _ => Some(false),
+    };
+    // another event of the collection may have saved it while it was checked
+    let id = match store.get_collection(evm_client.chain_name(), &address_string).await? {
+        Some(collection) => {
+            store.save_collection_erc721_support(collection.id, supports_erc721).await?;
+            collection.id
+        }
+        None => {
+            let chain_name = evm_client.chain_name();
+            store.add_collection_with_erc721_support(chain_name, &address_string, supports_erc721).await?
+        }
+    };
+    store.save_collection_code(id, code).await?;
+    // a cached collection whose support was not checked yet is stale
+    sources.remove_collection(address);
+    if code == CollectionCode::Missing {
+        info!("The address {:?} has no contract code.", address);
+    } else if supports_erc721 == Some(false) {
+        info!("The contract {:?} reports not supporting ERC721.", address);
+    }
+    Ok((id, supports_erc721))
+}
+
+/// Check if the code of a collection was removed since it was first seen, as by a selfdestruct.
+/// A removed collection is saved as not supporting ERC721, so that its metadata is not looked up anymore.
+async fn check_code_removed(
+    evm_client: &dyn EvmClientApi,
+    store: &dyn NftStore,
+    collection_id: usize,
+    address: &H160,
+    sources: &MetadataSources<'_>,
+) -> Result<bool> {
+    if evm_client.is_contract(*address).await? {
+        return Ok(false);
+    }
+    info!("The code of the contract {:?} was removed.", address);
+    sources.remove_collection(address);
+    store.save_collection_code(collection_id, CollectionCode::Removed).await?;
+    store.save_collection_erc721_support(collection_id, Some(false)).await?;
+    Ok(true)
+}
+
+/// The saved EIP-1967 implementation of a collection, None if it is not a proxy or was never resolved
+async fn get_implementation(store: &dyn NftStore, chain: &str, address: &str) -> Result<Option<H160>> {
+    let implementation = store.get_collection_implementation(chain, address).await?;
+    Ok(implementation
+        .and_then(|(implementation, _)| implementation)
+        .and_then(|implementation| implementation.trim_start_matches("0x").parse().ok()))
+}
+
+/// Resolve the EIP-1967 implementations of the collections of a range at its last block, with `resolve_proxies`.
+/// A collection is resolved when it is first seen, then again only if it is a proxy, the addresses without code
+/// are not. A proxy whose implementation changed is checked again with ERC165, so that the interfaces cached
+/// before the upgrade are not used for its events.
+async fn check_proxies(
+    evm_client: &dyn EvmClientApi,
+    store: &dyn NftStore,
+    events: &[Erc721Event],
+    block_number: u64,
+    sources: &MetadataSources<'_>,
+) -> Result<()> {
+    let mut addresses: Vec<H160> = vec![];
+    for event in events {
+        if !addresses.contains(&event.address) {
+            addresses.push(event.address);
+        }
+    }
+    for address in addresses {
+        let (id, _) = check_erc721_support(evm_client, store, &address, sources).await?;
+        if store.get_collection_code(id).await? != Some(CollectionCode::Live) {
+            continue;
+        }
+        let address_string = format!("{:?}", address);
+        let saved = store.get_collection_implementation(evm_client.chain_name(), &address_string).await?;
+        if let Some((None, _)) = saved {
+            // not a proxy, it never becomes one
+            continue;
+        }
+        let implementation = evm_client.resolve_proxy_implementation(address, Some(block_number)).await?;
+        let implementation_string = implementation.map(|implementation| format!("{:?}", implementation));
+        match saved {
+            None if implementation.is_some() => {
+                info!("The collection {:?} is a proxy of {:?}.", address, implementation);
+                sources.remove_collection(&address);
+            }
+            Some((saved, _)) if saved != implementation_string => {
+                info!("The implementation of the proxy {:?} changed to {:?}.", address, implementation);
+                let supports_erc721 = evm_client.supports_interface(address, ERC721_INTERFACE_ID).await?;
+                store.save_collection_erc721_support(id, supports_erc721).await?;
+                sources.remove_collection(&address);
+            }
+            _ => {}
+        }
+        store.save_collection_implementation(id, implementation_string.as_deref(), block_number).await?;
+    }
+    Ok(())
+}
+
+/// Whether the database says a contract reported not supporting ERC721
+async fn is_known_non_erc721(store: &dyn NftStore, chain: &str, address: &H160) -> Result<bool> {
+    match store.get_collection(chain, &format!("{:?}", address)).await? {
+        Some(collection) => Ok(store.get_collection_erc721_support(collection.id).await? == Some(Some(false))),
+        None => Ok(false),
+    }
+}
+
+/// The current time in unix seconds
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |duration| duration.as_secs())
+}
+
+/// Save the name and symbol of a contract to the database, it returns the database id of the collection.
+/// A collection whose last lookup failed is looked up again when `retry` says so.
+/// The name and symbol of a contract which reports with ERC165 not supporting ERC721 are not looked up,
+/// the others are looked up at `at_block` if any.
+async fn save_collection_if_not_exists(
+    evm_client: &dyn EvmClientApi,
+    store: &dyn NftStore,
+    address: &H160,
+    retry: &MetadataRetry,
+    at_block: Option<u64>,
+    sources: &MetadataSources<'_>,
+) -> Result<usize> {
+    // only the collections whose lookup is settled are cached
+    if let Some(collection) = sources.collection(address) {
+        return Ok(collection.id);
+    }
+    let address_string = format!("{:?}", address);
+    if let Some(collection) = store.get_collection(evm_client.chain_name(), &address_string).await? {
+        if !is_collection_lookup_due(store, collection.id, retry).await? {
+            return Ok(collection.id);
+        }
+    }
+    let (id, supports_erc721) = check_erc721_support(evm_client, store, address, sources).await?;
+    if supports_erc721 == Some(false) {
+        store.update_collection_metadata(id, None, None).await?;
+        return Ok(id);
+    }
+
+    let fetched = match sources.prefetched.take_name_symbol(address) {
+        Some(fetched) => fetched,
+        None => evm_client.get_erc721_name_symbol(address, at_block).await,
+    };
+    match fetched {
+        Ok(Some((name, symbol))) => {
+            store.update_collection_metadata(id, Some(name), Some(symbol)).await?;
+            save_contract_uri(evm_client, store, id, address).await?;
+            Ok(id)
+        }
+        // the contract does not provide its metadata, there is nothing to retry
+        Ok(None) => {
+            store.update_collection_metadata(id, None, None).await?;
+            Ok(id)
+        }
+        Err(err) => {
+            // the next events of the collection retry the lookup
+            store.record_collection_lookup_failure(id, now()).await?;
+            Err(err)
+        }
+    }
+}
+
+/// Save the uri of the collection-level metadata of a collection. The collection is delivered anyway
+/// if it can not be fetched, and it is not fetched again.
+async fn save_contract_uri(
+    evm_client: &dyn EvmClientApi,
+    store: &dyn NftStore,
+    id: usize,
+    address: &H160,
+) -> Result<()> {
+    if store.get_collection_contract_uri(evm_client.chain_name(), &format!("{:?}", address)).await?.is_some() {
+        return Ok(());
+    }
+    match evm_client.get_contract_uri(address).await {
+        Ok(contract_uri) => store.save_collection_contract_uri(id, contract_uri.as_deref()).await,
+        Err(err) => {
+            warn!("Encountered an error when get the contract uri of {:?}: {:?}.", address, err);
+            Ok(())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{
+        address, approval_for_all_log, erc4906_batch_metadata_update_log, erc4906_metadata_update_log,
+        erc721_approval_log, erc721_transfer_log, fixture_logs, receipt_logs, rpc_error, FailingCommitStore,
+        MockEvmClient,
+    };
+    use crate::{
+        Error, ErrorPolicy, EventKind, EventKindFilter, Marketplace, MetadataRefresh, MetricsSnapshot, Sale,
+        SqliteStore, StartBlock, TrackerMetrics, CRYPTOPUNKS_ADDRESS, DEAD_ADDRESS,
+    };
+    use crate::TrackerState;
+    use rusqlite::Connection;
+    use std::{
+        sync::{Arc, Mutex},
+        time::Duration,
+    };
+    use tokio_util::sync::CancellationToken;
+
+    struct EthereumErc721EventCallback {
+        events: Vec<Erc721Event>,
+    }
+
+    #[async_trait]
+    impl Erc721EventCallback for EthereumErc721EventCallback {
+        async fn on_erc721_event(
+            &mut self,
+            event: Erc721Event,
+            _name: String,
+            _symbol: String,
+            _total_supply: Option<u128>,
+            _token_uri: String,
+        ) -> Result<()> {
+            self.events.push(event);
+            Ok(())
+        }
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_erc721_metadata_json() {
+        let metadata = Erc721Metadata {
+            name: "Bored Ape Yacht Club".to_owned(),
+            symbol: "BAYC".to_owned(),
+            total_supply: Some(u128::MAX),
+            token_uri: "ipfs://QmeSjSinHpPnmXmspMjwiXyN6zS4E9zccariGR3jxcaWtq/1234".to_owned(),
+            ..Default::default()
+        };
+        let json = serde_json::to_value(&metadata).unwrap();
+        assert_eq!("340282366920938463463374607431768211455", json["total_supply"]);
+        assert_eq!(serde_json::Value::Null, json["owner"]);
+        assert_eq!(metadata, serde_json::from_value(json).unwrap());
+
+        let unknown_supply = Erc721Metadata { total_supply: None, ..metadata };
+        let json = serde_json::to_value(&unknown_supply).unwrap();
+        assert_eq!(serde_json::Value::Null, json["total_supply"]);
+        assert_eq!(unknown_supply, serde_json::from_value(json).unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_track_erc721_events() {
+        // 15 transfers of two collections over 3 blocks, served from memory
+        let (first, second) = (address(1), address(2));
+        let mut client = MockEvmClient::new("Ethereum", 13015400)
+            .with_erc721_collection(first, "First Collection", "FIRST")
+            .with_erc721_collection(second, "Second Collection", "SECOND");
+        for log_index in 0..15 {
+            let collection = if log_index % 3 == 0 { second } else { first };
+            let block_number = 13015344 + log_index % 3;
+            client = client
+                .with_erc721_token_uri(collection, log_index, "https://mock")
+                .with_log(erc721_transfer_log(
+                    collection,
+                    address(0),
+                    address(3),
+                    log_index,
+                    block_number,
+                    log_index,
+                ));
+        }
+
+        let store = SqliteStore::open_in_memory().await.unwrap();
+
+        let mut callback = EthereumErc721EventCallback { events: vec![] };
+        track_erc721_events(
+            &client,
+            &store,
+            13015344,
+            1,
+            Some(13015346),
+            &tiny_intervals(),
+            &mut callback,
+        )
+        .await
+        .unwrap();
+        assert_eq!(15, callback.events.len());
+    }
+
+    #[tokio::test]
+    async fn test_track_erc721_events_with_a_failing_commit() {
+        let collection = address(1);
+        let mut client = MockEvmClient::new("Mock", 100)
+            .with_erc721_collection(collection, "Mock Collection", "MOCK");
+        for block_number in 10..14 {
+            client = client
+                .with_erc721_token_uri(collection, block_number, "https://mock")
+                .with_log(erc721_transfer_log(collection, address(0), address(2), block_number, block_number, 0));
+        }
+        // the commit of the first range fails once
+        let store = FailingCommitStore::new(SqliteStore::open_in_memory().await.unwrap(), 1);
+        let options = ScanOptions {
+            dedup: true,
+            resume: true,
+            ..tiny_intervals()
+        };
+
+        let mut callback = EthereumErc721EventCallback { events: vec![] };
+        let report = track_erc721_events(&client, &store, 10, 2, Some(13), &options, &mut callback)
+            .await
+            .unwrap();
+
+        // the delivered range is committed again instead of being scanned and delivered again
+        let blocks: Vec<u64> = callback.events.iter().map(|event| event.block_number.unwrap()).collect();
+        assert_eq!(vec![10, 11, 12, 13], blocks);
+        assert_eq!(1, report.errors);
+        assert_eq!(vec![(10, 11), (12, 13)], client.scanned_ranges());
+        assert_eq!(Some(13), store.get_scan_progress("Mock").await.unwrap());
+        for event in &callback.events {
+            let transaction_hash = format!("{:?}", event.transaction_hash.unwrap());
+            assert!(store.is_event_delivered("Mock", &transaction_hash, 0).await.unwrap());
+        }
+    }
+
+    fn tiny_intervals() -> ScanOptions {
+        ScanOptions {
+            range_interval: Duration::from_millis(1),
+            idle_interval: Duration::from_millis(1),
+            error_interval: Duration::from_millis(1),
+            ..Default::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn test_track_erc721_events_with_tiny_intervals() {
+        let collection = address(1);
+        let client = MockEvmClient::new("Mock", 100)
+            // the first poll is "too fast", so the idle interval is used once
+            .with_latest_block_numbers(vec![15, 100])
+            .with_erc721_collection(collection, "Mock Collection", "MOCK")
+            .with_erc721_token_uri(collection, 1, "https://mock/1")
+            .with_log(erc721_transfer_log(collection, address(0), address(2), 1, 11, 0))
+            .fail_next_get_logs(Error::Other("mock rpc failure".to_owned()));
+
+        let store = SqliteStore::open_in_memory().await.unwrap();
+
+        let mut callback = EthereumErc721EventCallback { events: vec![] };
+        let start = Instant::now();
+        track_erc721_events(&client, &store, 10, 1, Some(12), &tiny_intervals(), &mut callback)
+            .await
+            .unwrap();
+
+        assert!(start.elapsed() < Duration::from_secs(1));
+        assert_eq!(1, callback.events.len());
+        assert_eq!(4, client.call_count("get_logs"));
+    }
+
+    #[tokio::test]
+    async fn test_track_erc721_events_without_confirmations() {
+        let collection = address(1);
+        let client = MockEvmClient::new("Mock", 100)
+            .with_latest_block_numbers(vec![12, 100])
+            .with_erc721_collection(collection, "Mock Collection", "MOCK")
+            .with_erc721_token_uri(collection, 1, "https://mock/1")
+            .with_log(erc721_transfer_log(collection, address(0), address(2), 1, 12, 0));
+
+        let store = SqliteStore::open_in_memory().await.unwrap();
+
+        // the latest block is scanned at the first poll
+        let options = ScanOptions {
+            confirmations: 0,
+            ..tiny_intervals()
+        };
+        let mut callback = EthereumErc721EventCallback { events: vec![] };
+        let last_processed =
+            track_erc721_events(&client, &store, 12, 1, Some(12), &options, &mut callback)
+                .await
+                .unwrap()
+                .last_processed_block;
+
+        assert_eq!(Some(12), last_processed);
+        assert_eq!(1, callback.events.len());
+        assert_eq!(1, client.call_count("get_latest_block_number"));
+    }
+
+    #[tokio::test]
+    async fn test_track_erc721_events_before_any_confirmed_block() {
+        // a fresh dev chain with fewer blocks than the confirmation depth
+        let client = MockEvmClient::new("Mock", 3);
+        let store = SqliteStore::open_in_memory().await.unwrap();
+
+        let token = CancellationToken::new();
+        let options = ScanOptions {
+            cancellation_token: Some(token.clone()),
+            ..tiny_intervals()
+        };
+        let mut callback = EthereumErc721EventCallback { events: vec![] };
+        let (report, _) = tokio::join!(
+            track_erc721_events(&client, &store, 0, 10, None, &options, &mut callback),
+            async {
+                tokio::time::sleep(Duration::from_millis(20)).await;
+                token.cancel();
+            }
+        );
+
+        assert_eq!(None, report.unwrap().last_processed_block);
+        assert!(client.call_count("get_latest_block_number") > 1);
+        assert_eq!(0, client.call_count("get_logs"));
+    }
+
+    #[tokio::test]
+    async fn test_track_erc721_events_resumable() {
+        let collection = address(1);
+        let mut client = MockEvmClient::new("Mock", 100)
+            .with_erc721_collection(collection, "Mock Collection", "MOCK");
+        for block_number in 10..18 {
+            client = client
+                .with_erc721_token_uri(collection, block_number, "https://mock")
+                .with_log(erc721_transfer_log(collection, address(0), address(2), block_number, block_number, 0));
+        }
+
+        let store = SqliteStore::open_in_memory().await.unwrap();
+        let options = ScanOptions {
+            resume: true,
+            ..tiny_intervals()
+        };
+
+        let mut callback = EthereumErc721EventCallback { events: vec![] };
+        let last_processed =
+            track_erc721_events(&client, &store, 10, 2, Some(13), &options, &mut callback)
+                .await
+                .unwrap()
+                .last_processed_block;
+        assert_eq!(Some(13), last_processed);
+        assert_eq!(Some(13), store.get_scan_progress("Mock").await.unwrap());
+
+        // restart with the same start block
+        let last_processed =
+            track_erc721_events(&client, &store, 10, 2, Some(17), &options, &mut callback)
+                .await
+                .unwrap()
+                .last_processed_block;
+        assert_eq!(Some(17), last_processed);
+        assert_eq!(Some(17), store.get_scan_progress("Mock").await.unwrap());
+
+        assert_eq!(
+            vec![(10, 11), (12, 13), (14, 15), (16, 17)],
+            client.scanned_ranges()
+        );
+        assert_eq!(8, callback.events.len());
+    }
+
+    #[tokio::test]
+    async fn test_track_erc721_events_step_recovers() {
+        let client = MockEvmClient::new("Mock", 100).fail_next_get_logs(rpc_error(
+            -32005,
+            "query returned more than 10000 results",
+        ));
+        let store = SqliteStore::open_in_memory().await.unwrap();
+
+        let options = ScanOptions {
+            step_growth_factor: 2,
+            step_growth_threshold: 2,
+            ..tiny_intervals()
+        };
+        let mut callback = EthereumErc721EventCallback { events: vec![] };
+        track_erc721_events(&client, &store, 0, 4, Some(20), &options, &mut callback)
+            .await
+            .unwrap();
+
+        assert_eq!(
+            vec![(0, 3), (0, 1), (2, 3), (4, 7), (8, 11), (12, 15), (16, 19), (20, 20)],
+            client.scanned_ranges()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_track_erc721_events_with_range_limit_errors() {
+        let client = MockEvmClient::new("Mock", 100)
+            .fail_next_get_logs(rpc_error(-32000, "exceed maximum block range: 5000"))
+            .fail_next_get_logs(rpc_error(-32000, "too many blocks requested"));
+        let store = SqliteStore::open_in_memory().await.unwrap();
+
+        let config = Erc721TrackerConfig::builder()
+            .start_from(0)
+            .step(8)
+            .end_block(15)
+            .options(tiny_intervals())
+            .step_growth(2, 1)
+            .error_policy(ErrorPolicy::default().with_range_limit_errors(|err| {
+                matches!(err, web3::Error::Rpc(e) if e.message.contains("too many blocks"))
+            }))
+            .build()
+            .unwrap();
+        let mut callback = EthereumErc721EventCallback { events: vec![] };
+        track_erc721_events_with_config(&client, &store, &config, &mut callback)
+            .await
+            .unwrap();
+
+        // both errors halve the step, which grows back after each successful range
+        assert_eq!(
+            vec![(0, 7), (0, 3), (0, 1), (2, 5), (6, 13), (14, 15)],
+            client.scanned_ranges()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_track_erc721_events_with_config() {
+        let collection = address(1);
+        let client = MockEvmClient::new("Mock", 100)
+            .with_erc721_collection(collection, "Mock Collection", "MOCK")
+            .with_erc721_token_uri(collection, 1, "https://mock/1")
+            .with_log(erc721_transfer_log(collection, address(0), address(2), 1, 11, 0));
+        let store = SqliteStore::open_in_memory().await.unwrap();
+
+        let config = Erc721TrackerConfig::builder()
+            .start_from(10)
+            .step(2)
+            .end_block(13)
+            .options(tiny_intervals())
+            .build()
+            .unwrap();
+        let mut callback = EthereumErc721EventCallback { events: vec![] };
+        let last_processed =
+            track_erc721_events_with_config(&client, &store, &config, &mut callback)
+                .await
+                .unwrap()
+                .last_processed_block;
+
+        assert_eq!(Some(13), last_processed);
+        assert_eq!(1, callback.events.len());
+        assert_eq!(vec![(10, 11), (12, 13)], client.scanned_ranges());
+    }
+
+    #[tokio::test]
+    async fn test_track_erc721_events_with_an_invalid_config() {
+        let client = MockEvmClient::new("Mock", 100);
+        let store = SqliteStore::open_in_memory().await.unwrap();
+        let mut callback = EthereumErc721EventCallback { events: vec![] };
+
+        // the compatibility wrapper builds its config without the builder
+        let result = track_erc721_events(&client, &store, 10, 0, Some(20), &tiny_intervals(), &mut callback).await;
+        assert!(matches!(result, Err(Error::InvalidConfig(_))));
+
+        let options = ScanOptions {
+            metadata_concurrency: 0,
+            ..tiny_intervals()
+        };
+        let result = track_erc721_events(&client, &store, 10, 2, Some(20), &options, &mut callback).await;
+        assert!(matches!(result, Err(Error::InvalidConfig(_))));
+        assert!(client.scanned_ranges().is_empty());
+    }
+
+    struct CancellingErc721EventCallback {
+        events: Vec<Erc721Event>,
+        cancel_after: usize,
+        token: CancellationToken,
+    }
+
+    #[async_trait]
+    impl Erc721EventCallback for CancellingErc721EventCallback {
+        async fn on_erc721_event(
+            &mut self,
+            event: Erc721Event,
+            _name: String,
+            _symbol: String,
+            _total_supply: Option<u128>,
+            _token_uri: String,
+        ) -> Result<()> {
+            self.events.push(event);
+            if self.events.len() == self.cancel_after {
+                self.token.cancel();
+            }
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_track_erc721_events_cancelled_between_ranges() {
+        let collection = address(1);
+        let mut client = MockEvmClient::new("Mock", 100)
+            .with_erc721_collection(collection, "Mock Collection", "MOCK");
+        for block_number in 10..20 {
+            client = client
+                .with_erc721_token_uri(collection, block_number, "https://mock")
+                .with_log(erc721_transfer_log(collection, address(0), address(2), block_number, block_number, 0));
+        }
+
+        let store = SqliteStore::open_in_memory().await.unwrap();
+
+        let token = CancellationToken::new();
+        let options = ScanOptions {
+            // the tracker must not wait for the full interval once cancelled
+            range_interval: Duration::from_secs(60),
+            cancellation_token: Some(token.clone()),
+            ..tiny_intervals()
+        };
+        let mut callback = CancellingErc721EventCallback {
+            events: vec![],
+            cancel_after: 2,
+            token,
+        };
+        let start = Instant::now();
+        let last_processed =
+            track_erc721_events(&client, &store, 10, 2, None, &options, &mut callback)
+                .await
+                .unwrap()
+                .last_processed_block;
+
+        assert!(start.elapsed() < Duration::from_secs(1));
+        assert_eq!(Some(11), last_processed);
+        assert_eq!(2, callback.events.len());
+    }
+
+    #[tokio::test]
+    async fn test_track_erc721_events_cancelled_in_a_range() {
+        let collection = address(1);
+        let mut client = MockEvmClient::new("Mock", 100)
+            .with_erc721_collection(collection, "Mock Collection", "MOCK");
+        for block_number in 10..20 {
+            client = client
+                .with_erc721_token_uri(collection, block_number, "https://mock")
+                .with_log(erc721_transfer_log(collection, address(0), address(2), block_number, block_number, 0));
+        }
+
+        let store = SqliteStore::open_in_memory().await.unwrap();
+
+        let token = CancellationToken::new();
+        let options = ScanOptions {
+            cancellation_token: Some(token.clone()),
+            ..tiny_intervals()
+        };
+        let mut callback = CancellingErc721EventCallback {
+            events: vec![],
+            cancel_after: 3,
+            token,
+        };
+        // the third event is in the middle of the range 10 - 14, which is delivered at once
+        let last_processed =
+            track_erc721_events(&client, &store, 10, 5, None, &options, &mut callback)
+                .await
+                .unwrap()
+                .last_processed_block;
+
+        assert_eq!(Some(14), last_processed);
+        assert_eq!(5, callback.events.len());
+    }
+
+    #[tokio::test]
+    async fn test_track_erc721_events_cancelled_while_idle() {
+        let client = MockEvmClient::new("Mock", 100);
+        let store = SqliteStore::open_in_memory().await.unwrap();
+
+        let token = CancellationToken::new();
+        let options = ScanOptions {
+            idle_interval: Duration::from_secs(60),
+            cancellation_token: Some(token.clone()),
+            ..tiny_intervals()
+        };
+        let mut callback = EthereumErc721EventCallback { events: vec![] };
+        let start = Instant::now();
+        let (report, _) = tokio::join!(
+            track_erc721_events(&client, &store, 1000, 1, None, &options, &mut callback),
+            async {
+                tokio::time::sleep(Duration::from_millis(20)).await;
+                token.cancel();
+            }
+        );
+
+        assert!(start.elapsed() < Duration::from_secs(1));
+        assert_eq!(None, report.unwrap().last_processed_block);
+    }
+
+    #[tokio::test]
+    async fn test_track_erc721_events_stops_on_terminal_error() {
+        let client =
+            MockEvmClient::new("Mock", 100).fail_next_get_logs(rpc_error(-32000, "Unauthorized"));
+        let store = SqliteStore::open_in_memory().await.unwrap();
+
+        let mut callback = EthereumErc721EventCallback { events: vec![] };
+        let result =
+            track_erc721_events(&client, &store, 10, 2, None, &tiny_intervals(), &mut callback).await;
+
+        assert!(matches!(result, Err(Error::Web3Error(web3::Error::Rpc(_)))));
+        assert_eq!(1, client.call_count("get_logs"));
+    }
+
+    #[tokio::test]
+    async fn test_track_erc721_events_with_error_policy() {
+        let client = MockEvmClient::new("Mock", 100)
+            .fail_next_get_logs(Error::Other("decode failure".to_owned()));
+        let store = SqliteStore::open_in_memory().await.unwrap();
+
+        let config = Erc721TrackerConfig::builder()
+            .start_from(10)
+            .options(tiny_intervals())
+            .error_policy(ErrorPolicy::new(|err| matches!(err, Error::Other(_))))
+            .build()
+            .unwrap();
+        let mut callback = EthereumErc721EventCallback { events: vec![] };
+        let result = track_erc721_events_with_config(&client, &store, &config, &mut callback).await;
+
+        assert!(matches!(result, Err(Error::Other(_))));
+    }
+
+    #[tokio::test]
+    async fn test_track_erc721_events_stops_after_consecutive_errors() {
+        let client = MockEvmClient::new("Mock", 100)
+            .fail_next_get_logs(Error::Other("mock rpc failure".to_owned()))
+            .fail_next_get_logs(Error::Other("mock rpc failure".to_owned()))
+            .fail_next_get_logs(Error::Other("mock rpc failure".to_owned()));
+        let store = SqliteStore::open_in_memory().await.unwrap();
+
+        let config = Erc721TrackerConfig::builder()
+            .start_from(10)
+            .options(tiny_intervals())
+            .error_policy(ErrorPolicy::default().with_max_consecutive_errors(3))
+            .build()
+            .unwrap();
+        let mut callback = EthereumErc721EventCallback { events: vec![] };
+        let result = track_erc721_events_with_config(&client, &store, &config, &mut callback).await;
+
+        assert!(result.is_err());
+        assert_eq!(3, client.call_count("get_logs"));
+    }
+
+    #[tokio::test]
+    async fn test_track_erc721_events_report() {
+        let collection = address(1);
+        let client = MockEvmClient::new("Mock", 100)
+            .with_erc721_collection(collection, "Mock Collection", "MOCK")
+            .with_erc721_token_uri(collection, 1, "https://mock/1")
+            .with_log(erc721_transfer_log(collection, address(0), address(2), 1, 11, 0))
+            // the token has no uri, so it is not delivered
+            .with_log(erc721_transfer_log(collection, address(0), address(2), 2, 12, 0))
+            .fail_next_get_logs(Error::Other("mock rpc failure".to_owned()));
+        let store = SqliteStore::open_in_memory().await.unwrap();
+
+        let mut callback = EthereumErc721EventCallback { events: vec![] };
+        let report =
+            track_erc721_events(&client, &store, 10, 2, Some(13), &tiny_intervals(), &mut callback)
+                .await
+                .unwrap();
+
+        assert_eq!(
+            ScanReport {
+                last_processed_block: Some(13),
+                blocks_scanned: 4,
+                events_decoded: 2,
+                events_delivered: 1,
+                transfers_rejected: 0,
+                metadata_cache_hits: 0,
+                metadata_cache_misses: 2,
+                errors: 1,
+                rpc_errors: 1,
+                duration: report.duration,
+            },
+            report
+        );
+    }
+
+    #[tokio::test]
+    async fn test_track_erc721_events_final_partial_range() {
+        let collection = address(1);
+        let client = MockEvmClient::new("Mock", 1000)
+            .with_erc721_collection(collection, "Mock Collection", "MOCK")
+            .with_erc721_token_uri(collection, 1, "https://mock/1")
+            .with_log(erc721_transfer_log(collection, address(0), address(2), 1, 104, 0))
+            // after end_block
+            .with_log(erc721_transfer_log(collection, address(0), address(2), 1, 106, 0));
+        let store = SqliteStore::open_in_memory().await.unwrap();
+
+        let mut callback = EthereumErc721EventCallback { events: vec![] };
+        let last_processed = track_erc721_events(
+            &client,
+            &store,
+            100,
+            10,
+            Some(105),
+            &tiny_intervals(),
+            &mut callback,
+        )
+        .await
+        .unwrap()
+        .last_processed_block;
+
+        assert_eq!(Some(105), last_processed);
+        assert_eq!(1, callback.events.len());
+        assert_eq!(Some(104), callback.events[0].block_number);
+        assert_eq!(vec![(100, 105)], client.scanned_ranges());
+    }
+
+    #[tokio::test]
+    async fn test_track_erc721_events_dedup() {
+        let collection = address(1);
+        let client = MockEvmClient::new("Mock", 100)
+            .with_erc721_collection(collection, "Mock Collection", "MOCK")
+            .with_erc721_token_uri(collection, 1, "https://mock/1")
+            .with_erc721_token_uri(collection, 2, "https://mock/2")
+            .with_log(erc721_transfer_log(collection, address(0), address(2), 1, 11, 0))
+            .with_log(erc721_transfer_log(collection, address(0), address(2), 2, 11, 1));
+        let store = SqliteStore::open_in_memory().await.unwrap();
+
+        let options = ScanOptions {
+            dedup: true,
+            ..tiny_intervals()
+        };
+        let mut callback = EthereumErc721EventCallback { events: vec![] };
+        track_erc721_events(&client, &store, 10, 2, Some(11), &options, &mut callback)
+            .await
+            .unwrap();
+        // restart from an earlier block
+        let report = track_erc721_events(&client, &store, 10, 2, Some(11), &options, &mut callback)
+            .await
+            .unwrap();
+
+        assert_eq!(0, report.events_delivered);
+        assert_eq!(2, callback.events.len());
+        let log_indexes: Vec<Option<u64>> = callback.events.iter().map(|e| e.log_index).collect();
+        assert_eq!(vec![Some(0), Some(1)], log_indexes);
+    }
+
+    #[tokio::test]
+    async fn test_track_erc721_events_with_mixed_transfers() {
+        let mut client = MockEvmClient::new("Mock", 13015344);
+        for log in fixture_logs(include_str!("./fixtures/mixed_transfer_logs.json")) {
+            client = client
+                .with_erc721_collection(log.address, "Mock Collection", "MOCK")
+                .with_erc721_token_uri(log.address, 1234, "https://mock/1234")
+                .with_log(log);
+        }
+        let store = SqliteStore::open_in_memory().await.unwrap();
+
+        let mut callback = EthereumErc721EventCallback { events: vec![] };
+        let report = track_erc721_events(&client, &store, 13015340, 5, Some(13015344), &tiny_intervals(), &mut callback)
+            .await
+            .unwrap();
+
+        // the CryptoKitties transfer is decoded from its data by default
+        let token_ids: Vec<U256> = callback.events.iter().map(|event| event.token_id).collect();
+        assert_eq!(vec![U256::from(1936010), U256::from(1234)], token_ids);
+        assert_eq!(2, report.events_decoded);
+        assert_eq!(3, report.transfers_rejected);
+    }
+
+    #[tokio::test]
+    async fn test_track_erc721_events_in_block_order() {
+        // the token is minted and flipped twice in the block 12, the node answers its logs out of order
+        let collection = address(1);
+        let client = MockEvmClient::new("Mock", 100)
+            .with_erc721_collection(collection, "Mock Collection", "MOCK")
+            .with_erc721_token_uri(collection, 1, "https://mock/1")
+            .with_erc721_token_uri(collection, 2, "https://mock/2")
+            .with_log(erc721_transfer_log(collection, address(3), address(4), 1, 12, 2))
+            .with_log(erc721_transfer_log(collection, address(0), address(2), 1, 12, 0))
+            .with_log(erc721_transfer_log(collection, address(0), address(2), 2, 11, 5))
+            .with_log(erc721_transfer_log(collection, address(2), address(3), 1, 12, 1));
+        let store = SqliteStore::open_in_memory().await.unwrap();
+
+        let mut callback = EthereumErc721EventCallback { events: vec![] };
+        track_erc721_events(&client, &store, 10, 5, Some(14), &tiny_intervals(), &mut callback)
+            .await
+            .unwrap();
+
+        let order: Vec<(Option<u64>, Option<u64>, Option<u64>)> = callback
+            .events
+            .iter()
+            .map(|event| (event.block_number, event.transaction_index, event.log_index))
+            .collect();
+        assert_eq!(
+            vec![
+                (Some(11), Some(5), Some(5)),
+                (Some(12), Some(0), Some(0)),
+                (Some(12), Some(1), Some(1)),
+                (Some(12), Some(2), Some(2)),
+            ],
+            order
+        );
+        let owners: Vec<H160> = callback.events.iter().map(|event| event.to).collect();
+        assert_eq!(vec![address(2), address(2), address(3), address(4)], owners);
+    }
+
+    #[tokio::test]
+    async fn test_track_erc721_events_with_address_allowlist() {
+        let mut client = MockEvmClient::new("Mock", 100);
+        for n in 1..5 {
+            client = client
+                .with_erc721_collection(address(n), "Mock Collection", "MOCK")
+                .with_erc721_token_uri(address(n), 1, "https://mock/1")
+                .with_log(erc721_transfer_log(address(n), address(0), address(9), 1, 10 + n, 0));
+        }
+        let store = SqliteStore::open_in_memory().await.unwrap();
+
+        let config = Erc721TrackerConfig::builder()
+            .start_from(10)
+            .step(10)
+            .end_block(19)
+            .options(tiny_intervals())
+            .address_allowlist(vec![address(4), address(1), address(3)])
+            .max_addresses_per_request(2)
+            .build()
+            .unwrap();
+        let mut callback = EthereumErc721EventCallback { events: vec![] };
+        track_erc721_events_with_config(&client, &store, &config, &mut callback)
+            .await
+            .unwrap();
+
+        let addresses: Vec<H160> = callback.events.iter().map(|e| e.address).collect();
+        assert_eq!(vec![address(1), address(3), address(4)], addresses);
+        // the allowlist is requested in two chunks
+        assert_eq!(2, client.call_count("get_logs_of_contracts"));
+        assert_eq!(0, client.call_count("get_logs"));
+        // the other contracts never reach the database
+        let excluded = format!("{:?}", address(2));
+        assert!(store.get_collection("Mock", &excluded).await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_track_erc721_events_with_denylist() {
+        let spam = address(2);
+        let client = MockEvmClient::new("Mock", 100)
+            .with_erc721_collection(address(1), "Mock Collection", "MOCK")
+            .with_erc721_token_uri(address(1), 1, "https://mock/1")
+            .with_erc721_collection(spam, "Spam", "SPAM")
+            .with_erc721_token_uri(spam, 1, "https://spam/1")
+            .with_log(erc721_transfer_log(address(1), address(0), address(9), 1, 11, 0))
+            .with_log(erc721_transfer_log(spam, address(0), address(9), 1, 11, 1));
+        let store = SqliteStore::open_in_memory().await.unwrap();
+
+        let config = Erc721TrackerConfig::builder()
+            .start_from(10)
+            .step(2)
+            .end_block(11)
+            .options(tiny_intervals())
+            .denylist(vec![spam])
+            .build()
+            .unwrap();
+        let mut callback = EthereumErc721EventCallback { events: vec![] };
+        track_erc721_events_with_config(&client, &store, &config, &mut callback)
+            .await
+            .unwrap();
+
+        assert_eq!(1, callback.events.len());
+        assert_eq!(address(1), callback.events[0].address);
+        assert_eq!(1, client.call_count("get_erc721_name_symbol"));
+        assert_eq!(1, client.call_count("get_erc721_token_uri"));
+        let spam = format!("{:?}", spam);
+        assert!(store.get_collection("Mock", &spam).await.unwrap().is_none());
+    }
+
+    async fn track_with_event_kinds(event_kinds: EventKindFilter) -> Vec<EventKind> {
+        let collection = address(1);
+        let client = MockEvmClient::new("Mock", 100)
+            .with_erc721_collection(collection, "Mock Collection", "MOCK")
+            .with_erc721_token_uri(collection, 1, "https://mock/1")
+            // mint, transfer and burn
+            .with_log(erc721_transfer_log(collection, address(0), address(2), 1, 11, 0))
+            .with_log(erc721_transfer_log(collection, address(2), address(3), 1, 12, 0))
+            .with_log(erc721_transfer_log(collection, address(3), address(0), 1, 13, 0));
+        let store = SqliteStore::open_in_memory().await.unwrap();
+
+        let config = Erc721TrackerConfig::builder()
+            .start_from(10)
+            .step(10)
+            .end_block(19)
+            .options(tiny_intervals())
+            .event_kinds(event_kinds)
+            .build()
+            .unwrap();
+        let mut callback = EthereumErc721EventCallback { events: vec![] };
+        track_erc721_events_with_config(&client, &store, &config, &mut callback)
+            .await
+            .unwrap();
+
+        callback.events.iter().map(|event| event.kind()).collect()
+    }
+
+    #[tokio::test]
+    async fn test_track_erc721_events_with_event_kinds() {
+        let kinds = track_with_event_kinds(EventKindFilter::All).await;
+        assert_eq!(vec![EventKind::Mint, EventKind::Transfer, EventKind::Burn], kinds);
+
+        let kinds = track_with_event_kinds(EventKindFilter::MintsOnly).await;
+        assert_eq!(vec![EventKind::Mint], kinds);
+
+        let kinds = track_with_event_kinds(EventKindFilter::BurnsOnly).await;
+        assert_eq!(vec![EventKind::Burn], kinds);
+
+        let kinds = track_with_event_kinds(EventKindFilter::TransfersOnly).await;
+        assert_eq!(vec![EventKind::Transfer], kinds);
+    }
+
+    #[tokio::test]
+    async fn test_track_erc721_events_with_burn_addresses() {
+        let collection = address(1);
+        let client = MockEvmClient::new("Mock", 100)
+            .with_erc721_collection(collection, "Mock Collection", "MOCK")
+            .with_erc721_token_uri(collection, 1, "https://mock/1")
+            .with_log(erc721_transfer_log(collection, address(0), address(2), 1, 11, 0))
+            .with_log(erc721_transfer_log(collection, address(2), address(9), 1, 12, 0))
+            .with_log(erc721_transfer_log(collection, address(0), address(2), 2, 13, 0))
+            .with_log(erc721_transfer_log(collection, address(2), DEAD_ADDRESS, 2, 14, 0));
+        let store = SqliteStore::open_in_memory().await.unwrap();
+
+        // address(9) is a burn address, and the default dead address is not one anymore
+        let config = Erc721TrackerConfig::builder()
+            .start_from(10)
+            .step(10)
+            .end_block(19)
+            .options(tiny_intervals())
+            .event_kinds(EventKindFilter::BurnsOnly)
+            .burn_addresses(vec![address(9)])
+            .build()
+            .unwrap();
+        let mut callback = EthereumErc721EventCallback { events: vec![] };
+        track_erc721_events_with_config(&client, &store, &config, &mut callback)
+            .await
+            .unwrap();
+
+        assert_eq!(1, callback.events.len());
+        assert_eq!(address(9), callback.events[0].to);
+        assert_eq!(EventKind::Burn, callback.events[0].kind);
+    }
+
+    #[tokio::test]
+    async fn test_track_erc721_events_with_raw_logs() {
+        let collection = address(1);
+        let mint = erc721_transfer_log(collection, address(0), address(2), 1, 11, 0);
+        let client = MockEvmClient::new("Mock", 100)
+            .with_erc721_collection(collection, "Mock Collection", "MOCK")
+            .with_erc721_token_uri(collection, 1, "https://mock/1")
+            .with_log(mint.clone());
+        let builder = Erc721TrackerConfig::builder()
+            .start_from(10)
+            .step(10)
+            .end_block(19)
+            .options(tiny_intervals());
+
+        let store = SqliteStore::open_in_memory().await.unwrap();
+        let mut callback = EthereumErc721EventCallback { events: vec![] };
+        track_erc721_events_with_config(&client, &store, &builder.clone().build().unwrap(), &mut callback)
+            .await
+            .unwrap();
+        assert_eq!(None, callback.events[0].raw);
+
+        let store = SqliteStore::open_in_memory().await.unwrap();
+        let mut callback = EthereumErc721EventCallback { events: vec![] };
+        let config = builder.keep_raw_logs(true).build().unwrap();
+        track_erc721_events_with_config(&client, &store, &config, &mut callback)
+            .await
+            .unwrap();
+        let event = &callback.events[0];
+        let raw = event.raw.as_ref().unwrap();
+        assert_eq!(Some(&mint), event.raw.as_ref());
+        assert_eq!(event.transaction_hash, raw.transaction_hash);
+        assert_eq!(
+            vec![H256::from(event.from), H256::from(event.to), H256::from_low_u64_be(event.token_id.as_u64())],
+            raw.topics[1..].to_vec()
+        );
+        assert_eq!(Some(false), raw.removed);
+    }
+
+    #[tokio::test]
+    async fn test_track_erc721_events_filtered_before_metadata() {
+        // no mint in the fixture, and no metadata is fetched at all
+        let collection = address(1);
+        let client = MockEvmClient::new("Mock", 100)
+            .with_erc721_collection(collection, "Mock Collection", "MOCK")
+            .with_erc721_token_uri(collection, 1, "https://mock/1")
+            .with_log(erc721_transfer_log(collection, address(2), address(3), 1, 12, 0));
+        let store = SqliteStore::open_in_memory().await.unwrap();
+
+        let config = Erc721TrackerConfig::builder()
+            .start_from(10)
+            .step(10)
+            .end_block(19)
+            .options(tiny_intervals())
+            .event_kinds(EventKindFilter::MintsOnly)
+            .build()
+            .unwrap();
+        let mut callback = EthereumErc721EventCallback { events: vec![] };
+        track_erc721_events_with_config(&client, &store, &config, &mut callback)
+            .await
+            .unwrap();
+
+        assert!(callback.events.is_empty());
+        assert_eq!(0, client.call_count("get_erc721_name_symbol"));
+        assert_eq!(0, client.call_count("get_erc721_token_uri"));
+    }
+
+    struct ReorgingErc721EventCallback {
+        client: Arc<MockEvmClient>,
+        reorg_at: u64,
+        delivered_blocks: Vec<u64>,
+        removed: Vec<RangeInclusive<u64>>,
+    }
+
+    #[async_trait]
+    impl Erc721EventCallback for ReorgingErc721EventCallback {
+        async fn on_erc721_event(
+            &mut self,
+            event: Erc721Event,
+            _name: String,
+            _symbol: String,
+            _total_supply: Option<u128>,
+            _token_uri: String,
+        ) -> Result<()> {
+            let block_number = event.block_number.unwrap();
+            self.delivered_blocks.push(block_number);
+            if block_number == 15 && self.removed.is_empty() {
+                self.client.reorg(self.reorg_at);
+            }
+            Ok(())
+        }
+
+        async fn on_erc721_events_removed(&mut self, block_range: RangeInclusive<u64>) -> Result<()> {
+            self.removed.push(block_range);
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_track_erc721_events_with_reorg() {
+        let collection = address(1);
+        let mut client = MockEvmClient::new("Mock", 100)
+            .with_erc721_collection(collection, "Mock Collection", "MOCK");
+        for block_number in 10..18 {
+            client = client
+                .with_erc721_token_uri(collection, block_number, "https://mock")
+                .with_log(erc721_transfer_log(collection, address(0), address(2), block_number, block_number, 0));
+        }
+        let client = Arc::new(client);
+        let store = SqliteStore::open_in_memory().await.unwrap();
+
+        let options = ScanOptions {
+            detect_reorgs: true,
+            dedup: true,
+            ..tiny_intervals()
+        };
+        // the blocks from 13 are replaced after the range 14 - 15 is delivered
+        let mut callback = ReorgingErc721EventCallback {
+            client: client.clone(),
+            reorg_at: 13,
+            delivered_blocks: vec![],
+            removed: vec![],
+        };
+        let last_processed =
+            track_erc721_events(&*client, &store, 10, 2, Some(17), &options, &mut callback)
+                .await
+                .unwrap()
+                .last_processed_block;
+
+        assert_eq!(Some(17), last_processed);
+        assert_eq!(vec![12..=15], callback.removed);
+        // the range 12 - 13 is partially orphaned, so it is rescanned too.
+        // The range 16 - 17 was fetched ahead before the reorg was detected.
+        assert_eq!(
+            vec![(10, 11), (12, 13), (14, 15), (16, 17), (12, 13), (14, 15), (16, 17)],
+            client.scanned_ranges()
+        );
+        // the dedup records of the removed blocks are forgotten
+        assert_eq!(vec![10, 11, 12, 13, 14, 15, 12, 13, 14, 15, 16, 17], callback.delivered_blocks);
+    }
+
+    #[tokio::test]
+    async fn test_track_erc721_events_with_reorg_forgets_the_orphaned_owners() {
+        let collection = address(1);
+        let (alice, bob, carol) = (address(2), address(3), address(4));
+        // the token 1 goes to carol in the canonical chain, the transfer to bob at block 15 is orphaned
+        let client = Arc::new(
+            MockEvmClient::new("Mock", 100)
+                .with_erc721_collection(collection, "Mock Collection", "MOCK")
+                .with_erc721_token_uri(collection, 1, "https://mock/1")
+                .with_log(erc721_transfer_log(collection, address(0), alice, 1, 11, 0))
+                .with_log(erc721_transfer_log(collection, alice, carol, 1, 14, 0))
+                .with_orphaned_log(erc721_transfer_log(collection, carol, bob, 1, 15, 0)),
+        );
+        let store = SqliteStore::open_in_memory().await.unwrap();
+
+        let config = Erc721TrackerConfig::builder()
+            .start_from(10)
+            .step(2)
+            .end_block(17)
+            .options(ScanOptions {
+                detect_reorgs: true,
+                ..tiny_intervals()
+            })
+            .save_owners(true)
+            .build()
+            .unwrap();
+        // the blocks from 13 are replaced after the range 14 - 15 is delivered
+        let mut callback = ReorgingErc721EventCallback {
+            client: client.clone(),
+            reorg_at: 13,
+            delivered_blocks: vec![],
+            removed: vec![],
+        };
+        track_erc721_events_with_config(&*client, &store, &config, &mut callback)
+            .await
+            .unwrap();
+
+        assert_eq!(vec![12..=15], callback.removed);
+        assert_eq!(vec![11, 14, 15, 14], callback.delivered_blocks);
+        // the orphaned transfer to bob is newer than the canonical one, it does not win
+        let owner = store.get_token_owner("Mock", &format!("{:?}", collection), "1").await.unwrap();
+        assert_eq!(Some(format!("{:?}", carol)), owner);
+    }
+
+    #[tokio::test]
+    async fn test_track_erc721_events_with_strict_block_hashes() {
+        let collection = address(1);
+        let mut client = MockEvmClient::new("Mock", 100)
+            .with_erc721_collection(collection, "Mock Collection", "MOCK");
+        for block_number in 10..18 {
+            client = client
+                .with_erc721_token_uri(collection, block_number, "https://mock")
+                .with_log(erc721_transfer_log(collection, address(0), address(2), block_number, block_number, 0));
+        }
+        let client = Arc::new(client);
+        let store = SqliteStore::open_in_memory().await.unwrap();
+
+        let options = ScanOptions {
+            strict_block_hashes: true,
+            dedup: true,
+            ..tiny_intervals()
+        };
+        // the blocks from 13 are replaced after the range 14 - 15 is delivered
+        let mut callback = ReorgingErc721EventCallback {
+            client: client.clone(),
+            reorg_at: 13,
+            delivered_blocks: vec![],
+            removed: vec![],
+        };
+        let last_processed =
+            track_erc721_events(&*client, &store, 10, 2, Some(17), &options, &mut callback)
+                .await
+                .unwrap()
+                .last_processed_block;
+
+        assert_eq!(Some(17), last_processed);
+        // the hash of every block is stored, so only the orphaned blocks are rescanned
+        assert_eq!(vec![13..=15], callback.removed);
+        assert_eq!(vec![10, 11, 12, 13, 14, 15, 13, 14, 15, 16, 17], callback.delivered_blocks);
+        // the logs are only queried by block hash
+        assert!(client.scanned_ranges().is_empty());
+        assert!(client.call_count("get_logs_by_block_hash") >= 13);
+    }
+
+    struct SharedErc721EventCallback {
+        delivered_blocks: Arc<Mutex<Vec<u64>>>,
+    }
+
+    #[async_trait]
+    impl Erc721EventCallback for SharedErc721EventCallback {
+        async fn on_erc721_event(
+            &mut self,
+            event: Erc721Event,
+            _name: String,
+            _symbol: String,
+            _total_supply: Option<u128>,
+            _token_uri: String,
+        ) -> Result<()> {
+            self.delivered_blocks.lock().unwrap().push(event.block_number.unwrap());
+            Ok(())
+        }
+    }
+
+    async fn wait_until<F: Fn() -> bool>(condition: F) {
+        let start = Instant::now();
+        while !condition() {
+            assert!(start.elapsed() < Duration::from_secs(5), "timed out");
+            tokio::time::sleep(Duration::from_millis(1)).await;
+        }
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_spawn_erc721_tracker_paused() {
+        let collection = address(1);
+        let mut client = MockEvmClient::new("Mock", 1000)
+            .with_erc721_collection(collection, "Mock Collection", "MOCK");
+        for block_number in 10..100 {
+            client = client
+                .with_erc721_token_uri(collection, block_number, "https://mock")
+                .with_log(erc721_transfer_log(collection, address(0), address(2), block_number, block_number, 0));
+        }
+        let store = SqliteStore::open_in_memory().await.unwrap();
+
+        let token = CancellationToken::new();
+        let config = Erc721TrackerConfig::builder()
+            .start_from(10)
+            .step(1)
+            .options(tiny_intervals())
+            .range_interval(Duration::from_millis(5))
+            .backfill(false)
+            .cancellation_token(token.clone())
+            .build()
+            .unwrap();
+        let delivered_blocks = Arc::new(Mutex::new(vec![]));
+        let callback = SharedErc721EventCallback {
+            delivered_blocks: delivered_blocks.clone(),
+        };
+        let handle = spawn_erc721_tracker(Arc::new(client), Arc::new(store), config, Box::new(callback));
+
+        wait_until(|| delivered_blocks.lock().unwrap().len() >= 3).await;
+        handle.pause();
+        wait_until(|| handle.status().state == TrackerState::Paused).await;
+        let paused_at = handle.status().from;
+        let delivered = delivered_blocks.lock().unwrap().len();
+
+        // no new events while paused
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert_eq!(delivered, delivered_blocks.lock().unwrap().len());
+        assert_eq!(paused_at, handle.status().from);
+        assert_eq!(1, handle.status().step);
+
+        handle.resume();
+        wait_until(|| delivered_blocks.lock().unwrap().len() > delivered).await;
+        token.cancel();
+        handle.join().await.unwrap();
+
+        // the scan continues from where it was paused, nothing is skipped
+        let delivered_blocks = delivered_blocks.lock().unwrap();
+        assert_eq!(paused_at, delivered_blocks[delivered]);
+        let expected: Vec<u64> = (10..10 + delivered_blocks.len() as u64).collect();
+        assert_eq!(expected, *delivered_blocks);
+    }
+
+    #[tokio::test]
+    async fn test_spawn_erc721_tracker_waiting_for_the_store() {
+        let store = SqliteStore::open_in_memory().await.unwrap();
+        let started = Instant::now();
+        // a slow transaction of another task holds the database, the test runtime has a single thread
+        let busy = store.clone();
+        let slow = tokio::spawn(async move {
+            busy.call(move |conn| {
+                let tx = conn.transaction()?;
+                std::thread::sleep(Duration::from_millis(300));
+                erc721_db::save_scan_progress(&tx, "Other", 100)?;
+                tx.commit()?;
+                Ok(started.elapsed())
+            })
+            .await
+        });
+        let config = Erc721TrackerConfig::builder()
+            .start_from(10)
+            .step(5)
+            .end_block(14)
+            .options(tiny_intervals())
+            .build()
+            .unwrap();
+        let delivered_blocks = Arc::new(Mutex::new(vec![]));
+        let callback = SharedErc721EventCallback {
+            delivered_blocks: delivered_blocks.clone(),
+        };
+        let client = Arc::new(client_with_events(10..15));
+        let handle = spawn_erc721_tracker(client, Arc::new(store), config, Box::new(callback));
+
+        let mut ticks = 0;
+        while started.elapsed() < Duration::from_millis(200) {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+            ticks += 1;
+        }
+        handle.join().await.unwrap();
+        let finished = started.elapsed();
+        let slow = slow.await.unwrap().unwrap();
+
+        // the timer went on while the tracker waited for the database, which it needs to deliver the events
+        assert!(ticks >= 5, "{}", ticks);
+        assert!(slow < finished, "{:?} {:?}", slow, finished);
+        assert_eq!((10..15).collect::<Vec<u64>>(), *delivered_blocks.lock().unwrap());
+    }
+
+    /// Fails the first `failures` times it is called for an event of `failing_block`
+    struct FlakyErc721EventCallback {
+        failing_block: u64,
+        failures: usize,
+        calls: usize,
+        delivered_blocks: Vec<u64>,
+    }
+
+    #[async_trait]
+    impl Erc721EventCallback for FlakyErc721EventCallback {
+        async fn on_erc721_event(
+            &mut self,
+            event: Erc721Event,
+            _name: String,
+            _symbol: String,
+            _total_supply: Option<u128>,
+            _token_uri: String,
+        ) -> Result<()> {
+            let block_number = event.block_number.unwrap();
+            if block_number == self.failing_block {
+                self.calls += 1;
+                if self.calls <= self.failures {
+                    return Err(Error::Other("downstream unavailable".to_owned()));
+                }
+            }
+            self.delivered_blocks.push(block_number);
+            Ok(())
+        }
+    }
+
+    fn client_with_events(blocks: std::ops::Range<u64>) -> MockEvmClient {
+        let collection = address(1);
+        let mut client = MockEvmClient::new("Mock", 100)
+            .with_erc721_collection(collection, "Mock Collection", "MOCK");
+        for block_number in blocks {
+            client = client
+                .with_erc721_token_uri(collection, block_number, "https://mock")
+                .with_log(erc721_transfer_log(collection, address(0), address(2), block_number, block_number, 0));
+        }
+        client
+    }
+
+    async fn track_with_callback_error_policy(
+        client: &MockEvmClient,
+        policy: CallbackErrorPolicy,
+        callback: &mut FlakyErc721EventCallback,
+    ) -> Result<ScanReport> {
+        let store = SqliteStore::open_in_memory().await.unwrap();
+        let config = Erc721TrackerConfig::builder()
+            .start_from(10)
+            .step(4)
+            .end_block(13)
+            .options(tiny_intervals())
+            .callback_error_policy(policy)
+            .build()
+            .unwrap();
+        track_erc721_events_with_config(client, &store, &config, callback).await
+    }
+
+    #[tokio::test]
+    async fn test_track_erc721_events_retry_callback() {
+        let client = client_with_events(10..14);
+        let mut callback = FlakyErc721EventCallback {
+            failing_block: 11,
+            failures: 2,
+            calls: 0,
+            delivered_blocks: vec![],
+        };
+        let policy = CallbackErrorPolicy::RetryWithBackoff {
+            max_attempts: 3,
+            base_delay: Duration::from_millis(1),
+        };
+        let report = track_with_callback_error_policy(&client, policy, &mut callback)
+            .await
+            .unwrap();
+
+        // the batch of the range is retried as a whole
+        assert_eq!(3, callback.calls);
+        assert_eq!(vec![10, 12, 13, 10, 12, 13, 10, 11, 12, 13], callback.delivered_blocks);
+        assert_eq!(4, report.events_delivered);
+        assert_eq!(vec![(10, 13)], client.scanned_ranges());
+    }
+
+    #[tokio::test]
+    async fn test_track_erc721_events_retry_callback_exhausted() {
+        let client = client_with_events(10..14);
+        let mut callback = FlakyErc721EventCallback {
+            failing_block: 11,
+            failures: 3,
+            calls: 0,
+            delivered_blocks: vec![],
+        };
+        let policy = CallbackErrorPolicy::RetryWithBackoff {
+            max_attempts: 2,
+            base_delay: Duration::from_millis(1),
+        };
+        let report = track_with_callback_error_policy(&client, policy, &mut callback)
+            .await
+            .unwrap();
+
+        // the tracker does not move past the range before the callback accepts its batch
+        assert_eq!(vec![(10, 13), (10, 13)], client.scanned_ranges());
+        assert_eq!(4, callback.calls);
+        assert!(callback.delivered_blocks.ends_with(&[10, 11, 12, 13]));
+        assert_eq!(Some(13), report.last_processed_block);
+    }
+
+    #[tokio::test]
+    async fn test_track_erc721_events_abort_on_callback_error() {
+        let client = client_with_events(10..14);
+        let mut callback = FlakyErc721EventCallback {
+            failing_block: 11,
+            failures: usize::MAX,
+            calls: 0,
+            delivered_blocks: vec![],
+        };
+        let result =
+            track_with_callback_error_policy(&client, CallbackErrorPolicy::Abort, &mut callback).await;
+
+        // the default batch implementation still delivers the events after the failing one
+        assert!(matches!(result, Err(Error::Other(_))));
+        assert_eq!(1, callback.calls);
+        assert_eq!(vec![10, 12, 13], callback.delivered_blocks);
+    }
+
+    #[tokio::test]
+    async fn test_track_erc721_events_abort_rolls_back_the_range() {
+        let client = client_with_events(10..14);
+        let store = SqliteStore::open_in_memory().await.unwrap();
+        let config = Erc721TrackerConfig::builder()
+            .start_from(10)
+            .step(2)
+            .end_block(13)
+            .options(tiny_intervals())
+            .resume(true)
+            .save_owners(true)
+            .persist_events(true)
+            .callback_error_policy(CallbackErrorPolicy::Abort)
+            .build()
+            .unwrap();
+        let mut callback = FlakyErc721EventCallback {
+            failing_block: 12,
+            failures: usize::MAX,
+            calls: 0,
+            delivered_blocks: vec![],
+        };
+        let result = track_erc721_events_with_config(&client, &store, &config, &mut callback).await;
+        assert!(matches!(result, Err(Error::Other(_))));
+
+        // the rejected range 12 - 13 saves nothing, the range before it is saved
+        let collection = format!("{:?}", address(1));
+        let owner = format!("{:?}", address(2));
+        assert_eq!(Some(11), store.get_scan_progress("Mock").await.unwrap());
+        assert_eq!(Some(owner), store.get_token_owner("Mock", &collection, "11").await.unwrap());
+        assert_eq!(None, store.get_token_owner("Mock", &collection, "12").await.unwrap());
+        let collection_id = store.get_collection("Mock", &collection).await.unwrap().unwrap().id;
+        assert_eq!(1, store.get_token_transfers(collection_id, "11").await.unwrap().len());
+        assert!(store.get_token_transfers(collection_id, "12").await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_track_erc721_events_retry_budget_exhausted() {
+        let client = MockEvmClient::new("Mock", 100)
+            .fail_next_get_logs(Error::Other("mock rpc failure".to_owned()))
+            .fail_next_get_logs(Error::Other("mock rpc failure".to_owned()))
+            .fail_next_get_logs(Error::Other("mock rpc failure".to_owned()));
+        let store = SqliteStore::open_in_memory().await.unwrap();
+
+        // the delays are 1ms and 2ms, which exceeds the budget
+        let config = Erc721TrackerConfig::builder()
+            .start_from(10)
+            .options(tiny_intervals())
+            .error_backoff(2, Duration::from_secs(1))
+            .error_jitter(false)
+            .retry_budget(Duration::from_millis(2))
+            .build()
+            .unwrap();
+        let mut callback = EthereumErc721EventCallback { events: vec![] };
+        let result = track_erc721_events_with_config(&client, &store, &config, &mut callback).await;
+
+        assert!(matches!(result, Err(Error::Other(_))));
+        assert_eq!(2, client.call_count("get_logs"));
+    }
+
+    struct ProgressErc721EventCallback {
+        progress: Vec<ScanProgress>,
+    }
+
+    #[async_trait]
+    impl Erc721EventCallback for ProgressErc721EventCallback {
+        async fn on_erc721_event(
+            &mut self,
+            _event: Erc721Event,
+            _name: String,
+            _symbol: String,
+            _total_supply: Option<u128>,
+            _token_uri: String,
+        ) -> Result<()> {
+            Ok(())
+        }
+
+        async fn on_progress(&mut self, progress: ScanProgress) {
+            self.progress.push(progress);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_track_erc721_events_progress() {
+        let client = client_with_events(12..13);
+        let store = SqliteStore::open_in_memory().await.unwrap();
+
+        let mut callback = ProgressErc721EventCallback { progress: vec![] };
+        track_erc721_events(&client, &store, 10, 3, Some(20), &tiny_intervals(), &mut callback)
+            .await
+            .unwrap();
+
+        // contiguous ranges covering 10 - 20, reported even without events
+        let ranges: Vec<(u64, u64)> = callback.progress.iter().map(|p| (p.from, p.to)).collect();
+        assert_eq!(vec![(10, 12), (13, 15), (16, 18), (19, 20)], ranges);
+        let events_found: Vec<usize> = callback.progress.iter().map(|p| p.events_found).collect();
+        assert_eq!(vec![1, 0, 0, 0], events_found);
+        assert!(callback.progress.iter().all(|p| p.chain == "Mock" && p.latest_block == 100));
+    }
+
+    #[tokio::test]
+    async fn test_track_erc721_events_scans_again_a_range_whose_commit_failed() {
+        let client = MockEvmClient::new("Mock", 100);
+        let store = SqliteStore::open_in_memory().await.unwrap();
+        store
+            .call(|conn| {
+                let trigger = "CREATE TRIGGER fail_progress BEFORE INSERT ON scan_progress
+                     BEGIN SELECT RAISE(ABORT, 'mock failure'); END;";
+                Ok(conn.execute_batch(trigger)?)
+            })
+            .await
+            .unwrap();
+
+        let options = ScanOptions {
+            resume: true,
+            retry_budget: Some(Duration::from_millis(5)),
+            ..tiny_intervals()
+        };
+        let mut callback = ProgressErc721EventCallback { progress: vec![] };
+        let result = track_erc721_events(&client, &store, 10, 3, Some(12), &options, &mut callback).await;
+
+        // the range is never reported as scanned
+        assert!(matches!(result, Err(Error::RusqliteError(_))));
+        assert!(callback.progress.is_empty());
+        assert_eq!(None, store.get_scan_progress("Mock").await.unwrap());
+        let scanned_ranges = client.scanned_ranges();
+        assert!(scanned_ranges.len() > 1, "{:?}", scanned_ranges);
+        assert!(scanned_ranges.iter().all(|range| *range == (10, 12)), "{:?}", scanned_ranges);
+    }
+
+    #[tokio::test]
+    async fn test_track_erc721_events_without_its_progress() {
+        let client = MockEvmClient::new("Mock", 100);
+        let store = SqliteStore::open_in_memory().await.unwrap();
+        store.call(|conn| Ok(conn.execute_batch("DROP TABLE scan_progress")?)).await.unwrap();
+
+        let options = ScanOptions {
+            resume: true,
+            ..tiny_intervals()
+        };
+        let mut callback = ProgressErc721EventCallback { progress: vec![] };
+        let result = track_erc721_events(&client, &store, 10, 3, Some(12), &options, &mut callback).await;
+
+        // the tracker does not start again from `start_from` when it can not read its progress
+        assert!(matches!(result, Err(Error::RusqliteError(_))));
+        assert!(client.scanned_ranges().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_track_erc721_events_metrics() {
+        let client = client_with_events(10..14)
+            .fail_next_get_logs(Error::Other("mock rpc failure".to_owned()));
+        let store = SqliteStore::open_in_memory().await.unwrap();
+
+        let metrics = Arc::new(TrackerMetrics::new());
+        let config = Erc721TrackerConfig::builder()
+            .start_from(10)
+            .step(2)
+            .end_block(13)
+            .options(tiny_intervals())
+            .metrics(metrics.clone())
+            .build()
+            .unwrap();
+        let mut callback = FlakyErc721EventCallback {
+            failing_block: 11,
+            failures: 1,
+            calls: 0,
+            delivered_blocks: vec![],
+        };
+        track_erc721_events_with_config(&client, &store, &config, &mut callback)
+            .await
+            .unwrap();
+
+        // the failed batch of the range 10 - 11 is skipped
+        assert_eq!(
+            MetricsSnapshot {
+                events_delivered: 2,
+                errors: 2,
+                callback_errors: 1,
+                rpc_errors: 1,
+                from: 14,
+                latest_block: 100,
+                lag: 86,
+                step: 2,
+                memory_cache_hits: 0,
+                memory_cache_misses: 4,
+            },
+            metrics.snapshot()
+        );
+    }
+
+    /// Track ten events of two tokens of a collection, and return the memory cache hits and misses
+    async fn metadata_cache_with(capacity: usize) -> (MockEvmClient, Vec<(u64, String, String, String)>, (u64, u64)) {
+        let collection = address(1);
+        let mut client = MockEvmClient::new("Mock", 100)
+            .with_erc721_collection(collection, "Mock Collection", "MOCK")
+            .with_erc721_token_uri(collection, 1, "https://mock/1")
+            .with_erc721_token_uri(collection, 2, "https://mock/2");
+        for log_index in 0..10 {
+            let token_id = log_index % 2 + 1;
+            client = client.with_log(erc721_transfer_log(collection, address(0), address(2), token_id, 10, log_index));
+        }
+        let store = SqliteStore::open_in_memory().await.unwrap();
+
+        let metrics = Arc::new(TrackerMetrics::new());
+        let config = Erc721TrackerConfig::builder()
+            .start_from(10)
+            .step(5)
+            .end_block(14)
+            .collection_cache_capacity(capacity)
+            .token_cache_capacity(capacity)
+            .options(tiny_intervals())
+            .metrics(metrics.clone())
+            .build()
+            .unwrap();
+        let mut callback = MetadataCallback::default();
+        track_erc721_events_with_config(&client, &store, &config, &mut callback)
+            .await
+            .unwrap();
+        let snapshot = metrics.snapshot();
+        (client, callback.metadata, (snapshot.memory_cache_hits, snapshot.memory_cache_misses))
+    }
+
+    #[tokio::test]
+    async fn test_track_erc721_events_with_metadata_cache() {
+        let (client, cached, lookups) = metadata_cache_with(16).await;
+        // the first event of each token queries the database
+        assert_eq!((8, 2), lookups);
+        assert_eq!(2, client.call_count("get_erc721_token_uri"));
+
+        let (client, uncached, lookups) = metadata_cache_with(0).await;
+        assert_eq!((0, 10), lookups);
+        assert_eq!(2, client.call_count("get_erc721_token_uri"));
+        assert_eq!(10, cached.len());
+        assert_eq!(uncached, cached);
+        assert_eq!((10, "Mock Collection".to_owned(), "MOCK".to_owned(), "https://mock/2".to_owned()), cached[1]);
+    }
+
+    #[tokio::test]
+    async fn test_track_erc721_events_with_historical_metadata() {
+        let collection = address(1);
+        let historical_metadata = |historical_metadata: bool| {
+            let client = MockEvmClient::new("Mock", 100)
+                .with_multicall()
+                .with_erc721_collection(collection, "Mock Collection", "MOCK")
+                .with_erc721_token_uri(collection, 1, "https://mock/1")
+                .with_erc721_token_uri(collection, 2, "https://mock/2")
+                .with_log(erc721_transfer_log(collection, address(0), address(2), 1, 10, 0))
+                .with_log(erc721_transfer_log(collection, address(0), address(2), 2, 12, 0));
+            let config = Erc721TrackerConfig::builder()
+                .start_from(10)
+                .step(5)
+                .end_block(14)
+                .historical_metadata(historical_metadata)
+                .options(tiny_intervals())
+                .build()
+                .unwrap();
+            (client, config)
+        };
+
+        // the metadata is looked up at the blocks of the events, one event at a time
+        let (client, config) = historical_metadata(true);
+        let store = SqliteStore::open_in_memory().await.unwrap();
+        let mut callback = MetadataCallback::default();
+        track_erc721_events_with_config(&client, &store, &config, &mut callback)
+            .await
+            .unwrap();
+        let mut metadata_blocks = client.metadata_blocks();
+        metadata_blocks.sort();
+        metadata_blocks.dedup();
+        assert_eq!(vec![Some(10), Some(12)], metadata_blocks);
+        assert_eq!(0, client.call_count("get_erc721_token_uris"));
+        assert_eq!(2, callback.metadata.len());
+
+        // at the latest block
+        let (client, config) = historical_metadata(false);
+        let store = SqliteStore::open_in_memory().await.unwrap();
+        let mut callback = MetadataCallback::default();
+        track_erc721_events_with_config(&client, &store, &config, &mut callback)
+            .await
+            .unwrap();
+        assert!(client.metadata_blocks().iter().all(Option::is_none));
+        assert_eq!(1, client.call_count("get_erc721_token_uris"));
+        assert_eq!(2, callback.metadata.len());
+    }
+
+    #[derive(Default)]
+    struct ApprovalCallback {
+        delivered: Vec<String>,
+    }
+
+    #[async_trait]
+    impl Erc721EventCallback for ApprovalCallback {
+        async fn on_erc721_event(
+            &mut self,
+            event: Erc721Event,
+            _name: String,
+            _symbol: String,
+            _total_supply: Option<u128>,
+            _token_uri: String,
+        ) -> Result<()> {
+            self.delivered.push(format!("transfer {} at {}", event.token_id, event.block_number.unwrap()));
+            Ok(())
+        }
+
+        async fn on_erc721_approval(&mut self, approval: Erc721ApprovalEvent) -> Result<()> {
+            self.delivered.push(format!("approval {} at {}", approval.token_id, approval.block_number.unwrap()));
+            Ok(())
+        }
+
+        async fn on_erc721_approval_for_all(&mut self, approval_for_all: Erc721ApprovalForAllEvent) -> Result<()> {
+            self.delivered.push(format!(
+                "approval for all {} at {}",
+                approval_for_all.approved,
+                approval_for_all.block_number.unwrap()
+            ));
+            Ok(())
+        }
+    }
+
+    async fn approvals_with(track_approvals: bool, track_approvals_for_all: bool) -> Vec<String> {
+        let collection = address(1);
+        let (owner, operator) = (address(2), address(3));
+        let client = MockEvmClient::new("Mock", 100)
+            .with_erc721_collection(collection, "Mock Collection", "MOCK")
+            .with_erc721_token_uri(collection, 1, "https://mock/1")
+            .with_log(erc721_transfer_log(collection, address(0), owner, 1, 10, 0))
+            .with_log(erc721_approval_log(collection, owner, operator, 1, 11, 0))
+            .with_log(approval_for_all_log(collection, owner, operator, true, 12, 0))
+            .with_log(erc721_transfer_log(collection, owner, operator, 1, 16, 0))
+            .with_log(approval_for_all_log(collection, owner, operator, false, 17, 0));
+        let store = SqliteStore::open_in_memory().await.unwrap();
+        let config = Erc721TrackerConfig::builder()
+            .start_from(10)
+            .step(5)
+            .end_block(19)
+            .track_approvals(track_approvals)
+            .track_approvals_for_all(track_approvals_for_all)
+            .options(tiny_intervals())
+            .build()
+            .unwrap();
+
+        let mut callback = ApprovalCallback::default();
+        track_erc721_events_with_config(&client, &store, &config, &mut callback)
+            .await
+            .unwrap();
+        callback.delivered
+    }
+
+    #[tokio::test]
+    async fn test_track_erc721_events_with_approvals() {
+        // the approvals come after the transfers of their range
+        assert_eq!(
+            vec![
+                "transfer 1 at 10",
+                "approval 1 at 11",
+                "approval for all true at 12",
+                "transfer 1 at 16",
+                "approval for all false at 17",
+            ],
+            approvals_with(true, true).await
+        );
+        assert_eq!(
+            vec!["transfer 1 at 10", "approval for all true at 12", "transfer 1 at 16", "approval for all false at 17"],
+            approvals_with(false, true).await
+        );
+        assert_eq!(vec!["transfer 1 at 10", "transfer 1 at 16"], approvals_with(false, false).await);
+    }
+
+    #[derive(Default)]
+    struct ConsecutiveTransferCallback {
+        delivered: Vec<String>,
+    }
+
+    #[async_trait]
+    impl Erc721EventCallback for ConsecutiveTransferCallback {
+        async fn on_erc721_event(
+            &mut self,
+            event: Erc721Event,
+            _name: String,
+            _symbol: String,
+            _total_supply: Option<u128>,
+            _token_uri: String,
+        ) -> Result<()> {
+            self.delivered.push(format!("transfer {} at {}", event.token_id, event.block_number.unwrap()));
+            Ok(())
+        }
+
+        async fn on_erc721_consecutive_transfer(&mut self, consecutive_transfer: Erc721ConsecutiveTransfer) -> Result<()> {
+            self.delivered.push(format!(
+                "consecutive transfer {:?} at {}",
+                consecutive_transfer.token_ids,
+                consecutive_transfer.block_number.unwrap()
+            ));
+            Ok(())
+        }
+    }
+
+    async fn consecutive_transfers_with(max_expanded_consecutive_transfer: u64) -> (MockEvmClient, Vec<String>) {
+        let collection: H160 = "0x8a90cab2b38dba80c64b7734e58ee1db38b8992e".parse().unwrap();
+        let mut client = MockEvmClient::new("Mock", 15000020)
+            .with_erc721_collection(collection, "Mock Collection", "MOCK")
+            .with_erc721_token_uri(collection, 2, "https://mock/2");
+        for log in fixture_logs(include_str!("./fixtures/erc2309_consecutive_transfer_logs.json")) {
+            client = client.with_log(log);
+        }
+        let store = SqliteStore::open_in_memory().await.unwrap();
+        let config = Erc721TrackerConfig::builder()
+            .start_from(15000000)
+            .step(10)
+            .end_block(15000020)
+            .max_expanded_consecutive_transfer(max_expanded_consecutive_transfer)
+            .options(tiny_intervals())
+            .build()
+            .unwrap();
+
+        let mut callback = ConsecutiveTransferCallback::default();
+        track_erc721_events_with_config(&client, &store, &config, &mut callback)
+            .await
+            .unwrap();
+        (client, callback.delivered)
+    }
+
+    #[tokio::test]
+    async fn test_track_erc721_events_with_consecutive_transfers() {
+        // the 5 tokens minted by the constructor are expanded, the 30000 next ones are not
+        let (client, delivered) = consecutive_transfers_with(10_000).await;
+        assert_eq!(
+            vec![
+                "transfer 0 at 15000000",
+                "transfer 1 at 15000000",
+                "transfer 2 at 15000000",
+                "transfer 3 at 15000000",
+                "transfer 4 at 15000000",
+                "consecutive transfer 5..=30004 at 15000010",
+                "transfer 2 at 15000020",
+            ],
+            delivered
+        );
+        assert_eq!(1, client.call_count("get_erc721_token_uris"));
+
+        let (_, delivered) = consecutive_transfers_with(4).await;
+        assert_eq!(
+            vec![
+                "consecutive transfer 0..=4 at 15000000",
+                "consecutive transfer 5..=30004 at 15000010",
+                "transfer 2 at 15000020",
+            ],
+            delivered
+        );
+    }
+
+    #[derive(Default)]
+    struct MetadataUpdateCallback {
+        token_uris: Vec<(u64, String)>,
+        updates: Vec<(H160, RangeInclusive<U256>, Option<String>)>,
+    }
+
+    #[async_trait]
+    impl Erc721EventCallback for MetadataUpdateCallback {
+        async fn on_erc721_event(
+            &mut self,
+            event: Erc721Event,
+            _name: String,
+            _symbol: String,
+            _total_supply: Option<u128>,
+            token_uri: String,
+        ) -> Result<()> {
+            self.token_uris.push((event.block_number.unwrap(), token_uri));
+            Ok(())
+        }
+
+        async fn on_erc721_metadata_update(
+            &mut self,
+            address: H160,
+            token_ids: RangeInclusive<U256>,
+            token_uri: Option<String>,
+        ) -> Result<()> {
+            self.updates.push((address, token_ids, token_uri));
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_track_erc721_events_with_metadata_updates() {
+        let collection = address(1);
+        let client = MockEvmClient::new("Mock", 100)
+            .with_erc721_collection(collection, "Mock Collection", "MOCK")
+            .with_erc721_token_uris(collection, 1, vec!["https://mock/unrevealed", "https://mock/1"])
+            .with_erc721_token_uri(collection, 2, "https://mock/2")
+            .with_log(erc721_transfer_log(collection, address(0), address(2), 1, 10, 0))
+            .with_log(erc721_transfer_log(collection, address(0), address(2), 2, 10, 1))
+            .with_log(erc4906_metadata_update_log(collection, 1, 11, 0))
+            .with_log(erc4906_batch_metadata_update_log(collection, 1, 2, 12, 0))
+            // too many tokens to fetch them again at once
+            .with_log(erc4906_batch_metadata_update_log(collection, 0, 1000, 13, 0))
+            .with_log(erc721_transfer_log(collection, address(2), address(3), 2, 14, 0));
+        let store = SqliteStore::open_in_memory().await.unwrap();
+
+        let mut callback = MetadataUpdateCallback::default();
+        track_erc721_events(&client, &store, 10, 2, Some(15), &tiny_intervals(), &mut callback)
+            .await
+            .unwrap();
+
+        let revealed = Some("https://mock/1".to_owned());
+        assert_eq!(
+            vec![
+                (collection, U256::from(1)..=U256::from(1), revealed.clone()),
+                (collection, U256::from(1)..=U256::from(1), revealed.clone()),
+                (collection, U256::from(2)..=U256::from(2), Some("https://mock/2".to_owned())),
+                (collection, U256::from(0)..=U256::from(1000), None),
+            ],
+            callback.updates
+        );
+        // the stale token is fetched again by its next event
+        assert_eq!(6, client.call_count("get_erc721_token_uri"));
+        assert_eq!(
+            vec![
+                (10, "https://mock/unrevealed".to_owned()),
+                (10, "https://mock/2".to_owned()),
+                (14, "https://mock/2".to_owned()),
+            ],
+            callback.token_uris
+        );
+        let collection_id = store.get_collection("Mock", &format!("{:?}", collection)).await.unwrap().unwrap().id;
+        let token = store.get_token(collection_id, "1").await.unwrap().unwrap();
+        assert_eq!(revealed, token.token_uri);
+        assert!(!store.is_token_stale(token.id).await.unwrap());
+    }
+
+    /// Records the batches, without the single event method being called
+    struct BatchErc721EventCallback {
+        batches: Vec<Vec<(Erc721Event, Erc721Metadata)>>,
+    }
+
+    #[async_trait]
+    impl Erc721EventCallback for BatchErc721EventCallback {
+        async fn on_erc721_event(
+            &mut self,
+            _event: Erc721Event,
+            _name: String,
+            _symbol: String,
+            _total_supply: Option<u128>,
+            _token_uri: String,
+        ) -> Result<()> {
+            panic!("the events are delivered in batches");
+        }
+
+        async fn on_erc721_events(&mut self, events: Vec<(Erc721Event, Erc721Metadata)>) -> Result<()> {
+            self.batches.push(events);
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_track_erc721_events_in_batches() {
+        let collection = address(1);
+        // the node returns the logs out of order
+        let client = MockEvmClient::new("Mock", 100)
+            .with_erc721_collection(collection, "Mock Collection", "MOCK")
+            .with_erc721_token_uri(collection, 1, "https://mock/1")
+            .with_erc721_token_uri(collection, 2, "https://mock/2")
+            .with_erc721_token_uri(collection, 3, "https://mock/3")
+            .with_erc721_token_uri(collection, 4, "https://mock/4")
+            .with_log(erc721_transfer_log(collection, address(0), address(2), 1, 12, 1))
+            .with_log(erc721_transfer_log(collection, address(0), address(2), 2, 11, 0))
+            .with_log(erc721_transfer_log(collection, address(0), address(2), 3, 12, 0))
+            .with_log(erc721_transfer_log(collection, address(0), address(2), 4, 14, 0));
+        let store = SqliteStore::open_in_memory().await.unwrap();
+
+        let mut callback = BatchErc721EventCallback { batches: vec![] };
+        let report =
+            track_erc721_events(&client, &store, 10, 3, Some(15), &tiny_intervals(), &mut callback)
+                .await
+                .unwrap();
+
+        assert_eq!(4, report.events_delivered);
+        let batches: Vec<Vec<(Option<u64>, Option<u64>)>> = callback
+            .batches
+            .iter()
+            .map(|batch| batch.iter().map(|(e, _)| (e.block_number, e.log_index)).collect())
+            .collect();
+        assert_eq!(
+            vec![
+                vec![(Some(11), Some(0)), (Some(12), Some(0)), (Some(12), Some(1))],
+                vec![(Some(14), Some(0))],
+            ],
+            batches
+        );
+        assert_eq!(
+            Erc721Metadata {
+                name: "Mock Collection".to_owned(),
+                symbol: "MOCK".to_owned(),
+                total_supply: None,
+                token_uri: "https://mock/3".to_owned(),
+                owner: None,
+                royalty: None,
+                contract_uri: None,
+                implementation: None,
+            },
+            callback.batches[0][1].1
+        );
+    }
+
+    #[tokio::test]
+    async fn test_track_erc721_events_backfill() {
+        let client = client_with_events(10..110);
+        let store = SqliteStore::open_in_memory().await.unwrap();
+
+        // the confirmed head is far away, so no range waits for the interval
+        let options = ScanOptions {
+            range_interval: Duration::from_secs(60),
+            ..tiny_intervals()
+        };
+        let mut callback = EthereumErc721EventCallback { events: vec![] };
+        let start = Instant::now();
+        track_erc721_events(&client, &store, 10, 10, Some(49), &options, &mut callback)
+            .await
+            .unwrap();
+
+        assert!(start.elapsed() < Duration::from_secs(1));
+        assert_eq!(4, client.scanned_ranges().len());
+        assert_eq!(40, callback.events.len());
+    }
+
+    #[tokio::test]
+    async fn test_track_erc721_events_backfill_near_head() {
+        let interval = Duration::from_millis(30);
+        let options = ScanOptions {
+            range_interval: interval,
+            backfill_threshold: Some(4),
+            ..tiny_intervals()
+        };
+        let track = |options: ScanOptions| async move {
+            let client = MockEvmClient::new("Mock", 100);
+            let store = SqliteStore::open_in_memory().await.unwrap();
+            let mut callback = EthereumErc721EventCallback { events: vec![] };
+            let start = Instant::now();
+            // the ranges 80 - 81 to 92 - 93, the confirmed head is 94
+            track_erc721_events(&client, &store, 80, 2, Some(93), &options, &mut callback)
+                .await
+                .unwrap();
+            start.elapsed()
+        };
+
+        // only the ranges 90 - 91 and 92 - 93 are close enough to the head to wait
+        let elapsed = track(options.clone()).await;
+        assert!(elapsed >= interval * 2);
+        assert!(elapsed < interval * 7);
+
+        let options = ScanOptions {
+            backfill: false,
+            ..options
+        };
+        assert!(track(options).await >= interval * 7);
+    }
+
+    /// Records how many ranges the node was asked for when each batch was processed
+    struct SlowErc721EventCallback {
+        client: Arc<MockEvmClient>,
+        delay: Duration,
+        delivered_blocks: Vec<u64>,
+        scanned_during_batches: Vec<(usize, usize)>,
+    }
+
+    #[async_trait]
+    impl Erc721EventCallback for SlowErc721EventCallback {
+        async fn on_erc721_event(
+            &mut self,
+            _event: Erc721Event,
+            _name: String,
+            _symbol: String,
+            _total_supply: Option<u128>,
+            _token_uri: String,
+        ) -> Result<()> {
+            Ok(())
+        }
+
+        async fn on_erc721_events(&mut self, events: Vec<(Erc721Event, Erc721Metadata)>) -> Result<()> {
+            let before = self.client.scanned_ranges().len();
+            tokio::time::sleep(self.delay).await;
+            self.delivered_blocks
+                .extend(events.iter().map(|(event, _)| event.block_number.unwrap()));
+            self.scanned_during_batches.push((before, self.client.scanned_ranges().len()));
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_track_erc721_events_pipelined() {
+        let client = Arc::new(
+            client_with_events(10..18).with_get_logs_delay(Duration::from_millis(20)),
+        );
+        let store = SqliteStore::open_in_memory().await.unwrap();
+
+        let mut callback = SlowErc721EventCallback {
+            client: client.clone(),
+            delay: Duration::from_millis(50),
+            delivered_blocks: vec![],
+            scanned_during_batches: vec![],
+        };
+        track_erc721_events(&*client, &store, 10, 2, Some(17), &tiny_intervals(), &mut callback)
+            .await
+            .unwrap();
+
+        // the next range is fetched while the first one is processed
+        let (before, after) = callback.scanned_during_batches[0];
+        assert!(after > before);
+        assert_eq!((10..18).collect::<Vec<u64>>(), callback.delivered_blocks);
+    }
+
+    #[tokio::test]
+    async fn test_track_erc721_events_pipeline_backpressure() {
+        let client = Arc::new(client_with_events(10..30));
+        let store = SqliteStore::open_in_memory().await.unwrap();
+
+        let options = ScanOptions {
+            pipeline_depth: 1,
+            ..tiny_intervals()
+        };
+        let mut callback = SlowErc721EventCallback {
+            client: client.clone(),
+            delay: Duration::from_millis(5),
+            delivered_blocks: vec![],
+            scanned_during_batches: vec![],
+        };
+        track_erc721_events(&*client, &store, 10, 1, Some(29), &options, &mut callback)
+            .await
+            .unwrap();
+
+        // the fetcher is at most one range in the channel and one waiting to be sent ahead
+        for (batch, (_, scanned)) in callback.scanned_during_batches.iter().enumerate() {
+            assert!(*scanned <= batch + 1 + 2);
+        }
+        assert_eq!((10..30).collect::<Vec<u64>>(), callback.delivered_blocks);
+    }
+
+    #[test]
+    fn test_split_range() {
+        assert_eq!(vec![(10, 13), (14, 17), (18, 19)], split_range(10, 19, 4));
+        assert_eq!(vec![(10, 10)], split_range(10, 10, 4));
+    }
+
+    async fn delivered_blocks_with(client: &MockEvmClient, options: ScanOptions) -> Vec<u64> {
+        let store = SqliteStore::open_in_memory().await.unwrap();
+        let mut callback = EthereumErc721EventCallback { events: vec![] };
+        track_erc721_events(client, &store, 10, 5, Some(49), &options, &mut callback)
+            .await
+            .unwrap();
+        callback.events.iter().map(|event| event.block_number.unwrap()).collect()
+    }
+
+    #[tokio::test]
+    async fn test_track_erc721_events_from_the_latest_block() {
+        let store = SqliteStore::open_in_memory().await.unwrap();
+        let config = Erc721TrackerConfig::builder()
+            .start_block(StartBlock::LatestMinus(4))
+            .step(5)
+            .end_block(99)
+            .options(tiny_intervals())
+            .confirmations(0)
+            .resume(true)
+            .build()
+            .unwrap();
+
+        // no saved progress, it starts from the latest block
+        let client = client_with_events(10..100);
+        let mut callback = EthereumErc721EventCallback { events: vec![] };
+        track_erc721_events_with_config(&client, &store, &config, &mut callback)
+            .await
+            .unwrap();
+        assert_eq!(vec![(96, 99)], client.scanned_ranges());
+
+        // the saved progress is used instead
+        store.save_scan_progress("Mock", 89).await.unwrap();
+        let client = client_with_events(10..100);
+        let mut callback = EthereumErc721EventCallback { events: vec![] };
+        track_erc721_events_with_config(&client, &store, &config, &mut callback)
+            .await
+            .unwrap();
+        assert_eq!(vec![(90, 94), (95, 99)], client.scanned_ranges());
+    }
+
+    #[tokio::test]
+    async fn test_track_erc721_events_metadata_concurrency() {
+        let client = client_with_events(10..50).with_token_uri_delay(Duration::from_millis(10));
+        let store = SqliteStore::open_in_memory().await.unwrap();
+        let config = Erc721TrackerConfig::builder()
+            .start_from(10)
+            .step(20)
+            .end_block(49)
+            .options(tiny_intervals())
+            .metadata_concurrency(4)
+            .build()
+            .unwrap();
+        let mut callback = EthereumErc721EventCallback { events: vec![] };
+        let report = track_erc721_events_with_config(&client, &store, &config, &mut callback)
+            .await
+            .unwrap();
+
+        assert_eq!(4, client.max_concurrent_token_uri_calls());
+        assert_eq!(40, report.metadata_cache_misses);
+        // the collection and its tokens are saved once
+        let collections: i64 = store
+            .call(|conn| Ok(conn.query_row("SELECT count(*) from erc721_collections", [], |row| row.get(0))?))
+            .await
+            .unwrap();
+        assert_eq!(1, collections);
+        let tokens: i64 = store
+            .call(|conn| Ok(conn.query_row("SELECT count(*) from erc721_tokens", [], |row| row.get(0))?))
+            .await
+            .unwrap();
+        assert_eq!(40, tokens);
+    }
+
+    #[tokio::test]
+    async fn test_track_erc721_events_metadata_concurrency_keeps_the_order() {
+        let client = client_with_events(10..50).with_token_uri_delay(Duration::from_millis(1));
+        let options = ScanOptions {
+            metadata_concurrency: 8,
+            ..tiny_intervals()
+        };
+        let delivered_blocks = delivered_blocks_with(&client, options).await;
+
+        assert!(client.max_concurrent_token_uri_calls() > 1);
+        let sequential_client = client_with_events(10..50);
+        assert_eq!(delivered_blocks_with(&sequential_client, tiny_intervals()).await, delivered_blocks);
+        assert_eq!(1, sequential_client.max_concurrent_token_uri_calls());
+        assert_eq!((10..50).collect::<Vec<u64>>(), delivered_blocks);
+    }
+
+    #[tokio::test]
+    async fn test_track_erc721_events_metadata_concurrency_looks_collections_up_once() {
+        // 50 events of 2 collections in a range, whose names take a while to look up
+        let collections = [address(1), address(2)];
+        let mut client = MockEvmClient::new("Mock", 100)
+            .with_erc721_collection(collections[0], "First", "ONE")
+            .with_erc721_collection(collections[1], "Second", "TWO")
+            .with_name_symbol_delay(Duration::from_millis(5));
+        for token_id in 0..50 {
+            let collection = collections[token_id as usize % 2];
+            let log = erc721_transfer_log(collection, address(0), address(3), token_id, 10 + token_id / 10, token_id % 10);
+            client = client.with_erc721_token_uri(collection, token_id, "https://mock").with_log(log);
+        }
+        let store = SqliteStore::open_in_memory().await.unwrap();
+        let config = Erc721TrackerConfig::builder()
+            .start_from(10)
+            .step(5)
+            .end_block(14)
+            .options(tiny_intervals())
+            .metadata_concurrency(8)
+            .build()
+            .unwrap();
+        let mut callback = EthereumErc721EventCallback { events: vec![] };
+        track_erc721_events_with_config(&client, &store, &config, &mut callback)
+            .await
+            .unwrap();
+
+        // the concurrent events of a collection wait for its first one, none is delivered without the metadata
+        assert_eq!(50, callback.events.len());
+        assert_eq!(2, client.call_count("get_erc721_name_symbol"));
+        assert_eq!(50, client.call_count("get_erc721_token_uri"));
+    }
+
+    #[tokio::test]
+    async fn test_track_erc721_events_parallel_ranges() {
+        let client = client_with_events(10..50).with_get_logs_delay(Duration::from_millis(20));
+        let options = ScanOptions {
+            parallel_ranges: 4,
+            ..tiny_intervals()
+        };
+        let delivered_blocks = delivered_blocks_with(&client, options).await;
+
+        assert_eq!(4, client.max_concurrent_get_logs());
+        assert_eq!(8, client.scanned_ranges().len());
+        // the same events in the same order as the sequential mode
+        let sequential_client = client_with_events(10..50);
+        let sequential_blocks = delivered_blocks_with(&sequential_client, tiny_intervals()).await;
+        assert_eq!(sequential_blocks, delivered_blocks);
+        assert_eq!(1, sequential_client.max_concurrent_get_logs());
+        assert_eq!((10..50).collect::<Vec<u64>>(), delivered_blocks);
+    }
+
+    #[tokio::test]
+    async fn test_track_erc721_events_parallel_ranges_step_halving() {
+        // every range of 5 blocks has too many events
+        let client = client_with_events(10..50)
+            .with_get_logs_delay(Duration::from_millis(1))
+            .with_max_logs_per_request(3);
+        let options = ScanOptions {
+            parallel_ranges: 4,
+            ..tiny_intervals()
+        };
+        let delivered_blocks = delivered_blocks_with(&client, options).await;
+
+        // the rejected sub-range is fetched again with the halved step
+        let scanned = client.scanned_ranges();
+        assert!(scanned.contains(&(10, 14)));
+        assert!(scanned.contains(&(10, 11)));
+        assert_eq!((10..50).collect::<Vec<u64>>(), delivered_blocks);
+    }
+
+    #[tokio::test]
+    async fn test_track_erc721_events_live() {
+        let client = client_with_events(12..13)
+            .with_latest_block_numbers(vec![10])
+            .with_new_heads();
+        // without the notifications, the new blocks would only be seen a minute later
+        let options = ScanOptions {
+            range_interval: Duration::from_secs(60),
+            idle_interval: Duration::from_secs(60),
+            confirmations: 1,
+            ..tiny_intervals()
+        };
+        let store = SqliteStore::open_in_memory().await.unwrap();
+        let delivered_blocks = Arc::new(Mutex::new(vec![]));
+        let mut callback = SharedErc721EventCallback {
+            delivered_blocks: delivered_blocks.clone(),
+        };
+
+        let started = Instant::now();
+        let track = track_erc721_events(&client, &store, 11, 1, Some(12), &options, &mut callback);
+        let notify = async {
+            client.new_head(12);
+            wait_until(|| client.scanned_ranges().contains(&(11, 11))).await;
+            // the block 12 is not confirmed yet
+            tokio::time::sleep(Duration::from_millis(20)).await;
+            assert!(delivered_blocks.lock().unwrap().is_empty());
+            client.new_head(13);
+        };
+        let (report, ()) = tokio::join!(track, notify);
+
+        assert_eq!(Some(12), report.unwrap().last_processed_block);
+        assert_eq!(vec![12], *delivered_blocks.lock().unwrap());
+        assert!(started.elapsed() < Duration::from_secs(5));
+        assert_eq!(1, client.call_count("subscribe_new_heads"));
+    }
+
+    #[tokio::test]
+    async fn test_track_erc721_events_live_subscription_dropped() {
+        let client = client_with_events(12..13)
+            .with_latest_block_numbers(vec![10])
+            .with_new_heads();
+        let options = ScanOptions {
+            idle_interval: Duration::from_millis(10),
+            confirmations: 0,
+            ..tiny_intervals()
+        };
+        let store = SqliteStore::open_in_memory().await.unwrap();
+        let mut callback = EthereumErc721EventCallback { events: vec![] };
+
+        let track = track_erc721_events(&client, &store, 11, 1, Some(12), &options, &mut callback);
+        let notify = async {
+            tokio::time::sleep(Duration::from_millis(20)).await;
+            client.drop_subscription();
+            tokio::time::sleep(Duration::from_millis(20)).await;
+            // the block is not notified anymore, the tracker polls it
+            client.new_head(12);
+        };
+        let (report, ()) = tokio::time::timeout(Duration::from_secs(5), async {
+            tokio::join!(track, notify)
+        })
+        .await
+        .unwrap();
+
+        assert_eq!(Some(12), report.unwrap().last_processed_block);
+        assert_eq!(1, callback.events.len());
+    }
+
+    #[tokio::test]
+    async fn test_track_erc721_events_without_live_mode() {
+        let client = client_with_events(10..20).with_new_heads();
+        let options = ScanOptions {
+            live: false,
+            ..tiny_intervals()
+        };
+        let delivered_blocks = delivered_blocks_with(&client, options).await;
+
+        assert_eq!((10..20).collect::<Vec<u64>>(), delivered_blocks);
+        assert_eq!(0, client.call_count("subscribe_new_heads"));
+    }
+
+    #[tokio::test]
+    async fn test_find_creation_block() {
+        let client = MockEvmClient::new("Mock", 100_000).with_contract_created_at(address(1), 12_345);
+        let creation_block = find_creation_block(&client, address(1), 100_000).await.unwrap();
+
+        assert_eq!(Some(12_345), creation_block);
+        // the latest block, then a binary search over 100_001 blocks
+        assert!(client.call_count("get_code") <= 18);
+        assert_eq!(None, find_creation_block(&client, address(2), 100_000).await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_find_creation_block_at_the_bounds() {
+        let client = MockEvmClient::new("Mock", 100)
+            .with_contract_created_at(address(1), 0)
+            .with_contract_created_at(address(2), 100);
+
+        assert_eq!(Some(0), find_creation_block(&client, address(1), 100).await.unwrap());
+        assert_eq!(Some(100), find_creation_block(&client, address(2), 100).await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_track_erc721_collection() {
+        let client = client_with_events(30..40)
+            .with_contract_created_at(address(1), 30)
+            .with_log(erc721_transfer_log(address(5), address(0), address(2), 1, 35, 1));
+        let store = SqliteStore::open_in_memory().await.unwrap();
+        let config = Erc721TrackerConfig::builder()
+            .step(5)
+            .end_block(44)
+            .options(tiny_intervals())
+            .build()
+            .unwrap();
+
+        let mut callback = EthereumErc721EventCallback { events: vec![] };
+        track_erc721_collection(&client, &store, address(1), &config, &mut callback)
+            .await
+            .unwrap();
+
+        let delivered_blocks: Vec<u64> =
+            callback.events.iter().map(|event| event.block_number.unwrap()).collect();
+        assert_eq!((30..40).collect::<Vec<u64>>(), delivered_blocks);
+        assert_eq!((30, 34), client.scanned_ranges()[0]);
+        assert_eq!(0, client.call_count("get_logs"));
+        let address_string = format!("{:?}", address(1));
+        assert_eq!(
+            Some(30),
+            store.get_collection_creation_block("Mock", &address_string).await.unwrap()
+        );
+
+        // the creation block is not searched again
+        let get_code_calls = client.call_count("get_code");
+        let mut callback = EthereumErc721EventCallback { events: vec![] };
+        track_erc721_collection(&client, &store, address(1), &config, &mut callback)
+            .await
+            .unwrap();
+        assert_eq!(get_code_calls, client.call_count("get_code"));
+        assert_eq!(10, callback.events.len());
+    }
+
+    #[tokio::test]
+    async fn test_track_erc721_collection_not_a_contract() {
+        let client = client_with_events(30..40);
+        let store = SqliteStore::open_in_memory().await.unwrap();
+        let config = Erc721TrackerConfig::builder().options(tiny_intervals()).build().unwrap();
+
+        let mut callback = EthereumErc721EventCallback { events: vec![] };
+        let result = track_erc721_collection(&client, &store, address(1), &config, &mut callback).await;
+        assert!(matches!(result, Err(Error::Other(_))));
+        assert_eq!(0, client.scanned_ranges().len());
+    }
+
+    async fn collection_code(store: &dyn NftStore, address: H160) -> Option<CollectionCode> {
+        let collection_id = store.get_collection("Mock", &format!("{:?}", address)).await.unwrap()?.id;
+        store.get_collection_code(collection_id).await.unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_track_erc721_events_from_an_address_without_code() {
+        // the address 6 is an externally owned account with a log looking like a transfer
+        let client = client_with_events(10..12)
+            .without_code(address(6))
+            .with_log(erc721_transfer_log(address(6), address(0), address(2), 1, 11, 1));
+        let store = SqliteStore::open_in_memory().await.unwrap();
+
+        let mut callback = EthereumErc721EventCallback { events: vec![] };
+        track_erc721_events(&client, &store, 10, 5, Some(14), &tiny_intervals(), &mut callback)
+            .await
+            .unwrap();
+
+        let addresses: Vec<H160> = callback.events.iter().map(|event| event.address).collect();
+        assert_eq!(vec![address(1), address(1)], addresses);
+        assert_eq!(Some(CollectionCode::Live), collection_code(&store, address(1)).await);
+        assert_eq!(Some(CollectionCode::Missing), collection_code(&store, address(6)).await);
+        // nothing is called on the address without code
+        assert_eq!(1, client.call_count("supports_interface"));
+        assert_eq!(1, client.call_count("get_erc721_name_symbol"));
+        assert_eq!(2, client.call_count("is_contract"));
+
+        // the answers are cached
+        let mut callback = EthereumErc721EventCallback { events: vec![] };
+        track_erc721_events(&client, &store, 10, 5, Some(14), &tiny_intervals(), &mut callback)
+            .await
+            .unwrap();
+        assert_eq!(2, client.call_count("is_contract"));
+        assert_eq!(1, client.call_count("get_erc721_name_symbol"));
+    }
+
+    #[tokio::test]
+    async fn test_track_erc721_events_of_a_destroyed_collection() {
+        // the token 1 is minted before the contract is destroyed, the tokens 2 and 3 after
+        let collection = address(1);
+        let client = MockEvmClient::new("Mock", 100)
+            .with_erc721_collection(collection, "Mock Collection", "MOCK")
+            .with_erc721_token_uri(collection, 1, "https://mock/1")
+            .with_log(erc721_transfer_log(collection, address(0), address(2), 1, 10, 0))
+            .with_log(erc721_transfer_log(collection, address(0), address(2), 2, 20, 0))
+            .with_log(erc721_transfer_log(collection, address(0), address(2), 3, 21, 0));
+        let store = SqliteStore::open_in_memory().await.unwrap();
+
+        let mut callback = EthereumErc721EventCallback { events: vec![] };
+        track_erc721_events(&client, &store, 10, 5, Some(14), &tiny_intervals(), &mut callback)
+            .await
+            .unwrap();
+        assert_eq!(1, callback.events.len());
+        assert_eq!(Some(CollectionCode::Live), collection_code(&store, collection).await);
+
+        client.remove_code(collection);
+        let mut callback = EthereumErc721EventCallback { events: vec![] };
+        track_erc721_events(&client, &store, 15, 10, Some(24), &tiny_intervals(), &mut callback)
+            .await
+            .unwrap();
+
+        // the token uri of the token 2 is not found, the code is checked again and found removed
+        let delivered_blocks: Vec<u64> =
+            callback.events.iter().map(|event| event.block_number.unwrap()).collect();
+        assert_eq!(vec![20, 21], delivered_blocks);
+        assert_eq!(Some(CollectionCode::Removed), collection_code(&store, collection).await);
+        let collection_id = store.get_collection("Mock", &format!("{:?}", collection)).await.unwrap().unwrap().id;
+        let token_id = store.get_token(collection_id, "2").await.unwrap().unwrap().id;
+        assert_eq!(None, store.get_token_lookup_failures(token_id).await.unwrap());
+        // the token 3 is not looked up
+        assert_eq!(2, client.call_count("get_erc721_token_uri"));
+    }
+
+    fn enumerable_client() -> MockEvmClient {
+        let mut client = MockEvmClient::new("Mock", 100)
+            .with_erc721_collection(address(1), "Mock Collection", "MOCK")
+            .with_erc721_tokens_by_index(address(1), &[3, 1, 4, 15, 9]);
+        for token_id in [3, 1, 4, 15, 9] {
+            client = client.with_erc721_token_uri(address(1), token_id, &format!("https://mock/{}", token_id));
+        }
+        client
+    }
+
+    async fn saved_token_uri(store: &dyn NftStore, token_id: u64) -> Option<String> {
+        let collection_id = store.get_collection("Mock", &format!("{:?}", address(1))).await.unwrap()?.id;
+        store.get_token(collection_id, &token_id.to_string()).await.unwrap()?.token_uri
+    }
+
+    #[tokio::test]
+    async fn test_snapshot_erc721_collection() {
+        let client = enumerable_client();
+        let store = SqliteStore::open_in_memory().await.unwrap();
+
+        assert_eq!(5, snapshot_erc721_collection(&client, &store, address(1), 90).await.unwrap());
+        for token_id in [3, 1, 4, 15, 9] {
+            assert_eq!(Some(format!("https://mock/{}", token_id)), saved_token_uri(&store, token_id).await);
+        }
+        assert_eq!(0, client.call_count("get_logs"));
+        let address_string = format!("{:?}", address(1));
+        assert_eq!(Some((90, 5)), store.get_collection_snapshot("Mock", &address_string).await.unwrap());
+
+        // a finished snapshot has nothing left to save
+        assert_eq!(0, snapshot_erc721_collection(&client, &store, address(1), 90).await.unwrap());
+        assert_eq!(5, client.call_count("get_erc721_token_by_index"));
+    }
+
+    #[tokio::test]
+    async fn test_snapshot_erc721_collection_resumes() {
+        let client = enumerable_client();
+        let store = SqliteStore::open_in_memory().await.unwrap();
+        // interrupted after the first 2 tokens
+        let collection_id =
+            store.put_collection("Mock", &format!("{:?}", address(1)), None).await.unwrap();
+        store.save_collection_snapshot(collection_id, 90, 2).await.unwrap();
+
+        assert_eq!(3, snapshot_erc721_collection(&client, &store, address(1), 90).await.unwrap());
+        assert_eq!(3, client.call_count("get_erc721_token_by_index"));
+        assert_eq!(None, saved_token_uri(&store, 3).await);
+        for token_id in [4, 15, 9] {
+            assert_eq!(Some(format!("https://mock/{}", token_id)), saved_token_uri(&store, token_id).await);
+        }
+
+        // a snapshot at another block starts over
+        assert_eq!(5, snapshot_erc721_collection(&client, &store, address(1), 95).await.unwrap());
+        assert_eq!(Some("https://mock/3".to_owned()), saved_token_uri(&store, 3).await);
+    }
+
+    #[tokio::test]
+    async fn test_snapshot_erc721_collection_not_enumerable() {
+        let client = MockEvmClient::new("Mock", 100)
+            .with_erc721_collection(address(1), "Mock Collection", "MOCK")
+            .with_interface(address(1), ERC721_ENUMERABLE_INTERFACE_ID, false);
+        let store = SqliteStore::open_in_memory().await.unwrap();
+
+        let result = snapshot_erc721_collection(&client, &store, address(1), 90).await;
+        assert!(matches!(result, Err(Error::NotEnumerable(contract)) if contract == address(1)));
+        assert_eq!(0, client.call_count("get_erc721_total_supply"));
+
+        // without ERC165 nor `totalSupply`
+        let client = MockEvmClient::new("Mock", 100).with_erc721_collection(address(1), "Mock Collection", "MOCK");
+        let result = snapshot_erc721_collection(&client, &store, address(1), 90).await;
+        assert!(matches!(result, Err(Error::NotEnumerable(_))));
+    }
+
+    #[derive(Default)]
+    struct IdleErc721EventCallback {
+        delivered_blocks: Vec<u64>,
+        idle: Vec<(String, u64)>,
+    }
+
+    #[async_trait]
+    impl Erc721EventCallback for IdleErc721EventCallback {
+        async fn on_erc721_event(
+            &mut self,
+            event: Erc721Event,
+            _name: String,
+            _symbol: String,
+            _total_supply: Option<u128>,
+            _token_uri: String,
+        ) -> Result<()> {
+            self.delivered_blocks.push(event.block_number.unwrap());
+            Ok(())
+        }
+
+        async fn on_idle(&mut self, chain: &str, up_to_block: u64) {
+            self.idle.push((chain.to_owned(), up_to_block));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_track_erc721_events_on_idle() {
+        let client = client_with_events(10..15);
+        let store = SqliteStore::open_in_memory().await.unwrap();
+
+        let mut callback = IdleErc721EventCallback::default();
+        track_erc721_events(&client, &store, 10, 5, Some(29), &tiny_intervals(), &mut callback)
+            .await
+            .unwrap();
+
+        assert_eq!((10..15).collect::<Vec<u64>>(), callback.delivered_blocks);
+        // only the ranges without any event
+        let idle: Vec<(String, u64)> =
+            vec![("Mock".to_owned(), 19), ("Mock".to_owned(), 24), ("Mock".to_owned(), 29)];
+        assert_eq!(idle, callback.idle);
+    }
+
+    #[tokio::test]
+    async fn test_track_erc721_events_with_end_time() {
+        let client = client_with_events(10..50).with_block_time(1_000, 10);
+        let store = SqliteStore::open_in_memory().await.unwrap();
+        // between the blocks 29 and 30
+        let config = Erc721TrackerConfig::builder()
+            .start_from(10)
+            .step(5)
+            .end_time(1_000 + 10 * 29 + 5)
+            .options(tiny_intervals())
+            .build()
+            .unwrap();
+
+        let mut callback = EthereumErc721EventCallback { events: vec![] };
+        let report = track_erc721_events_with_config(&client, &store, &config, &mut callback)
+            .await
+            .unwrap();
+
+        assert_eq!(Some(29), report.last_processed_block);
+        assert_eq!(20, callback.events.len());
+    }
+
+    async fn count_rows(store: &SqliteStore) -> Vec<(&'static str, i64)> {
+        let tables = [
+            "erc721_collections",
+            "erc721_tokens",
+            "scan_progress",
+            "delivered_events",
+            "scanned_blocks",
+        ];
+        store
+            .call(move |conn| {
+                tables
+                    .iter()
+                    .map(|table| {
+                        let sql = format!("SELECT count(*) from {}", table);
+                        Ok((*table, conn.query_row(&sql, [], |row| row.get(0))?))
+                    })
+                    .collect()
+            })
+            .await
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_track_erc721_events_dry_run() {
+        let path = std::env::temp_dir().join("erc721_dry_run.db");
+        let _ = std::fs::remove_file(&path);
+        let store = SqliteStore::open(&path).await.unwrap();
+        let before = count_rows(&store).await;
+
+        let options = ScanOptions {
+            dry_run: true,
+            resume: true,
+            dedup: true,
+            detect_reorgs: true,
+            ..tiny_intervals()
+        };
+        let client = client_with_events(10..20);
+        let mut callback = BatchErc721EventCallback { batches: vec![] };
+        track_erc721_events(&client, &store, 10, 5, Some(29), &options, &mut callback)
+            .await
+            .unwrap();
+        // replayed with the same result, nothing was saved in between
+        track_erc721_events(&client, &store, 10, 5, Some(29), &options, &mut callback)
+            .await
+            .unwrap();
+        drop(store);
+
+        let events: Vec<(Erc721Event, Erc721Metadata)> = callback.batches.concat();
+        assert_eq!(20, events.len());
+        for (_, metadata) in events {
+            assert_eq!("Mock Collection", metadata.name);
+            assert_eq!("https://mock", metadata.token_uri);
+        }
+        let store = SqliteStore::open(&path).await.unwrap();
+        assert_eq!(before, count_rows(&store).await);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_track_erc721_events_dry_run_with_cached_metadata() {
+        let store = SqliteStore::open_in_memory().await.unwrap();
+        let name_symbol = ("Cached Collection".to_owned(), "CACHED".to_owned());
+        let address_string = format!("{:?}", address(1));
+        let collection_id = store.put_collection("Mock", &address_string, Some(name_symbol)).await.unwrap();
+        store.put_token(collection_id, "10", Some("https://cached".to_owned())).await.unwrap();
+        let before = count_rows(&store).await;
+
+        let options = ScanOptions {
+            dry_run: true,
+            ..tiny_intervals()
+        };
+        let client = client_with_events(10..12);
+        let mut callback = BatchErc721EventCallback { batches: vec![] };
+        track_erc721_events(&client, &store, 10, 5, Some(14), &options, &mut callback)
+            .await
+            .unwrap();
+
+        let metadata: Vec<(String, String)> = callback
+            .batches
+            .concat()
+            .into_iter()
+            .map(|(_, metadata)| (metadata.name, metadata.token_uri))
+            .collect();
+        assert_eq!(
+            vec![
+                ("Cached Collection".to_owned(), "https://cached".to_owned()),
+                ("Cached Collection".to_owned(), "https://mock".to_owned()),
+            ],
+            metadata
+        );
+        assert_eq!(0, client.call_count("get_erc721_name_symbol"));
+        assert_eq!(before, count_rows(&store).await);
+    }
+
+    #[tokio::test]
+    async fn test_get_metadata_of_a_collection_deleted_meanwhile() {
+        let path = std::env::temp_dir().join("erc721_deleted_meanwhile.db");
+        let _ = std::fs::remove_file(&path);
+        let store = SqliteStore::open(&path).await.unwrap();
+        let collection = address(1);
+        // another process deletes the collection while the token uri is fetched
+        let hook_path = path.clone();
+        let client = MockEvmClient::new("Mock", 100)
+            .with_erc721_collection(collection, "Mock Collection", "MOCK")
+            .with_erc721_token_uri(collection, 1, "https://mock/1")
+            .with_token_uri_hook(move |_, _| {
+                let conn = Connection::open(&hook_path).unwrap();
+                conn.execute("DELETE FROM erc721_collections", []).unwrap();
+            });
+        let event = Erc721Event {
+            block_number: Some(10),
+            block_timestamp: None,
+            address: collection,
+            transaction_hash: Some(H256::from_low_u64_be(10)),
+            tx_sender: None,
+            transaction_index: Some(0),
+            log_index: Some(0),
+            from: address(0),
+            to: address(2),
+            token_id: U256::from(1),
+            kind: EventKind::Mint,
+            sale: None,
+            raw: None,
+        };
+
+        let mut report = ScanReport::default();
+        let result = get_metadata(
+            &client,
+            &store,
+            &event,
+            &Erc721TrackerConfig::default(),
+            &MetadataSources::default(),
+            &mut report,
+        )
+        .await;
+        match result {
+            Err(Error::DbInconsistent { address: deleted, token_id }) => {
+                assert_eq!((collection, U256::from(1)), (deleted, token_id))
+            }
+            other => panic!("{:?}", other),
+        }
+        assert_eq!(1, client.call_count("get_erc721_token_uri"));
+
+        drop(store);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_track_erc721_events_report_matches_the_callback() {
+        let collection = address(1);
+        let client = client_with_events(10..20)
+            // the tokens 10 and 11 are transferred again, their metadata is cached
+            .with_log(erc721_transfer_log(collection, address(2), address(3), 10, 25, 0))
+            .with_log(erc721_transfer_log(collection, address(2), address(3), 11, 26, 0))
+            // a denied contract
+            .with_log(erc721_transfer_log(address(9), address(0), address(2), 1, 27, 0))
+            .fail_next_get_logs(rpc_error(-32000, "header not found"));
+        let store = SqliteStore::open_in_memory().await.unwrap();
+        let config = Erc721TrackerConfig::builder()
+            .start_from(10)
+            .step(5)
+            .end_block(39)
+            .denylist(vec![address(9)])
+            .options(tiny_intervals())
+            .build()
+            .unwrap();
+
+        let mut callback = EthereumErc721EventCallback { events: vec![] };
+        let report = track_erc721_events_with_config(&client, &store, &config, &mut callback)
+            .await
+            .unwrap();
+
+        assert_eq!(Some(39), report.last_processed_block);
+        assert_eq!(30, report.blocks_scanned);
+        assert_eq!(13, report.events_decoded);
+        assert_eq!(callback.events.len() as u64, report.events_delivered);
+        assert_eq!(12, report.events_delivered);
+        assert_eq!(2, report.metadata_cache_hits);
+        assert_eq!(10, report.metadata_cache_misses);
+        assert_eq!(1, report.rpc_errors);
+        assert_eq!(1, report.errors);
+        assert!(report.duration > Duration::ZERO);
+    }
+
+    #[derive(Default)]
+    struct RawCallback {
+        events: Vec<Erc721Event>,
+    }
+
+    #[async_trait]
+    impl Erc721RawEventCallback for RawCallback {
+        async fn on_erc721_raw_event(&mut self, event: Erc721Event) -> Result<()> {
+            self.events.push(event);
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_track_erc721_raw_events() {
+        let client = client_with_events(10..20)
+            // not a visual ERC721 contract, its events are delivered anyway
+            .with_log(erc721_transfer_log(address(9), address(0), address(2), 1, 25, 0));
+        let store = SqliteStore::open_in_memory().await.unwrap();
+        let config = Erc721TrackerConfig::builder()
+            .start_from(10)
+            .step(5)
+            .end_block(29)
+            .options(tiny_intervals())
+            .build()
+            .unwrap();
+
+        let mut callback = RawCallback::default();
+        let report = track_erc721_raw_events(&client, &store, &config, &mut callback)
+            .await
+            .unwrap();
+
+        assert_eq!(11, callback.events.len());
+        assert_eq!(11, report.events_delivered);
+        assert_eq!(Some(address(9)), callback.events.last().map(|event| event.address));
+        for method in &["is_visual_erc721", "get_erc721_name_symbol", "get_erc721_token_uri", "get_erc721_total_supply"] {
+            assert_eq!(0, client.call_count(method), "{}", method);
+        }
+        assert_eq!(0, report.metadata_cache_misses);
+        let rows: i64 = store
+            .call(|conn| Ok(conn.query_row("SELECT count(*) from erc721_collections", [], |row| row.get(0))?))
+            .await
+            .unwrap();
+        assert_eq!(0, rows);
+    }
+
+    #[derive(Default)]
+    struct TokenUriCallback {
+        token_uris: Vec<String>,
+    }
+
+    #[async_trait]
+    impl Erc721EventCallback for TokenUriCallback {
+        async fn on_erc721_event(
+            &mut self,
+            _event: Erc721Event,
+            _name: String,
+            _symbol: String,
+            _total_supply: Option<u128>,
+            token_uri: String,
+        ) -> Result<()> {
+            self.token_uris.push(token_uri);
+            Ok(())
+        }
+    }
+
+    /// Track a token which is revealed between its mint and its first transfer
+    async fn token_uris_with(refresh: MetadataRefresh) -> (Vec<String>, SqliteStore) {
+        let collection = address(1);
+        let client = MockEvmClient::new("Mock", 100)
+            .with_erc721_collection(collection, "Mock Collection", "MOCK")
+            .with_erc721_token_uris(collection, 1, vec!["pre-reveal", "revealed"])
+            .with_log(erc721_transfer_log(collection, address(0), address(2), 1, 10, 0))
+            .with_log(erc721_transfer_log(collection, address(2), address(3), 1, 11, 0));
+        let store = SqliteStore::open_in_memory().await.unwrap();
+        let config = Erc721TrackerConfig::builder()
+            .start_from(10)
+            .step(5)
+            .end_block(14)
+            .metadata_refresh(refresh)
+            .options(tiny_intervals())
+            .build()
+            .unwrap();
+
+        let mut callback = TokenUriCallback::default();
+        track_erc721_events_with_config(&client, &store, &config, &mut callback)
+            .await
+            .unwrap();
+        (callback.token_uris, store)
+    }
+
+    #[tokio::test]
+    async fn test_track_erc721_events_refresh_every_event() {
+        let (token_uris, store) = token_uris_with(MetadataRefresh::EveryEvent).await;
+
+        assert_eq!(vec!["pre-reveal".to_owned(), "revealed".to_owned()], token_uris);
+        let collection_id = store.get_collection("Mock", &format!("{:?}", address(1))).await.unwrap().unwrap().id;
+        let token = store.get_token(collection_id, "1").await.unwrap().unwrap();
+        assert_eq!(Some("revealed".to_owned()), token.token_uri);
+        assert!(store.get_token_fetched_at(token.id).await.unwrap().is_some());
+    }
+
+    #[tokio::test]
+    async fn test_track_erc721_events_never_refresh() {
+        let (token_uris, _) = token_uris_with(MetadataRefresh::Never).await;
+
+        assert_eq!(vec!["pre-reveal".to_owned(), "pre-reveal".to_owned()], token_uris);
+    }
+
+    /// Track two events of a collection whose first metadata lookup fails
+    async fn track_after_a_failed_lookup(retry: MetadataRetry) -> (MockEvmClient, Vec<String>, SqliteStore) {
+        let collection = address(1);
+        let client = MockEvmClient::new("Mock", 100)
+            .with_erc721_collection(collection, "Mock Collection", "MOCK")
+            .with_erc721_token_uri(collection, 1, "https://mock/1")
+            .with_erc721_token_uri(collection, 2, "https://mock/2")
+            .fail_next_erc721_name_symbol(rpc_error(-32000, "execution reverted"))
+            .with_log(erc721_transfer_log(collection, address(0), address(2), 1, 10, 0))
+            .with_log(erc721_transfer_log(collection, address(0), address(2), 2, 11, 0));
+        let store = SqliteStore::open_in_memory().await.unwrap();
+        let config = Erc721TrackerConfig::builder()
+            .start_from(10)
+            .step(5)
+            .end_block(14)
+            .metadata_retry(retry)
+            .options(tiny_intervals())
+            .build()
+            .unwrap();
+
+        let mut callback = TokenUriCallback::default();
+        track_erc721_events_with_config(&client, &store, &config, &mut callback)
+            .await
+            .unwrap();
+        (client, callback.token_uris, store)
+    }
+
+    #[tokio::test]
+    async fn test_track_erc721_events_retries_the_failed_lookups() {
+        let (client, token_uris, store) = track_after_a_failed_lookup(MetadataRetry {
+            max_attempts: 3,
+            base_delay: Duration::ZERO,
+        })
+        .await;
+
+        // the event of the failed lookup is skipped, the next one gets the metadata
+        assert_eq!(vec!["https://mock/2".to_owned()], token_uris);
+        assert_eq!(2, client.call_count("get_erc721_name_symbol"));
+        let collection = store.get_collection("Mock", &format!("{:?}", address(1))).await.unwrap().unwrap();
+        assert_eq!(Some("Mock Collection".to_owned()), collection.name);
+        assert_eq!(None, store.get_collection_lookup_failures(collection.id).await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_track_erc721_events_waits_before_retrying_a_failed_lookup() {
+        let (client, token_uris, store) = track_after_a_failed_lookup(MetadataRetry {
+            max_attempts: 3,
+            base_delay: Duration::from_secs(3600),
+        })
+        .await;
+
+        assert!(token_uris.is_empty());
+        assert_eq!(1, client.call_count("get_erc721_name_symbol"));
+        let collection = store.get_collection("Mock", &format!("{:?}", address(1))).await.unwrap().unwrap();
+        assert_eq!(None, collection.name);
+        assert!(matches!(
+            store.get_collection_lookup_failures(collection.id).await.unwrap(),
+            Some((1, _))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_track_erc721_events_without_token_uri() {
+        // the `tokenURI` of the token 1 reverts or returns nothing, as for an unrevealed token
+        let collection = address(1);
+        let client = MockEvmClient::new("Mock", 100)
+            .with_erc721_collection(collection, "Mock Collection", "MOCK")
+            .with_erc721_token_uri(collection, 2, "https://mock/2")
+            .with_log(erc721_transfer_log(collection, address(0), address(2), 1, 10, 0))
+            .with_log(erc721_transfer_log(collection, address(0), address(2), 2, 11, 0))
+            .with_log(erc721_transfer_log(collection, address(2), address(3), 1, 12, 0));
+        let store = SqliteStore::open_in_memory().await.unwrap();
+        let config = Erc721TrackerConfig::builder()
+            .start_from(10)
+            .step(5)
+            .end_block(14)
+            .metadata_retry(MetadataRetry {
+                max_attempts: 3,
+                base_delay: Duration::ZERO,
+            })
+            .options(tiny_intervals())
+            .build()
+            .unwrap();
+
+        let mut callback = TokenUriCallback::default();
+        track_erc721_events_with_config(&client, &store, &config, &mut callback)
+            .await
+            .unwrap();
+
+        // the events are delivered anyway, and the next event of the token retries the lookup
+        assert_eq!(vec!["".to_owned(), "https://mock/2".to_owned(), "".to_owned()], callback.token_uris);
+        assert_eq!(3, client.call_count("get_erc721_token_uri"));
+        let collection_id = store.get_collection("Mock", &format!("{:?}", collection)).await.unwrap().unwrap().id;
+        let token = store.get_token(collection_id, "1").await.unwrap().unwrap();
+        assert_eq!(None, token.token_uri);
+        assert!(matches!(store.get_token_lookup_failures(token.id).await.unwrap(), Some((2, _))));
+    }
+
+    #[derive(Default)]
+    struct TotalSupplyCallback {
+        total_supplies: Vec<(H160, Option<u128>)>,
+    }
+
+    #[async_trait]
+    impl Erc721EventCallback for TotalSupplyCallback {
+        async fn on_erc721_event(
+            &mut self,
+            event: Erc721Event,
+            _name: String,
+            _symbol: String,
+            total_supply: Option<u128>,
+            _token_uri: String,
+        ) -> Result<()> {
+            self.total_supplies.push((event.address, total_supply));
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_track_erc721_events_total_supply() {
+        let enumerable = address(1);
+        let not_enumerable = address(3);
+        let mut client = MockEvmClient::new("Mock", 100)
+            .with_erc721_collection(enumerable, "Enumerable", "ENUM")
+            .with_erc721_total_supply(enumerable, 42)
+            .with_erc721_collection(not_enumerable, "Not Enumerable", "NOPE")
+            .with_erc721_token_uri(not_enumerable, 1, "https://mock/1")
+            .with_log(erc721_transfer_log(not_enumerable, address(0), address(2), 1, 12, 0));
+        for block_number in 10..20 {
+            client = client
+                .with_erc721_token_uri(enumerable, block_number, "https://mock")
+                .with_log(erc721_transfer_log(enumerable, address(0), address(2), block_number, block_number, 1));
+        }
+        let store = SqliteStore::open_in_memory().await.unwrap();
+        let config = Erc721TrackerConfig::builder()
+            .start_from(10)
+            .step(5)
+            .end_block(19)
+            .options(tiny_intervals())
+            .build()
+            .unwrap();
+
+        let mut callback = TotalSupplyCallback::default();
+        track_erc721_events_with_config(&client, &store, &config, &mut callback)
+            .await
+            .unwrap();
+
+        assert_eq!(11, callback.total_supplies.len());
+        for (collection, total_supply) in &callback.total_supplies {
+            let expected = if *collection == enumerable { Some(42) } else { None };
+            assert_eq!(expected, *total_supply);
+        }
+        // once per collection per range
+        assert_eq!(3, client.call_count("get_erc721_total_supply"));
+    }
+
+    #[derive(Default)]
+    struct MetadataCallback {
+        metadata: Vec<(u64, String, String, String)>,
+    }
+
+    #[async_trait]
+    impl Erc721EventCallback for MetadataCallback {
+        async fn on_erc721_event(
+            &mut self,
+            event: Erc721Event,
+            name: String,
+            symbol: String,
+            _total_supply: Option<u128>,
+            token_uri: String,
+        ) -> Result<()> {
+            self.metadata.push((event.block_number.unwrap(), name, symbol, token_uri));
+            Ok(())
+        }
+    }
+
+    /// Track the events of two collections whose tokens are transferred several times,
+    /// the first token uri lookup fails
+    async fn track_metadata_with(client: MockEvmClient) -> (MockEvmClient, Vec<(u64, String, String, String)>) {
+        let mut client = client
+            .with_erc721_collection(address(1), "First", "FIRST")
+            .with_erc721_collection(address(3), "Second", "SECOND")
+            .fail_next_erc721_token_uri(rpc_error(-32000, "execution reverted"));
+        for block_number in 10..20 {
+            let collection = if block_number % 2 == 0 { address(1) } else { address(3) };
+            let token_id = block_number % 4;
+            client = client
+                .with_erc721_token_uri(collection, token_id, &format!("https://mock/{}", token_id))
+                .with_log(erc721_transfer_log(collection, address(0), address(2), token_id, block_number, 0));
+        }
+        let store = SqliteStore::open_in_memory().await.unwrap();
+        let config = Erc721TrackerConfig::builder()
+            .start_from(10)
+            .step(5)
+            .end_block(19)
+            .metadata_retry(MetadataRetry {
+                max_attempts: 3,
+                base_delay: Duration::ZERO,
+            })
+            .options(tiny_intervals())
+            .build()
+            .unwrap();
+
+        let mut callback = MetadataCallback::default();
+        track_erc721_events_with_config(&client, &store, &config, &mut callback)
+            .await
+            .unwrap();
+        (client, callback.metadata)
+    }
+
+    #[tokio::test]
+    async fn test_track_erc721_events_with_multicall() {
+        let (unbatched_client, unbatched) = track_metadata_with(MockEvmClient::new("Mock", 100)).await;
+        let (batched_client, batched) = track_metadata_with(MockEvmClient::new("Mock", 100).with_multicall()).await;
+
+        // the event of the failed lookup is skipped, the next event of its token retries it
+        assert_eq!(9, unbatched.len());
+        assert_eq!(unbatched, batched);
+        assert_eq!(5, unbatched_client.call_count("get_erc721_token_uri"));
+        assert_eq!(2, unbatched_client.call_count("get_erc721_name_symbol"));
+
+        // the lookups of the first range are batched, the second range finds everything in the database
+        assert_eq!(1, batched_client.call_count("get_erc721_name_symbols"));
+        assert_eq!(1, batched_client.call_count("get_erc721_token_uris"));
+        assert_eq!(0, batched_client.call_count("get_erc721_name_symbol"));
+        // the retry of the failed lookup
+        assert_eq!(1, batched_client.call_count("get_erc721_token_uri"));
+    }
+
+    /// A collection, a contract reporting with ERC165 not supporting ERC721 and a collection without ERC165
+    fn client_with_a_non_erc721_contract() -> MockEvmClient {
+        MockEvmClient::new("Mock", 100)
+            .with_erc721_collection(address(1), "ERC721", "NFT")
+            .with_erc721_token_uri(address(1), 1, "https://mock/1")
+            .with_interface(address(1), ERC721_INTERFACE_ID, true)
+            .with_erc721_collection(address(3), "Not ERC721", "ERC20")
+            .with_erc721_token_uri(address(3), 1, "https://mock/1")
+            .with_interface(address(3), ERC721_INTERFACE_ID, false)
+            .with_erc721_collection(address(4), "Before ERC165", "OLD")
+            .with_erc721_token_uri(address(4), 1, "https://mock/1")
+            .with_log(erc721_transfer_log(address(1), address(0), address(2), 1, 10, 0))
+            .with_log(erc721_transfer_log(address(3), address(0), address(2), 1, 10, 1))
+            .with_log(erc721_transfer_log(address(4), address(0), address(2), 1, 10, 2))
+            .with_log(erc721_transfer_log(address(3), address(2), address(5), 1, 11, 0))
+    }
+
+    #[tokio::test]
+    async fn test_track_erc721_events_of_a_non_erc721_contract() {
+        let client = client_with_a_non_erc721_contract();
+        let store = SqliteStore::open_in_memory().await.unwrap();
+        let config = Erc721TrackerConfig::builder()
+            .start_from(10)
+            .step(5)
+            .end_block(14)
+            .options(tiny_intervals())
+            .build()
+            .unwrap();
+
+        let mut callback = EthereumErc721EventCallback { events: vec![] };
+        track_erc721_events_with_config(&client, &store, &config, &mut callback)
+            .await
+            .unwrap();
+
+        // the metadata of the contract which is not ERC721 is not looked up
+        let addresses: Vec<H160> = callback.events.iter().map(|event| event.address).collect();
+        assert_eq!(vec![address(1), address(4)], addresses);
+        assert_eq!(2, client.call_count("get_erc721_name_symbol"));
+        assert_eq!(2, client.call_count("get_erc721_token_uri"));
+        // the answers are cached
+        assert_eq!(3, client.call_count("supports_interface"));
+        for (collection, supports_erc721) in [(address(1), Some(true)), (address(3), Some(false)), (address(4), None)] {
+            let collection = store.get_collection("Mock", &format!("{:?}", collection)).await.unwrap().unwrap();
+            assert_eq!(Some(supports_erc721), store.get_collection_erc721_support(collection.id).await.unwrap());
+        }
+    }
+
+    #[tokio::test]
+    async fn test_track_erc721_raw_events_skipping_non_erc721_contracts() {
+        let client = client_with_a_non_erc721_contract();
+        let store = SqliteStore::open_in_memory().await.unwrap();
+        let builder = Erc721TrackerConfig::builder()
+            .start_from(10)
+            .step(5)
+            .end_block(14)
+            .options(tiny_intervals());
+
+        let mut callback = RawCallback { events: vec![] };
+        let config = builder.clone().build().unwrap();
+        track_erc721_raw_events(&client, &store, &config, &mut callback)
+            .await
+            .unwrap();
+        assert_eq!(4, callback.events.len());
+        assert_eq!(0, client.call_count("supports_interface"));
+
+        let store = SqliteStore::open_in_memory().await.unwrap();
+        let mut callback = RawCallback { events: vec![] };
+        let config = builder.skip_non_erc721_contracts(true).build().unwrap();
+        track_erc721_raw_events(&client, &store, &config, &mut callback)
+            .await
+            .unwrap();
+        // the contract without ERC165 keeps its events
+        let addresses: Vec<H160> = callback.events.iter().map(|event| event.address).collect();
+        assert_eq!(vec![address(1), address(4)], addresses);
+        assert_eq!(0, client.call_count("get_erc721_name_symbol"));
+
+        // the collections checked by the raw tracker still get their metadata later
+        let mut callback = EthereumErc721EventCallback { events: vec![] };
+        track_erc721_events_with_config(&client, &store, &config, &mut callback)
+            .await
+            .unwrap();
+        assert_eq!(2, callback.events.len());
+    }
+
+    #[derive(Default)]
+    struct OwnerCallback {
+        owners: Vec<(u64, Option<H160>)>,
+    }
+
+    #[async_trait]
+    impl Erc721EventCallback for OwnerCallback {
+        async fn on_erc721_event(
+            &mut self,
+            _event: Erc721Event,
+            _name: String,
+            _symbol: String,
+            _total_supply: Option<u128>,
+            _token_uri: String,
+        ) -> Result<()> {
+            unreachable!("the events are delivered in batches")
+        }
+
+        async fn on_erc721_events(&mut self, events: Vec<(Erc721Event, Erc721Metadata)>) -> Result<()> {
+            for (event, metadata) in events {
+                self.owners.push((event.token_id.as_u64(), metadata.owner));
+            }
+            Ok(())
+        }
+    }
+
+    async fn owners_with(fetch_owners: bool) -> (MockEvmClient, Vec<(u64, Option<H160>)>) {
+        // the token 1 is transferred twice, the token 2 is burned
+        let collection = address(1);
+        let client = MockEvmClient::new("Mock", 100)
+            .with_erc721_collection(collection, "Mock Collection", "MOCK")
+            .with_erc721_token_uri(collection, 1, "https://mock/1")
+            .with_erc721_token_uri(collection, 2, "https://mock/2")
+            .with_erc721_owner(collection, 1, address(5))
+            .with_log(erc721_transfer_log(collection, address(0), address(2), 1, 10, 0))
+            .with_log(erc721_transfer_log(collection, address(2), address(5), 1, 11, 0))
+            .with_log(erc721_transfer_log(collection, address(0), address(2), 2, 12, 0));
+        let store = SqliteStore::open_in_memory().await.unwrap();
+        let config = Erc721TrackerConfig::builder()
+            .start_from(10)
+            .step(5)
+            .end_block(14)
+            .fetch_owners(fetch_owners)
+            .options(tiny_intervals())
+            .build()
+            .unwrap();
+
+        let mut callback = OwnerCallback::default();
+        track_erc721_events_with_config(&client, &store, &config, &mut callback)
+            .await
+            .unwrap();
+        (client, callback.owners)
+    }
+
+    #[tokio::test]
+    async fn test_track_erc721_events_with_owners() {
+        let (client, owners) = owners_with(true).await;
+
+        assert_eq!(vec![(1, Some(address(5))), (1, Some(address(5))), (2, None)], owners);
+        // once per token per range
+        assert_eq!(2, client.call_count("get_erc721_owner_of"));
+    }
+
+    #[tokio::test]
+    async fn test_track_erc721_events_without_owners() {
+        let (client, owners) = owners_with(false).await;
+
+        assert_eq!(vec![(1, None), (1, None), (2, None)], owners);
+        assert_eq!(0, client.call_count("get_erc721_owner_of"));
+    }
+
+    #[tokio::test]
+    async fn test_track_erc721_events_on_another_chain() {
+        let client = client_with_events(10..20).with_chain_id(Some(137)).with_expected_chain_id(1);
+        let store = SqliteStore::open_in_memory().await.unwrap();
+
+        let mut callback = EthereumErc721EventCallback { events: vec![] };
+        let result = track_erc721_events(&client, &store, 10, 5, Some(19), &tiny_intervals(), &mut callback).await;
+
+        assert!(matches!(result, Err(Error::ChainIdMismatch { expected: 1, actual: 137 })));
+        assert!(callback.events.is_empty());
+        assert_eq!(0, client.call_count("get_logs"));
+
+        let client = client_with_events(10..20).with_chain_id(Some(1)).with_expected_chain_id(1);
+        let result = track_erc721_events(&client, &store, 10, 5, Some(19), &tiny_intervals(), &mut callback).await;
+        assert!(result.is_ok());
+        assert_eq!(10, callback.events.len());
+    }
+
+    /// Collect the metadata delivered with the events
+    #[derive(Default)]
+    struct BatchedMetadataCallback {
+        metadata: Vec<Erc721Metadata>,
+    }
+
+    #[async_trait]
+    impl Erc721EventCallback for BatchedMetadataCallback {
+        async fn on_erc721_event(
+            &mut self,
+            _event: Erc721Event,
+            _name: String,
+            _symbol: String,
+            _total_supply: Option<u128>,
+            _token_uri: String,
+        ) -> Result<()> {
+            unreachable!("the events are delivered in batches")
+        }
+
+        async fn on_erc721_events(&mut self, events: Vec<(Erc721Event, Erc721Metadata)>) -> Result<()> {
+            self.metadata.extend(events.into_iter().map(|(_, metadata)| metadata));
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_track_erc721_events_with_cryptopunks() {
+        let mut client = MockEvmClient::new("Mock", 3925630).with_erc721_collection(CRYPTOPUNKS_ADDRESS, "CRYPTOPUNKS", "Ͼ");
+        for log in fixture_logs(include_str!("./fixtures/cryptopunks_logs.json")) {
+            client = client.with_log(log);
+        }
+        let store = SqliteStore::open_in_memory().await.unwrap();
+
+        // the contract does not implement ERC165, it is not skipped
+        let config = Erc721TrackerConfig::builder()
+            .start_from(3914495)
+            .step(20000)
+            .end_block(3925630)
+            .options(tiny_intervals())
+            .address_allowlist(vec![CRYPTOPUNKS_ADDRESS])
+            .skip_non_erc721_contracts(true)
+            .build()
+            .unwrap();
+        let mut callback = BatchedMetadataCallback::default();
+        track_erc721_events_with_config(&client, &store, &config, &mut callback)
+            .await
+            .unwrap();
+
+        assert_eq!(4, callback.metadata.len());
+        for metadata in &callback.metadata {
+            assert_eq!("CRYPTOPUNKS", metadata.name);
+            assert_eq!("https://cryptopunks.app/cryptopunks/details/2", metadata.token_uri);
+        }
+        assert_eq!(0, client.call_count("get_erc721_token_uri"));
+        assert_eq!(0, client.call_count("get_erc721_token_uris"));
+    }
+
+    async fn royalties_with(fetch_royalties: bool) -> (MockEvmClient, SqliteStore, Vec<Option<(H160, u64)>>) {
+        // the collection 1 pays 5% of its sales to the address 9, the collection 3 pays no royalty
+        let client = MockEvmClient::new("Mock", 100)
+            .with_erc721_collection(address(1), "Mock Collection", "MOCK")
+            .with_erc721_collection(address(3), "Other Collection", "OTHER")
+            .with_erc721_token_uri(address(1), 1, "https://mock/1")
+            .with_erc721_token_uri(address(1), 2, "https://mock/2")
+            .with_erc721_token_uri(address(3), 1, "https://other/1")
+            .with_royalty(address(1), address(9), 500)
+            .with_log(erc721_transfer_log(address(1), address(0), address(2), 1, 10, 0))
+            .with_log(erc721_transfer_log(address(1), address(0), address(2), 2, 11, 0))
+            .with_log(erc721_transfer_log(address(3), address(0), address(2), 1, 12, 0));
+        let store = SqliteStore::open_in_memory().await.unwrap();
+        let config = Erc721TrackerConfig::builder()
+            .start_from(10)
+            .step(5)
+            .end_block(14)
+            .fetch_royalties(fetch_royalties)
+            .options(tiny_intervals())
+            .build()
+            .unwrap();
+
+        let mut callback = BatchedMetadataCallback::default();
+        track_erc721_events_with_config(&client, &store, &config, &mut callback)
+            .await
+            .unwrap();
+        let royalties = callback.metadata.into_iter().map(|metadata| metadata.royalty).collect();
+        (client, store, royalties)
+    }
+
+    #[tokio::test]
+    async fn test_track_erc721_events_with_royalties() {
+        let (client, store, royalties) = royalties_with(true).await;
+
+        let royalty = Some((address(9), 500));
+        assert_eq!(vec![royalty, royalty, None], royalties);
+        // once per collection per range
+        assert_eq!(2, client.call_count("get_royalty_info"));
+        assert_eq!(
+            Some((format!("{:?}", address(9)), 500)),
+            store.get_collection_royalty("Mock", &format!("{:?}", address(1))).await.unwrap()
+        );
+        assert_eq!(None, store.get_collection_royalty("Mock", &format!("{:?}", address(3))).await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_track_erc721_events_without_royalties() {
+        let (client, store, royalties) = royalties_with(false).await;
+
+        assert_eq!(vec![None, None, None], royalties);
+        assert_eq!(0, client.call_count("get_royalty_info"));
+        assert_eq!(None, store.get_collection_royalty("Mock", &format!("{:?}", address(1))).await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_track_erc721_events_with_proxies() {
+        // the collection 1 is a proxy upgraded from an ERC721 implementation to another one, the collection 3 is not
+        let client = MockEvmClient::new("Mock", 100)
+            .with_erc721_collection(address(1), "Proxied Collection", "PROXY")
+            .with_erc721_collection(address(3), "Other Collection", "OTHER")
+            .with_erc721_token_uri(address(1), 1, "https://proxy/1")
+            .with_erc721_token_uri(address(1), 2, "https://proxy/2")
+            .with_erc721_token_uri(address(3), 1, "https://other/1")
+            .with_erc721_token_uri(address(3), 2, "https://other/2")
+            .with_proxy_implementations(address(1), vec![address(7), address(8)])
+            .with_interface(address(7), ERC721_INTERFACE_ID, true)
+            .with_interface(address(8), ERC721_INTERFACE_ID, false)
+            .with_log(erc721_transfer_log(address(1), address(0), address(2), 1, 11, 0))
+            .with_log(erc721_transfer_log(address(3), address(0), address(2), 1, 12, 0))
+            .with_log(erc721_transfer_log(address(1), address(0), address(2), 2, 16, 0))
+            .with_log(erc721_transfer_log(address(3), address(0), address(2), 2, 17, 0));
+        let store = SqliteStore::open_in_memory().await.unwrap();
+        let config = Erc721TrackerConfig::builder()
+            .start_from(10)
+            .step(5)
+            .end_block(19)
+            .skip_non_erc721_contracts(true)
+            .resolve_proxies(true)
+            .options(tiny_intervals())
+            .build()
+            .unwrap();
+
+        let mut callback = BatchedMetadataCallback::default();
+        track_erc721_events_with_config(&client, &store, &config, &mut callback)
+            .await
+            .unwrap();
+
+        // the events of the proxy are skipped after its upgrade
+        let implementations: Vec<Option<H160>> =
+            callback.metadata.iter().map(|metadata| metadata.implementation).collect();
+        assert_eq!(vec![Some(address(7)), None, None], implementations);
+        assert!(callback.metadata[0].is_proxy());
+        // the collection 3 is resolved once
+        assert_eq!(3, client.call_count("resolve_proxy_implementation"));
+        assert_eq!(3, client.call_count("supports_interface"));
+        assert_eq!(
+            Some((Some(format!("{:?}", address(8))), 19)),
+            store.get_collection_implementation("Mock", &format!("{:?}", address(1))).await.unwrap()
+        );
+        assert_eq!(
+            Some((None, 14)),
+            store.get_collection_implementation("Mock", &format!("{:?}", address(3))).await.unwrap()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_track_erc721_events_with_contract_uris() {
+        // fetching the contract uri of the collection 4 fails, the collection 1 has one, the collection 3 has none
+        let client = MockEvmClient::new("Mock", 100)
+            .with_erc721_collection(address(1), "Mock Collection", "MOCK")
+            .with_erc721_collection(address(3), "Other Collection", "OTHER")
+            .with_erc721_collection(address(4), "Failing Collection", "FAIL")
+            .with_contract_uri(address(1), "https://mock/collection.json")
+            .with_contract_uri(address(4), "https://failing/collection.json")
+            .with_erc721_token_uri(address(1), 1, "https://mock/1")
+            .with_erc721_token_uri(address(1), 2, "https://mock/2")
+            .with_erc721_token_uri(address(3), 1, "https://other/1")
+            .with_erc721_token_uri(address(4), 1, "https://failing/1")
+            .with_log(erc721_transfer_log(address(4), address(0), address(2), 1, 10, 0))
+            .with_log(erc721_transfer_log(address(1), address(0), address(2), 1, 11, 0))
+            .with_log(erc721_transfer_log(address(1), address(0), address(2), 2, 12, 0))
+            .with_log(erc721_transfer_log(address(3), address(0), address(2), 1, 13, 0))
+            .fail_next_contract_uri(rpc_error(-32000, "header not found"));
+        let store = SqliteStore::open_in_memory().await.unwrap();
+
+        let mut callback = BatchedMetadataCallback::default();
+        track_erc721_events(&client, &store, 10, 5, Some(14), &tiny_intervals(), &mut callback)
+            .await
+            .unwrap();
+
+        let contract_uris: Vec<Option<String>> =
+            callback.metadata.into_iter().map(|metadata| metadata.contract_uri).collect();
+        let contract_uri = Some("https://mock/collection.json".to_owned());
+        assert_eq!(vec![None, contract_uri.clone(), contract_uri.clone(), None], contract_uris);
+        // once per collection
+        assert_eq!(3, client.call_count("get_contract_uri"));
+        let saved = store.get_collection_contract_uri("Mock", &format!("{:?}", address(1))).await.unwrap();
+        assert_eq!(contract_uri, saved);
+    }
+
+    async fn block_timestamps_with(fetch_block_timestamps: bool) -> (MockEvmClient, Vec<(u64, Option<u64>)>) {
+        // three events in the block 10 and one in the block 12
+        let collection = address(1);
+        let mut client = MockEvmClient::new("Mock", 100)
+            .with_erc721_collection(collection, "Mock Collection", "MOCK")
+            .with_block_time(1_000, 10);
+        for (token_id, block_number, log_index) in [(1, 10, 0), (2, 10, 1), (3, 10, 2), (4, 12, 0)] {
+            client = client
+                .with_erc721_token_uri(collection, token_id, "https://mock")
+                .with_log(erc721_transfer_log(collection, address(0), address(2), token_id, block_number, log_index));
+        }
+        let store = SqliteStore::open_in_memory().await.unwrap();
+        let config = Erc721TrackerConfig::builder()
+            .start_from(10)
+            .step(5)
+            .end_block(14)
+            .fetch_block_timestamps(fetch_block_timestamps)
+            .options(tiny_intervals())
+            .build()
+            .unwrap();
+
+        let mut callback = EthereumErc721EventCallback { events: vec![] };
+        track_erc721_events_with_config(&client, &store, &config, &mut callback)
+            .await
+            .unwrap();
+        let timestamps = callback
+            .events
+            .iter()
+            .map(|event| (event.block_number.unwrap(), event.block_timestamp))
+            .collect();
+        (client, timestamps)
+    }
+
+    #[tokio::test]
+    async fn test_track_erc721_events_with_block_timestamps() {
+        let (client, timestamps) = block_timestamps_with(true).await;
+
+        assert_eq!(
+            vec![(10, Some(1_100)), (10, Some(1_100)), (10, Some(1_100)), (12, Some(1_120))],
+            timestamps
+        );
+        // once per block
+        assert_eq!(2, client.call_count("get_block_timestamp"));
+    }
+
+    #[tokio::test]
+    async fn test_track_erc721_events_without_block_timestamps() {
+        let (client, timestamps) = block_timestamps_with(false).await;
+
+        assert_eq!(vec![(10, None), (10, None), (10, None), (12, None)], timestamps);
+        assert_eq!(0, client.call_count("get_block_timestamp"));
+    }
+
+    async fn transaction_senders_with(fetch_transaction_senders: bool) -> (MockEvmClient, Vec<Option<H160>>) {
+        // three events in the block 10 and one in the block 12, the sender of the third one is unknown
+        let collection = address(1);
+        let mut client = MockEvmClient::new("Mock", 100)
+            .with_erc721_collection(collection, "Mock Collection", "MOCK")
+            .with_transaction_sender(10, H256::from_low_u64_be(10_000), address(7))
+            .with_transaction_sender(10, H256::from_low_u64_be(10_001), address(8))
+            .with_transaction_sender(12, H256::from_low_u64_be(12_000), address(7));
+        for (token_id, block_number, log_index) in [(1, 10, 0), (2, 10, 1), (3, 10, 2), (4, 12, 0)] {
+            client = client
+                .with_erc721_token_uri(collection, token_id, "https://mock")
+                .with_log(erc721_transfer_log(collection, address(0), address(2), token_id, block_number, log_index));
+        }
+        let store = SqliteStore::open_in_memory().await.unwrap();
+        let config = Erc721TrackerConfig::builder()
+            .start_from(10)
+            .step(5)
+            .end_block(14)
+            .fetch_transaction_senders(fetch_transaction_senders)
+            .options(tiny_intervals())
+            .build()
+            .unwrap();
+
+        let mut callback = EthereumErc721EventCallback { events: vec![] };
+        track_erc721_events_with_config(&client, &store, &config, &mut callback)
+            .await
+            .unwrap();
+        let senders = callback.events.iter().map(|event| event.tx_sender).collect();
+        (client, senders)
+    }
+
+    #[tokio::test]
+    async fn test_track_erc721_events_with_transaction_senders() {
+        let (client, senders) = transaction_senders_with(true).await;
+
+        assert_eq!(vec![Some(address(7)), Some(address(8)), None, Some(address(7))], senders);
+        // once per block
+        assert_eq!(2, client.call_count("get_transaction_senders"));
+    }
+
+    #[tokio::test]
+    async fn test_track_erc721_events_without_transaction_senders() {
+        let (client, senders) = transaction_senders_with(false).await;
+
+        assert_eq!(vec![None, None, None, None], senders);
+        assert_eq!(0, client.call_count("get_transaction_senders"));
+    }
+
+    #[tokio::test]
+    async fn test_verify_owner_holdings() {
+        let collection = address(1);
+        let (alice, bob) = (address(2), address(3));
+        let client = MockEvmClient::new("Mock", 100)
+            .with_erc721_collection(collection, "Mock Collection", "MOCK")
+            .with_log(erc721_transfer_log(collection, address(0), alice, 1, 10, 0))
+            .with_log(erc721_transfer_log(collection, address(0), alice, 2, 10, 1))
+            .with_log(erc721_transfer_log(collection, alice, bob, 2, 11, 0))
+            .with_log(erc721_transfer_log(collection, address(0), alice, 3, 12, 0))
+            .with_log(erc721_transfer_log(collection, alice, address(0), 3, 13, 0))
+            .with_erc721_balance(collection, alice, 1)
+            // one more token than the transfers tell
+            .with_erc721_balance(collection, bob, 2);
+        let store = SqliteStore::open_in_memory().await.unwrap();
+        // only the mints are delivered, the owners follow all the transfers
+        let config = Erc721TrackerConfig::builder()
+            .start_from(10)
+            .step(5)
+            .end_block(14)
+            .event_kinds(EventKindFilter::MintsOnly)
+            .save_owners(true)
+            .options(tiny_intervals())
+            .resume(true)
+            .build()
+            .unwrap();
+        let mut callback = EthereumErc721EventCallback { events: vec![] };
+        track_erc721_events_with_config(&client, &store, &config, &mut callback)
+            .await
+            .unwrap();
+
+        let report = verify_owner_holdings(&store, &client, collection, alice).await.unwrap();
+        assert_eq!(
+            HoldingsReport {
+                collection,
+                owner: alice,
+                block_number: Some(14),
+                indexed: 1,
+                on_chain: Some(U256::from(1)),
+            },
+            report
+        );
+        assert!(!report.has_discrepancy());
+
+        let report = verify_owner_holdings(&store, &client, collection, bob).await.unwrap();
+        assert_eq!((1, Some(U256::from(2))), (report.indexed, report.on_chain));
+        assert!(report.has_discrepancy());
+        assert_eq!(vec![Some(14), Some(14)], client.balance_blocks());
+
+        // no balanceOf to compare with
+        let report = verify_owner_holdings(&store, &client, address(4), bob).await.unwrap();
+        assert_eq!((0, None), (report.indexed, report.on_chain));
+        assert!(!report.has_discrepancy());
+    }
+
+    #[tokio::test]
+    async fn test_track_erc721_events_persist_events() {
+        let collection = address(1);
+        let (alice, bob) = (address(2), address(3));
+        let client = MockEvmClient::new("Mock", 100)
+            .with_erc721_collection(collection, "Mock Collection", "MOCK")
+            .with_erc721_token_uri(collection, 1, "https://mock/1")
+            .with_erc721_token_uri(collection, 2, "https://mock/2")
+            .with_block_time(1_000, 10)
+            .with_log(erc721_transfer_log(collection, address(0), alice, 1, 10, 0))
+            .with_log(erc721_transfer_log(collection, address(0), alice, 2, 10, 1))
+            .with_log(erc721_transfer_log(collection, alice, bob, 1, 12, 0));
+        let store = SqliteStore::open_in_memory().await.unwrap();
+        let config = Erc721TrackerConfig::builder()
+            .start_from(10)
+            .step(5)
+            .end_block(14)
+            .fetch_block_timestamps(true)
+            .persist_events(true)
+            .options(tiny_intervals())
+            .build()
+            .unwrap();
+
+        // the range is scanned twice, its transfers are saved once
+        for _ in 0..2 {
+            let mut callback = EthereumErc721EventCallback { events: vec![] };
+            track_erc721_events_with_config(&client, &store, &config, &mut callback)
+                .await
+                .unwrap();
+            assert_eq!(3, callback.events.len());
+        }
+
+        let collection_id = store.get_collection("Mock", &format!("{:?}", collection)).await.unwrap().unwrap().id;
+        let transfers = store
+            .call(move |conn| erc721_db::get_collection_transfers(conn, collection_id, 10, 14))
+            .await
+            .unwrap();
+        let (zero, alice, bob) = (format!("{:?}", address(0)), format!("{:?}", alice), format!("{:?}", bob));
+        assert_eq!(
+            vec![(zero, alice.clone(), Some(1_100)), (alice, bob, Some(1_120))],
+            store
+                .get_token_transfers(collection_id, "1")
+                .await
+                .unwrap()
+                .into_iter()
+                .map(|transfer| (transfer.from, transfer.to, transfer.timestamp))
+                .collect::<Vec<_>>()
+        );
+        assert_eq!(
+            vec![("1", 10, 0), ("2", 10, 1), ("1", 12, 0)],
+            transfers
+                .iter()
+                .map(|transfer| (&transfer.token_id[..], transfer.block_number, transfer.log_index))
+                .collect::<Vec<_>>()
+        );
+        assert_eq!(format!("{:?}", H256::from_low_u64_be(12_000)), transfers[2].tx_hash);
+    }
+
+    #[tokio::test]
+    async fn test_track_erc721_events_of_two_chains_in_one_database() {
+        // the same contract address and the same transaction hashes on both chains
+        let collection = address(1);
+        let client_of = |chain: &str, name: &str, owner: H160| {
+            MockEvmClient::new(chain, 100)
+                .with_erc721_collection(collection, name, "MOCK")
+                .with_erc721_token_uri(collection, 1, &format!("https://{}/1", chain))
+                .with_log(erc721_transfer_log(collection, address(0), owner, 1, 10, 0))
+        };
+        let ethereum = client_of("Ethereum", "Ethereum Collection", address(2));
+        let bsc = client_of("BSC", "BSC Collection", address(3));
+        let store = SqliteStore::open_in_memory().await.unwrap();
+        let config = Erc721TrackerConfig::builder()
+            .start_from(10)
+            .step(5)
+            .end_block(14)
+            .save_owners(true)
+            .persist_events(true)
+            .options(tiny_intervals())
+            .resume(true)
+            .build()
+            .unwrap();
+
+        let mut metadata = vec![];
+        for client in [&ethereum, &bsc].iter() {
+            let mut callback = MetadataCallback::default();
+            track_erc721_events_with_config(*client, &store, &config, &mut callback)
+                .await
+                .unwrap();
+            metadata.extend(callback.metadata);
+        }
+
+        assert_eq!(
+            vec![
+                (10, "Ethereum Collection".to_owned(), "MOCK".to_owned(), "https://Ethereum/1".to_owned()),
+                (10, "BSC Collection".to_owned(), "MOCK".to_owned(), "https://BSC/1".to_owned()),
+            ],
+            metadata
+        );
+        let address_string = format!("{:?}", collection);
+        for (chain, owner) in [("Ethereum", address(2)), ("BSC", address(3))].iter() {
+            let saved = store.get_collection(chain, &address_string).await.unwrap().unwrap();
+            assert_eq!(Some(format!("{} Collection", chain)), saved.name);
+            let owner = format!("{:?}", owner);
+            assert_eq!(Some(owner.clone()), store.get_token_owner(chain, &address_string, "1").await.unwrap());
+            let transfers = store.get_token_transfers(saved.id, "1").await.unwrap();
+            assert_eq!(vec![owner], transfers.into_iter().map(|transfer| transfer.to).collect::<Vec<_>>());
+            assert_eq!(Some(14), store.get_scan_progress(chain).await.unwrap());
+        }
+    }
+
+    #[tokio::test]
+    async fn test_refresh_token_and_collection_metadata() {
+        let collection = address(1);
+        let client = MockEvmClient::new("Mock", 100)
+            .with_erc721_collection(collection, "Mock Collection", "MOCK")
+            .with_erc721_token_uri(collection, 1, "https://mock/unrevealed")
+            .with_log(erc721_transfer_log(collection, address(0), address(2), 1, 10, 0));
+        let store = SqliteStore::open_in_memory().await.unwrap();
+        let config = Erc721TrackerConfig::builder()
+            .start_from(10)
+            .step(5)
+            .end_block(14)
+            .options(tiny_intervals())
+            .build()
+            .unwrap();
+        let mut callback = EthereumErc721EventCallback { events: vec![] };
+        track_erc721_events_with_config(&client, &store, &config, &mut callback)
+            .await
+            .unwrap();
+        let collection_id = store.get_collection("Mock", &format!("{:?}", collection)).await.unwrap().unwrap().id;
+        let token = store.get_token(collection_id, "1").await.unwrap().unwrap();
+        store.record_token_lookup_failure(token.id, now()).await.unwrap();
+        store.record_collection_lookup_failure(collection_id, now()).await.unwrap();
+
+        // revealed
+        let client = MockEvmClient::new("Mock", 100)
+            .with_erc721_collection(collection, "Revealed Collection", "REVEALED")
+            .with_erc721_token_uri(collection, 1, "https://mock/1");
+        let change = refresh_token_metadata(&store, &client, collection, U256::from(1)).await.unwrap().unwrap();
+        assert_eq!(
+            MetadataChange {
+                old: Some("https://mock/unrevealed".to_owned()),
+                new: Some("https://mock/1".to_owned()),
+            },
+            change
+        );
+        assert!(change.is_changed());
+        // the token is updated in place
+        let tokens = store.call(move |conn| erc721_db::get_tokens_of_collection(conn, collection_id)).await.unwrap();
+        assert_eq!(vec![(token.id, "1".to_owned())], tokens);
+        let refreshed = store.get_token(collection_id, "1").await.unwrap().unwrap();
+        assert_eq!(Some("https://mock/1".to_owned()), refreshed.token_uri);
+        assert_eq!(None, store.get_token_lookup_failures(token.id).await.unwrap());
+
+        let change = refresh_collection_metadata(&store, &client, collection).await.unwrap().unwrap();
+        assert_eq!(
+            MetadataChange {
+                old: Some(("Mock Collection".to_owned(), "MOCK".to_owned())),
+                new: Some(("Revealed Collection".to_owned(), "REVEALED".to_owned())),
+            },
+            change
+        );
+        let refreshed = store.get_collection("Mock", &format!("{:?}", collection)).await.unwrap().unwrap();
+        assert_eq!((collection_id, Some("REVEALED".to_owned())), (refreshed.id, refreshed.symbol));
+        assert_eq!(None, store.get_collection_lookup_failures(collection_id).await.unwrap());
+
+        // refreshed again, nothing changed
+        let change = refresh_token_metadata(&store, &client, collection, U256::from(1)).await.unwrap().unwrap();
+        assert!(!change.is_changed());
+        // not saved
+        assert_eq!(None, refresh_token_metadata(&store, &client, collection, U256::from(2)).await.unwrap());
+        assert_eq!(None, refresh_collection_metadata(&store, &client, address(3)).await.unwrap());
+    }
+
+    async fn sales_with(fetch_sales: bool) -> (MockEvmClient, Vec<Option<Sale>>) {
+        // the token 7537 is sold on Seaport in the transaction of its transfer, the token 8 is only transferred
+        let collection = "0xbc4ca0eda7647a8ab7c2061c2e118a18a936f13d".parse().unwrap();
+        let client = MockEvmClient::new("Mock", 100)
+            .with_erc721_collection(collection, "Mock Collection", "MOCK")
+            .with_erc721_token_uri(collection, 7537, "https://mock/7537")
+            .with_erc721_token_uri(collection, 8, "https://mock/8")
+            .with_log(erc721_transfer_log(collection, address(2), address(3), 7537, 10, 0))
+            .with_log(erc721_transfer_log(collection, address(2), address(3), 8, 10, 1))
+            .with_receipt_logs(
+                H256::from_low_u64_be(10_000),
+                receipt_logs(include_str!("./fixtures/seaport_listing_receipt.json")),
+            );
+        let store = SqliteStore::open_in_memory().await.unwrap();
+        let config = Erc721TrackerConfig::builder()
+            .start_from(10)
+            .step(5)
+            .end_block(14)
+            .fetch_sales(fetch_sales)
+            .options(tiny_intervals())
+            .build()
+            .unwrap();
+
+        let mut callback = EthereumErc721EventCallback { events: vec![] };
+        track_erc721_events_with_config(&client, &store, &config, &mut callback)
+            .await
+            .unwrap();
+        let sales = callback.events.iter().map(|event| event.sale.clone()).collect();
+        (client, sales)
+    }
+
+    #[tokio::test]
+    async fn test_track_erc721_events_with_sales() {
+        let (client, sales) = sales_with(true).await;
+
+        let sale = Sale {
+            marketplace: Marketplace::Seaport,
+            price_wei: U256::exp10(19),
+            currency: H160::zero(),
+        };
+        assert_eq!(vec![Some(sale), None], sales);
+        // once per transaction
+        assert_eq!(2, client.call_count("get_transaction_logs"));
+    }
+
+    #[tokio::test]
+    async fn test_track_erc721_events_without_sales() {
+        let (client, sales) = sales_with(false).await;
 
-        std::fs::remove_file("./test7.db").unwrap();
+        assert_eq!(vec![None, None], sales);
+        assert_eq!(0, client.call_count("get_transaction_logs"));
     }
 }
@@ -1,157 +1,1718 @@
 //! This module defines several functions to access ERC721 metadata in the database.
-use crate::Result;
+//! The getters only read, they can run on a read-only connection while a tracker writes the database.
+use crate::{Error, Result};
 
-use rusqlite::{params, Connection};
+use rusqlite::{params, Connection, ErrorCode};
+use std::{io::Write, ops::RangeInclusive, time::Duration};
 
-/// This function is used to create the tables used to store the ERC721 metadatas
+/// How long a statement waits for a lock of the database held by another connection
+pub const BUSY_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// How many more times a write is tried when the database is still locked after `BUSY_TIMEOUT`
+const BUSY_RETRIES: u32 = 3;
+
+/// Configure a connection of a tracker, which writes while other connections read the database: with the WAL
+/// journal the readers do not block the writes, the journal is synced at its checkpoints only, and a locked database
+/// is waited for up to `BUSY_TIMEOUT`. An in-memory database keeps its journal in memory.
+pub fn configure_connection(conn: &Connection) -> Result<()> {
+    // the pragma returns the journal mode
+    conn.query_row("PRAGMA journal_mode=WAL", [], |row| row.get::<_, String>(0))?;
+    conn.execute_batch("PRAGMA synchronous=NORMAL")?;
+    conn.busy_timeout(BUSY_TIMEOUT)?;
+    Ok(())
+}
+
+/// Run a write, again up to `BUSY_RETRIES` times while the database is locked by another connection.
+fn retry_busy<T>(mut write: impl FnMut() -> rusqlite::Result<T>) -> rusqlite::Result<T> {
+    let mut retries = 0;
+    loop {
+        match write() {
+            Err(rusqlite::Error::SqliteFailure(err, _))
+                if err.code == ErrorCode::DatabaseBusy && retries < BUSY_RETRIES =>
+            {
+                retries += 1;
+                warn!("The database is locked, write again ({}/{}).", retries, BUSY_RETRIES);
+            }
+            result => return result,
+        }
+    }
+}
+
+/// The version of the database schema, the number of its migrations
+pub const SCHEMA_VERSION: u32 = 6;
+
+/// A migration of the database schema to the next version
+type Migration = fn(&Connection) -> Result<()>;
+
+/// The migrations in order, the one at index `i` upgrades the databases at version `i` to the version `i + 1`
+const MIGRATIONS: [Migration; SCHEMA_VERSION as usize] = [
+    create_tables,
+    add_transfer_history,
+    add_unique_tokens,
+    add_tokens_by_collection,
+    add_progress_updated_at,
+    add_chains,
+];
+
+/// This function is used to create the tables used to store the ERC721 metadatas,
+/// or to upgrade the tables of an older version, see `migrate`.
 pub fn create_tables_if_not_exist(conn: &Connection) -> Result<()> {
+    migrate(conn)
+}
+
+/// Apply in order the migrations the database is missing, each one in a transaction with the version it upgrades
+/// the database to. A database at a version newer than `SCHEMA_VERSION` is refused, as its tables are unknown.
+/// A database with the rows saved before the chains were namespaced is migrated but refused, a tracker would not find
+/// them and save their collections again: it is migrated with `migrate_with_chain` instead.
+pub fn migrate(conn: &Connection) -> Result<()> {
+    migrate_to(conn, SCHEMA_VERSION)?;
+    if has_unstamped_rows(conn)? {
+        return Err(Error::UnstampedChain);
+    }
+    Ok(())
+}
+
+/// Migrate the database of the tracker of `chain` as `migrate` does, and assign the rows saved before the chains
+/// were namespaced to `chain`, see `stamp_chain`.
+pub fn migrate_with_chain(conn: &Connection, chain: &str) -> Result<()> {
+    migrate_to(conn, SCHEMA_VERSION)?;
+    stamp_chain(conn, chain)?;
+    Ok(())
+}
+
+/// Whether some collections or owners have the empty chain, as the ones saved before the chains were namespaced
+fn has_unstamped_rows(conn: &Connection) -> Result<bool> {
+    let unstamped = conn.query_row(
+        "SELECT exists (SELECT 1 from erc721_collections where chain='')
+             or exists (SELECT 1 from erc721_token_owners where chain='')",
+        [],
+        |row| row.get(0),
+    )?;
+    Ok(unstamped)
+}
+
+fn migrate_to(conn: &Connection, target_version: u32) -> Result<()> {
+    conn.execute("create table if not exists schema_version (version integer primary key)", [])?;
+    let version = get_schema_version(conn)?;
+    if version > SCHEMA_VERSION {
+        return Err(Error::SchemaTooNew {
+            version,
+            supported: SCHEMA_VERSION,
+        });
+    }
+    for new_version in (version + 1)..=target_version {
+        let tx = conn.unchecked_transaction()?;
+        MIGRATIONS[new_version as usize - 1](&tx)?;
+        tx.execute("INSERT INTO schema_version (version) values (?1)", params![new_version as i64])?;
+        tx.commit()?;
+    }
+    Ok(())
+}
+
+/// Get the version of the database schema, 0 if the database was created before the schema was versioned.
+pub fn get_schema_version(conn: &Connection) -> Result<u32> {
+    let mut stmt = match conn.prepare("SELECT max(version) from schema_version") {
+        Ok(stmt) => stmt,
+        Err(_) => return Ok(0),
+    };
+    let version = stmt.query_row([], |row| row.get::<_, Option<i64>>(0))?;
+    Ok(version.unwrap_or_default() as u32)
+}
+
+/// The migration #1, the tables of the databases created before the schema was versioned.
+/// Their columns were added one by one since, the ones a database is missing are added.
+fn create_tables(conn: &Connection) -> Result<()> {
     conn.execute(
         "create table if not exists erc721_collections (
              id integer primary key,
              address text not null unique,
              name text,
-             symbol text
+             symbol text,
+             creation_block integer,
+             metadata_attempts integer,
+             last_attempt_at integer,
+             supports_erc721 integer,
+             royalty_receiver text,
+             royalty_bps integer,
+             contract_uri text,
+             snapshot_block integer,
+             snapshot_index integer,
+             contract_code integer,
+             implementation text,
+             last_checked_block integer
          )",
         [],
     )?;
+    // the databases created before the creation block was cached do not have the column
+    if conn.prepare("SELECT creation_block from erc721_collections").is_err() {
+        conn.execute("ALTER TABLE erc721_collections ADD COLUMN creation_block integer", [])?;
+    }
     conn.execute(
         "create table if not exists erc721_tokens (
              id integer primary key,
              token_id text not null,
              collection_id integer not null references erc721_collections(id),
-             token_uri text
+             token_uri text,
+             last_fetched_at integer,
+             metadata_attempts integer,
+             last_attempt_at integer,
+             stale integer
+         )",
+        [],
+    )?;
+    // the databases created before the token uris were refreshed do not have the column
+    if conn.prepare("SELECT last_fetched_at from erc721_tokens").is_err() {
+        conn.execute("ALTER TABLE erc721_tokens ADD COLUMN last_fetched_at integer", [])?;
+    }
+    // the databases created before the failed lookups were retried do not have the columns
+    for table in &["erc721_collections", "erc721_tokens"] {
+        if conn.prepare(&format!("SELECT metadata_attempts from {}", table)).is_err() {
+            conn.execute(&format!("ALTER TABLE {} ADD COLUMN metadata_attempts integer", table), [])?;
+            conn.execute(&format!("ALTER TABLE {} ADD COLUMN last_attempt_at integer", table), [])?;
+        }
+    }
+    // the databases created before the metadata updates were tracked do not have the column
+    if conn.prepare("SELECT stale from erc721_tokens").is_err() {
+        conn.execute("ALTER TABLE erc721_tokens ADD COLUMN stale integer", [])?;
+    }
+    // the databases created before the contracts were checked with ERC165 do not have the column
+    if conn.prepare("SELECT supports_erc721 from erc721_collections").is_err() {
+        conn.execute("ALTER TABLE erc721_collections ADD COLUMN supports_erc721 integer", [])?;
+    }
+    // the databases created before the royalties were fetched do not have the columns
+    if conn.prepare("SELECT royalty_bps from erc721_collections").is_err() {
+        conn.execute("ALTER TABLE erc721_collections ADD COLUMN royalty_receiver text", [])?;
+        conn.execute("ALTER TABLE erc721_collections ADD COLUMN royalty_bps integer", [])?;
+    }
+    // the databases created before the contract uris were fetched do not have the column
+    if conn.prepare("SELECT contract_uri from erc721_collections").is_err() {
+        conn.execute("ALTER TABLE erc721_collections ADD COLUMN contract_uri text", [])?;
+    }
+    // the databases created before the collections were snapshot do not have the columns
+    if conn.prepare("SELECT snapshot_index from erc721_collections").is_err() {
+        conn.execute("ALTER TABLE erc721_collections ADD COLUMN snapshot_block integer", [])?;
+        conn.execute("ALTER TABLE erc721_collections ADD COLUMN snapshot_index integer", [])?;
+    }
+    // the databases created before the addresses were checked for code do not have the column
+    if conn.prepare("SELECT contract_code from erc721_collections").is_err() {
+        conn.execute("ALTER TABLE erc721_collections ADD COLUMN contract_code integer", [])?;
+    }
+    // the databases created before the proxies were resolved do not have the columns
+    if conn.prepare("SELECT last_checked_block from erc721_collections").is_err() {
+        conn.execute("ALTER TABLE erc721_collections ADD COLUMN implementation text", [])?;
+        conn.execute("ALTER TABLE erc721_collections ADD COLUMN last_checked_block integer", [])?;
+    }
+    conn.execute(
+        "create table if not exists erc721_token_owners (
+             address text not null,
+             token_id text not null,
+             owner text not null,
+             block_number integer not null,
+             primary key(address, token_id)
+         )",
+        [],
+    )?;
+    conn.execute(
+        "create table if not exists scan_progress (
+             chain text primary key,
+             last_scanned_block integer not null
+         )",
+        [],
+    )?;
+    conn.execute(
+        "create table if not exists delivered_events (
+             chain text not null,
+             transaction_hash text not null,
+             log_index integer not null,
+             block_number integer not null,
+             unique(chain, transaction_hash, log_index)
+         )",
+        [],
+    )?;
+    conn.execute(
+        "create table if not exists scanned_blocks (
+             chain text not null,
+             block_number integer not null,
+             block_hash text not null,
+             primary key(chain, block_number)
+         )",
+        [],
+    )?;
+
+    Ok(())
+}
+
+/// The migration #2, the position of the saved owners and the history of the transfers.
+fn add_transfer_history(conn: &Connection) -> Result<()> {
+    // the databases created before the schema was versioned may have the columns already
+    if conn.prepare("SELECT burnt from erc721_token_owners").is_err() {
+        conn.execute("ALTER TABLE erc721_token_owners ADD COLUMN log_index integer", [])?;
+        conn.execute("ALTER TABLE erc721_token_owners ADD COLUMN burnt integer", [])?;
+    }
+    // the tokens expanded from one ERC2309 batch share its log index
+    conn.execute(
+        "create table if not exists transfers (
+             id integer primary key,
+             collection_id integer not null references erc721_collections(id),
+             token_id text not null,
+             from_address text not null,
+             to_address text not null,
+             block_number integer not null,
+             tx_hash text not null,
+             log_index integer not null,
+             timestamp integer,
+             unique(tx_hash, log_index, token_id)
+         )",
+        [],
+    )?;
+    conn.execute(
+        "create index if not exists transfers_of_tokens on transfers (collection_id, token_id, block_number)",
+        [],
+    )?;
+    Ok(())
+}
+
+/// The migration #3, a token saved once per collection, as the address of a collection has been unique since
+/// the first tables. Of the tokens saved twice, the first one is kept, the one `get_token` returned.
+fn add_unique_tokens(conn: &Connection) -> Result<()> {
+    conn.execute(
+        "DELETE FROM erc721_tokens where id not in (SELECT min(id) from erc721_tokens group by collection_id, token_id)",
+        [],
+    )?;
+    conn.execute(
+        "create unique index if not exists erc721_tokens_of_collections on erc721_tokens (collection_id, token_id)",
+        [],
+    )?;
+    Ok(())
+}
+
+/// The migration #4, the tokens of each collection in the order they were saved, for `get_tokens`
+/// and `count_tokens_per_collection`. The index ends with the database id of the tokens.
+fn add_tokens_by_collection(conn: &Connection) -> Result<()> {
+    conn.execute("create index if not exists erc721_tokens_by_collection on erc721_tokens (collection_id)", [])?;
+    Ok(())
+}
+
+/// The migration #5, when the progress of each tracker was saved, for `status`.
+fn add_progress_updated_at(conn: &Connection) -> Result<()> {
+    // the table created by `erc1155_db` has the column already
+    if conn.prepare("SELECT updated_at from scan_progress").is_err() {
+        conn.execute("ALTER TABLE scan_progress ADD COLUMN updated_at integer", [])?;
+    }
+    Ok(())
+}
+
+/// The migration #6, the collections and the owners of several chains in one database, as an address is not
+/// unique across the chains. The tokens and the transfers are of the chain of their collection, the transfers of
+/// the chains sharing a transaction hash, as the replayed ones, are saved per collection. The tables are rebuilt
+/// with the new unique constraints, the saved rows get the empty chain, until `stamp_chain` assigns their chain.
+fn add_chains(conn: &Connection) -> Result<()> {
+    let collection_columns = "id, address, name, symbol, creation_block, metadata_attempts, last_attempt_at,
+        supports_erc721, royalty_receiver, royalty_bps, contract_uri, snapshot_block, snapshot_index, contract_code,
+        implementation, last_checked_block";
+    conn.execute(
+        "create table erc721_collections_of_chains (
+             id integer primary key,
+             chain text not null default '',
+             address text not null,
+             name text,
+             symbol text,
+             creation_block integer,
+             metadata_attempts integer,
+             last_attempt_at integer,
+             supports_erc721 integer,
+             royalty_receiver text,
+             royalty_bps integer,
+             contract_uri text,
+             snapshot_block integer,
+             snapshot_index integer,
+             contract_code integer,
+             implementation text,
+             last_checked_block integer,
+             unique(chain, address)
+         )",
+        [],
+    )?;
+    conn.execute(
+        &format!(
+            "INSERT INTO erc721_collections_of_chains ({columns}) SELECT {columns} from erc721_collections",
+            columns = collection_columns
+        ),
+        [],
+    )?;
+    conn.execute("DROP TABLE erc721_collections", [])?;
+    conn.execute("ALTER TABLE erc721_collections_of_chains RENAME TO erc721_collections", [])?;
+
+    // the rowids are kept, `get_tokens_by_owner` returns the tokens in the order of the saves
+    conn.execute(
+        "create table erc721_token_owners_of_chains (
+             chain text not null default '',
+             address text not null,
+             token_id text not null,
+             owner text not null,
+             block_number integer not null,
+             log_index integer,
+             burnt integer,
+             primary key(chain, address, token_id)
          )",
         [],
     )?;
+    conn.execute(
+        "INSERT INTO erc721_token_owners_of_chains (rowid, address, token_id, owner, block_number, log_index, burnt)
+             SELECT rowid, address, token_id, owner, block_number, log_index, burnt from erc721_token_owners",
+        [],
+    )?;
+    conn.execute("DROP TABLE erc721_token_owners", [])?;
+    conn.execute("ALTER TABLE erc721_token_owners_of_chains RENAME TO erc721_token_owners", [])?;
 
+    let transfer_columns =
+        "id, collection_id, token_id, from_address, to_address, block_number, tx_hash, log_index, timestamp";
+    conn.execute(
+        "create table transfers_of_chains (
+             id integer primary key,
+             collection_id integer not null references erc721_collections(id),
+             token_id text not null,
+             from_address text not null,
+             to_address text not null,
+             block_number integer not null,
+             tx_hash text not null,
+             log_index integer not null,
+             timestamp integer,
+             unique(collection_id, tx_hash, log_index, token_id)
+         )",
+        [],
+    )?;
+    conn.execute(
+        &format!(
+            "INSERT INTO transfers_of_chains ({columns}) SELECT {columns} from transfers",
+            columns = transfer_columns
+        ),
+        [],
+    )?;
+    conn.execute("DROP TABLE transfers", [])?;
+    conn.execute("ALTER TABLE transfers_of_chains RENAME TO transfers", [])?;
+    conn.execute(
+        "create index if not exists transfers_of_tokens on transfers (collection_id, token_id, block_number)",
+        [],
+    )?;
     Ok(())
 }
 
+/// Assign the collections and the owners saved before the chains were namespaced, with the empty chain, to
+/// `chain`. The database of a single chain is stamped once after its upgrade, before its tracker runs again:
+/// the tracker of a chain does not find the rows of another chain. A collection the tracker of `chain` saved again
+/// meanwhile is merged with the saved one: the saved tokens and transfers move to the collection of the chain, whose
+/// own tokens and owners are newer and win. It returns how many collections were stamped, the merged ones included.
+pub fn stamp_chain(conn: &Connection, chain: &str) -> Result<u64> {
+    let tx = conn.unchecked_transaction()?;
+    let duplicates = {
+        let mut stmt = tx.prepare(
+            "SELECT unstamped.id, stamped.id from erc721_collections unstamped
+                 join erc721_collections stamped on stamped.address=unstamped.address and stamped.chain=?1
+                 where unstamped.chain=''",
+        )?;
+        let rows = stmt.query_map(params![chain], |row| Ok((row.get::<_, i64>(0)?, row.get::<_, i64>(1)?)))?;
+        rows.collect::<rusqlite::Result<Vec<_>>>()?
+    };
+    for (unstamped, stamped) in &duplicates {
+        for table in &["erc721_tokens", "transfers"] {
+            // the rows the collection of the chain has already are left behind and deleted with the old collection
+            tx.execute(
+                &format!("UPDATE OR IGNORE {} set collection_id=?1 where collection_id=?2", table),
+                params![stamped, unstamped],
+            )?;
+            tx.execute(&format!("DELETE FROM {} where collection_id=?1", table), params![unstamped])?;
+        }
+        tx.execute("DELETE FROM erc721_collections where id=?1", params![unstamped])?;
+    }
+    let stamped = tx.execute("UPDATE erc721_collections set chain=?1 where chain=''", params![chain])?;
+    tx.execute("UPDATE OR IGNORE erc721_token_owners set chain=?1 where chain=''", params![chain])?;
+    tx.execute("DELETE FROM erc721_token_owners where chain=''", [])?;
+    tx.commit()?;
+    Ok((duplicates.len() + stamped) as u64)
+}
+
+/// A ERC721 contract saved in the database
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Collection {
+    /// The database id of the collection
+    pub id: usize,
+    /// The address of the contract, as formatted by `{:?}`
+    pub address: String,
+    /// The name of the collection, None if the contract has none or if it is not looked up yet
+    pub name: Option<String>,
+    /// The symbol of the collection, None if the contract has none or if it is not looked up yet
+    pub symbol: Option<String>,
+}
+
+impl Collection {
+    /// The name and the symbol of the collection, if it has both
+    pub fn name_symbol(&self) -> Option<(String, String)> {
+        self.name.clone().zip(self.symbol.clone())
+    }
+}
+
+/// Get a ERC721 contract of a chain from database, with its name and symbol.
+pub fn get_collection(conn: &Connection, chain: &str, address: &str) -> Result<Option<Collection>> {
+    let mut stmt = conn.prepare_cached(
+        "SELECT id, address, name, symbol from erc721_collections where chain=?1 and address=?2",
+    )?;
+
+    match stmt.query_row(params![chain, address], collection_from_row) {
+        Ok(collection) => Ok(Some(collection)),
+        Err(_err @ rusqlite::Error::QueryReturnedNoRows) => Ok(None),
+        Err(err) => Err(err)?,
+    }
+}
+
+/// A collection from a row of `id, address, name, symbol`
+fn collection_from_row(row: &rusqlite::Row) -> rusqlite::Result<Collection> {
+    Ok(Collection {
+        id: row.get(0)?,
+        address: row.get(1)?,
+        name: row.get(2)?,
+        symbol: row.get(3)?,
+    })
+}
+
+/// Get the saved ERC721 contracts of a chain by page, in the order they were saved: the `limit` ones saved after
+/// the one whose database id is `after`, or the first ones if it is None. The next page is after the last collection.
+pub fn get_collections(conn: &Connection, chain: &str, after: Option<usize>, limit: u32) -> Result<Vec<Collection>> {
+    let mut stmt = conn.prepare(
+        "SELECT id, address, name, symbol from erc721_collections where chain=?1 and id>?2 order by id limit ?3",
+    )?;
+    let rows = stmt.query_map(params![chain, after.map_or(0, |after| after as i64), limit], collection_from_row)?;
+    let mut collections = vec![];
+    for row in rows {
+        collections.push(row?);
+    }
+    Ok(collections)
+}
+
 /// Get the name and symbol of a ERC721 contract.
 /// The returned tuple is (_, contract_address, name, symbol), the name and symbol may be None
 /// if the contract has no name and symbol.
+#[deprecated(note = "use `get_collection`, which returns a `Collection`")]
 pub fn get_collection_from_db(
     conn: &Connection,
+    chain: &str,
     address: &str,
 ) -> Result<Option<(usize, String, Option<String>, Option<String>)>> {
-    let sql = format!(
-        "SELECT * from erc721_collections where address='{}'",
-        address
-    );
-    let mut stmt = conn.prepare(sql.as_str())?;
-
-    match stmt.query_row([], |row| {
-        Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?))
-    }) {
-        Ok(collection) => Ok(Some(collection)),
-        Err(_err @ rusqlite::Error::QueryReturnedNoRows) => Ok(None),
-        Err(err) => Err(err)?,
-    }
+    Ok(get_collection(conn, chain, address)?.map(|collection| {
+        (collection.id, collection.address, collection.name, collection.symbol)
+    }))
 }
 
-/// Save the name and symbol of a ERC721 contract to database.
+/// Save the name and symbol of a ERC721 contract of a chain to database.
+/// A contract saved already, as by another tracker sharing the database, gets the name and symbol if both are some.
 /// It returns the database id.
 pub fn add_collection_to_db(
     conn: &Connection,
+    chain: &str,
     address: String,
     name: Option<String>,
     symbol: Option<String>,
 ) -> Result<usize> {
     if name.is_some() && symbol.is_some() {
-        conn.execute(
-            "INSERT INTO erc721_collections (address, name, symbol) values (?1, ?2, ?3)",
-            params![&address, &name, &symbol],
-        )?;
+        retry_busy(|| {
+            conn.prepare_cached(
+                "INSERT INTO erc721_collections (chain, address, name, symbol) values (?1, ?2, ?3, ?4)
+                     ON CONFLICT(chain, address) DO UPDATE set name=excluded.name, symbol=excluded.symbol",
+            )?
+            .execute(params![chain, &address, &name, &symbol])
+        })?;
     } else {
-        conn.execute(
-            "INSERT INTO erc721_collections (address) values (?1)",
-            params![&address],
-        )?;
+        retry_busy(|| {
+            conn.prepare_cached(
+                "INSERT INTO erc721_collections (chain, address) values (?1, ?2)
+                     ON CONFLICT(chain, address) DO NOTHING",
+            )?
+            .execute(params![chain, &address])
+        })?;
     }
-    let id = conn.last_insert_rowid() as usize;
-    Ok(id)
+    get_collection_id(conn, chain, &address)
 }
 
-// pub fn save_collection_if_not_exists(conn: &Connection, event: &Erc721Event, metadata: Option<(String, String, String)>) -> Result<(usize, String, Option<String>, Option<String>)> {
-//     let collection_result = erc721::get_collection_from_db(conn, event.address.clone())?;
-//     let collection = collection_result.unwrap_or_else(|| {
-//         if let Some(metadata) = metadata {
-//             add_collection_to_db(&self.db_conn, event.address.clone(), metadata.0, metadata.1)?
-//         } else {
-//             add_collection_to_db(&self.db_conn, event.address.clone(), None, None)?
-//         }
-//     });
-//     Ok(collection)
-// }
+/// The database id of a saved contract, as the last insert is not the contract when it was saved already
+fn get_collection_id(conn: &Connection, chain: &str, address: &str) -> Result<usize> {
+    let mut stmt = conn.prepare_cached("SELECT id from erc721_collections where chain=?1 and address=?2")?;
+    Ok(stmt.query_row(params![chain, address], |row| row.get(0))?)
+}
 
-// pub fn save_token_if_not_exists(conn: &Connection, event: &Erc721Event, metadata: Option<(String, String, String)>) -> Result<(usize, String, Option<String>)> {
-// }
+/// Save a contract whose metadata is not looked up yet, with whether it supports ERC721 as in
+/// `save_collection_erc721_support`. Its metadata lookup is due right away, as if it had never failed.
+/// A contract saved already only gets whether it supports ERC721. It returns the database id.
+pub fn add_collection_with_erc721_support(
+    conn: &Connection,
+    chain: &str,
+    address: String,
+    supports_erc721: Option<bool>,
+) -> Result<usize> {
+    conn.execute(
+        "INSERT INTO erc721_collections (chain, address, supports_erc721, metadata_attempts, last_attempt_at)
+             values (?1, ?2, ?3, 0, 0)
+             ON CONFLICT(chain, address) DO UPDATE set supports_erc721=excluded.supports_erc721",
+        params![chain, &address, erc721_support_to_db(supports_erc721)],
+    )?;
+    get_collection_id(conn, chain, &address)
+}
 
-/// Get the token_uri of a ERC721 token from database.
-/// token_id here is the `token_id` in contract.
-/// The returned tuple is (_, contract_address, _, token_uri)
-pub fn get_token_from_db(
+/// Get whether a contract reported supporting ERC721 with ERC165.
+/// None if it has not been checked yet, Some(None) if the contract does not implement ERC165.
+pub fn get_collection_erc721_support(conn: &Connection, collection_id: usize) -> Result<Option<Option<bool>>> {
+    let mut stmt = conn.prepare("SELECT supports_erc721 from erc721_collections where id=?1")?;
+
+    match stmt.query_row(params![collection_id as i64], |row| row.get::<_, Option<i64>>(0)) {
+        Ok(Some(1)) => Ok(Some(Some(true))),
+        Ok(Some(0)) => Ok(Some(Some(false))),
+        Ok(Some(_)) => Ok(Some(None)),
+        Ok(None) => Ok(None),
+        Err(_err @ rusqlite::Error::QueryReturnedNoRows) => Ok(None),
+        Err(err) => Err(err)?,
+    }
+}
+
+/// Cache whether a contract reported supporting ERC721 with ERC165, None if the contract does not implement ERC165.
+pub fn save_collection_erc721_support(
     conn: &Connection,
     collection_id: usize,
-    token_id: &str,
-) -> Result<Option<(usize, String, usize, Option<String>)>> {
-    let sql = format!(
-        "SELECT * from erc721_tokens where collection_id={} and token_id='{}'",
-        collection_id, token_id
-    );
-    let mut stmt = conn.prepare(sql.as_str())?;
+    supports_erc721: Option<bool>,
+) -> Result<()> {
+    conn.execute(
+        "UPDATE erc721_collections set supports_erc721=?1 where id=?2",
+        params![erc721_support_to_db(supports_erc721), collection_id as i64],
+    )?;
+    Ok(())
+}
 
-    match stmt.query_row([], |row| {
-        Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?))
-    }) {
-        Ok(token) => Ok(Some(token)),
+/// 1 if the contract supports ERC721, 0 if it does not, -1 if it does not implement ERC165
+fn erc721_support_to_db(supports_erc721: Option<bool>) -> i64 {
+    match supports_erc721 {
+        Some(true) => 1,
+        Some(false) => 0,
+        None => -1,
+    }
+}
+
+/// The contract code at the address of a ERC721 collection, when it was last checked
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CollectionCode {
+    /// The address has some contract code
+    Live,
+    /// The address had no code when it was first seen, as an externally owned account
+    Missing,
+    /// The code of the contract was removed since it was first seen, as by a selfdestruct
+    Removed,
+}
+
+/// Get the contract code at the address of a ERC721 collection, None if it has not been checked yet.
+pub fn get_collection_code(conn: &Connection, collection_id: usize) -> Result<Option<CollectionCode>> {
+    let mut stmt = conn.prepare("SELECT contract_code from erc721_collections where id=?1")?;
+
+    match stmt.query_row(params![collection_id as i64], |row| row.get::<_, Option<i64>>(0)) {
+        Ok(Some(1)) => Ok(Some(CollectionCode::Live)),
+        Ok(Some(0)) => Ok(Some(CollectionCode::Missing)),
+        Ok(Some(_)) => Ok(Some(CollectionCode::Removed)),
+        Ok(None) => Ok(None),
         Err(_err @ rusqlite::Error::QueryReturnedNoRows) => Ok(None),
         Err(err) => Err(err)?,
     }
 }
 
-/// Save the token uri to database.
-/// It returns the database id.
-pub fn add_token_to_db(
+/// Cache the contract code at the address of a ERC721 collection,
+/// 1 if it has some code, 0 if it never had, -1 if it was removed
+pub fn save_collection_code(conn: &Connection, collection_id: usize, code: CollectionCode) -> Result<()> {
+    let code = match code {
+        CollectionCode::Live => 1,
+        CollectionCode::Missing => 0,
+        CollectionCode::Removed => -1,
+    };
+    conn.execute(
+        "UPDATE erc721_collections set contract_code=?1 where id=?2",
+        params![code, collection_id as i64],
+    )?;
+    Ok(())
+}
+
+/// Get the cached block where a ERC721 contract was created.
+pub fn get_collection_creation_block(conn: &Connection, chain: &str, address: &str) -> Result<Option<u64>> {
+    let mut stmt = conn.prepare("SELECT creation_block from erc721_collections where chain=?1 and address=?2")?;
+
+    match stmt.query_row(params![chain, address], |row| row.get::<_, Option<i64>>(0)) {
+        Ok(block_number) => Ok(block_number.map(|block_number| block_number as u64)),
+        Err(_err @ rusqlite::Error::QueryReturnedNoRows) => Ok(None),
+        Err(err) => Err(err)?,
+    }
+}
+
+/// Cache the block where a ERC721 contract was created.
+pub fn save_collection_creation_block(
     conn: &Connection,
-    token_id: String,
     collection_id: usize,
-    token_uri: Option<String>,
-) -> Result<usize> {
-    if token_uri.is_some() {
-        conn.execute(
-            "INSERT INTO erc721_tokens (token_id, collection_id, token_uri) values (?1, ?2, ?3)",
-            params![&token_id, &collection_id, &token_uri],
-            // &[&token_id, &collection_id.to_string(), &the_token_uri],
-        )?;
-    } else {
-        conn.execute(
-            "INSERT INTO erc721_tokens (token_id, collection_id) values (?1, ?2)",
-            params![&token_id, &collection_id],
-        )?;
+    block_number: u64,
+) -> Result<()> {
+    conn.execute(
+        "UPDATE erc721_collections set creation_block=?1 where id=?2",
+        params![block_number as i64, collection_id as i64],
+    )?;
+    Ok(())
+}
+
+/// Get the last ERC2981 royalty saved for a ERC721 contract: its receiver and its basis points.
+/// None if the contract is not saved, or if it had no royalty.
+pub fn get_collection_royalty(conn: &Connection, chain: &str, address: &str) -> Result<Option<(String, u64)>> {
+    let mut stmt =
+        conn.prepare("SELECT royalty_receiver, royalty_bps from erc721_collections where chain=?1 and address=?2")?;
+
+    match stmt.query_row(params![chain, address], |row| {
+        Ok((row.get::<_, Option<String>>(0)?, row.get::<_, Option<i64>>(1)?))
+    }) {
+        Ok((Some(receiver), Some(bps))) => Ok(Some((receiver, bps as u64))),
+        Ok(_) => Ok(None),
+        Err(_err @ rusqlite::Error::QueryReturnedNoRows) => Ok(None),
+        Err(err) => Err(err)?,
+    }
+}
+
+/// Save the last ERC2981 royalty of a ERC721 contract, None if it has none anymore.
+pub fn save_collection_royalty(conn: &Connection, collection_id: usize, royalty: Option<(&str, u64)>) -> Result<()> {
+    conn.execute(
+        "UPDATE erc721_collections set royalty_receiver=?1, royalty_bps=?2 where id=?3",
+        params![
+            royalty.map(|(receiver, _)| receiver),
+            royalty.map(|(_, bps)| bps as i64),
+            collection_id as i64
+        ],
+    )?;
+    Ok(())
+}
+
+/// Get the uri of the collection-level metadata of a ERC721 contract, from its `contractURI`.
+/// None if the contract is not saved, or if it has no contract uri.
+pub fn get_collection_contract_uri(conn: &Connection, chain: &str, address: &str) -> Result<Option<String>> {
+    let mut stmt = conn.prepare("SELECT contract_uri from erc721_collections where chain=?1 and address=?2")?;
+
+    match stmt.query_row(params![chain, address], |row| row.get(0)) {
+        Ok(contract_uri) => Ok(contract_uri),
+        Err(_err @ rusqlite::Error::QueryReturnedNoRows) => Ok(None),
+        Err(err) => Err(err)?,
     }
+}
+
+/// Save the uri of the collection-level metadata of a ERC721 contract
+pub fn save_collection_contract_uri(conn: &Connection, collection_id: usize, contract_uri: Option<&str>) -> Result<()> {
+    conn.execute(
+        "UPDATE erc721_collections set contract_uri=?1 where id=?2",
+        params![contract_uri, collection_id as i64],
+    )?;
+    Ok(())
+}
 
-    let id = conn.last_insert_rowid() as usize;
-    Ok(id)
+/// Get the EIP-1967 implementation of a ERC721 contract and the block it was last resolved at.
+/// None if the contract is not saved, or if it was never resolved. The implementation is None if it is not a proxy.
+pub fn get_collection_implementation(
+    conn: &Connection,
+    chain: &str,
+    address: &str,
+) -> Result<Option<(Option<String>, u64)>> {
+    let mut stmt = conn.prepare(
+        "SELECT implementation, last_checked_block from erc721_collections where chain=?1 and address=?2",
+    )?;
+
+    match stmt.query_row(params![chain, address], |row| {
+        Ok((row.get::<_, Option<String>>(0)?, row.get::<_, Option<i64>>(1)?))
+    }) {
+        Ok((implementation, Some(block_number))) => Ok(Some((implementation, block_number as u64))),
+        Ok(_) => Ok(None),
+        Err(_err @ rusqlite::Error::QueryReturnedNoRows) => Ok(None),
+        Err(err) => Err(err)?,
+    }
+}
+
+/// Save the EIP-1967 implementation of a ERC721 contract resolved at `block_number`, None if it is not a proxy
+pub fn save_collection_implementation(
+    conn: &Connection,
+    collection_id: usize,
+    implementation: Option<&str>,
+    block_number: u64,
+) -> Result<()> {
+    conn.execute(
+        "UPDATE erc721_collections set implementation=?1, last_checked_block=?2 where id=?3",
+        params![implementation, block_number as i64, collection_id as i64],
+    )?;
+    Ok(())
+}
+
+/// Get the progress of the last snapshot of a ERC721 contract: its block and the index of the next token to save.
+/// None if the contract is not saved, or if it was never snapshot.
+pub fn get_collection_snapshot(conn: &Connection, chain: &str, address: &str) -> Result<Option<(u64, u64)>> {
+    let mut stmt =
+        conn.prepare("SELECT snapshot_block, snapshot_index from erc721_collections where chain=?1 and address=?2")?;
+
+    match stmt.query_row(params![chain, address], |row| {
+        Ok((row.get::<_, Option<i64>>(0)?, row.get::<_, Option<i64>>(1)?))
+    }) {
+        Ok((Some(block_number), Some(index))) => Ok(Some((block_number as u64, index as u64))),
+        Ok(_) => Ok(None),
+        Err(_err @ rusqlite::Error::QueryReturnedNoRows) => Ok(None),
+        Err(err) => Err(err)?,
+    }
+}
+
+/// Save the progress of the snapshot of a ERC721 contract at `block_number`
+pub fn save_collection_snapshot(conn: &Connection, collection_id: usize, block_number: u64, index: u64) -> Result<()> {
+    conn.execute(
+        "UPDATE erc721_collections set snapshot_block=?1, snapshot_index=?2 where id=?3",
+        params![block_number as i64, index as i64, collection_id as i64],
+    )?;
+    Ok(())
+}
+
+// pub fn save_collection_if_not_exists(conn: &Connection, event: &Erc721Event, metadata: Option<(String, String, String)>) -> Result<(usize, String, Option<String>, Option<String>)> {
+//     let collection_result = erc721::get_collection_from_db(conn, event.address.clone())?;
+//     let collection = collection_result.unwrap_or_else(|| {
+//         if let Some(metadata) = metadata {
+//             add_collection_to_db(&self.db_conn, event.address.clone(), metadata.0, metadata.1)?
+//         } else {
+//             add_collection_to_db(&self.db_conn, event.address.clone(), None, None)?
+//         }
+//     });
+//     Ok(collection)
+// }
+
+// pub fn save_token_if_not_exists(conn: &Connection, event: &Erc721Event, metadata: Option<(String, String, String)>) -> Result<(usize, String, Option<String>)> {
+// }
+
+/// A ERC721 token saved in the database
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Token {
+    /// The database id of the token
+    pub id: usize,
+    /// The `token_id` in contract, in decimal
+    pub token_id: String,
+    /// The database id of the collection of the token
+    pub collection_id: usize,
+    /// The token uri of the token, None if the contract has none for it or if it is not looked up yet
+    pub token_uri: Option<String>,
+}
+
+/// Get a ERC721 token from database, with its token uri.
+/// token_id here is the `token_id` in contract.
+pub fn get_token(conn: &Connection, collection_id: usize, token_id: &str) -> Result<Option<Token>> {
+    let mut stmt = conn.prepare_cached(
+        "SELECT id, token_id, collection_id, token_uri from erc721_tokens where collection_id=?1 and token_id=?2",
+    )?;
+
+    match stmt.query_row(params![collection_id as i64, token_id], token_from_row) {
+        Ok(token) => Ok(Some(token)),
+        Err(_err @ rusqlite::Error::QueryReturnedNoRows) => Ok(None),
+        Err(err) => Err(err)?,
+    }
+}
+
+/// A token from a row of `id, token_id, collection_id, token_uri`
+fn token_from_row(row: &rusqlite::Row) -> rusqlite::Result<Token> {
+    Ok(Token {
+        id: row.get(0)?,
+        token_id: row.get(1)?,
+        collection_id: row.get(2)?,
+        token_uri: row.get(3)?,
+    })
+}
+
+/// Get a ERC721 token from database by the chain and the address of its contract, with its token uri.
+/// token_id here is the `token_id` in contract.
+pub fn find_token(conn: &Connection, chain: &str, address: &str, token_id: &str) -> Result<Option<Token>> {
+    let mut stmt = conn.prepare(
+        "SELECT t.id, t.token_id, t.collection_id, t.token_uri from erc721_tokens t
+             join erc721_collections c on c.id=t.collection_id where c.chain=?1 and c.address=?2 and t.token_id=?3",
+    )?;
+
+    match stmt.query_row(params![chain, address, token_id], token_from_row) {
+        Ok(token) => Ok(Some(token)),
+        Err(_err @ rusqlite::Error::QueryReturnedNoRows) => Ok(None),
+        Err(err) => Err(err)?,
+    }
+}
+
+/// Get the saved tokens of a ERC721 collection by page, in the order they were saved: the `limit` ones saved after
+/// the one whose database id is `after`, or the first ones if it is None. The next page is after the last token.
+pub fn get_tokens(conn: &Connection, collection_id: usize, after: Option<usize>, limit: u32) -> Result<Vec<Token>> {
+    let mut stmt = conn.prepare(
+        "SELECT id, token_id, collection_id, token_uri from erc721_tokens where collection_id=?1 and id>?2
+             order by id limit ?3",
+    )?;
+    let after = after.map_or(0, |after| after as i64);
+    let rows = stmt.query_map(params![collection_id as i64, after, limit], token_from_row)?;
+    let mut tokens = vec![];
+    for row in rows {
+        tokens.push(row?);
+    }
+    Ok(tokens)
+}
+
+/// Count the saved tokens of each ERC721 collection with some tokens.
+/// The returned tuples are (collection_id, tokens), in the order the collections were saved.
+pub fn count_tokens_per_collection(conn: &Connection) -> Result<Vec<(usize, u64)>> {
+    let mut stmt = conn.prepare(
+        "SELECT collection_id, count(*) from erc721_tokens group by collection_id order by collection_id",
+    )?;
+    let rows = stmt.query_map([], |row| Ok((row.get(0)?, row.get::<_, i64>(1)? as u64)))?;
+    let mut counts = vec![];
+    for row in rows {
+        counts.push(row?);
+    }
+    Ok(counts)
+}
+
+/// Get the token_uri of a ERC721 token from database.
+/// token_id here is the `token_id` in contract.
+/// The returned tuple is (_, token_id, collection_id, token_uri)
+#[deprecated(note = "use `get_token`, which returns a `Token`")]
+pub fn get_token_from_db(
+    conn: &Connection,
+    collection_id: usize,
+    token_id: &str,
+) -> Result<Option<(usize, String, usize, Option<String>)>> {
+    Ok(get_token(conn, collection_id, token_id)?
+        .map(|token| (token.id, token.token_id, token.collection_id, token.token_uri)))
+}
+
+/// Save the token uri to database.
+/// A token saved already, as by another tracker sharing the database, gets the token uri if it is some.
+/// It returns the database id.
+pub fn add_token_to_db(
+    conn: &Connection,
+    token_id: String,
+    collection_id: usize,
+    token_uri: Option<String>,
+) -> Result<usize> {
+    if token_uri.is_some() {
+        retry_busy(|| {
+            conn.prepare_cached(
+                "INSERT INTO erc721_tokens (token_id, collection_id, token_uri) values (?1, ?2, ?3)
+                     ON CONFLICT(collection_id, token_id) DO UPDATE set token_uri=excluded.token_uri",
+            )?
+            .execute(params![&token_id, &collection_id, &token_uri])
+        })?;
+    } else {
+        retry_busy(|| {
+            conn.prepare_cached(
+                "INSERT INTO erc721_tokens (token_id, collection_id) values (?1, ?2)
+                     ON CONFLICT(collection_id, token_id) DO NOTHING",
+            )?
+            .execute(params![&token_id, &collection_id])
+        })?;
+    }
+
+    let mut stmt = conn.prepare_cached("SELECT id from erc721_tokens where collection_id=?1 and token_id=?2")?;
+    Ok(stmt.query_row(params![&collection_id, &token_id], |row| row.get(0))?)
+}
+
+/// Get when the token uri of a ERC721 token was fetched, in unix seconds.
+/// token_db_id here is the database id of the token.
+pub fn get_token_fetched_at(conn: &Connection, token_db_id: usize) -> Result<Option<u64>> {
+    let mut stmt = conn.prepare("SELECT last_fetched_at from erc721_tokens where id=?1")?;
+
+    match stmt.query_row(params![token_db_id as i64], |row| row.get::<_, Option<i64>>(0)) {
+        Ok(fetched_at) => Ok(fetched_at.map(|fetched_at| fetched_at as u64)),
+        Err(_err @ rusqlite::Error::QueryReturnedNoRows) => Ok(None),
+        Err(err) => Err(err)?,
+    }
+}
+
+/// Save the token uri of a ERC721 token and when it was fetched, in unix seconds.
+/// It forgets the failed lookups of the token, and that it was stale.
+pub fn update_token_uri(
+    conn: &Connection,
+    token_db_id: usize,
+    token_uri: Option<String>,
+    fetched_at: u64,
+) -> Result<()> {
+    conn.execute(
+        "UPDATE erc721_tokens set token_uri=?1, last_fetched_at=?2, metadata_attempts=NULL, last_attempt_at=NULL, stale=NULL
+             where id=?3",
+        params![&token_uri, fetched_at as i64, token_db_id as i64],
+    )?;
+    Ok(())
+}
+
+/// Get the tokens of a ERC721 collection.
+/// The returned tuples are (_, token_id), the token_id being the `token_id` in contract.
+pub fn get_tokens_of_collection(conn: &Connection, collection_id: usize) -> Result<Vec<(usize, String)>> {
+    let mut stmt = conn.prepare("SELECT id, token_id from erc721_tokens where collection_id=?1")?;
+    let rows = stmt.query_map(params![collection_id as i64], |row| {
+        Ok((row.get::<_, i64>(0)? as usize, row.get(1)?))
+    })?;
+    let mut tokens = vec![];
+    for row in rows {
+        tokens.push(row?);
+    }
+    Ok(tokens)
+}
+
+/// Mark the token uri of a ERC721 token as stale, after its collection updated the metadata of the token.
+/// token_db_id here is the database id of the token.
+pub fn mark_token_stale(conn: &Connection, token_db_id: usize) -> Result<()> {
+    conn.execute("UPDATE erc721_tokens set stale=1 where id=?1", params![token_db_id as i64])?;
+    Ok(())
+}
+
+/// Mark as stale the token uris of the saved tokens of a collection from `first` to `last`, after a
+/// `BatchMetadataUpdate` of the collection, in a single statement. It returns how many tokens were marked.
+/// The token ids are decimal strings without leading zeros, so they are compared by their length first.
+pub fn mark_tokens_stale(conn: &Connection, collection_id: usize, first: &str, last: &str) -> Result<usize> {
+    let marked = conn.execute(
+        "UPDATE erc721_tokens set stale=1 where collection_id=?1
+             and (length(token_id)>length(?2) or (length(token_id)=length(?2) and token_id>=?2))
+             and (length(token_id)<length(?3) or (length(token_id)=length(?3) and token_id<=?3))",
+        params![collection_id as i64, first, last],
+    )?;
+    Ok(marked)
+}
+
+/// Check if the token uri of a ERC721 token is stale, so it has to be fetched again.
+/// token_db_id here is the database id of the token.
+pub fn is_token_stale(conn: &Connection, token_db_id: usize) -> Result<bool> {
+    let mut stmt = conn.prepare("SELECT stale from erc721_tokens where id=?1")?;
+
+    match stmt.query_row(params![token_db_id as i64], |row| row.get::<_, Option<i64>>(0)) {
+        Ok(stale) => Ok(stale == Some(1)),
+        Err(_err @ rusqlite::Error::QueryReturnedNoRows) => Ok(false),
+        Err(err) => Err(err)?,
+    }
+}
+
+/// Save the name and symbol of a ERC721 contract, None if the contract has no metadata.
+/// It forgets the failed lookups of the collection.
+pub fn update_collection_metadata(
+    conn: &Connection,
+    collection_id: usize,
+    name: Option<String>,
+    symbol: Option<String>,
+) -> Result<()> {
+    conn.execute(
+        "UPDATE erc721_collections set name=?1, symbol=?2, metadata_attempts=NULL, last_attempt_at=NULL where id=?3",
+        params![&name, &symbol, collection_id as i64],
+    )?;
+    Ok(())
+}
+
+/// Get how many times looking up the metadata of a ERC721 contract failed, and when it failed last, in unix seconds.
+/// None if no lookup failed since the last successful one.
+pub fn get_collection_lookup_failures(conn: &Connection, collection_id: usize) -> Result<Option<(u32, u64)>> {
+    get_lookup_failures(conn, "erc721_collections", collection_id)
+}
+
+/// Record a failed lookup of the metadata of a ERC721 contract, at `attempted_at` in unix seconds.
+pub fn record_collection_lookup_failure(conn: &Connection, collection_id: usize, attempted_at: u64) -> Result<()> {
+    record_lookup_failure(conn, "erc721_collections", collection_id, attempted_at)
+}
+
+/// Get how many times looking up the token uri of a ERC721 token failed, and when it failed last, in unix seconds.
+/// None if no lookup failed since the last successful one.
+pub fn get_token_lookup_failures(conn: &Connection, token_db_id: usize) -> Result<Option<(u32, u64)>> {
+    get_lookup_failures(conn, "erc721_tokens", token_db_id)
+}
+
+/// Record a failed lookup of the token uri of a ERC721 token, at `attempted_at` in unix seconds.
+pub fn record_token_lookup_failure(conn: &Connection, token_db_id: usize, attempted_at: u64) -> Result<()> {
+    record_lookup_failure(conn, "erc721_tokens", token_db_id, attempted_at)
+}
+
+fn get_lookup_failures(conn: &Connection, table: &str, id: usize) -> Result<Option<(u32, u64)>> {
+    let sql = format!("SELECT metadata_attempts, last_attempt_at from {} where id=?1", table);
+    let mut stmt = conn.prepare(sql.as_str())?;
+
+    match stmt.query_row(params![id as i64], |row| {
+        Ok((row.get::<_, Option<i64>>(0)?, row.get::<_, Option<i64>>(1)?))
+    }) {
+        Ok((Some(attempts), Some(last_attempt_at))) => Ok(Some((attempts as u32, last_attempt_at as u64))),
+        Ok(_) => Ok(None),
+        Err(_err @ rusqlite::Error::QueryReturnedNoRows) => Ok(None),
+        Err(err) => Err(err)?,
+    }
+}
+
+fn record_lookup_failure(conn: &Connection, table: &str, id: usize, attempted_at: u64) -> Result<()> {
+    let sql = format!(
+        "UPDATE {} set metadata_attempts=coalesce(metadata_attempts, 0) + 1, last_attempt_at=?1 where id=?2",
+        table
+    );
+    conn.execute(sql.as_str(), params![attempted_at as i64, id as i64])?;
+    Ok(())
+}
+
+/// Save the owner of a token of a chain after its transfer at `block_number`.
+pub fn save_token_owner(
+    conn: &Connection,
+    chain: &str,
+    address: &str,
+    token_id: &str,
+    owner: &str,
+    block_number: u64,
+) -> Result<()> {
+    conn.execute(
+        "INSERT OR REPLACE INTO erc721_token_owners (chain, address, token_id, owner, block_number)
+             values (?1, ?2, ?3, ?4, ?5)",
+        params![chain, address, token_id, owner, block_number as i64],
+    )?;
+    Ok(())
+}
+
+/// Save the recipient of the transfer of a token at `log_index` of `block_number`, as its owner unless it burnt
+/// the token. A transfer older than the saved one, as when a range is scanned again, changes nothing.
+/// It returns whether the transfer was saved.
+pub fn save_token_transfer(
+    conn: &Connection,
+    chain: &str,
+    address: &str,
+    token_id: &str,
+    to: &str,
+    burnt: bool,
+    block_number: u64,
+    log_index: u64,
+) -> Result<bool> {
+    let mut stmt = conn.prepare(
+        "SELECT block_number, log_index from erc721_token_owners where chain=?1 and address=?2 and token_id=?3",
+    )?;
+    let saved = match stmt.query_row(params![chain, address, token_id], |row| {
+        Ok((row.get::<_, i64>(0)? as u64, row.get::<_, Option<i64>>(1)?.map(|log_index| log_index as u64)))
+    }) {
+        Ok(saved) => Some(saved),
+        Err(_err @ rusqlite::Error::QueryReturnedNoRows) => None,
+        Err(err) => Err(err)?,
+    };
+    // the owners saved without their log index are replaced by the transfers of their block
+    if saved.map_or(false, |saved| saved > (block_number, Some(log_index))) {
+        return Ok(false);
+    }
+    conn.execute(
+        "INSERT OR REPLACE INTO erc721_token_owners (chain, address, token_id, owner, block_number, log_index, burnt)
+             values (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+        params![chain, address, token_id, to, block_number as i64, log_index as i64, burnt.then(|| 1)],
+    )?;
+    Ok(true)
+}
+
+/// Forget the owner of a burnt token.
+pub fn remove_token_owner(conn: &Connection, chain: &str, address: &str, token_id: &str) -> Result<()> {
+    conn.execute(
+        "DELETE FROM erc721_token_owners where chain=?1 and address=?2 and token_id=?3",
+        params![chain, address, token_id],
+    )?;
+    Ok(())
+}
+
+/// Get the saved owner of a token, None if the token is burnt.
+pub fn get_token_owner(conn: &Connection, chain: &str, address: &str, token_id: &str) -> Result<Option<String>> {
+    let mut stmt = conn.prepare(
+        "SELECT owner from erc721_token_owners where chain=?1 and address=?2 and token_id=?3 and burnt is null",
+    )?;
+
+    match stmt.query_row(params![chain, address, token_id], |row| row.get(0)) {
+        Ok(owner) => Ok(Some(owner)),
+        Err(_err @ rusqlite::Error::QueryReturnedNoRows) => Ok(None),
+        Err(err) => Err(err)?,
+    }
+}
+
+/// Forget the owners of the tokens of a chain saved from the transfers of `block_number` on, as after a reorg:
+/// the transfers scanned again are saved even if they are older than the orphaned ones.
+pub fn remove_token_owners_from(conn: &Connection, chain: &str, block_number: u64) -> Result<()> {
+    conn.execute(
+        "DELETE FROM erc721_token_owners where chain=?1 and block_number>=?2",
+        params![chain, block_number as i64],
+    )?;
+    Ok(())
+}
+
+/// Count the saved tokens of a collection held by `owner`.
+pub fn count_tokens_of_owner(conn: &Connection, chain: &str, address: &str, owner: &str) -> Result<u64> {
+    let count: i64 = conn.query_row(
+        "SELECT count(*) from erc721_token_owners where chain=?1 and address=?2 and owner=?3 and burnt is null",
+        params![chain, address, owner],
+        |row| row.get(0),
+    )?;
+    Ok(count as u64)
+}
+
+/// Get the saved tokens held by `owner` in all the collections of a chain.
+/// The returned tuples are (contract_address, token_id), ordered by collection and in the order of the saves.
+pub fn get_tokens_by_owner(conn: &Connection, chain: &str, owner: &str) -> Result<Vec<(String, String)>> {
+    let mut stmt = conn.prepare(
+        "SELECT address, token_id from erc721_token_owners where chain=?1 and owner=?2 and burnt is null
+             order by address, rowid",
+    )?;
+    let rows = stmt.query_map(params![chain, owner], |row| Ok((row.get(0)?, row.get(1)?)))?;
+    let mut tokens = vec![];
+    for row in rows {
+        tokens.push(row?);
+    }
+    Ok(tokens)
+}
+
+/// A ERC721 transfer saved in the database
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Transfer {
+    /// The database id of the collection
+    pub collection_id: usize,
+    /// The token id, in decimal
+    pub token_id: String,
+    /// The sender of the token, as formatted by `{:?}`
+    pub from: String,
+    /// The recipient of the token, as formatted by `{:?}`
+    pub to: String,
+    /// The block of the transfer
+    pub block_number: u64,
+    /// The hash of the transaction of the transfer, as formatted by `{:?}`
+    pub tx_hash: String,
+    /// The index of the log of the transfer in its block
+    pub log_index: u64,
+    /// The timestamp of the block, if it was fetched
+    pub timestamp: Option<u64>,
+}
+
+/// Save a contract of some transfers, whose metadata is not looked up yet.
+/// Its metadata lookup is due right away, as if it had never failed. It returns the database id.
+pub fn add_collection_of_transfers(conn: &Connection, chain: &str, address: String) -> Result<usize> {
+    conn.execute(
+        "INSERT INTO erc721_collections (chain, address, metadata_attempts, last_attempt_at) values (?1, ?2, 0, 0)
+             ON CONFLICT(chain, address) DO NOTHING",
+        params![chain, &address],
+    )?;
+    get_collection_id(conn, chain, &address)
+}
+
+/// Save a transfer. A transfer saved already, as when a range is scanned again, is not saved twice.
+/// It returns whether the transfer was saved.
+pub fn save_transfer(conn: &Connection, transfer: &Transfer) -> Result<bool> {
+    let saved = conn.execute(
+        "INSERT OR IGNORE INTO transfers
+             (collection_id, token_id, from_address, to_address, block_number, tx_hash, log_index, timestamp)
+             values (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
+        params![
+            transfer.collection_id as i64,
+            &transfer.token_id,
+            &transfer.from,
+            &transfer.to,
+            transfer.block_number as i64,
+            &transfer.tx_hash,
+            transfer.log_index as i64,
+            transfer.timestamp.map(|timestamp| timestamp as i64),
+        ],
+    )?;
+    Ok(saved > 0)
+}
+
+fn query_transfers(conn: &Connection, sql: &str, params: &[&dyn rusqlite::ToSql]) -> Result<Vec<Transfer>> {
+    let mut stmt = conn.prepare(sql)?;
+    let rows = stmt.query_map(params, |row| {
+        Ok(Transfer {
+            collection_id: row.get(0)?,
+            token_id: row.get(1)?,
+            from: row.get(2)?,
+            to: row.get(3)?,
+            block_number: row.get::<_, i64>(4)? as u64,
+            tx_hash: row.get(5)?,
+            log_index: row.get::<_, i64>(6)? as u64,
+            timestamp: row.get::<_, Option<i64>>(7)?.map(|timestamp| timestamp as u64),
+        })
+    })?;
+    let mut transfers = vec![];
+    for row in rows {
+        transfers.push(row?);
+    }
+    Ok(transfers)
+}
+
+/// Get the saved transfers of a token, in the order of the chain.
+pub fn get_token_transfers(conn: &Connection, collection_id: usize, token_id: &str) -> Result<Vec<Transfer>> {
+    query_transfers(
+        conn,
+        "SELECT collection_id, token_id, from_address, to_address, block_number, tx_hash, log_index, timestamp
+             from transfers where collection_id=?1 and token_id=?2 order by block_number, log_index, id",
+        params![collection_id as i64, token_id],
+    )
+}
+
+/// Get the saved transfers of the tokens of a collection from the block `from` to the block `to`, inclusive,
+/// in the order of the chain.
+pub fn get_collection_transfers(conn: &Connection, collection_id: usize, from: u64, to: u64) -> Result<Vec<Transfer>> {
+    query_transfers(
+        conn,
+        "SELECT collection_id, token_id, from_address, to_address, block_number, tx_hash, log_index, timestamp
+             from transfers where collection_id=?1 and block_number>=?2 and block_number<=?3
+             order by block_number, log_index, id",
+        params![collection_id as i64, from as i64, to as i64],
+    )
+}
+
+/// Forget the transfers of a chain from `block_number`, which are not in the canonical chain anymore.
+pub fn remove_transfers_from(conn: &Connection, chain: &str, block_number: u64) -> Result<()> {
+    conn.execute(
+        "DELETE FROM transfers where block_number>=?1
+             and collection_id in (SELECT id from erc721_collections where chain=?2)",
+        params![block_number as i64, chain],
+    )?;
+    Ok(())
+}
+
+/// The format of the exported rows
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExportFormat {
+    /// Comma-separated values with a header line, an empty field for a null value
+    Csv,
+    /// A JSON object per line, the integers as JSON numbers except the token ids, which are JSON strings
+    JsonLines,
+}
+
+/// A value of an exported row
+enum ExportValue {
+    Text(Option<String>),
+    Integer(Option<i64>),
+}
+
+/// Run `sql` and write each of its rows to `writer` one by one, without loading them all.
+/// The columns of `sql` are either text or integers, as in `integer_columns`. It returns how many rows were written.
+fn export_rows(
+    conn: &Connection,
+    writer: &mut dyn Write,
+    format: ExportFormat,
+    sql: &str,
+    params: &[&dyn rusqlite::ToSql],
+    columns: &[&str],
+    integer_columns: &[&str],
+) -> Result<u64> {
+    if format == ExportFormat::Csv {
+        let header: Vec<_> = columns.iter().map(|column| csv_field(column)).collect();
+        writeln!(writer, "{}", header.join(","))?;
+    }
+    let mut stmt = conn.prepare(sql)?;
+    let mut rows = stmt.query(params)?;
+    let mut exported = 0;
+    while let Some(row) = rows.next()? {
+        let mut values = Vec::with_capacity(columns.len());
+        for (index, column) in columns.iter().enumerate() {
+            values.push(if integer_columns.contains(column) {
+                ExportValue::Integer(row.get(index)?)
+            } else {
+                ExportValue::Text(row.get(index)?)
+            });
+        }
+        match format {
+            ExportFormat::Csv => {
+                let fields: Vec<_> = values
+                    .iter()
+                    .map(|value| match value {
+                        ExportValue::Text(text) => text.as_deref().map(csv_field).unwrap_or_default(),
+                        ExportValue::Integer(integer) => integer.map(|integer| integer.to_string()).unwrap_or_default(),
+                    })
+                    .collect();
+                writeln!(writer, "{}", fields.join(","))?;
+            }
+            ExportFormat::JsonLines => {
+                let members: Vec<_> = columns
+                    .iter()
+                    .zip(values.iter())
+                    .map(|(column, value)| {
+                        let value = match value {
+                            ExportValue::Text(Some(text)) => json_string(text),
+                            ExportValue::Integer(Some(integer)) => integer.to_string(),
+                            ExportValue::Text(None) | ExportValue::Integer(None) => "null".to_owned(),
+                        };
+                        format!("{}:{}", json_string(column), value)
+                    })
+                    .collect();
+                writeln!(writer, "{{{}}}", members.join(","))?;
+            }
+        }
+        exported += 1;
+    }
+    writer.flush()?;
+    Ok(exported)
+}
+
+/// A CSV field, quoted if it has a separator, a quote or a line break
+fn csv_field(text: &str) -> String {
+    if text.contains(&[',', '"', '\n', '\r'][..]) {
+        format!("\"{}\"", text.replace('"', "\"\""))
+    } else {
+        text.to_owned()
+    }
+}
+
+/// A JSON string
+fn json_string(text: &str) -> String {
+    let mut json = String::with_capacity(text.len() + 2);
+    json.push('"');
+    for c in text.chars() {
+        match c {
+            '"' => json.push_str("\\\""),
+            '\\' => json.push_str("\\\\"),
+            '\n' => json.push_str("\\n"),
+            '\r' => json.push_str("\\r"),
+            '\t' => json.push_str("\\t"),
+            c if (c as u32) < 0x20 => json.push_str(&format!("\\u{:04x}", c as u32)),
+            c => json.push(c),
+        }
+    }
+    json.push('"');
+    json
+}
+
+/// Write the saved ERC721 contracts to `writer`, or only the ones at `address` of all the chains, in the order they
+/// were saved. The columns are `id, chain, address, name, symbol`. It returns how many collections were written.
+pub fn export_collections(
+    conn: &Connection,
+    writer: &mut dyn Write,
+    format: ExportFormat,
+    address: Option<&str>,
+) -> Result<u64> {
+    export_rows(
+        conn,
+        writer,
+        format,
+        "SELECT id, chain, address, name, symbol from erc721_collections where ?1 is null or address=?1 order by id",
+        params![address],
+        &["id", "chain", "address", "name", "symbol"],
+        &["id"],
+    )
+}
+
+/// Write the saved ERC721 tokens to `writer`, or only the ones of the contracts at `address` of all the chains, by
+/// collection and in the order they were saved. The columns are `id, chain, address, token_id, token_uri`, the token
+/// ids are in decimal. It returns how many tokens were written.
+pub fn export_tokens(
+    conn: &Connection,
+    writer: &mut dyn Write,
+    format: ExportFormat,
+    address: Option<&str>,
+) -> Result<u64> {
+    export_rows(
+        conn,
+        writer,
+        format,
+        "SELECT t.id, c.chain, c.address, t.token_id, t.token_uri from erc721_tokens t
+             join erc721_collections c on c.id=t.collection_id where ?1 is null or c.address=?1
+             order by t.collection_id, t.id",
+        params![address],
+        &["id", "chain", "address", "token_id", "token_uri"],
+        &["id"],
+    )
+}
+
+/// Write the saved transfers to `writer` in the order of the chains, or only the ones of the contracts at `address`
+/// of all the chains, or of the blocks of `blocks`. The columns are `chain, address, token_id, from, to,
+/// block_number, tx_hash, log_index, timestamp`, the token ids are in decimal. It returns how many transfers were
+/// written.
+pub fn export_transfers(
+    conn: &Connection,
+    writer: &mut dyn Write,
+    format: ExportFormat,
+    address: Option<&str>,
+    blocks: Option<RangeInclusive<u64>>,
+) -> Result<u64> {
+    let (from, to) = match blocks {
+        Some(blocks) => (Some(*blocks.start() as i64), Some(*blocks.end() as i64)),
+        None => (None, None),
+    };
+    export_rows(
+        conn,
+        writer,
+        format,
+        "SELECT c.chain, c.address, t.token_id, t.from_address, t.to_address, t.block_number, t.tx_hash, t.log_index,
+                 t.timestamp
+             from transfers t join erc721_collections c on c.id=t.collection_id
+             where (?1 is null or c.address=?1) and (?2 is null or t.block_number>=?2)
+                 and (?3 is null or t.block_number<=?3)
+             order by c.chain, t.block_number, t.log_index, t.id",
+        params![address, from, to],
+        &["chain", "address", "token_id", "from", "to", "block_number", "tx_hash", "log_index", "timestamp"],
+        &["block_number", "log_index", "timestamp"],
+    )
+}
+
+/// Get the last block scanned by the tracker of a chain. The ERC721 tracker saves its progress under the name of
+/// the chain, the ERC1155 tracker under `erc1155::progress_key`.
+pub fn get_scan_progress(conn: &Connection, chain: &str) -> Result<Option<u64>> {
+    let mut stmt = conn.prepare("SELECT last_scanned_block from scan_progress where chain=?1")?;
+
+    match stmt.query_row(params![chain], |row| row.get::<_, i64>(0)) {
+        Ok(block_number) => Ok(Some(block_number as u64)),
+        Err(_err @ rusqlite::Error::QueryReturnedNoRows) => Ok(None),
+        Err(err) => Err(err)?,
+    }
+}
+
+/// Save the last block scanned by the tracker of a chain, with the current time in seconds.
+/// The trackers save it in the transaction of the other writes of the range.
+pub fn save_scan_progress(conn: &Connection, chain: &str, block_number: u64) -> Result<()> {
+    retry_busy(|| {
+        conn.prepare_cached(
+            "INSERT OR REPLACE INTO scan_progress (chain, last_scanned_block, updated_at)
+                 values (?1, ?2, cast(strftime('%s', 'now') as integer))",
+        )?
+        .execute(params![chain, block_number as i64])
+    })?;
+    Ok(())
+}
+
+/// The progress of a tracker saved in the database
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ChainProgress {
+    /// The name of the chain, or the key of the tracker
+    pub chain: String,
+    /// The last block scanned
+    pub last_scanned_block: u64,
+    /// When the progress was saved, in seconds since the UNIX epoch. None if it was saved before the time was
+    /// recorded.
+    pub updated_at: Option<u64>,
+}
+
+/// Get the saved progress of all the trackers, by chain name, for the dashboards.
+pub fn status(conn: &Connection) -> Result<Vec<ChainProgress>> {
+    let mut stmt = conn.prepare("SELECT chain, last_scanned_block, updated_at from scan_progress order by chain")?;
+    let rows = stmt.query_map([], |row| {
+        Ok(ChainProgress {
+            chain: row.get(0)?,
+            last_scanned_block: row.get::<_, i64>(1)? as u64,
+            updated_at: row.get::<_, Option<i64>>(2)?.map(|updated_at| updated_at as u64),
+        })
+    })?;
+    let mut progress = vec![];
+    for row in rows {
+        progress.push(row?);
+    }
+    Ok(progress)
+}
+
+/// Check if an event has already been delivered to the callback.
+pub fn is_event_delivered(
+    conn: &Connection,
+    chain: &str,
+    transaction_hash: &str,
+    log_index: u64,
+) -> Result<bool> {
+    let mut stmt = conn.prepare(
+        "SELECT 1 from delivered_events where chain=?1 and transaction_hash=?2 and log_index=?3",
+    )?;
+    Ok(stmt.exists(params![chain, transaction_hash, log_index as i64])?)
+}
+
+/// Record that an event has been delivered to the callback.
+pub fn mark_event_delivered(
+    conn: &Connection,
+    chain: &str,
+    transaction_hash: &str,
+    log_index: u64,
+    block_number: u64,
+) -> Result<()> {
+    conn.execute(
+        "INSERT OR IGNORE INTO delivered_events (chain, transaction_hash, log_index, block_number) values (?1, ?2, ?3, ?4)",
+        params![chain, transaction_hash, log_index as i64, block_number as i64],
+    )?;
+    Ok(())
+}
+
+/// How many range boundaries are kept per chain to detect reorgs
+const SCANNED_BLOCKS_KEPT: i64 = 64;
+
+/// Save the hash of the last block of a scanned range, only the latest ones are kept.
+pub fn save_scanned_block(
+    conn: &Connection,
+    chain: &str,
+    block_number: u64,
+    block_hash: &str,
+) -> Result<()> {
+    conn.execute(
+        "INSERT OR REPLACE INTO scanned_blocks (chain, block_number, block_hash) values (?1, ?2, ?3)",
+        params![chain, block_number as i64, block_hash],
+    )?;
+    conn.execute(
+        "DELETE FROM scanned_blocks where chain=?1 and block_number not in
+             (SELECT block_number from scanned_blocks where chain=?1 order by block_number desc limit ?2)",
+        params![chain, SCANNED_BLOCKS_KEPT],
+    )?;
+    Ok(())
+}
+
+/// Get the saved (block_number, block_hash) before `block_number`, the latest first.
+pub fn get_scanned_blocks_before(
+    conn: &Connection,
+    chain: &str,
+    block_number: u64,
+) -> Result<Vec<(u64, String)>> {
+    let mut stmt = conn.prepare(
+        "SELECT block_number, block_hash from scanned_blocks where chain=?1 and block_number<?2 order by block_number desc",
+    )?;
+    let rows = stmt.query_map(params![chain, block_number as i64], |row| {
+        Ok((row.get::<_, i64>(0)? as u64, row.get(1)?))
+    })?;
+    let mut blocks = vec![];
+    for row in rows {
+        blocks.push(row?);
+    }
+    Ok(blocks)
+}
+
+/// Forget the scanned blocks and the delivered events from `block_number`, after a reorg.
+pub fn remove_scanned_blocks_from(conn: &Connection, chain: &str, block_number: u64) -> Result<()> {
+    conn.execute(
+        "DELETE FROM scanned_blocks where chain=?1 and block_number>=?2",
+        params![chain, block_number as i64],
+    )?;
+    conn.execute(
+        "DELETE FROM delivered_events where chain=?1 and block_number>=?2",
+        params![chain, block_number as i64],
+    )?;
+    Ok(())
+}
+
+/// What `prune` deletes
+#[derive(Debug, Clone)]
+pub struct PruneOptions {
+    /// Delete the transfers before this block
+    pub transfers_before: Option<u64>,
+    /// Delete the collections at these addresses, as formatted by `{:?}`, on all the chains, with their tokens,
+    /// owners and transfers
+    pub denylist: Vec<String>,
+    /// Delete the collections without a saved transfer or owner from this block, with their tokens, owners and
+    /// transfers. The collections of a tracker which saves neither transfers nor owners are all inactive.
+    pub inactive_since: Option<u64>,
+    /// VACUUM the database after the deletions, so that its file shrinks. The database is locked meanwhile.
+    pub vacuum: bool,
+    /// How many rows are deleted in each transaction, the trackers write between the transactions
+    pub batch_size: u32,
+}
+
+impl Default for PruneOptions {
+    fn default() -> Self {
+        PruneOptions {
+            transfers_before: None,
+            denylist: vec![],
+            inactive_since: None,
+            vacuum: false,
+            batch_size: 10_000,
+        }
+    }
+}
+
+/// The number of rows deleted by `prune` in each table
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct PruneReport {
+    /// The rows deleted in `erc721_collections`
+    pub collections: u64,
+    /// The rows deleted in `erc721_tokens`
+    pub tokens: u64,
+    /// The rows deleted in `erc721_token_owners`
+    pub owners: u64,
+    /// The rows deleted in `transfers`
+    pub transfers: u64,
+}
+
+/// Delete the rows of `table` matching `condition`, `batch_size` rows per transaction. It returns how many
+/// rows were deleted.
+fn delete_in_batches(
+    conn: &Connection,
+    table: &str,
+    condition: &str,
+    params: &[&dyn rusqlite::ToSql],
+    batch_size: u32,
+) -> Result<u64> {
+    let sql = format!(
+        "DELETE FROM {table} where rowid in (SELECT rowid from {table} where {condition} limit {batch_size})",
+        table = table,
+        condition = condition,
+        batch_size = batch_size.max(1),
+    );
+    let mut deleted = 0;
+    loop {
+        let tx = conn.unchecked_transaction()?;
+        let batch = retry_busy(|| tx.execute(&sql, params))? as u64;
+        tx.commit()?;
+        deleted += batch;
+        if batch < batch_size.max(1) as u64 {
+            return Ok(deleted);
+        }
+    }
+}
+
+/// Delete the transfers, the collections of the denylist and the inactive collections as set by `options`.
+/// The collections are deleted first, so that the transfers deleted do not make them inactive. The rows of a
+/// collection are deleted before it, a collection left by an interrupted prune is deleted by the next one.
+pub fn prune(conn: &Connection, options: PruneOptions) -> Result<PruneReport> {
+    let mut report = PruneReport::default();
+
+    // the (id, chain, address) of the collections to delete
+    let mut collections: Vec<(usize, String, String)> = vec![];
+    let mut stmt = conn.prepare("SELECT id, chain, address from erc721_collections where address=?1")?;
+    for address in &options.denylist {
+        let rows = stmt.query_map(params![address], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)))?;
+        for row in rows {
+            collections.push(row?);
+        }
+    }
+    if let Some(block_number) = options.inactive_since {
+        let mut stmt = conn.prepare(
+            "SELECT id, chain, address from erc721_collections c
+                 where not exists (SELECT 1 from transfers t where t.collection_id=c.id and t.block_number>=?1)
+                 and not exists (SELECT 1 from erc721_token_owners o
+                     where o.chain=c.chain and o.address=c.address and o.block_number>=?1)",
+        )?;
+        let rows = stmt.query_map(params![block_number as i64], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)))?;
+        for row in rows {
+            let collection = row?;
+            if !collections.contains(&collection) {
+                collections.push(collection);
+            }
+        }
+    }
+    for (id, chain, address) in collections {
+        let id = id as i64;
+        report.transfers += delete_in_batches(conn, "transfers", "collection_id=?1", params![id], options.batch_size)?;
+        report.owners += delete_in_batches(
+            conn,
+            "erc721_token_owners",
+            "chain=?1 and address=?2",
+            params![chain, address],
+            options.batch_size,
+        )?;
+        report.tokens +=
+            delete_in_batches(conn, "erc721_tokens", "collection_id=?1", params![id], options.batch_size)?;
+        let deleted = retry_busy(|| conn.execute("DELETE FROM erc721_collections where id=?1", params![id]))?;
+        report.collections += deleted as u64;
+    }
+
+    if let Some(block_number) = options.transfers_before {
+        report.transfers +=
+            delete_in_batches(conn, "transfers", "block_number<?1", params![block_number as i64], options.batch_size)?;
+    }
+
+    if options.vacuum {
+        conn.execute_batch("VACUUM")?;
+    }
+    Ok(report)
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use web3::types::{H160, U256};
+    use web3::types::{H160, H256, U256};
 
     #[tokio::test]
+    #[allow(deprecated)]
     async fn test_get_collection_from_db() {
         let conn = Connection::open("./test1.db").unwrap();
         create_tables_if_not_exist(&conn).unwrap();
 
         let address = format!("{:?}", H160::zero());
 
-        let result = get_collection_from_db(&conn, &address).unwrap();
+        let result = get_collection_from_db(&conn, "Ethereum", &address).unwrap();
         assert_eq!(None, result);
 
-        add_collection_to_db(&conn, address.clone(), None, None).unwrap();
-        let result = get_collection_from_db(&conn, &address).unwrap();
+        add_collection_to_db(&conn, "Ethereum", address.clone(), None, None).unwrap();
+        let result = get_collection_from_db(&conn, "Ethereum", &address).unwrap();
         assert_eq!(
             Some((
                 1,
@@ -165,46 +1726,438 @@ mod tests {
         std::fs::remove_file("./test1.db").unwrap();
     }
 
-    #[tokio::test]
-    async fn test_add_collection_to_db() {
-        let conn = Connection::open("./test2.db").unwrap();
+    #[test]
+    fn test_token_stale() {
+        let conn = Connection::open_in_memory().unwrap();
         create_tables_if_not_exist(&conn).unwrap();
 
-        // 1
-        let address = "0xC5c1C9c3cEA2f4A68E540b18e63310310FD8af57";
+        let collection_id = add_collection_to_db(&conn, "Ethereum", format!("{:?}", H160::zero()), None, None).unwrap();
+        let id = add_token_to_db(&conn, "1".to_owned(), collection_id, Some("https://mock/1".to_owned())).unwrap();
+        add_token_to_db(&conn, "2".to_owned(), collection_id, None).unwrap();
+        assert_eq!(
+            vec![(id, "1".to_owned()), (id + 1, "2".to_owned())],
+            get_tokens_of_collection(&conn, collection_id).unwrap()
+        );
+        assert!(!is_token_stale(&conn, id).unwrap());
 
-        let result = get_collection_from_db(&conn, address).unwrap();
-        assert_eq!(None, result);
+        mark_token_stale(&conn, id).unwrap();
+        assert!(is_token_stale(&conn, id).unwrap());
+        assert!(!is_token_stale(&conn, id + 1).unwrap());
+
+        // a token uri fetched again is not stale anymore
+        update_token_uri(&conn, id, Some("https://mock/1/revealed".to_owned()), 100).unwrap();
+        assert!(!is_token_stale(&conn, id).unwrap());
+    }
+
+    #[test]
+    fn test_mark_tokens_stale() {
+        let conn = Connection::open_in_memory().unwrap();
+        create_tables_if_not_exist(&conn).unwrap();
+
+        let collection_id = add_collection_to_db(&conn, "Ethereum", format!("{:?}", H160::zero()), None, None).unwrap();
+        let other_id = add_collection_to_db(&conn, "Ethereum", format!("{:?}", H160::from_low_u64_be(1)), None, None)
+            .unwrap();
+        let ids: Vec<usize> = ["2", "9", "10", "25", "100", "1000"]
+            .iter()
+            .map(|token_id| add_token_to_db(&conn, token_id.to_string(), collection_id, None).unwrap())
+            .collect();
+        let other = add_token_to_db(&conn, "10".to_owned(), other_id, None).unwrap();
+
+        // compared as numbers, "100" is not between "9" and "25" though it is as a string
+        assert_eq!(3, mark_tokens_stale(&conn, collection_id, "9", "25").unwrap());
+        let stale: Vec<bool> = ids.iter().map(|id| is_token_stale(&conn, *id).unwrap()).collect();
+        assert_eq!(vec![false, true, true, true, false, false], stale);
+        assert!(!is_token_stale(&conn, other).unwrap());
+    }
+
+    #[test]
+    fn test_token_owners() {
+        let conn = Connection::open_in_memory().unwrap();
+        create_tables_if_not_exist(&conn).unwrap();
+
+        let address = format!("{:?}", H160::from_low_u64_be(1));
+        let (alice, bob) = (format!("{:?}", H160::from_low_u64_be(2)), format!("{:?}", H160::from_low_u64_be(3)));
+        save_token_owner(&conn, "Ethereum", &address, "1", &alice, 10).unwrap();
+        save_token_owner(&conn, "Ethereum", &address, "2", &alice, 10).unwrap();
+        assert_eq!(2, count_tokens_of_owner(&conn, "Ethereum", &address, &alice).unwrap());
+
+        // transferred to bob
+        save_token_owner(&conn, "Ethereum", &address, "2", &bob, 11).unwrap();
+        assert_eq!(Some(bob.clone()), get_token_owner(&conn, "Ethereum", &address, "2").unwrap());
+        assert_eq!(1, count_tokens_of_owner(&conn, "Ethereum", &address, &alice).unwrap());
+        assert_eq!(1, count_tokens_of_owner(&conn, "Ethereum", &address, &bob).unwrap());
+
+        // burnt
+        remove_token_owner(&conn, "Ethereum", &address, "1").unwrap();
+        assert_eq!(None, get_token_owner(&conn, "Ethereum", &address, "1").unwrap());
+        assert_eq!(0, count_tokens_of_owner(&conn, "Ethereum", &address, &alice).unwrap());
+        let other = format!("{:?}", H160::from_low_u64_be(4));
+        assert_eq!(0, count_tokens_of_owner(&conn, "Ethereum", &other, &bob).unwrap());
+    }
+
+    #[test]
+    fn test_remove_token_owners_from() {
+        let conn = Connection::open_in_memory().unwrap();
+        create_tables_if_not_exist(&conn).unwrap();
+
+        let address = format!("{:?}", H160::from_low_u64_be(1));
+        let (alice, bob) = (format!("{:?}", H160::from_low_u64_be(2)), format!("{:?}", H160::from_low_u64_be(3)));
+        save_token_transfer(&conn, "Ethereum", &address, "1", &alice, false, 10, 0).unwrap();
+        save_token_transfer(&conn, "Ethereum", &address, "2", &alice, false, 11, 0).unwrap();
+        save_token_transfer(&conn, "Ethereum", &address, "2", &bob, false, 12, 0).unwrap();
+        save_token_transfer(&conn, "Polygon", &address, "2", &bob, false, 12, 0).unwrap();
+
+        remove_token_owners_from(&conn, "Ethereum", 11).unwrap();
+        assert_eq!(Some(alice.clone()), get_token_owner(&conn, "Ethereum", &address, "1").unwrap());
+        assert_eq!(None, get_token_owner(&conn, "Ethereum", &address, "2").unwrap());
+        assert_eq!(Some(bob.clone()), get_token_owner(&conn, "Polygon", &address, "2").unwrap());
+
+        // an older transfer scanned again is saved
+        assert!(save_token_transfer(&conn, "Ethereum", &address, "2", &alice, false, 11, 0).unwrap());
+        assert_eq!(Some(alice), get_token_owner(&conn, "Ethereum", &address, "2").unwrap());
+    }
+
+    #[test]
+    fn test_token_transfers() {
+        let conn = Connection::open_in_memory().unwrap();
+        create_tables_if_not_exist(&conn).unwrap();
+
+        let address = format!("{:?}", H160::from_low_u64_be(1));
+        let (alice, bob) = (format!("{:?}", H160::from_low_u64_be(2)), format!("{:?}", H160::from_low_u64_be(3)));
+        let zero = format!("{:?}", H160::zero());
+        let owner_of = |token_id: &str| get_token_owner(&conn, "Ethereum", &address, token_id).unwrap();
+
+        // minted to alice
+        assert!(save_token_transfer(&conn, "Ethereum", &address, "1", &alice, false, 10, 3).unwrap());
+        assert_eq!(Some(alice.clone()), owner_of("1"));
+        assert_eq!(vec![(address.clone(), "1".to_owned())], get_tokens_by_owner(&conn, "Ethereum", &alice).unwrap());
+
+        // transferred to bob in the same block
+        assert!(save_token_transfer(&conn, "Ethereum", &address, "1", &bob, false, 10, 5).unwrap());
+        assert_eq!(Some(bob.clone()), owner_of("1"));
+        assert!(get_tokens_by_owner(&conn, "Ethereum", &alice).unwrap().is_empty());
+        assert_eq!(vec![(address.clone(), "1".to_owned())], get_tokens_by_owner(&conn, "Ethereum", &bob).unwrap());
+
+        // the mint scanned again does not give it back to alice
+        assert!(!save_token_transfer(&conn, "Ethereum", &address, "1", &alice, false, 10, 3).unwrap());
+        assert_eq!(Some(bob.clone()), owner_of("1"));
+
+        // burnt, the transfer to bob scanned again does not bring it back
+        assert!(save_token_transfer(&conn, "Ethereum", &address, "1", &zero, true, 12, 0).unwrap());
+        assert_eq!(None, owner_of("1"));
+        assert!(!save_token_transfer(&conn, "Ethereum", &address, "1", &bob, false, 10, 5).unwrap());
+        assert_eq!(None, owner_of("1"));
+        assert!(get_tokens_by_owner(&conn, "Ethereum", &bob).unwrap().is_empty());
+        assert!(get_tokens_by_owner(&conn, "Ethereum", &zero).unwrap().is_empty());
+        assert_eq!(0, count_tokens_of_owner(&conn, "Ethereum", &address, &zero).unwrap());
+
+        // the owners saved before the log indexes are replaced by the transfers of their block
+        save_token_owner(&conn, "Ethereum", &address, "2", &alice, 20).unwrap();
+        assert!(save_token_transfer(&conn, "Ethereum", &address, "2", &bob, false, 20, 0).unwrap());
+        assert!(!save_token_transfer(&conn, "Ethereum", &address, "2", &alice, false, 19, 7).unwrap());
+        assert_eq!(Some(bob), owner_of("2"));
+    }
+
+    #[test]
+    fn test_transfers() {
+        let conn = Connection::open_in_memory().unwrap();
+        create_tables_if_not_exist(&conn).unwrap();
+        let address = format!("{:?}", H160::from_low_u64_be(1));
+        let collection_id = add_collection_of_transfers(&conn, "Ethereum", address).unwrap();
+        let transfer = |token_id: &str, block_number: u64, log_index: u64| Transfer {
+            collection_id,
+            token_id: token_id.to_owned(),
+            from: format!("{:?}", H160::zero()),
+            to: format!("{:?}", H160::from_low_u64_be(2)),
+            block_number,
+            tx_hash: format!("{:?}", H256::from_low_u64_be(block_number)),
+            log_index,
+            timestamp: Some(block_number * 12),
+        };
+
+        assert!(save_transfer(&conn, &transfer("1", 11, 0)).unwrap());
+        assert!(save_transfer(&conn, &transfer("1", 10, 2)).unwrap());
+        assert!(save_transfer(&conn, &transfer("2", 10, 1)).unwrap());
+        // saved already
+        assert!(!save_transfer(&conn, &transfer("1", 10, 2)).unwrap());
+
+        assert_eq!(
+            vec![transfer("1", 10, 2), transfer("1", 11, 0)],
+            get_token_transfers(&conn, collection_id, "1").unwrap()
+        );
+        assert_eq!(
+            vec![transfer("2", 10, 1), transfer("1", 10, 2), transfer("1", 11, 0)],
+            get_collection_transfers(&conn, collection_id, 10, 11).unwrap()
+        );
+        assert_eq!(vec![transfer("1", 11, 0)], get_collection_transfers(&conn, collection_id, 11, 20).unwrap());
+        assert!(get_collection_transfers(&conn, collection_id + 1, 0, 20).unwrap().is_empty());
+
+        remove_transfers_from(&conn, "Ethereum", 11).unwrap();
+        assert_eq!(vec![transfer("1", 10, 2)], get_token_transfers(&conn, collection_id, "1").unwrap());
+        // the collection is looked up as a new one
+        assert_eq!(Some((0, 0)), get_collection_lookup_failures(&conn, collection_id).unwrap());
+    }
+
+    #[test]
+    fn test_same_address_on_two_chains() {
+        let conn = Connection::open_in_memory().unwrap();
+        create_tables_if_not_exist(&conn).unwrap();
+        let address = format!("{:?}", H160::from_low_u64_be(1));
+        let (alice, bob) = (format!("{:?}", H160::from_low_u64_be(2)), format!("{:?}", H160::from_low_u64_be(3)));
+
+        let ethereum = add_collection_to_db(&conn, "Ethereum", address.clone(), Some("A".to_owned()), None).unwrap();
+        let bsc = add_collection_to_db(&conn, "BSC", address.clone(), Some("B".to_owned()), Some("B".to_owned()))
+            .unwrap();
+        assert_ne!(ethereum, bsc);
+        assert_eq!(None, get_collection(&conn, "Ethereum", &address).unwrap().unwrap().name_symbol());
+        assert_eq!(
+            Some(("B".to_owned(), "B".to_owned())),
+            get_collection(&conn, "BSC", &address).unwrap().unwrap().name_symbol()
+        );
+        assert_eq!(None, get_collection(&conn, "Polygon", &address).unwrap());
+        assert_eq!(1, get_collections(&conn, "BSC", None, 10).unwrap().len());
+
+        // the same token has an owner on each chain
+        save_token_owner(&conn, "Ethereum", &address, "1", &alice, 10).unwrap();
+        save_token_owner(&conn, "BSC", &address, "1", &bob, 10).unwrap();
+        assert_eq!(Some(alice.clone()), get_token_owner(&conn, "Ethereum", &address, "1").unwrap());
+        assert_eq!(Some(bob.clone()), get_token_owner(&conn, "BSC", &address, "1").unwrap());
+        assert!(get_tokens_by_owner(&conn, "BSC", &alice).unwrap().is_empty());
+        remove_token_owner(&conn, "BSC", &address, "1").unwrap();
+        assert_eq!(Some(alice), get_token_owner(&conn, "Ethereum", &address, "1").unwrap());
+
+        // a transaction hash may be seen on both chains
+        let transfer = |collection_id: usize| Transfer {
+            collection_id,
+            token_id: "1".to_owned(),
+            from: format!("{:?}", H160::zero()),
+            to: bob.clone(),
+            block_number: 10,
+            tx_hash: format!("{:?}", H256::from_low_u64_be(10)),
+            log_index: 0,
+            timestamp: None,
+        };
+        assert!(save_transfer(&conn, &transfer(ethereum)).unwrap());
+        assert!(save_transfer(&conn, &transfer(bsc)).unwrap());
+        remove_transfers_from(&conn, "BSC", 10).unwrap();
+        assert!(get_token_transfers(&conn, bsc, "1").unwrap().is_empty());
+        assert_eq!(vec![transfer(ethereum)], get_token_transfers(&conn, ethereum, "1").unwrap());
+    }
 
-        add_collection_to_db(&conn, address.to_string(), None, None).unwrap();
-        let result = get_collection_from_db(&conn, address).unwrap();
-        assert_eq!(Some((1usize, address.to_string(), None, None)), result);
+    #[test]
+    fn test_collection_creation_block() {
+        let conn = Connection::open_in_memory().unwrap();
+        create_tables_if_not_exist(&conn).unwrap();
 
-        // 2
         let address = "0xa7d8d9ef8d8ce8992df33d8b8cf4aebabd5bd270";
+        assert_eq!(None, get_collection_creation_block(&conn, "Ethereum", address).unwrap());
 
-        let result = get_collection_from_db(&conn, address).unwrap();
-        assert_eq!(None, result);
+        let collection_id = add_collection_to_db(&conn, "Ethereum", address.to_string(), None, None).unwrap();
+        assert_eq!(None, get_collection_creation_block(&conn, "Ethereum", address).unwrap());
 
-        add_collection_to_db(
-            &conn,
-            address.to_string(),
-            Some("Art Blocks".to_owned()),
-            Some("BLOCKS".to_owned()),
+        save_collection_creation_block(&conn, collection_id, 11244553).unwrap();
+        assert_eq!(Some(11244553), get_collection_creation_block(&conn, "Ethereum", address).unwrap());
+    }
+
+    #[test]
+    fn test_collection_royalty() {
+        let conn = Connection::open_in_memory().unwrap();
+        create_tables_if_not_exist(&conn).unwrap();
+
+        let address = "0xa7d8d9ef8d8ce8992df33d8b8cf4aebabd5bd270";
+        let receiver = "0x0000000000000000000000000000000000000009";
+        let collection_id = add_collection_to_db(&conn, "Ethereum", address.to_string(), None, None).unwrap();
+        assert_eq!(None, get_collection_royalty(&conn, "Ethereum", address).unwrap());
+
+        save_collection_royalty(&conn, collection_id, Some((receiver, 500))).unwrap();
+        assert_eq!(Some((receiver.to_owned(), 500)), get_collection_royalty(&conn, "Ethereum", address).unwrap());
+
+        save_collection_royalty(&conn, collection_id, None).unwrap();
+        assert_eq!(None, get_collection_royalty(&conn, "Ethereum", address).unwrap());
+    }
+
+    #[test]
+    fn test_collection_contract_uri() {
+        let conn = Connection::open_in_memory().unwrap();
+        create_tables_if_not_exist(&conn).unwrap();
+
+        let address = "0xa7d8d9ef8d8ce8992df33d8b8cf4aebabd5bd270";
+        assert_eq!(None, get_collection_contract_uri(&conn, "Ethereum", address).unwrap());
+
+        let collection_id = add_collection_to_db(&conn, "Ethereum", address.to_string(), None, None).unwrap();
+        assert_eq!(None, get_collection_contract_uri(&conn, "Ethereum", address).unwrap());
+
+        save_collection_contract_uri(&conn, collection_id, Some("https://mock/collection.json")).unwrap();
+        assert_eq!(
+            Some("https://mock/collection.json".to_owned()),
+            get_collection_contract_uri(&conn, "Ethereum", address).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_collection_snapshot() {
+        let conn = Connection::open_in_memory().unwrap();
+        create_tables_if_not_exist(&conn).unwrap();
+
+        let address = "0xa7d8d9ef8d8ce8992df33d8b8cf4aebabd5bd270";
+        let collection_id = add_collection_to_db(&conn, "Ethereum", address.to_string(), None, None).unwrap();
+        assert_eq!(None, get_collection_snapshot(&conn, "Ethereum", address).unwrap());
+
+        save_collection_snapshot(&conn, collection_id, 13000000, 42).unwrap();
+        assert_eq!(Some((13000000, 42)), get_collection_snapshot(&conn, "Ethereum", address).unwrap());
+    }
+
+    #[test]
+    fn test_collection_implementation() {
+        let conn = Connection::open_in_memory().unwrap();
+        create_tables_if_not_exist(&conn).unwrap();
+
+        let address = "0xa7d8d9ef8d8ce8992df33d8b8cf4aebabd5bd270";
+        let collection_id = add_collection_to_db(&conn, "Ethereum", address.to_string(), None, None).unwrap();
+        assert_eq!(None, get_collection_implementation(&conn, "Ethereum", address).unwrap());
+
+        // not a proxy
+        save_collection_implementation(&conn, collection_id, None, 100).unwrap();
+        assert_eq!(Some((None, 100)), get_collection_implementation(&conn, "Ethereum", address).unwrap());
+
+        let implementation = "0x0000000000000000000000000000000000000007";
+        save_collection_implementation(&conn, collection_id, Some(implementation), 120).unwrap();
+        assert_eq!(
+            Some((Some(implementation.to_owned()), 120)),
+            get_collection_implementation(&conn, "Ethereum", address).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_create_tables_adds_creation_block() {
+        let conn = Connection::open_in_memory().unwrap();
+        conn.execute(
+            "create table erc721_collections (
+                 id integer primary key,
+                 address text not null unique,
+                 name text,
+                 symbol text
+             )",
+            [],
+        )
+        .unwrap();
+        create_tables_if_not_exist(&conn).unwrap();
+
+        let address = "0xa7d8d9ef8d8ce8992df33d8b8cf4aebabd5bd270";
+        let collection_id = add_collection_to_db(&conn, "Ethereum", address.to_string(), None, None).unwrap();
+        save_collection_creation_block(&conn, collection_id, 100).unwrap();
+        assert_eq!(Some(100), get_collection_creation_block(&conn, "Ethereum", address).unwrap());
+        assert_eq!(SCHEMA_VERSION, get_schema_version(&conn).unwrap());
+    }
+
+    #[test]
+    fn test_migrate_from_version_1() {
+        let conn = Connection::open_in_memory().unwrap();
+        assert_eq!(0, get_schema_version(&conn).unwrap());
+        migrate_to(&conn, 1).unwrap();
+        assert_eq!(1, get_schema_version(&conn).unwrap());
+        assert!(conn.prepare("SELECT * from transfers").is_err());
+
+        let address = "0xa7d8d9ef8d8ce8992df33d8b8cf4aebabd5bd270";
+        let owner = format!("{:?}", H160::from_low_u64_be(2));
+        conn.execute(
+            "INSERT INTO erc721_collections (address, name, symbol) values (?1, 'Art Blocks', 'BLOCKS')",
+            params![address],
         )
         .unwrap();
-        let result = get_collection_from_db(&conn, address).unwrap();
+        let collection_id = conn.last_insert_rowid() as usize;
+        // saved twice, as the tokens were not unique before the version 3
+        for token_uri in &["https://", "https://again"] {
+            conn.execute(
+                "INSERT INTO erc721_tokens (token_id, collection_id, token_uri) values ('1', ?1, ?2)",
+                params![collection_id as i64, token_uri],
+            )
+            .unwrap();
+        }
+        let token_db_id = conn.last_insert_rowid() as usize - 1;
+        conn.execute("INSERT INTO scan_progress (chain, last_scanned_block) values ('Ethereum', 100)", []).unwrap();
+        conn.execute(
+            "INSERT INTO erc721_token_owners (address, token_id, owner, block_number) values (?1, '1', ?2, 90)",
+            params![address, &owner],
+        )
+        .unwrap();
+
+        // the rows saved before the chains get the empty chain, the database is refused until they are stamped
+        assert!(matches!(migrate(&conn), Err(Error::UnstampedChain)));
+        assert_eq!(SCHEMA_VERSION, get_schema_version(&conn).unwrap());
+        assert!(conn.prepare("SELECT log_index, burnt from erc721_token_owners").is_ok());
+        assert!(conn.prepare("SELECT * from transfers").is_ok());
+        assert_eq!(collection_id, get_collection(&conn, "", address).unwrap().unwrap().id);
+        assert_eq!(None, get_collection(&conn, "Ethereum", address).unwrap());
+        migrate_with_chain(&conn, "Ethereum").unwrap();
+        assert_eq!(0, stamp_chain(&conn, "Ethereum").unwrap());
+        let collection = get_collection(&conn, "Ethereum", address).unwrap().unwrap();
+        assert_eq!((collection_id, Some("Art Blocks".to_owned())), (collection.id, collection.name));
+        let token = get_token(&conn, collection_id, "1").unwrap().unwrap();
+        assert_eq!((token_db_id, Some("https://".to_owned())), (token.id, token.token_uri));
+        assert_eq!(vec![(token_db_id, "1".to_owned())], get_tokens_of_collection(&conn, collection_id).unwrap());
+        assert_eq!(Some(100), get_scan_progress(&conn, "Ethereum").unwrap());
+        assert_eq!(None, status(&conn).unwrap()[0].updated_at);
+        assert_eq!(Some(owner.clone()), get_token_owner(&conn, "Ethereum", address, "1").unwrap());
+        // the owner saved without its log index is replaced by the transfers of its block
+        assert!(save_token_transfer(&conn, "Ethereum", address, "1", &owner, false, 90, 0).unwrap());
+
+        // migrated already
+        migrate(&conn).unwrap();
         assert_eq!(
-            Some((
-                2usize,
-                address.to_string(),
-                Some("Art Blocks".to_owned()),
-                Some("BLOCKS".to_owned())
-            )),
-            result
+            (1..=SCHEMA_VERSION as i64).collect::<Vec<_>>(),
+            conn.prepare("SELECT version from schema_version order by version")
+                .unwrap()
+                .query_map([], |row| row.get::<_, i64>(0))
+                .unwrap()
+                .map(|version| version.unwrap())
+                .collect::<Vec<_>>()
         );
+    }
+
+    #[test]
+    fn test_stamp_chain_merges_the_collections_saved_again() {
+        let conn = Connection::open_in_memory().unwrap();
+        migrate(&conn).unwrap();
+
+        // saved before the upgrade, then again by the tracker of the chain before the database was stamped
+        let address = format!("{:?}", H160::from_low_u64_be(1));
+        let (alice, bob) = (format!("{:?}", H160::from_low_u64_be(2)), format!("{:?}", H160::from_low_u64_be(3)));
+        let unstamped = add_collection_to_db(&conn, "", address.clone(), None, None).unwrap();
+        add_token_to_db(&conn, "1".to_owned(), unstamped, Some("https://mock/1".to_owned())).unwrap();
+        add_token_to_db(&conn, "2".to_owned(), unstamped, Some("https://mock/2".to_owned())).unwrap();
+        save_token_transfer(&conn, "", &address, "1", &alice, false, 90, 0).unwrap();
+        save_token_transfer(&conn, "", &address, "2", &alice, false, 90, 1).unwrap();
+        let stamped = add_collection_to_db(&conn, "Ethereum", address.clone(), None, None).unwrap();
+        add_token_to_db(&conn, "1".to_owned(), stamped, Some("https://mock/1/revealed".to_owned())).unwrap();
+        save_token_transfer(&conn, "Ethereum", &address, "1", &bob, false, 100, 0).unwrap();
+        assert!(matches!(migrate(&conn), Err(Error::UnstampedChain)));
+
+        assert_eq!(1, stamp_chain(&conn, "Ethereum").unwrap());
+        migrate(&conn).unwrap();
+        assert_eq!(None, get_collection(&conn, "", &address).unwrap());
+        assert_eq!(stamped, get_collection(&conn, "Ethereum", &address).unwrap().unwrap().id);
+        let token_uri = |token_id: &str| get_token(&conn, stamped, token_id).unwrap().unwrap().token_uri;
+        assert_eq!(Some("https://mock/1/revealed".to_owned()), token_uri("1"));
+        assert_eq!(Some("https://mock/2".to_owned()), token_uri("2"));
+        assert_eq!(None, get_token(&conn, unstamped, "2").unwrap());
+        assert_eq!(Some(bob), get_token_owner(&conn, "Ethereum", &address, "1").unwrap());
+        assert_eq!(Some(alice), get_token_owner(&conn, "Ethereum", &address, "2").unwrap());
+    }
+
+    #[test]
+    fn test_migrate_refuses_newer_databases() {
+        let conn = Connection::open_in_memory().unwrap();
+        migrate(&conn).unwrap();
+        conn.execute("INSERT INTO schema_version (version) values (?1)", params![SCHEMA_VERSION as i64 + 1])
+            .unwrap();
 
-        std::fs::remove_file("./test2.db").unwrap();
+        match migrate(&conn) {
+            Err(Error::SchemaTooNew { version, supported }) => {
+                assert_eq!((SCHEMA_VERSION + 1, SCHEMA_VERSION), (version, supported))
+            }
+            other => panic!("{:?}", other),
+        }
     }
 
     #[tokio::test]
@@ -215,6 +2168,7 @@ mod tests {
         let address = "0xa7d8d9ef8d8ce8992df33d8b8cf4aebabd5bd270";
         let collection_id = add_collection_to_db(
             &conn,
+            "Ethereum",
             address.to_string(),
             Some("Art Blocks".to_owned()),
             Some("BLOCKS".to_owned()),
@@ -232,16 +2186,98 @@ mod tests {
         assert_eq!(1usize, id);
 
         // test u256 can be save as string correctly
-        let token = get_token_from_db(&conn, collection_id, &token_id.to_string())
+        let token = get_token(&conn, collection_id, &token_id.to_string())
             .unwrap()
             .unwrap();
 
-        assert_eq!("129000030".to_string(), token.1);
+        assert_eq!("129000030".to_string(), token.token_id);
 
         std::fs::remove_file("./test3.db").unwrap();
     }
 
+    #[test]
+    fn test_update_token_uri() {
+        let conn = Connection::open_in_memory().unwrap();
+        create_tables_if_not_exist(&conn).unwrap();
+        let collection_id =
+            add_collection_to_db(&conn, "Ethereum", "0xa7d8d9ef8d8ce8992df33d8b8cf4aebabd5bd270".to_owned(), None, None)
+                .unwrap();
+        let id = add_token_to_db(&conn, "1".to_owned(), collection_id, None).unwrap();
+        assert_eq!(None, get_token_fetched_at(&conn, id).unwrap());
+
+        update_token_uri(&conn, id, Some("ipfs://revealed/1".to_owned()), 1_650_000_000).unwrap();
+        assert_eq!(Some(1_650_000_000), get_token_fetched_at(&conn, id).unwrap());
+        let token = get_token(&conn, collection_id, "1").unwrap().unwrap();
+        assert_eq!(Some("ipfs://revealed/1".to_owned()), token.token_uri);
+    }
+
+    #[test]
+    fn test_lookup_failures() {
+        let conn = Connection::open_in_memory().unwrap();
+        create_tables_if_not_exist(&conn).unwrap();
+        let collection_id =
+            add_collection_to_db(&conn, "Ethereum", "0xa7d8d9ef8d8ce8992df33d8b8cf4aebabd5bd270".to_owned(), None, None)
+                .unwrap();
+        let id = add_token_to_db(&conn, "1".to_owned(), collection_id, None).unwrap();
+        assert_eq!(None, get_collection_lookup_failures(&conn, collection_id).unwrap());
+        assert_eq!(None, get_token_lookup_failures(&conn, id).unwrap());
+
+        record_collection_lookup_failure(&conn, collection_id, 1_650_000_000).unwrap();
+        record_collection_lookup_failure(&conn, collection_id, 1_650_000_060).unwrap();
+        record_token_lookup_failure(&conn, id, 1_650_000_000).unwrap();
+        assert_eq!(Some((2, 1_650_000_060)), get_collection_lookup_failures(&conn, collection_id).unwrap());
+        assert_eq!(Some((1, 1_650_000_000)), get_token_lookup_failures(&conn, id).unwrap());
+
+        // a successful lookup forgets the failures
+        update_collection_metadata(&conn, collection_id, Some("Art Blocks".to_owned()), Some("BLOCKS".to_owned()))
+            .unwrap();
+        update_token_uri(&conn, id, Some("ipfs://1".to_owned()), 1_650_000_120).unwrap();
+        assert_eq!(None, get_collection_lookup_failures(&conn, collection_id).unwrap());
+        assert_eq!(None, get_token_lookup_failures(&conn, id).unwrap());
+        let collection = get_collection(&conn, "Ethereum", "0xa7d8d9ef8d8ce8992df33d8b8cf4aebabd5bd270")
+            .unwrap()
+            .unwrap();
+        assert_eq!(Some("Art Blocks".to_owned()), collection.name);
+    }
+
+    #[test]
+    fn test_collection_erc721_support() {
+        let conn = Connection::open_in_memory().unwrap();
+        create_tables_if_not_exist(&conn).unwrap();
+        let collection_id =
+            add_collection_to_db(&conn, "Ethereum", "0xa7d8d9ef8d8ce8992df33d8b8cf4aebabd5bd270".to_owned(), None, None)
+                .unwrap();
+        assert_eq!(None, get_collection_erc721_support(&conn, collection_id).unwrap());
+
+        save_collection_erc721_support(&conn, collection_id, Some(true)).unwrap();
+        assert_eq!(Some(Some(true)), get_collection_erc721_support(&conn, collection_id).unwrap());
+        save_collection_erc721_support(&conn, collection_id, None).unwrap();
+        assert_eq!(Some(None), get_collection_erc721_support(&conn, collection_id).unwrap());
+
+        // a contract checked before its metadata is looked up
+        let address = "0xa0b86991c6218b36c1d19d4a2e9eb0ce3606eb48".to_owned();
+        let collection_id = add_collection_with_erc721_support(&conn, "Ethereum", address, Some(false)).unwrap();
+        assert_eq!(Some(Some(false)), get_collection_erc721_support(&conn, collection_id).unwrap());
+        assert_eq!(Some((0, 0)), get_collection_lookup_failures(&conn, collection_id).unwrap());
+    }
+
+    #[test]
+    fn test_collection_code() {
+        let conn = Connection::open_in_memory().unwrap();
+        create_tables_if_not_exist(&conn).unwrap();
+        let collection_id =
+            add_collection_to_db(&conn, "Ethereum", "0xa7d8d9ef8d8ce8992df33d8b8cf4aebabd5bd270".to_owned(), None, None)
+                .unwrap();
+        assert_eq!(None, get_collection_code(&conn, collection_id).unwrap());
+
+        for code in [CollectionCode::Live, CollectionCode::Missing, CollectionCode::Removed] {
+            save_collection_code(&conn, collection_id, code).unwrap();
+            assert_eq!(Some(code), get_collection_code(&conn, collection_id).unwrap());
+        }
+    }
+
     #[tokio::test]
+    #[allow(deprecated)]
     async fn test_get_token_from_db() {
         let conn = Connection::open("./test4.db").unwrap();
         create_tables_if_not_exist(&conn).unwrap();
@@ -253,6 +2289,7 @@ mod tests {
         let address = "0xa7d8d9ef8d8ce8992df33d8b8cf4aebabd5bd270";
         let collection_id = add_collection_to_db(
             &conn,
+            "Ethereum",
             address.to_string(),
             Some("Art Blocks".to_owned()),
             Some("BLOCKS".to_owned()),
@@ -269,4 +2306,642 @@ mod tests {
 
         std::fs::remove_file("./test4.db").unwrap();
     }
+
+    #[test]
+    fn test_get_collection_and_token() {
+        let conn = Connection::open_in_memory().unwrap();
+        create_tables_if_not_exist(&conn).unwrap();
+        let address = "0xbc4ca0eda7647a8ab7c2061c2e118a18a936f13d";
+        assert_eq!(None, get_collection(&conn, "Ethereum", address).unwrap());
+
+        let (name, symbol) = (Some("BoredApeYachtClub".to_owned()), Some("BAYC".to_owned()));
+        let collection_id =
+            add_collection_to_db(&conn, "Ethereum", address.to_owned(), name.clone(), symbol.clone()).unwrap();
+        let id = add_token_to_db(&conn, "1234".to_owned(), collection_id, None).unwrap();
+        let collection = get_collection(&conn, "Ethereum", address).unwrap().unwrap();
+        assert_eq!(
+            Collection {
+                id: collection_id,
+                address: address.to_owned(),
+                name: name.clone(),
+                symbol,
+            },
+            collection
+        );
+        assert_eq!(Some(("BoredApeYachtClub".to_owned(), "BAYC".to_owned())), collection.name_symbol());
+        assert_eq!(None, get_token(&conn, collection_id, "1235").unwrap());
+        assert_eq!(
+            Some(Token {
+                id,
+                token_id: "1234".to_owned(),
+                collection_id,
+                token_uri: None,
+            }),
+            get_token(&conn, collection_id, "1234").unwrap()
+        );
+
+        // the columns added later do not shift the fields
+        let token_uri = Some("ipfs://QmeSjSinHpPnmXmspMjwiXyN6zS4E9zccariGR3jxcaWtq/1234".to_owned());
+        update_token_uri(&conn, id, token_uri.clone(), 1).unwrap();
+        save_collection_creation_block(&conn, collection_id, 12287507).unwrap();
+        assert_eq!(token_uri, get_token(&conn, collection_id, "1234").unwrap().unwrap().token_uri);
+        assert_eq!(collection, get_collection(&conn, "Ethereum", address).unwrap().unwrap());
+
+        // a collection without a symbol has no name and symbol
+        update_collection_metadata(&conn, collection_id, name, None).unwrap();
+        assert_eq!(None, get_collection(&conn, "Ethereum", address).unwrap().unwrap().name_symbol());
+    }
+
+    #[test]
+    fn test_scan_progress() {
+        let conn = Connection::open_in_memory().unwrap();
+        create_tables_if_not_exist(&conn).unwrap();
+
+        assert_eq!(None, get_scan_progress(&conn, "Ethereum").unwrap());
+
+        save_scan_progress(&conn, "Ethereum", 13015344).unwrap();
+        save_scan_progress(&conn, "Pangolin", 100).unwrap();
+        assert_eq!(Some(13015344), get_scan_progress(&conn, "Ethereum").unwrap());
+
+        save_scan_progress(&conn, "Ethereum", 13015350).unwrap();
+        assert_eq!(Some(13015350), get_scan_progress(&conn, "Ethereum").unwrap());
+        assert_eq!(Some(100), get_scan_progress(&conn, "Pangolin").unwrap());
+
+        let now = std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).unwrap().as_secs();
+        let progress = status(&conn).unwrap();
+        assert_eq!(
+            vec![("Ethereum", 13015350), ("Pangolin", 100)],
+            progress.iter().map(|progress| (&progress.chain[..], progress.last_scanned_block)).collect::<Vec<_>>()
+        );
+        for progress in progress {
+            let updated_at = progress.updated_at.unwrap();
+            assert!(updated_at + 60 > now && updated_at <= now + 1);
+        }
+    }
+
+    #[test]
+    fn test_delivered_events() {
+        let conn = Connection::open_in_memory().unwrap();
+        create_tables_if_not_exist(&conn).unwrap();
+
+        let tx_hash = "0x8b5ed8d5d9b5d3bb3c1fc4f3b3a0f0ccbf0b4c5ba8c0d8cdb1e4d59ba8ea0b0a";
+        assert!(!is_event_delivered(&conn, "Ethereum", tx_hash, 3).unwrap());
+
+        mark_event_delivered(&conn, "Ethereum", tx_hash, 3, 13015344).unwrap();
+        // marking twice is fine
+        mark_event_delivered(&conn, "Ethereum", tx_hash, 3, 13015344).unwrap();
+        assert!(is_event_delivered(&conn, "Ethereum", tx_hash, 3).unwrap());
+
+        assert!(!is_event_delivered(&conn, "Ethereum", tx_hash, 4).unwrap());
+        assert!(!is_event_delivered(&conn, "Pangolin", tx_hash, 3).unwrap());
+    }
+
+    #[test]
+    fn test_scanned_blocks() {
+        let conn = Connection::open_in_memory().unwrap();
+        create_tables_if_not_exist(&conn).unwrap();
+
+        save_scanned_block(&conn, "Ethereum", 11, "0x11").unwrap();
+        save_scanned_block(&conn, "Ethereum", 13, "0x13").unwrap();
+        save_scanned_block(&conn, "Ethereum", 15, "0x15").unwrap();
+        save_scanned_block(&conn, "Pangolin", 12, "0x12").unwrap();
+        assert_eq!(
+            vec![(13, "0x13".to_owned()), (11, "0x11".to_owned())],
+            get_scanned_blocks_before(&conn, "Ethereum", 15).unwrap()
+        );
+
+        let tx_hash = "0x8b5ed8d5d9b5d3bb3c1fc4f3b3a0f0ccbf0b4c5ba8c0d8cdb1e4d59ba8ea0b0a";
+        mark_event_delivered(&conn, "Ethereum", tx_hash, 0, 12).unwrap();
+        mark_event_delivered(&conn, "Ethereum", tx_hash, 1, 14).unwrap();
+        remove_scanned_blocks_from(&conn, "Ethereum", 13).unwrap();
+        assert_eq!(
+            vec![(11, "0x11".to_owned())],
+            get_scanned_blocks_before(&conn, "Ethereum", 100).unwrap()
+        );
+        assert!(is_event_delivered(&conn, "Ethereum", tx_hash, 0).unwrap());
+        assert!(!is_event_delivered(&conn, "Ethereum", tx_hash, 1).unwrap());
+        assert_eq!(1, get_scanned_blocks_before(&conn, "Pangolin", 100).unwrap().len());
+    }
+
+    #[test]
+    fn test_scanned_blocks_are_pruned() {
+        let conn = Connection::open_in_memory().unwrap();
+        create_tables_if_not_exist(&conn).unwrap();
+
+        for block_number in 0..100 {
+            save_scanned_block(&conn, "Ethereum", block_number, "0x").unwrap();
+        }
+        let blocks = get_scanned_blocks_before(&conn, "Ethereum", 100).unwrap();
+        assert_eq!(SCANNED_BLOCKS_KEPT as usize, blocks.len());
+        assert_eq!(99, blocks[0].0);
+    }
+
+    #[test]
+    fn test_pages_from_read_only_connection() {
+        let path = std::env::temp_dir().join("erc721_pages.db");
+        let remove_database = || {
+            for suffix in &["", "-wal", "-shm"] {
+                let _ = std::fs::remove_file(format!("{}{}", path.display(), suffix));
+            }
+        };
+        remove_database();
+        let conn = Connection::open(&path).unwrap();
+        configure_connection(&conn).unwrap();
+        create_tables_if_not_exist(&conn).unwrap();
+        let addresses: Vec<_> = (1..=3).map(|i| format!("{:?}", H160::from_low_u64_be(i))).collect();
+        let collection_ids: Vec<_> = addresses
+            .iter()
+            .map(|address| add_collection_to_db(&conn, "Ethereum", address.clone(), None, None).unwrap())
+            .collect();
+        // the tokens of the second and the third collections are saved in turn
+        for i in 0..300 {
+            let collection_id = collection_ids[1 + i % 2];
+            let token_uri = Some(format!("https://mock/{}", i));
+            add_token_to_db(&conn, i.to_string(), collection_id, token_uri).unwrap();
+        }
+
+        let reader = Connection::open_with_flags(&path, rusqlite::OpenFlags::SQLITE_OPEN_READ_ONLY).unwrap();
+        reader.busy_timeout(BUSY_TIMEOUT).unwrap();
+
+        let collections = get_collections(&reader, "Ethereum", None, 2).unwrap();
+        assert_eq!(vec![&addresses[0], &addresses[1]], collections.iter().map(|c| &c.address).collect::<Vec<_>>());
+        let collections = get_collections(&reader, "Ethereum", Some(collections[1].id), 2).unwrap();
+        assert_eq!(vec![collection_ids[2]], collections.iter().map(|c| c.id).collect::<Vec<_>>());
+        assert!(get_collections(&reader, "Ethereum", Some(collection_ids[2]), 2).unwrap().is_empty());
+
+        // the tokens of the second collection by pages of 60
+        let mut pages = vec![];
+        let mut after = None;
+        loop {
+            let page = get_tokens(&reader, collection_ids[1], after, 60).unwrap();
+            match page.last() {
+                Some(last) => after = Some(last.id),
+                None => break,
+            }
+            pages.push(page);
+        }
+        assert_eq!(vec![60, 60, 30], pages.iter().map(|page| page.len()).collect::<Vec<_>>());
+        let tokens: Vec<_> = pages.into_iter().flatten().collect();
+        assert_eq!(
+            (0..300).step_by(2).map(|i| i.to_string()).collect::<Vec<_>>(),
+            tokens.iter().map(|token| token.token_id.clone()).collect::<Vec<_>>()
+        );
+        assert!(tokens.windows(2).all(|tokens| tokens[0].id < tokens[1].id));
+        assert!(tokens.iter().all(|token| token.collection_id == collection_ids[1]));
+        assert!(get_tokens(&reader, collection_ids[0], None, 60).unwrap().is_empty());
+
+        assert_eq!(
+            vec![(collection_ids[1], 150), (collection_ids[2], 150)],
+            count_tokens_per_collection(&reader).unwrap()
+        );
+        let token = find_token(&reader, "Ethereum", &addresses[2], "299").unwrap().unwrap();
+        assert_eq!((collection_ids[2], Some("https://mock/299".to_owned())), (token.collection_id, token.token_uri));
+        assert_eq!(None, find_token(&reader, "Ethereum", &addresses[1], "299").unwrap());
+
+        // the tracker writes while the reader reads
+        add_token_to_db(&conn, "300".to_owned(), collection_ids[1], None).unwrap();
+        assert_eq!(1, get_tokens(&reader, collection_ids[1], after, 60).unwrap().len());
+        assert!(reader.execute("DELETE FROM erc721_tokens", []).is_err());
+
+        drop((conn, reader));
+        remove_database();
+    }
+
+    /// Parse the lines of a CSV export, the fields of each line
+    fn parse_csv(csv: &str) -> Vec<Vec<String>> {
+        let mut lines = vec![];
+        let (mut line, mut field) = (vec![], String::new());
+        let (mut quoted, mut chars) = (false, csv.chars().peekable());
+        while let Some(c) = chars.next() {
+            match (quoted, c) {
+                (true, '"') if chars.peek() == Some(&'"') => {
+                    chars.next();
+                    field.push('"');
+                }
+                (_, '"') => quoted = !quoted,
+                (false, ',') => line.push(std::mem::take(&mut field)),
+                (false, '\n') => {
+                    line.push(std::mem::take(&mut field));
+                    lines.push(std::mem::take(&mut line));
+                }
+                (_, c) => field.push(c),
+            }
+        }
+        lines
+    }
+
+    fn export_fixture() -> (Connection, String) {
+        let conn = Connection::open_in_memory().unwrap();
+        create_tables_if_not_exist(&conn).unwrap();
+        let address = format!("{:?}", H160::from_low_u64_be(1));
+        let name = Some("Mock, \"the first\"\ncollection".to_owned());
+        let collection_id =
+            add_collection_to_db(&conn, "Ethereum", address.clone(), name, Some("MOCK".to_owned())).unwrap();
+        add_collection_to_db(&conn, "Ethereum", format!("{:?}", H160::from_low_u64_be(2)), None, None).unwrap();
+        add_token_to_db(&conn, U256::MAX.to_string(), collection_id, Some("https://mock/max".to_owned())).unwrap();
+        add_token_to_db(&conn, "1".to_owned(), collection_id, None).unwrap();
+        for (token_id, block_number, timestamp) in &[("1", 10, Some(120)), (&U256::MAX.to_string()[..], 20, None)] {
+            let transfer = Transfer {
+                collection_id,
+                token_id: token_id.to_string(),
+                from: format!("{:?}", H160::zero()),
+                to: format!("{:?}", H160::from_low_u64_be(3)),
+                block_number: *block_number,
+                tx_hash: format!("{:?}", H256::from_low_u64_be(*block_number)),
+                log_index: 0,
+                timestamp: *timestamp,
+            };
+            save_transfer(&conn, &transfer).unwrap();
+        }
+        (conn, address)
+    }
+
+    #[test]
+    fn test_export_csv() {
+        let (conn, address) = export_fixture();
+
+        let mut csv = vec![];
+        assert_eq!(2, export_collections(&conn, &mut csv, ExportFormat::Csv, None).unwrap());
+        assert_eq!(
+            vec![
+                vec!["id", "chain", "address", "name", "symbol"],
+                vec!["1", "Ethereum", &address[..], "Mock, \"the first\"\ncollection", "MOCK"],
+                vec!["2", "Ethereum", &format!("{:?}", H160::from_low_u64_be(2))[..], "", ""],
+            ],
+            parse_csv(&String::from_utf8(csv).unwrap())
+        );
+
+        let mut csv = vec![];
+        assert_eq!(2, export_tokens(&conn, &mut csv, ExportFormat::Csv, Some(&address)).unwrap());
+        assert_eq!(
+            vec![
+                vec!["id", "chain", "address", "token_id", "token_uri"],
+                vec!["1", "Ethereum", &address[..], &U256::MAX.to_string()[..], "https://mock/max"],
+                vec!["2", "Ethereum", &address[..], "1", ""],
+            ],
+            parse_csv(&String::from_utf8(csv).unwrap())
+        );
+
+        let mut csv = vec![];
+        assert_eq!(1, export_transfers(&conn, &mut csv, ExportFormat::Csv, None, Some(0..=10)).unwrap());
+        let lines = parse_csv(&String::from_utf8(csv).unwrap());
+        let header =
+            vec!["chain", "address", "token_id", "from", "to", "block_number", "tx_hash", "log_index", "timestamp"];
+        assert_eq!(header, lines[0]);
+        assert_eq!(
+            vec![
+                "Ethereum",
+                &address[..],
+                "1",
+                &format!("{:?}", H160::zero())[..],
+                &format!("{:?}", H160::from_low_u64_be(3))[..]
+            ],
+            lines[1][..5].to_vec()
+        );
+        assert_eq!(
+            vec!["10", &format!("{:?}", H256::from_low_u64_be(10))[..], "0", "120"],
+            lines[1][5..].to_vec()
+        );
+
+        // nothing of another collection
+        let mut csv = vec![];
+        let other = format!("{:?}", H160::from_low_u64_be(2));
+        assert_eq!(0, export_tokens(&conn, &mut csv, ExportFormat::Csv, Some(&other)).unwrap());
+        assert_eq!(1, parse_csv(&String::from_utf8(csv).unwrap()).len());
+    }
+
+    #[test]
+    fn test_export_json_lines() {
+        let (conn, address) = export_fixture();
+        let parse = |json: Vec<u8>| -> Vec<serde_json::Value> {
+            String::from_utf8(json).unwrap().lines().map(|line| serde_json::from_str(line).unwrap()).collect()
+        };
+
+        let mut json = vec![];
+        assert_eq!(1, export_collections(&conn, &mut json, ExportFormat::JsonLines, Some(&address)).unwrap());
+        assert_eq!(
+            vec![serde_json::json!({
+                "id": 1,
+                "chain": "Ethereum",
+                "address": address,
+                "name": "Mock, \"the first\"\ncollection",
+                "symbol": "MOCK",
+            })],
+            parse(json)
+        );
+
+        let mut json = vec![];
+        assert_eq!(2, export_tokens(&conn, &mut json, ExportFormat::JsonLines, None).unwrap());
+        let tokens = parse(json);
+        // the token id is a string, as it does not fit a JSON number
+        assert_eq!(serde_json::json!(U256::MAX.to_string()), tokens[0]["token_id"]);
+        assert_eq!(serde_json::Value::Null, tokens[1]["token_uri"]);
+
+        let mut json = vec![];
+        assert_eq!(2, export_transfers(&conn, &mut json, ExportFormat::JsonLines, Some(&address), None).unwrap());
+        let transfers = parse(json);
+        assert_eq!(
+            vec![
+                serde_json::json!(["1", 10, 120]),
+                serde_json::json!([U256::MAX.to_string(), 20, null]),
+            ],
+            transfers
+                .iter()
+                .map(|t| serde_json::json!([t["token_id"], t["block_number"], t["timestamp"]]))
+                .collect::<Vec<_>>()
+        );
+        let mut json = vec![];
+        assert_eq!(0, export_transfers(&conn, &mut json, ExportFormat::JsonLines, None, Some(11..=19)).unwrap());
+        assert!(json.is_empty());
+    }
+
+    #[test]
+    fn test_add_collection_and_token_twice() {
+        let conn = Connection::open_in_memory().unwrap();
+        create_tables_if_not_exist(&conn).unwrap();
+        let address = format!("{:?}", H160::from_low_u64_be(1));
+
+        let collection_id = add_collection_to_db(&conn, "Ethereum", address.clone(), None, None).unwrap();
+        let (name, symbol) = (Some("Mock".to_owned()), Some("MOCK".to_owned()));
+        assert_eq!(collection_id, add_collection_to_db(&conn, "Ethereum", address.clone(), name, symbol).unwrap());
+        // the metadata saved is kept
+        assert_eq!(collection_id, add_collection_to_db(&conn, "Ethereum", address.clone(), None, None).unwrap());
+        assert_eq!(collection_id, add_collection_of_transfers(&conn, "Ethereum", address.clone()).unwrap());
+        assert_eq!(
+            collection_id,
+            add_collection_with_erc721_support(&conn, "Ethereum", address.clone(), Some(true)).unwrap()
+        );
+        let collection = get_collection(&conn, "Ethereum", &address).unwrap().unwrap();
+        assert_eq!(Some(("Mock".to_owned(), "MOCK".to_owned())), collection.name_symbol());
+        assert_eq!(Some(Some(true)), get_collection_erc721_support(&conn, collection_id).unwrap());
+
+        let token_db_id = add_token_to_db(&conn, "1".to_owned(), collection_id, None).unwrap();
+        let token_uri = Some("https://".to_owned());
+        assert_eq!(token_db_id, add_token_to_db(&conn, "1".to_owned(), collection_id, token_uri).unwrap());
+        assert_eq!(token_db_id, add_token_to_db(&conn, "1".to_owned(), collection_id, None).unwrap());
+        assert_eq!(Some("https://".to_owned()), get_token(&conn, collection_id, "1").unwrap().unwrap().token_uri);
+        assert_eq!(vec![(token_db_id, "1".to_owned())], get_tokens_of_collection(&conn, collection_id).unwrap());
+    }
+
+    #[test]
+    fn test_add_collection_and_token_from_two_connections() {
+        let path = std::env::temp_dir().join("erc721_unique.db");
+        let remove_database = || {
+            for suffix in &["", "-wal", "-shm"] {
+                let _ = std::fs::remove_file(format!("{}{}", path.display(), suffix));
+            }
+        };
+        remove_database();
+        let conn = Connection::open(&path).unwrap();
+        configure_connection(&conn).unwrap();
+        create_tables_if_not_exist(&conn).unwrap();
+
+        // two trackers sharing the database save the same collections and tokens
+        let trackers: Vec<_> = (0..2)
+            .map(|_| {
+                let path = path.clone();
+                std::thread::spawn(move || {
+                    let conn = Connection::open(&path).unwrap();
+                    configure_connection(&conn).unwrap();
+                    (0..50)
+                        .map(|i| {
+                            let address = format!("{:?}", H160::from_low_u64_be(i % 5));
+                            let collection_id = add_collection_to_db(&conn, "Ethereum", address, None, None).unwrap();
+                            let token_id = (i % 10).to_string();
+                            let token_db_id = add_token_to_db(&conn, token_id, collection_id, None).unwrap();
+                            (collection_id, token_db_id)
+                        })
+                        .collect::<Vec<_>>()
+                })
+            })
+            .collect();
+        let ids: Vec<_> = trackers.into_iter().map(|tracker| tracker.join().unwrap()).collect();
+        assert_eq!(ids[0], ids[1]);
+
+        let count = |table: &str| -> i64 {
+            conn.query_row(&format!("SELECT count(*) from {}", table), [], |row| row.get(0)).unwrap()
+        };
+        assert_eq!((5, 10), (count("erc721_collections"), count("erc721_tokens")));
+
+        drop(conn);
+        remove_database();
+    }
+
+    #[test]
+    fn test_configure_connection_reads_while_writing() {
+        fn count_collections(conn: &Connection) -> i64 {
+            conn.query_row("SELECT count(*) from erc721_collections", [], |row| row.get(0)).unwrap()
+        }
+        fn remove_database(path: &std::path::Path) {
+            for suffix in &["", "-wal", "-shm"] {
+                let _ = std::fs::remove_file(format!("{}{}", path.display(), suffix));
+            }
+        }
+
+        let path = std::env::temp_dir().join("erc721_wal.db");
+        remove_database(&path);
+        let conn = Connection::open(&path).unwrap();
+        configure_connection(&conn).unwrap();
+        create_tables_if_not_exist(&conn).unwrap();
+        let journal_mode: String = conn.query_row("PRAGMA journal_mode", [], |row| row.get(0)).unwrap();
+        assert_eq!("wal", journal_mode);
+
+        let (reading_sender, reading) = std::sync::mpsc::channel();
+        let (written_sender, written) = std::sync::mpsc::channel();
+        let reader_path = path.clone();
+        let reader = std::thread::spawn(move || {
+            let conn = Connection::open(&reader_path).unwrap();
+            configure_connection(&conn).unwrap();
+            // a long read, as by a dashboard
+            let tx = conn.unchecked_transaction().unwrap();
+            assert_eq!(0, count_collections(&tx));
+            reading_sender.send(()).unwrap();
+            written.recv().unwrap();
+            // it still reads the database as it was when it started
+            assert_eq!(0, count_collections(&tx));
+            tx.commit().unwrap();
+            count_collections(&conn)
+        });
+
+        // the writes do not wait for the end of the read
+        reading.recv().unwrap();
+        for i in 0..100 {
+            let address = format!("{:?}", H160::from_low_u64_be(i));
+            let collection_id = add_collection_to_db(&conn, "Ethereum", address, None, None).unwrap();
+            add_token_to_db(&conn, "1".to_owned(), collection_id, None).unwrap();
+        }
+        written_sender.send(()).unwrap();
+        assert_eq!(100, reader.join().unwrap());
+
+        drop(conn);
+        remove_database(&path);
+    }
+
+    /// Collection 1 has transfers at the blocks 10 and 100, collection 2 at the block 50, collection 3 an owner at
+    /// the block 200 and no transfer, collection 4 nothing.
+    fn prune_fixture() -> Connection {
+        let conn = Connection::open_in_memory().unwrap();
+        create_tables_if_not_exist(&conn).unwrap();
+        let activity: [&[(&str, u64, bool)]; 4] =
+            [&[("1", 10, true), ("2", 100, true)], &[("1", 50, true)], &[("1", 200, false)], &[]];
+        for (i, tokens) in activity.iter().enumerate() {
+            let address = format!("{:?}", H160::from_low_u64_be(i as u64 + 1));
+            let collection_id = add_collection_to_db(&conn, "Ethereum", address.clone(), None, None).unwrap();
+            for (token_id, block_number, transferred) in tokens.iter() {
+                add_token_to_db(&conn, token_id.to_string(), collection_id, None).unwrap();
+                let to = format!("{:?}", H160::from_low_u64_be(10));
+                save_token_transfer(&conn, "Ethereum", &address, token_id, &to, false, *block_number, 0).unwrap();
+                if *transferred {
+                    let transfer = Transfer {
+                        collection_id,
+                        token_id: token_id.to_string(),
+                        from: format!("{:?}", H160::zero()),
+                        to,
+                        block_number: *block_number,
+                        tx_hash: format!("{:?}", H256::from_low_u64_be(*block_number)),
+                        log_index: 0,
+                        timestamp: None,
+                    };
+                    save_transfer(&conn, &transfer).unwrap();
+                }
+            }
+        }
+        conn
+    }
+
+    /// The ids of the collections left, which are their numbers, and the (collection, token id) of the tokens,
+    /// owners and transfers left
+    fn rows_left(conn: &Connection) -> (Vec<u64>, Vec<(u64, String)>, Vec<(u64, String)>, Vec<(u64, String)>) {
+        let query = |sql: &str| -> Vec<(u64, String)> {
+            let mut stmt = conn.prepare(sql).unwrap();
+            let rows = stmt.query_map([], |row| Ok((row.get::<_, i64>(0)? as u64, row.get(1)?))).unwrap();
+            rows.map(|row| row.unwrap()).collect()
+        };
+        let mut stmt =
+            conn.prepare("SELECT address, token_id from erc721_token_owners order by address, token_id").unwrap();
+        let owners = stmt
+            .query_map([], |row| Ok((row.get::<_, String>(0)?, row.get(1)?)))
+            .unwrap()
+            .map(|row| row.unwrap())
+            .map(|(address, token_id)| {
+                let number = (1..=4).find(|i| format!("{:?}", H160::from_low_u64_be(*i)) == address).unwrap();
+                (number, token_id)
+            })
+            .collect();
+        (
+            query("SELECT id, address from erc721_collections order by id").into_iter().map(|(id, _)| id).collect(),
+            query("SELECT collection_id, token_id from erc721_tokens order by id"),
+            owners,
+            query("SELECT collection_id, token_id from transfers order by id"),
+        )
+    }
+
+    #[test]
+    fn test_prune_transfers() {
+        let conn = prune_fixture();
+        let options = PruneOptions {
+            transfers_before: Some(60),
+            batch_size: 1,
+            ..Default::default()
+        };
+        let report = prune(&conn, options).unwrap();
+        assert_eq!(PruneReport { transfers: 2, ..Default::default() }, report);
+        let (collections, tokens, owners, transfers) = rows_left(&conn);
+        assert_eq!(vec![1, 2, 3, 4], collections);
+        assert_eq!(4, tokens.len());
+        assert_eq!(4, owners.len());
+        assert_eq!(vec![(1, "2".to_owned())], transfers);
+    }
+
+    #[test]
+    fn test_prune_denylist() {
+        let conn = prune_fixture();
+        let options = PruneOptions {
+            // the unknown addresses are ignored
+            denylist: vec![format!("{:?}", H160::from_low_u64_be(2)), format!("{:?}", H160::from_low_u64_be(9))],
+            ..Default::default()
+        };
+        let report = prune(&conn, options).unwrap();
+        let expected = PruneReport {
+            collections: 1,
+            tokens: 1,
+            owners: 1,
+            transfers: 1,
+        };
+        assert_eq!(expected, report);
+        let (collections, tokens, owners, transfers) = rows_left(&conn);
+        assert_eq!(vec![1, 3, 4], collections);
+        assert_eq!(vec![(1, "1".to_owned()), (1, "2".to_owned()), (3, "1".to_owned())], tokens);
+        assert_eq!(vec![(1, "1".to_owned()), (1, "2".to_owned()), (3, "1".to_owned())], owners);
+        assert_eq!(vec![(1, "1".to_owned()), (1, "2".to_owned())], transfers);
+    }
+
+    #[test]
+    fn test_prune_inactive_collections() {
+        let conn = prune_fixture();
+        let options = PruneOptions {
+            // collection 1 is in the denylist and inactive, it is deleted once
+            denylist: vec![format!("{:?}", H160::from_low_u64_be(1))],
+            inactive_since: Some(150),
+            batch_size: 1,
+            ..Default::default()
+        };
+        let report = prune(&conn, options).unwrap();
+        let expected = PruneReport {
+            collections: 3,
+            tokens: 3,
+            owners: 3,
+            transfers: 3,
+        };
+        assert_eq!(expected, report);
+        // collection 3 is active with its owner only
+        let (collections, tokens, owners, transfers) = rows_left(&conn);
+        assert_eq!(vec![3], collections);
+        assert_eq!(vec![(3, "1".to_owned())], tokens);
+        assert_eq!(vec![(3, "1".to_owned())], owners);
+        assert!(transfers.is_empty());
+        assert_eq!(PruneReport::default(), prune(&conn, PruneOptions::default()).unwrap());
+    }
+
+    #[test]
+    fn test_prune_and_vacuum() {
+        let free_pages =
+            |conn: &Connection| conn.query_row("PRAGMA freelist_count", [], |row| row.get::<_, i64>(0)).unwrap();
+        for vacuum in &[false, true] {
+            let conn = prune_fixture();
+            // enough transfers of collection 2 to free some pages
+            for log_index in 1..1_000 {
+                let transfer = Transfer {
+                    collection_id: 2,
+                    token_id: "1".to_owned(),
+                    from: format!("{:?}", H160::zero()),
+                    to: format!("{:?}", H160::from_low_u64_be(10)),
+                    block_number: 50,
+                    tx_hash: format!("{:?}", H256::from_low_u64_be(50)),
+                    log_index,
+                    timestamp: None,
+                };
+                save_transfer(&conn, &transfer).unwrap();
+            }
+            let options = PruneOptions {
+                transfers_before: Some(60),
+                inactive_since: Some(80),
+                vacuum: *vacuum,
+                ..Default::default()
+            };
+            let report = prune(&conn, options).unwrap();
+            // collections 2 and 4 are inactive, the transfer of collection 1 at the block 10 is too old
+            let expected = PruneReport {
+                collections: 2,
+                tokens: 1,
+                owners: 1,
+                transfers: 1_001,
+            };
+            assert_eq!(expected, report);
+            let (collections, _, _, transfers) = rows_left(&conn);
+            assert_eq!(vec![1, 3], collections);
+            assert_eq!(vec![(1, "2".to_owned())], transfers);
+            assert_eq!(*vacuum, free_pages(&conn) == 0);
+        }
+    }
 }
@@ -1,37 +1,420 @@
 //! This module is a library to get ERC721 transfer events.
-use crate::{EvmClient, Result};
+use crate::{
+    config::{EventKind, DEAD_ADDRESS},
+    EvmClientApi, Result, Sale,
+};
 use array_bytes::hex2bytes_unchecked as bytes;
+use std::ops::RangeInclusive;
 use web3::types::{Log, H160, H256, U256};
 
+const TRANSFER_TOPIC: &str = "0xddf252ad1be2c89b69c2b068fc378daa952ba7f163c4a11628f55a4df523b3ef";
+/// The topic of the ERC4906 `MetadataUpdate(uint256)` event
+const METADATA_UPDATE_TOPIC: &str = "0xf8e1a15aba9398e019f0b49df1a4fde98ee17ae345cb5f6b5e2c27f5033e8ce7";
+/// The topic of the ERC4906 `BatchMetadataUpdate(uint256,uint256)` event
+const BATCH_METADATA_UPDATE_TOPIC: &str = "0x6bd5c950a8d8df17f772f5af37cb3655737899cbf903264b9795592da439661c";
+/// The topic of the `Approval(address,address,uint256)` event, ERC20 has the same one with a non indexed value
+const APPROVAL_TOPIC: &str = "0x8c5be1e5ebec7d5bd14f71427d1e84f3dd0314c0f7b2291e5b200ac8c7c3b925";
+/// The topic of the `ApprovalForAll(address,address,bool)` event, ERC1155 has the same one
+const APPROVAL_FOR_ALL_TOPIC: &str = "0x17307eab39ab6107e8899845ad3d59bd9653f200f220920489ca2b5937696c31";
+
+/// How many addresses a logs filter has at most, some providers reject the filters with more
+pub const MAX_ADDRESSES_PER_FILTER: usize = 100;
+
+/// The topic of the ERC2309 `ConsecutiveTransfer(uint256,uint256,address,address)` event
+const CONSECUTIVE_TRANSFER_TOPIC: &str = "0xdeaa91b6123d068f5821d0fb0678463d1a8a6079fe8af5de3ce5e896dcf9133d";
+
+/// The CryptoPunks contract, which predates ERC721 and emits its own events instead of the `Transfer` ones
+pub const CRYPTOPUNKS_ADDRESS: H160 = H160([
+    0xb4, 0x7e, 0x3c, 0xd8, 0x37, 0xdd, 0xf8, 0xe4, 0xc5, 0x7f, 0x05, 0xd7, 0x0a, 0xb8, 0x65, 0xde, 0x6e, 0x19, 0x3b, 0xbb,
+]);
+/// The CryptoKitties contract, whose `Transfer` events index nothing
+pub const CRYPTOKITTIES_ADDRESS: H160 = H160([
+    0x06, 0x01, 0x2c, 0x8c, 0xf9, 0x7b, 0xea, 0xd5, 0xde, 0xae, 0x23, 0x70, 0x70, 0xf9, 0x58, 0x7f, 0x8e, 0x7a, 0x26, 0x6d,
+]);
+/// The topic of the CryptoPunks `Assign(address,uint256)` event, emitted when a punk is claimed
+const PUNK_ASSIGN_TOPIC: &str = "0x8a0e37b73a0d9c82e205d4d1a3ff3d0b57ce5f4d7bccf6bac03336dc101cb7ba";
+/// The topic of the CryptoPunks `PunkTransfer(address,address,uint256)` event
+const PUNK_TRANSFER_TOPIC: &str = "0x05af636b70da6819000c49f85b21fa82081c632069bb626f30932034099107d8";
+/// The topic of the CryptoPunks `PunkBought(uint256,uint256,address,address)` event, the punk is indexed
+const PUNK_BOUGHT_TOPIC: &str = "0x58e5d5a525e3b40bc15abaa38b5882678db1ee68befd2f60bafe3a7fd06db9e3";
+
+/// The token uri of a punk, which the CryptoPunks contract does not have: the page of the punk
+pub fn cryptopunk_token_uri(token_id: &U256) -> String {
+    format!("https://cryptopunks.app/cryptopunks/details/{}", token_id)
+}
+
 /// The Erc721 Transfer Event Wrapper
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Erc721Event {
     /// The block to which this event belongs
     pub block_number: Option<u64>,
+    /// The timestamp of the block in unix seconds, only with `TrackerConfig::fetch_block_timestamps`
+    pub block_timestamp: Option<u64>,
     /// The ERC721 contract address
     pub address: H160,
     /// The transaction that issued this event
     pub transaction_hash: Option<H256>,
+    /// The account which sent the transaction, only with `TrackerConfig::fetch_transaction_senders`
+    pub tx_sender: Option<H160>,
+    /// The index of the transaction in its block
+    pub transaction_index: Option<u64>,
+    /// The index of this event in its block
+    pub log_index: Option<u64>,
     /// Transfer from
     pub from: H160,
     /// Transfer to
     pub to: H160,
     /// Transferred ERC721 token
+    #[cfg_attr(feature = "serde", serde(with = "crate::serde_decimal"))]
+    pub token_id: U256,
+    /// Whether this event is a mint, a burn or a transfer. The transfers to `DEAD_ADDRESS` are decoded as burns,
+    /// the trackers classify them with the burn addresses of their config.
+    pub kind: EventKind,
+    /// The marketplace sale of the token in the transaction, only with `TrackerConfig::fetch_sales`
+    pub sale: Option<Sale>,
+    /// The log decoded as this event, with its topics, its data and its removed flag,
+    /// only with `TrackerConfig::keep_raw_logs`. None for the transfers of an ERC2309 `ConsecutiveTransfer`.
+    pub raw: Option<Log>,
+}
+
+impl Erc721Event {
+    /// Whether this event is a mint, a burn or a transfer
+    pub fn kind(&self) -> EventKind {
+        self.kind
+    }
+}
+
+/// An ERC4906 `MetadataUpdate` or `BatchMetadataUpdate` event, emitted when the metadata of some tokens changed
+#[derive(Debug, Clone, PartialEq)]
+pub struct Erc721MetadataUpdate {
+    /// The block to which this event belongs
+    pub block_number: Option<u64>,
+    /// The ERC721 contract address
+    pub address: H160,
+    /// The transaction that issued this event
+    pub transaction_hash: Option<H256>,
+    /// The index of this event in its block
+    pub log_index: Option<u64>,
+    /// The tokens whose metadata changed, a single one for a `MetadataUpdate`
+    pub token_ids: RangeInclusive<U256>,
+}
+
+/// An ERC2309 `ConsecutiveTransfer` event, emitted for the batch mints of the ERC721A-style contracts
+/// instead of a `Transfer` per token
+#[derive(Debug, Clone, PartialEq)]
+pub struct Erc721ConsecutiveTransfer {
+    /// The block to which this event belongs
+    pub block_number: Option<u64>,
+    /// The ERC721 contract address
+    pub address: H160,
+    /// The transaction that issued this event
+    pub transaction_hash: Option<H256>,
+    /// The index of the transaction in its block
+    pub transaction_index: Option<u64>,
+    /// The index of this event in its block
+    pub log_index: Option<u64>,
+    /// Transfer from
+    pub from: H160,
+    /// Transfer to
+    pub to: H160,
+    /// The transferred tokens
+    pub token_ids: RangeInclusive<U256>,
+}
+
+impl Erc721ConsecutiveTransfer {
+    /// How many tokens are transferred
+    pub fn token_count(&self) -> U256 {
+        (*self.token_ids.end() - *self.token_ids.start()).saturating_add(U256::one())
+    }
+
+    /// The transfer of each token, in the order of the ids. They share the position of the event in its block.
+    pub fn events(&self) -> Vec<Erc721Event> {
+        let mut events = vec![];
+        let mut token_id = *self.token_ids.start();
+        loop {
+            events.push(Erc721Event {
+                block_number: self.block_number,
+                block_timestamp: None,
+                address: self.address,
+                transaction_hash: self.transaction_hash,
+                tx_sender: None,
+                transaction_index: self.transaction_index,
+                log_index: self.log_index,
+                from: self.from,
+                to: self.to,
+                token_id,
+                kind: EventKind::classify(&self.from, &self.to, &[DEAD_ADDRESS]),
+                sale: None,
+                raw: None,
+            });
+            if token_id == *self.token_ids.end() {
+                return events;
+            }
+            token_id += U256::one();
+        }
+    }
+}
+
+/// An ERC721 `Approval` event, emitted when an account is approved to transfer a token,
+/// or when the approval is cleared with the zero address
+#[derive(Debug, Clone, PartialEq)]
+pub struct Erc721ApprovalEvent {
+    /// The block to which this event belongs
+    pub block_number: Option<u64>,
+    /// The ERC721 contract address
+    pub address: H160,
+    /// The transaction that issued this event
+    pub transaction_hash: Option<H256>,
+    /// The index of this event in its block
+    pub log_index: Option<u64>,
+    /// The owner of the token
+    pub owner: H160,
+    /// The approved account, the zero address if the approval is cleared
+    pub approved: H160,
+    /// The token
     pub token_id: U256,
 }
 
-/// Get all erc721 events between `from` and `to`.
-/// the `from` and `to` blocks are included.
-pub async fn get_erc721_events(client: &EvmClient, from: u64, to: u64) -> Result<Vec<Erc721Event>> {
-    let transfer_topic = H256::from_slice(&bytes(
-        "0xddf252ad1be2c89b69c2b068fc378daa952ba7f163c4a11628f55a4df523b3ef",
-    ));
+/// An ERC721 `ApprovalForAll` event, emitted when an operator is allowed or disallowed
+/// to transfer all the tokens of an owner
+#[derive(Debug, Clone, PartialEq)]
+pub struct Erc721ApprovalForAllEvent {
+    /// The block to which this event belongs
+    pub block_number: Option<u64>,
+    /// The ERC721 contract address
+    pub address: H160,
+    /// The transaction that issued this event
+    pub transaction_hash: Option<H256>,
+    /// The index of this event in its block
+    pub log_index: Option<u64>,
+    /// The owner of the tokens
+    pub owner: H160,
+    /// The operator, like the conduit of a marketplace
+    pub operator: H160,
+    /// Whether the operator is allowed, false if it is revoked
+    pub approved: bool,
+}
+
+/// The logs requested along with the transfers, and how the transfers are decoded
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct LogKinds<'a> {
+    /// The ERC4906 metadata updates
+    pub metadata_updates: bool,
+    /// The `Approval` events
+    pub approvals: bool,
+    /// The `ApprovalForAll` events
+    pub approvals_for_all: bool,
+    /// The ERC2309 `ConsecutiveTransfer` events
+    pub consecutive_transfers: bool,
+    /// The events of `CRYPTOPUNKS_ADDRESS`, decoded as transfers
+    pub cryptopunks: bool,
+    /// The contracts whose `Transfer` events have their from, to and token id in the data, none indexed
+    pub non_indexed_erc721_contracts: &'a [H160],
+    /// Keep the log of each transfer event in `Erc721Event::raw`
+    pub raw_logs: bool,
+}
+
+/// The erc721 events, the ERC4906 metadata updates and the approvals of some blocks, in log order
+#[derive(Debug, Default)]
+pub struct Erc721Logs {
+    /// The transfer events
+    pub events: Vec<Erc721Event>,
+    /// The metadata updates
+    pub metadata_updates: Vec<Erc721MetadataUpdate>,
+    /// The `Approval` events
+    pub approvals: Vec<Erc721ApprovalEvent>,
+    /// The `ApprovalForAll` events
+    pub approvals_for_all: Vec<Erc721ApprovalForAllEvent>,
+    /// The ERC2309 `ConsecutiveTransfer` events
+    pub consecutive_transfers: Vec<Erc721ConsecutiveTransfer>,
+    /// How many `Transfer` logs were skipped as they are not shaped like ERC721 ones, like the ERC20 transfers
+    pub transfers_rejected: u64,
+}
+
+/// Get all erc721 events between `from` and `to`, emitted by any of `contract_addresses` if any.
+/// the `from` and `to` blocks are included. The addresses are set in the filters of the requests,
+/// `MAX_ADDRESSES_PER_FILTER` at a time as with `get_erc721_events_of_contracts`.
+pub async fn get_erc721_events(
+    client: &dyn EvmClientApi,
+    contract_addresses: Option<&[H160]>,
+    from: u64,
+    to: u64,
+) -> Result<Vec<Erc721Event>> {
+    if let Some(contract_addresses) = contract_addresses {
+        return get_erc721_events_of_contracts(client, contract_addresses, MAX_ADDRESSES_PER_FILTER, from, to).await;
+    }
+    let transfer_topic = H256::from_slice(&bytes(TRANSFER_TOPIC));
     let logs = client
         .get_logs(None, vec![transfer_topic], from, to)
         .await?;
+    build_events(client, logs).await
+}
+
+/// Get the erc721 events emitted by `contract_addresses` between `from` and `to`.
+/// The addresses are requested `chunk_size` at a time, for the providers limiting the address count of a filter.
+/// The client splits the range of each request it rejects as too large, the events are merged in block order.
+pub async fn get_erc721_events_of_contracts(
+    client: &dyn EvmClientApi,
+    contract_addresses: &[H160],
+    chunk_size: usize,
+    from: u64,
+    to: u64,
+) -> Result<Vec<Erc721Event>> {
+    let transfer_topic = H256::from_slice(&bytes(TRANSFER_TOPIC));
+    let mut logs = vec![];
+    for chunk in contract_addresses.chunks(chunk_size) {
+        let mut chunk_logs = client
+            .get_logs_of_contracts(chunk.to_vec(), vec![transfer_topic], from, to)
+            .await?;
+        logs.append(&mut chunk_logs);
+    }
+    // keep the chain order across the chunks
+    logs.sort_by_key(|log| (log.block_number, log.transaction_index, log.log_index));
+    build_events(client, logs).await
+}
+
+/// Get the erc721 events between `from` and `to`, with the other logs of `kinds`,
+/// emitted by any of `contract_addresses` if any. The addresses are requested `chunk_size` at a time,
+/// as with `get_erc721_events_of_contracts`.
+pub async fn get_erc721_logs(
+    client: &dyn EvmClientApi,
+    contract_addresses: Option<&[H160]>,
+    chunk_size: usize,
+    kinds: LogKinds<'_>,
+    from: u64,
+    to: u64,
+) -> Result<Erc721Logs> {
+    let topics = tracked_topics(kinds);
+    let mut logs = match contract_addresses {
+        Some(contract_addresses) => {
+            let mut logs = vec![];
+            for chunk in contract_addresses.chunks(chunk_size) {
+                let mut chunk_logs = client
+                    .get_logs_of_contracts(chunk.to_vec(), topics.clone(), from, to)
+                    .await?;
+                logs.append(&mut chunk_logs);
+            }
+            logs
+        }
+        None => client.get_logs(None, topics, from, to).await?,
+    };
+    logs.sort_by_key(|log| (log.block_number, log.log_index));
+    build_logs(client, logs, kinds).await
+}
+
+/// Get the erc721 events of the block `block_hash`, with the other logs of `kinds`,
+/// emitted by any of `contract_addresses` if any. The addresses are requested `chunk_size` at a time.
+pub async fn get_erc721_logs_by_block_hash(
+    client: &dyn EvmClientApi,
+    block_hash: H256,
+    contract_addresses: Option<&[H160]>,
+    chunk_size: usize,
+    kinds: LogKinds<'_>,
+) -> Result<Erc721Logs> {
+    let topics = tracked_topics(kinds);
+    let mut logs = match contract_addresses {
+        Some(contract_addresses) => {
+            let mut logs = vec![];
+            for chunk in contract_addresses.chunks(chunk_size) {
+                let mut chunk_logs = client
+                    .get_logs_by_block_hash(block_hash, Some(chunk.to_vec()), topics.clone())
+                    .await?;
+                logs.append(&mut chunk_logs);
+            }
+            logs
+        }
+        None => client.get_logs_by_block_hash(block_hash, None, topics).await?,
+    };
+    logs.sort_by_key(|log| log.log_index);
+    build_logs(client, logs, kinds).await
+}
+
+/// The topics of the transfers, and of the other logs of `kinds`
+fn tracked_topics(kinds: LogKinds<'_>) -> Vec<H256> {
+    let mut topics = vec![H256::from_slice(&bytes(TRANSFER_TOPIC))];
+    if kinds.metadata_updates {
+        topics.push(H256::from_slice(&bytes(METADATA_UPDATE_TOPIC)));
+        topics.push(H256::from_slice(&bytes(BATCH_METADATA_UPDATE_TOPIC)));
+    }
+    if kinds.approvals {
+        topics.push(H256::from_slice(&bytes(APPROVAL_TOPIC)));
+    }
+    if kinds.approvals_for_all {
+        topics.push(H256::from_slice(&bytes(APPROVAL_FOR_ALL_TOPIC)));
+    }
+    if kinds.consecutive_transfers {
+        topics.push(H256::from_slice(&bytes(CONSECUTIVE_TRANSFER_TOPIC)));
+    }
+    if kinds.cryptopunks {
+        topics.push(H256::from_slice(&bytes(PUNK_ASSIGN_TOPIC)));
+        topics.push(H256::from_slice(&bytes(PUNK_TRANSFER_TOPIC)));
+        topics.push(H256::from_slice(&bytes(PUNK_BOUGHT_TOPIC)));
+    }
+    topics
+}
+
+/// Split the logs into the transfers, the metadata updates and the approvals of the visual erc721 contracts.
+/// The ERC20 approvals and the ERC1155 `ApprovalForAll` events, which share their topics, are left out
+/// as their contracts are not ERC721 ones. The CryptoPunks logs are decoded as transfers with `kinds.cryptopunks`.
+/// The contracts of `kinds.non_indexed_erc721_contracts` predate ERC165 and are not checked with it.
+async fn build_logs(client: &dyn EvmClientApi, logs: Vec<Log>, kinds: LogKinds<'_>) -> Result<Erc721Logs> {
+    let transfer_topic = H256::from_slice(&bytes(TRANSFER_TOPIC));
+    let mut erc721_logs = Erc721Logs::default();
+    let mut punk_balance_transfer = None;
+    let with_raw = |mut event: Erc721Event, log: &Log| {
+        if kinds.raw_logs {
+            event.raw = Some(log.clone());
+        }
+        event
+    };
+    for log in logs {
+        if kinds.cryptopunks && log.address == CRYPTOPUNKS_ADDRESS {
+            if let Some(event) = build_punk_event(&log, &mut punk_balance_transfer) {
+                erc721_logs.events.push(with_raw(event, &log));
+            }
+        } else if log.topics.first() == Some(&transfer_topic) {
+            if kinds.non_indexed_erc721_contracts.contains(&log.address) {
+                match build_non_indexed_event(&log) {
+                    Some(event) => erc721_logs.events.push(with_raw(event, &log)),
+                    None => {
+                        skip_transfer(&log);
+                        erc721_logs.transfers_rejected += 1;
+                    }
+                }
+            } else if !is_erc721_transfer(&log) {
+                skip_transfer(&log);
+                erc721_logs.transfers_rejected += 1;
+            } else if client.is_visual_erc721(log.address).await? {
+                erc721_logs.events.push(with_raw(build_event(&log), &log));
+            }
+        } else if let Some(consecutive_transfer) = build_consecutive_transfer(&log) {
+            if client.is_visual_erc721(log.address).await? {
+                erc721_logs.consecutive_transfers.push(consecutive_transfer);
+            }
+        } else if let Some(metadata_update) = build_metadata_update(&log) {
+            if client.is_visual_erc721(log.address).await? {
+                erc721_logs.metadata_updates.push(metadata_update);
+            }
+        } else if let Some(approval) = build_approval(&log) {
+            if client.is_visual_erc721(log.address).await? {
+                erc721_logs.approvals.push(approval);
+            }
+        } else if let Some(approval_for_all) = build_approval_for_all(&log) {
+            if client.is_visual_erc721(log.address).await? {
+                erc721_logs.approvals_for_all.push(approval_for_all);
+            }
+        }
+    }
+    Ok(erc721_logs)
+}
+
+async fn build_events(client: &dyn EvmClientApi, logs: Vec<Log>) -> Result<Vec<Erc721Event>> {
     let mut events = vec![];
     for log in logs {
-        if log.topics.len() == 4 && client.is_visual_erc721(log.address).await? {
+        if !is_erc721_transfer(&log) {
+            skip_transfer(&log);
+        } else if client.is_visual_erc721(log.address).await? {
             events.push(build_event(&log));
         }
     }
@@ -39,33 +422,504 @@ pub async fn get_erc721_events(client: &EvmClient, from: u64, to: u64) -> Result
     Ok(events)
 }
 
+/// Whether a `Transfer` log is shaped like an ERC721 one: its from, to and token id are indexed and its data is empty.
+/// The ERC20 transfers have no indexed value, and the pre-standard contracts like CryptoKitties index nothing.
+/// Any topic is a valid token id, but the from and to topics have to be addresses.
+fn is_erc721_transfer(log: &Log) -> bool {
+    let is_address = |topic: &H256| topic.0[0..12].iter().all(|byte| *byte == 0);
+    log.topics.len() == 4
+        && log.topics[0] == H256::from_slice(&bytes(TRANSFER_TOPIC))
+        && log.data.0.is_empty()
+        && is_address(&log.topics[1])
+        && is_address(&log.topics[2])
+}
+
+fn skip_transfer(log: &Log) {
+    debug!(
+        "Skip the Transfer log {:?} of {:?} in {:?}, it is not an ERC721 one",
+        log.log_index, log.address, log.transaction_hash
+    );
+}
+
+/// Decode a `MetadataUpdate` or a `BatchMetadataUpdate` log, None if it is neither
+fn build_metadata_update(log: &Log) -> Option<Erc721MetadataUpdate> {
+    let topic = log.topics.first()?;
+    let data = &log.data.0;
+    let token_ids = if *topic == H256::from_slice(&bytes(METADATA_UPDATE_TOPIC)) && data.len() == 32 {
+        let token_id = U256::from_big_endian(&data[0..32]);
+        token_id..=token_id
+    } else if *topic == H256::from_slice(&bytes(BATCH_METADATA_UPDATE_TOPIC)) && data.len() == 64 {
+        U256::from_big_endian(&data[0..32])..=U256::from_big_endian(&data[32..64])
+    } else {
+        return None;
+    };
+    Some(Erc721MetadataUpdate {
+        block_number: log.block_number.map(|b| b.as_u64()),
+        address: log.address,
+        transaction_hash: log.transaction_hash,
+        log_index: log.log_index.map(|i| i.as_u64()),
+        token_ids,
+    })
+}
+
+/// Decode a `ConsecutiveTransfer` log, None if it is not one: the first token is indexed, not the last one
+fn build_consecutive_transfer(log: &Log) -> Option<Erc721ConsecutiveTransfer> {
+    let data = &log.data.0;
+    if log.topics.len() != 4 || log.topics[0] != H256::from_slice(&bytes(CONSECUTIVE_TRANSFER_TOPIC)) || data.len() != 32
+    {
+        return None;
+    }
+    let (first, last) = (U256::from(log.topics[1].0), U256::from_big_endian(data));
+    if first > last {
+        return None;
+    }
+    Some(Erc721ConsecutiveTransfer {
+        block_number: log.block_number.map(|b| b.as_u64()),
+        address: log.address,
+        transaction_hash: log.transaction_hash,
+        transaction_index: log.transaction_index.map(|i| i.as_u64()),
+        log_index: log.log_index.map(|i| i.as_u64()),
+        from: H160::from(log.topics[2]),
+        to: H160::from(log.topics[3]),
+        token_ids: first..=last,
+    })
+}
+
+/// Decode an `Approval` log, None if it is not one of ERC721: the token id of an ERC20 approval is its value,
+/// which is not indexed
+fn build_approval(log: &Log) -> Option<Erc721ApprovalEvent> {
+    if log.topics.len() != 4 || log.topics[0] != H256::from_slice(&bytes(APPROVAL_TOPIC)) {
+        return None;
+    }
+    Some(Erc721ApprovalEvent {
+        block_number: log.block_number.map(|b| b.as_u64()),
+        address: log.address,
+        transaction_hash: log.transaction_hash,
+        log_index: log.log_index.map(|i| i.as_u64()),
+        owner: H160::from(log.topics[1]),
+        approved: H160::from(log.topics[2]),
+        token_id: U256::from(log.topics[3].0),
+    })
+}
+
+/// Decode an `ApprovalForAll` log, None if it is not one. An ERC1155 one is decoded the same.
+fn build_approval_for_all(log: &Log) -> Option<Erc721ApprovalForAllEvent> {
+    let data = &log.data.0;
+    if log.topics.len() != 3 || log.topics[0] != H256::from_slice(&bytes(APPROVAL_FOR_ALL_TOPIC)) || data.len() != 32 {
+        return None;
+    }
+    Some(Erc721ApprovalForAllEvent {
+        block_number: log.block_number.map(|b| b.as_u64()),
+        address: log.address,
+        transaction_hash: log.transaction_hash,
+        log_index: log.log_index.map(|i| i.as_u64()),
+        owner: H160::from(log.topics[1]),
+        operator: H160::from(log.topics[2]),
+        approved: !U256::from_big_endian(data).is_zero(),
+    })
+}
+
 fn build_event(log: &Log) -> Erc721Event {
     let from = H160::from(log.topics[1]);
     let to = H160::from(log.topics[2]);
     let token_id = U256::from(log.topics[3].0);
+    transfer_event(log, from, to, token_id)
+}
+
+/// Decode a `Transfer` log whose from, to and token id are all in the data, as the 2017 contracts like CryptoKitties
+/// emit them. None if it is not shaped like that.
+fn build_non_indexed_event(log: &Log) -> Option<Erc721Event> {
+    let data = &log.data.0;
+    let is_address = |word: &[u8]| word[0..12].iter().all(|byte| *byte == 0);
+    if log.topics.len() != 1 || data.len() != 96 || !is_address(&data[0..32]) || !is_address(&data[32..64]) {
+        return None;
+    }
+    let from = H160::from_slice(&data[12..32]);
+    let to = H160::from_slice(&data[44..64]);
+    let token_id = U256::from_big_endian(&data[64..96]);
+    Some(transfer_event(log, from, to, token_id))
+}
+
+/// Decode a CryptoPunks log into the transfer of a punk, None if it is not one. The contract also emits
+/// an ERC20-like `Transfer(from, to, 1)` of the balances before a `PunkTransfer` or a `PunkBought`, which is kept
+/// in `balance_transfer`: the `PunkBought` of an accepted bid is emitted once the bid is cleared, with a zero buyer,
+/// and the buyer is the receiver of the balance transfer of its transaction.
+fn build_punk_event(log: &Log, balance_transfer: &mut Option<(Option<H256>, H160, H160)>) -> Option<Erc721Event> {
+    let topic = *log.topics.first()?;
+    let data = &log.data.0;
+    let (from, to, token_id) = if topic == H256::from_slice(&bytes(TRANSFER_TOPIC)) && log.topics.len() == 3 {
+        *balance_transfer = Some((log.transaction_hash, H160::from(log.topics[1]), H160::from(log.topics[2])));
+        return None;
+    } else if topic == H256::from_slice(&bytes(PUNK_ASSIGN_TOPIC)) && log.topics.len() == 2 && data.len() == 32 {
+        // a claim, from nobody
+        (H160::zero(), H160::from(log.topics[1]), U256::from_big_endian(data))
+    } else if topic == H256::from_slice(&bytes(PUNK_TRANSFER_TOPIC)) && log.topics.len() == 3 && data.len() == 32 {
+        (H160::from(log.topics[1]), H160::from(log.topics[2]), U256::from_big_endian(data))
+    } else if topic == H256::from_slice(&bytes(PUNK_BOUGHT_TOPIC)) && log.topics.len() == 4 && data.len() == 32 {
+        let from = H160::from(log.topics[2]);
+        let to = match (H160::from(log.topics[3]), *balance_transfer) {
+            (to, _) if !to.is_zero() => to,
+            (_, Some((transaction_hash, seller, buyer))) if transaction_hash == log.transaction_hash && seller == from => {
+                buyer
+            }
+            _ => {
+                warn!("The buyer of the punk bought in {:?} is unknown.", log.transaction_hash);
+                return None;
+            }
+        };
+        (from, to, U256::from(log.topics[1].0))
+    } else {
+        return None;
+    };
+    Some(transfer_event(log, from, to, token_id))
+}
+
+fn transfer_event(log: &Log, from: H160, to: H160, token_id: U256) -> Erc721Event {
     Erc721Event {
         block_number: log.block_number.map(|b| b.as_u64()),
+        block_timestamp: None,
         address: log.address,
         transaction_hash: log.transaction_hash,
+        tx_sender: None,
+        transaction_index: log.transaction_index.map(|i| i.as_u64()),
+        log_index: log.log_index.map(|i| i.as_u64()),
         from,
         to,
         token_id,
+        kind: EventKind::classify(&from, &to, &[DEAD_ADDRESS]),
+        sale: None,
+        raw: None,
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use web3::{transports::http::Http, Web3};
+    use crate::test_support::{
+        address, approval_for_all_log, erc20_approval_log, erc4906_batch_metadata_update_log,
+        erc4906_metadata_update_log, erc721_approval_log, erc721_transfer_log, fixture_logs, MockEvmClient,
+        MockTransport,
+    };
+    use crate::EvmClient;
+    use web3::{ethabi, transports::http::Http, types::Bytes, Web3};
+
+    #[tokio::test]
+    async fn test_get_erc721_logs_with_metadata_updates() {
+        let collection = address(1);
+        let client = MockEvmClient::new("Mock", 100)
+            .with_erc721_collection(collection, "Mock Collection", "MOCK")
+            .with_log(erc721_transfer_log(collection, address(0), address(2), 1, 10, 0))
+            .with_log(erc4906_metadata_update_log(collection, 1, 10, 1))
+            .with_log(erc4906_batch_metadata_update_log(collection, 1, 100, 11, 0));
+
+        let kinds = LogKinds {
+            metadata_updates: true,
+            ..Default::default()
+        };
+        let logs = get_erc721_logs(&client, None, 1, kinds, 10, 11).await.unwrap();
+        assert_eq!(1, logs.events.len());
+        let token_ids: Vec<_> = logs
+            .metadata_updates
+            .iter()
+            .map(|metadata_update| (metadata_update.block_number, metadata_update.token_ids.clone()))
+            .collect();
+        assert_eq!(
+            vec![
+                (Some(10), U256::from(1)..=U256::from(1)),
+                (Some(11), U256::from(1)..=U256::from(100)),
+            ],
+            token_ids
+        );
+
+        // the metadata updates are not requested when the metadata is not fetched
+        let logs = get_erc721_logs(&client, None, 1, LogKinds::default(), 10, 11).await.unwrap();
+        assert_eq!(1, logs.events.len());
+        assert!(logs.metadata_updates.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_get_erc721_logs_with_mixed_transfers() {
+        let mut client = MockEvmClient::new("Mock", 13015344);
+        let logs = fixture_logs(include_str!("./fixtures/mixed_transfer_logs.json"));
+        for log in logs.iter().cloned() {
+            // every contract claims ERC721, only the shape of the logs tells them apart
+            client = client
+                .with_erc721_collection(log.address, "Mock Collection", "MOCK")
+                .with_log(log);
+        }
+
+        let logs = get_erc721_logs(&client, None, 100, LogKinds::default(), 13015344, 13015344)
+            .await
+            .unwrap();
+
+        // the USDT transfer, an ERC20 transfer with data, a CryptoKitties one and one from a garbage topic
+        assert_eq!(4, logs.transfers_rejected);
+        assert_eq!(1, logs.events.len());
+        let bayc: H160 = "0xbc4ca0eda7647a8ab7c2061c2e118a18a936f13d".parse().unwrap();
+        assert_eq!(bayc, logs.events[0].address);
+        assert_eq!(EventKind::Mint, logs.events[0].kind);
+        assert_eq!(U256::from(1234), logs.events[0].token_id);
+        assert_eq!(1, client.call_count("is_visual_erc721"));
+
+        let events = get_erc721_events(&client, None, 13015344, 13015344).await.unwrap();
+        assert_eq!(logs.events, events);
+    }
+
+    #[tokio::test]
+    async fn test_get_erc721_logs_with_cryptopunks() {
+        // a claim, a transfer, a sale and an accepted bid of the punk 2
+        let mut client = MockEvmClient::new("Mock", 3925630);
+        for log in fixture_logs(include_str!("./fixtures/cryptopunks_logs.json")) {
+            client = client.with_log(log);
+        }
+        let kinds = LogKinds {
+            cryptopunks: true,
+            ..Default::default()
+        };
+
+        let logs = get_erc721_logs(&client, None, 100, kinds, 3914495, 3925630).await.unwrap();
+
+        let claimer: H160 = "0x00bd9fd57c423a1b1c969823d409156d90974d77".parse().unwrap();
+        let friend: H160 = "0x3bf010bf121381e3a50f6ddb6b3c1ad902144e90".parse().unwrap();
+        let buyer: H160 = "0x5b098b00621eda6a96b7a476220661ad265f083f".parse().unwrap();
+        let bidder: H160 = "0x6f4a2d3a4f47f9c647d86c929755593911ee91ec".parse().unwrap();
+        let transfers: Vec<(H160, H160, EventKind)> =
+            logs.events.iter().map(|event| (event.from, event.to, event.kind)).collect();
+        assert_eq!(
+            vec![
+                (H160::zero(), claimer, EventKind::Mint),
+                (claimer, friend, EventKind::Transfer),
+                (friend, buyer, EventKind::Transfer),
+                // from the balance transfer, the buyer of the `PunkBought` is zero
+                (buyer, bidder, EventKind::Transfer),
+            ],
+            transfers
+        );
+        assert!(logs.events.iter().all(|event| event.address == CRYPTOPUNKS_ADDRESS && event.token_id == 2.into()));
+        assert_eq!(0, logs.transfers_rejected);
+        assert_eq!(0, client.call_count("is_visual_erc721"));
+
+        // without the adapter, only the balance transfers are seen, and they are not ERC721 ones
+        let logs = get_erc721_logs(&client, None, 100, LogKinds::default(), 3914495, 3925630).await.unwrap();
+        assert!(logs.events.is_empty());
+        assert_eq!(3, logs.transfers_rejected);
+    }
+
+    #[tokio::test]
+    async fn test_get_erc721_logs_with_non_indexed_transfers() {
+        // the birth of the kitty 1 and its transfer, the contract does not report supporting ERC721
+        let mut client = MockEvmClient::new("Mock", 4606122);
+        for log in fixture_logs(include_str!("./fixtures/cryptokitties_logs.json")) {
+            client = client.with_log(log);
+        }
+        let non_indexed_erc721_contracts = [CRYPTOKITTIES_ADDRESS];
+        let kinds = LogKinds {
+            non_indexed_erc721_contracts: &non_indexed_erc721_contracts,
+            ..Default::default()
+        };
+
+        let logs = get_erc721_logs(&client, None, 100, kinds, 4605346, 4606122).await.unwrap();
+
+        let owner: H160 = "0xba52c75764d6f594735dc735be7f1830cdf58ddf".parse().unwrap();
+        let buyer: H160 = "0x47e0d9ce2c3a5d7e13a0d1575b0be6b2f7e3bb9c".parse().unwrap();
+        let transfers: Vec<(H160, H160, U256, EventKind)> =
+            logs.events.iter().map(|event| (event.from, event.to, event.token_id, event.kind)).collect();
+        assert_eq!(
+            vec![
+                (H160::zero(), owner, U256::from(1), EventKind::Mint),
+                (owner, buyer, U256::from(1), EventKind::Transfer),
+            ],
+            transfers
+        );
+        assert_eq!(Some(4605346), logs.events[0].block_number);
+        assert_eq!(0, logs.transfers_rejected);
+        assert_eq!(0, client.call_count("is_visual_erc721"));
+
+        // they are not ERC721 ones for the other contracts
+        let logs = get_erc721_logs(&client, None, 100, LogKinds::default(), 4605346, 4606122).await.unwrap();
+        assert!(logs.events.is_empty());
+        assert_eq!(2, logs.transfers_rejected);
+    }
+
+    #[tokio::test]
+    async fn test_get_erc721_logs_with_consecutive_transfers() {
+        let collection: H160 = "0x8a90cab2b38dba80c64b7734e58ee1db38b8992e".parse().unwrap();
+        let minter: H160 = "0x6c2f360747d3d1f9a1bdc56f3d7f3bc8e3a5e50b".parse().unwrap();
+        let mut client = MockEvmClient::new("Mock", 15000020).with_erc721_collection(collection, "Mock Collection", "MOCK");
+        for log in fixture_logs(include_str!("./fixtures/erc2309_consecutive_transfer_logs.json")) {
+            client = client.with_log(log);
+        }
+        let kinds = LogKinds {
+            consecutive_transfers: true,
+            ..Default::default()
+        };
+
+        let logs = get_erc721_logs(&client, None, 100, kinds, 15000000, 15000020).await.unwrap();
+
+        assert_eq!(1, logs.events.len());
+        let batches: Vec<(RangeInclusive<U256>, U256)> = logs
+            .consecutive_transfers
+            .iter()
+            .map(|batch| (batch.token_ids.clone(), batch.token_count()))
+            .collect();
+        assert_eq!(
+            vec![
+                (U256::from(0)..=U256::from(4), U256::from(5)),
+                (U256::from(5)..=U256::from(30004), U256::from(30000)),
+            ],
+            batches
+        );
+        let events = logs.consecutive_transfers[0].events();
+        let token_ids: Vec<U256> = events.iter().map(|event| event.token_id).collect();
+        assert_eq!((0..5).map(U256::from).collect::<Vec<_>>(), token_ids);
+        assert!(events
+            .iter()
+            .all(|event| event.kind == EventKind::Mint && event.to == minter && event.log_index == Some(0x31)));
+
+        // they are not requested without the kind
+        let logs = get_erc721_logs(&client, None, 100, LogKinds::default(), 15000000, 15000020).await.unwrap();
+        assert!(logs.consecutive_transfers.is_empty());
+    }
+
+    #[test]
+    fn test_build_event_kind() {
+        let collection = address(1);
+        let kind_of = |from: H160, to: H160| build_event(&erc721_transfer_log(collection, from, to, 1, 10, 0)).kind;
+        assert_eq!(EventKind::Mint, kind_of(address(0), address(2)));
+        assert_eq!(EventKind::Burn, kind_of(address(2), address(0)));
+        assert_eq!(EventKind::Burn, kind_of(address(2), DEAD_ADDRESS));
+        assert_eq!(EventKind::Transfer, kind_of(address(2), address(3)));
+        assert_eq!("0x000000000000000000000000000000000000dead", format!("{:?}", DEAD_ADDRESS));
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_erc721_event_json() {
+        let mut event = build_event(&erc721_transfer_log(address(1), address(0), address(2), u64::MAX, 12, 3));
+        let price_wei = U256::exp10(20);
+        event.sale = Some(Sale { marketplace: crate::Marketplace::Seaport, price_wei, currency: H160::zero() });
+        let mut json = serde_json::to_value(&event).unwrap();
+        assert_eq!("0x0000000000000000000000000000000000000002", json["to"]);
+        // the integers above 2^53 are decimal strings
+        assert_eq!("18446744073709551615", json["token_id"]);
+        assert_eq!("100000000000000000000", json["sale"]["price_wei"]);
+        assert_eq!("Mint", json["kind"]);
+        assert_eq!(event, serde_json::from_value(json.clone()).unwrap());
+
+        json["token_id"] = "0xffffffffffffffff".into();
+        assert!(serde_json::from_value::<Erc721Event>(json).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_get_erc721_logs_with_approvals() {
+        let collection = address(1);
+        let (owner, operator) = (address(2), address(3));
+        // the ERC20 token 4 and the ERC1155 contract 5 emit logs with the same topics
+        let client = MockEvmClient::new("Mock", 100)
+            .with_erc721_collection(collection, "Mock Collection", "MOCK")
+            .with_log(erc721_approval_log(collection, owner, operator, 7, 10, 0))
+            .with_log(erc20_approval_log(address(4), owner, operator, 1000, 10, 1))
+            .with_log(approval_for_all_log(collection, owner, operator, true, 10, 2))
+            .with_log(approval_for_all_log(address(5), owner, operator, true, 10, 3))
+            .with_log(approval_for_all_log(collection, owner, operator, false, 11, 0))
+            .with_log(erc721_transfer_log(collection, owner, operator, 7, 11, 1));
+
+        let kinds = LogKinds {
+            approvals: true,
+            approvals_for_all: true,
+            ..Default::default()
+        };
+        let logs = get_erc721_logs(&client, None, 1, kinds, 10, 11).await.unwrap();
+        assert_eq!(1, logs.events.len());
+        assert_eq!(
+            vec![Erc721ApprovalEvent {
+                block_number: Some(10),
+                address: collection,
+                transaction_hash: Some(H256::from_low_u64_be(10000)),
+                log_index: Some(0),
+                owner,
+                approved: operator,
+                token_id: U256::from(7),
+            }],
+            logs.approvals
+        );
+        let approvals_for_all: Vec<_> = logs
+            .approvals_for_all
+            .iter()
+            .map(|approval| (approval.block_number, approval.address, approval.owner, approval.operator, approval.approved))
+            .collect();
+        assert_eq!(
+            vec![
+                (Some(10), collection, owner, operator, true),
+                (Some(11), collection, owner, operator, false),
+            ],
+            approvals_for_all
+        );
+
+        // only the approvals opted in are requested
+        let kinds = LogKinds {
+            approvals_for_all: true,
+            ..Default::default()
+        };
+        let logs = get_erc721_logs(&client, None, 1, kinds, 10, 11).await.unwrap();
+        assert!(logs.approvals.is_empty());
+        assert_eq!(2, logs.approvals_for_all.len());
+        let logs = get_erc721_logs(&client, None, 1, LogKinds::default(), 10, 11).await.unwrap();
+        assert!(logs.approvals.is_empty() && logs.approvals_for_all.is_empty());
+    }
 
     #[tokio::test]
     async fn test_get_erc721_events() {
         let web3 = Web3::new(Http::new("https://main-light.eth.linkpool.io").unwrap());
         let client = EvmClient::new("Ethereum".to_owned(), web3);
 
-        let events = get_erc721_events(&client, 13015344, 13015344)
+        let events = get_erc721_events(&client, None, 13015344, 13015344)
             .await
             .unwrap();
         assert_eq!(10, events.len());
     }
+
+    #[tokio::test]
+    async fn test_get_erc721_events_of_addresses() {
+        let collections = [address(1), address(2), address(3)];
+        let logs = (10..18)
+            .map(|block_number| {
+                let collection = collections[block_number as usize % 3];
+                erc721_transfer_log(collection, H160::zero(), address(9), block_number, block_number, 0)
+            })
+            .collect();
+        let supported = web3::helpers::serialize(&Bytes(ethabi::encode(&[ethabi::Token::Bool(true)])));
+        let transport = MockTransport::default().with_logs(logs, 4).with_response("eth_call", Ok(supported));
+        let client = EvmClient::from_transport("Mock".to_owned(), Web3::new(transport.clone()));
+
+        let wanted = [collections[2], collections[1]];
+        let events = get_erc721_events_of_contracts(&client, &wanted, 1, 10, 17).await.unwrap();
+        // the events of both chunks are merged in block order
+        assert_eq!(vec![10, 11, 13, 14, 16, 17], events.iter().map(|event| event.block_number).collect::<Vec<u64>>());
+        assert!(events.iter().all(|event| wanted.contains(&event.address)));
+        // the range of each chunk is split as the provider rejects it
+        assert_eq!(
+            vec![(10, 17), (10, 13), (14, 17), (10, 17), (10, 13), (14, 17)],
+            transport.log_ranges()
+        );
+        let filtered = |collection: H160| vec![format!("{:?}", collection)];
+        let (first, second) = (filtered(wanted[0]), filtered(wanted[1]));
+        assert_eq!(
+            vec![first.clone(), first.clone(), first, second.clone(), second.clone(), second],
+            transport.log_filter_addresses()
+        );
+
+        // a single filter takes all the addresses under the provider limit
+        let sent = transport.sent_count("eth_getLogs");
+        let events = get_erc721_events(&client, Some(&wanted[..]), 10, 17).await.unwrap();
+        assert_eq!(6, events.len());
+        assert_eq!(sent + 3, transport.sent_count("eth_getLogs"));
+        let both = wanted.iter().map(|collection| format!("{:?}", collection)).collect::<Vec<String>>();
+        assert_eq!(Some(&both), transport.log_filter_addresses().last());
+
+        // no address filters nothing
+        let events = get_erc721_events(&client, None, 10, 17).await.unwrap();
+        assert_eq!(8, events.len());
+        assert_eq!(Some(&vec![]), transport.log_filter_addresses().last());
+    }
 }
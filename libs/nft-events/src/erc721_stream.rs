@@ -0,0 +1,231 @@
+//! This module exposes the ERC721 events as a `Stream`, for the consumers which are easier to write
+//! with `while let Some(event) = stream.next().await` than with an `Erc721EventCallback`.
+use crate::{
+    erc721::{track_erc721_events_with_config, Erc721EventCallback},
+    Erc721Event, Erc721TrackerConfig, EvmClientApi, NftStore, Result,
+};
+use futures::Stream;
+use std::{
+    pin::Pin,
+    sync::Arc,
+    task::{Context, Poll},
+};
+use tokio::sync::mpsc;
+use tokio_util::sync::CancellationToken;
+
+/// How many events the background tracker can get ahead of the consumer
+const CHANNEL_CAPACITY: usize = 64;
+
+/// An ERC721 event with everything that is passed to `Erc721EventCallback::on_erc721_event`
+#[derive(Debug, Clone, PartialEq)]
+pub struct Erc721EventWithMetadata {
+    /// The event
+    pub event: Erc721Event,
+    /// The name of the collection
+    pub name: String,
+    /// The symbol of the collection
+    pub symbol: String,
+    /// The total supply of the collection, if it is enumerable
+    pub total_supply: Option<u128>,
+    /// The uri of the token
+    pub token_uri: String,
+}
+
+/// Stream the ERC721 events tracked as configured by `config`.
+/// The tracker runs in a spawned task which feeds a bounded channel, so it waits for the consumer
+/// when it gets too far ahead. The stream ends when the tracker returns; if it stopped because of
+/// an error, the error is the last item. Dropping the stream cancels the tracker.
+/// The reorgs are not reported, use `Erc721EventCallback::on_erc721_events_removed` for that.
+pub fn erc721_event_stream(
+    evm_client: Arc<dyn EvmClientApi>,
+    store: Arc<dyn NftStore>,
+    mut config: Erc721TrackerConfig,
+) -> impl Stream<Item = Result<Erc721EventWithMetadata>> + Unpin {
+    let (sender, receiver) = mpsc::channel(CHANNEL_CAPACITY);
+    // a child token, so that the caller can still cancel the tracker through its own token
+    let token = match &config.options.cancellation_token {
+        Some(token) => token.child_token(),
+        None => CancellationToken::new(),
+    };
+    config.options.cancellation_token = Some(token.clone());
+
+    let mut callback = ChannelErc721EventCallback {
+        sender: sender.clone(),
+        token: token.clone(),
+    };
+    tokio::spawn(async move {
+        let result = track_erc721_events_with_config(&*evm_client, &*store, &config, &mut callback).await;
+        if let Err(err) = result {
+            let _ = sender.send(Err(err)).await;
+        }
+    });
+
+    Erc721EventStream { receiver, token }
+}
+
+/// Forwards the events to the stream, it cancels the tracker once the stream is dropped
+struct ChannelErc721EventCallback {
+    sender: mpsc::Sender<Result<Erc721EventWithMetadata>>,
+    token: CancellationToken,
+}
+
+#[async_trait]
+impl Erc721EventCallback for ChannelErc721EventCallback {
+    async fn on_erc721_event(
+        &mut self,
+        event: Erc721Event,
+        name: String,
+        symbol: String,
+        total_supply: Option<u128>,
+        token_uri: String,
+    ) -> Result<()> {
+        let item = Erc721EventWithMetadata {
+            event,
+            name,
+            symbol,
+            total_supply,
+            token_uri,
+        };
+        if self.sender.send(Ok(item)).await.is_err() {
+            self.token.cancel();
+        }
+        Ok(())
+    }
+}
+
+/// The receiving side of the channel, it cancels the tracker when it is dropped
+struct Erc721EventStream {
+    receiver: mpsc::Receiver<Result<Erc721EventWithMetadata>>,
+    token: CancellationToken,
+}
+
+impl Stream for Erc721EventStream {
+    type Item = Result<Erc721EventWithMetadata>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.receiver.poll_recv(cx)
+    }
+}
+
+impl Drop for Erc721EventStream {
+    fn drop(&mut self) {
+        self.token.cancel();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{address, erc721_transfer_log, MockEvmClient};
+    use crate::{ScanOptions, SqliteStore};
+    use futures::StreamExt;
+    use std::time::Duration;
+
+    fn tiny_intervals() -> ScanOptions {
+        ScanOptions {
+            range_interval: Duration::from_millis(1),
+            idle_interval: Duration::from_millis(1),
+            error_interval: Duration::from_millis(1),
+            ..Default::default()
+        }
+    }
+
+    fn client_with_events(blocks: std::ops::Range<u64>) -> MockEvmClient {
+        let collection = address(1);
+        let mut client = MockEvmClient::new("Mock", 1000)
+            .with_erc721_collection(collection, "Mock Collection", "MOCK");
+        for block_number in blocks {
+            client = client
+                .with_erc721_token_uri(collection, block_number, &format!("https://mock/{}", block_number))
+                .with_log(erc721_transfer_log(collection, address(0), address(2), block_number, block_number, 0));
+        }
+        client
+    }
+
+    async fn store() -> SqliteStore {
+        SqliteStore::open_in_memory().await.unwrap()
+    }
+
+    #[derive(Default)]
+    struct CollectingErc721EventCallback {
+        events: Vec<Erc721EventWithMetadata>,
+    }
+
+    #[async_trait]
+    impl Erc721EventCallback for CollectingErc721EventCallback {
+        async fn on_erc721_event(
+            &mut self,
+            event: Erc721Event,
+            name: String,
+            symbol: String,
+            total_supply: Option<u128>,
+            token_uri: String,
+        ) -> Result<()> {
+            self.events.push(Erc721EventWithMetadata {
+                event,
+                name,
+                symbol,
+                total_supply,
+                token_uri,
+            });
+            Ok(())
+        }
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_erc721_event_stream() {
+        let config = Erc721TrackerConfig::builder()
+            .start_from(10)
+            .step(3)
+            .end_block(30)
+            .options(tiny_intervals())
+            .build()
+            .unwrap();
+
+        let mut callback = CollectingErc721EventCallback::default();
+        let client = client_with_events(10..30);
+        track_erc721_events_with_config(&client, &store().await, &config, &mut callback)
+            .await
+            .unwrap();
+
+        let stream = erc721_event_stream(Arc::new(client_with_events(10..30)), Arc::new(store().await), config);
+        let events: Vec<Erc721EventWithMetadata> =
+            stream.map(|item| item.unwrap()).collect().await;
+
+        assert_eq!(20, events.len());
+        assert_eq!(callback.events, events);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_erc721_event_stream_dropped() {
+        let client = Arc::new(client_with_events(10..1000));
+        let config = Erc721TrackerConfig::builder()
+            .start_from(10)
+            .step(1)
+            .options(tiny_intervals())
+            .build()
+            .unwrap();
+
+        let mut stream = erc721_event_stream(client.clone(), Arc::new(store().await), config);
+        let first = stream.next().await.unwrap().unwrap();
+        assert_eq!(Some(10), first.event.block_number);
+        drop(stream);
+
+        // the tracker stops scanning once it notices the stream is gone
+        let ranges = wait_until_stable(|| client.scanned_ranges().len()).await;
+        assert!(ranges < 990);
+    }
+
+    /// Wait until `f` returns the same value twice in a row, some time apart
+    async fn wait_until_stable(f: impl Fn() -> usize) -> usize {
+        let mut last = f();
+        loop {
+            tokio::time::sleep(Duration::from_millis(50)).await;
+            let current = f();
+            if current == last {
+                return current;
+            }
+            last = current;
+        }
+    }
+}
@@ -11,6 +11,49 @@ pub enum Error {
     Web3ContractError(#[from] web3::contract::Error),
     #[error(transparent)]
     RusqliteError(#[from] rusqlite::Error),
+    #[error(transparent)]
+    IoError(#[from] std::io::Error),
+    #[cfg(feature = "postgres")]
+    #[error(transparent)]
+    PostgresError(#[from] tokio_postgres::Error),
+    #[error("The request timed out after {0:?}")]
+    Timeout(std::time::Duration),
+    #[error("The node is for the chain {actual}, not for the expected chain {expected}")]
+    ChainIdMismatch { expected: u64, actual: u64 },
+    #[error("The contract {0:?} does not implement ERC721Enumerable")]
+    NotEnumerable(web3::types::H160),
+    #[error("The {function} of {contract:?} is {length} bytes long, longer than {max} bytes")]
+    MetadataTooLong {
+        contract: web3::types::H160,
+        function: String,
+        length: usize,
+        max: usize,
+    },
+    #[error("The token {token_id} of {address:?} or its collection is missing from the database after being saved")]
+    DbInconsistent {
+        address: web3::types::H160,
+        token_id: web3::types::U256,
+    },
+    #[error("The database is at schema version {version}, newer than the version {supported} of this library")]
+    SchemaTooNew { version: u32, supported: u32 },
+    #[error("The database has rows saved before the chains were namespaced, migrate it with `migrate_with_chain`")]
+    UnstampedChain,
+    #[error("Invalid tracker config: {0}")]
+    InvalidConfig(String),
     #[error("Other error: {0}")]
     Other(String),
 }
+
+/// Why an event could not be processed by a tracker
+pub(crate) enum ProcessError {
+    /// Getting the metadata or accessing the database failed
+    Metadata(Error),
+    /// The callback did not accept the event
+    Callback(Error),
+}
+
+impl From<Error> for ProcessError {
+    fn from(err: Error) -> Self {
+        ProcessError::Metadata(err)
+    }
+}
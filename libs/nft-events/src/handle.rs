@@ -0,0 +1,154 @@
+//! This module contains the handle used to control a spawned tracker at runtime.
+use crate::{Error, Result, ScanOptions, ScanReport};
+use std::time::Duration;
+use tokio::{sync::watch, task::JoinHandle};
+
+/// What a tracker is doing
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TrackerState {
+    /// Processing the events of a block range, or waiting for the next one to be fetched
+    Scanning,
+    /// Waiting before processing a block range again after an error
+    Sleeping,
+    /// Paused through its handle
+    Paused,
+    /// Returned
+    Stopped,
+}
+
+/// The status of a tracker
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TrackerStatus {
+    /// The first block of the next range to scan
+    pub from: u64,
+    /// The current step
+    pub step: u64,
+    /// What the tracker is doing
+    pub state: TrackerState,
+}
+
+/// The handle of a spawned tracker, used to pause, resume and watch it.
+#[derive(Debug)]
+pub struct TrackerHandle {
+    paused: watch::Sender<bool>,
+    status: watch::Receiver<TrackerStatus>,
+    join_handle: JoinHandle<Result<ScanReport>>,
+}
+
+impl TrackerHandle {
+    /// Pause the tracker once the range it is scanning has been processed
+    pub fn pause(&self) {
+        let _ = self.paused.send(true);
+    }
+
+    /// Resume a paused tracker
+    pub fn resume(&self) {
+        let _ = self.paused.send(false);
+    }
+
+    /// The current status of the tracker
+    pub fn status(&self) -> TrackerStatus {
+        self.status.borrow().clone()
+    }
+
+    /// Wait for the tracker to return
+    pub async fn join(self) -> Result<ScanReport> {
+        self.join_handle
+            .await
+            .map_err(|err| Error::Other(format!("The tracker task failed: {}", err)))?
+    }
+}
+
+/// The tracker side of a `TrackerHandle`.
+/// The trackers which are not spawned use a detached control, which is never paused.
+pub(crate) struct TrackerControl {
+    paused: Option<watch::Receiver<bool>>,
+    status_sender: Option<watch::Sender<TrackerStatus>>,
+    status: TrackerStatus,
+}
+
+impl TrackerControl {
+    pub(crate) fn detached(from: u64, step: u64) -> TrackerControl {
+        TrackerControl {
+            paused: None,
+            status_sender: None,
+            status: TrackerStatus {
+                from,
+                step,
+                state: TrackerState::Scanning,
+            },
+        }
+    }
+
+    /// Update the status of the tracker
+    pub(crate) fn update(&mut self, from: u64, step: u64, state: TrackerState) {
+        self.status = TrackerStatus { from, step, state };
+        if let Some(sender) = &self.status_sender {
+            let _ = sender.send(self.status.clone());
+        }
+    }
+
+    /// Update the state of the tracker, keeping its position
+    pub(crate) fn set_state(&mut self, state: TrackerState) {
+        let TrackerStatus { from, step, .. } = self.status;
+        self.update(from, step, state);
+    }
+
+    /// Sleep for `duration`, reporting the tracker as sleeping
+    pub(crate) async fn sleep(&mut self, options: &ScanOptions, duration: Duration) {
+        self.set_state(TrackerState::Sleeping);
+        options.sleep(duration).await;
+        self.set_state(TrackerState::Scanning);
+    }
+
+    /// Wait while the tracker is paused, or until it is cancelled
+    pub(crate) async fn wait_while_paused(&mut self, options: &ScanOptions) {
+        let mut paused = match self.paused.clone() {
+            Some(paused) => paused,
+            None => return,
+        };
+        if !*paused.borrow() {
+            return;
+        }
+
+        self.set_state(TrackerState::Paused);
+        while *paused.borrow() && !options.is_cancelled() {
+            let changed = async {
+                match &options.cancellation_token {
+                    Some(token) => {
+                        tokio::select! {
+                            _ = token.cancelled() => Ok(()),
+                            changed = paused.changed() => changed,
+                        }
+                    }
+                    None => paused.changed().await,
+                }
+            };
+            // the handle is dropped, so the tracker can not be paused anymore
+            if changed.await.is_err() {
+                break;
+            }
+        }
+        self.set_state(TrackerState::Scanning);
+    }
+}
+
+/// Create a handle for a tracker starting at `from` with `step`, and the control used by its loop
+pub(crate) fn tracker_handle<F>(from: u64, step: u64, spawn: F) -> TrackerHandle
+where
+    F: FnOnce(TrackerControl) -> JoinHandle<Result<ScanReport>>,
+{
+    let (paused_sender, paused) = watch::channel(false);
+    let control = TrackerControl::detached(from, step);
+    let (status_sender, status) = watch::channel(control.status.clone());
+    let control = TrackerControl {
+        paused: Some(paused),
+        status_sender: Some(status_sender),
+        ..control
+    };
+    TrackerHandle {
+        paused: paused_sender,
+        status,
+        join_handle: spawn(control),
+    }
+}
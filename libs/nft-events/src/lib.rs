@@ -4,11 +4,32 @@
 //! It consider only visual NFTs. If a NFT contract has no metadata, it will be ignored.
 mod error;
 mod evm_client;
+mod metadata_cache;
+mod rate_limiter;
+mod rpc_batch;
+#[cfg(feature = "serde")]
+mod serde_decimal;
+mod transport;
+pub mod config;
+pub mod handle;
+pub mod marketplace;
+pub mod metrics;
+pub mod multi_chain;
+#[cfg(feature = "postgres")]
+pub mod postgres_store;
+pub mod report;
+#[cfg(feature = "signal")]
+pub mod signal;
+pub mod sqlite_store;
+pub mod store;
+#[cfg(test)]
+mod test_support;
 
 // erc721
 pub mod erc721;
 pub mod erc721_db;
 pub mod erc721_evm;
+pub mod erc721_stream;
 
 // erc1155
 pub mod erc1155;
@@ -19,10 +40,34 @@ pub use error::Error;
 /// The lib's result
 pub type Result<T> = std::result::Result<T, Error>;
 
-pub use evm_client::EvmClient;
-
-pub use erc721::Erc721EventCallback;
-pub use erc721_evm::Erc721Event;
+pub use evm_client::{
+    CircuitBreaker, EvmClient, EvmClientApi, HeadStream, HttpEvmClient, MetadataLimits, OversizedMetadata,
+    RequestRetry, RequestTimeouts, ERC721_ENUMERABLE_INTERFACE_ID, ERC721_INTERFACE_ID, ERC721_METADATA_INTERFACE_ID, ERC2981_INTERFACE_ID,
+    MULTICALL3_ADDRESS,
+};
+pub use transport::EvmTransport;
+pub use config::{
+    CallbackErrorPolicy, Erc1155TrackerConfig, Erc721TrackerConfig, ErrorPolicy, EventKind,
+    EventKindFilter, MetadataRefresh, MetadataRetry, ScanOptions, StartBlock, TrackerConfig, TrackerConfigBuilder,
+    DEAD_ADDRESS,
+};
+pub use handle::{TrackerHandle, TrackerState, TrackerStatus};
+pub use marketplace::{Marketplace, Sale};
+pub use metrics::{
+    CircuitState, ClientMetrics, ClientMetricsSnapshot, MethodMetrics, MetricsSnapshot, TrackerMetrics,
+    LATENCY_BUCKETS_MS,
+};
+pub use multi_chain::{MultiChainErc721EventCallback, MultiChainHandle, MultiChainTracker};
+pub use report::{ScanProgress, ScanReport};
+pub use sqlite_store::SqliteStore;
+pub use store::{MemoryStore, NftStore, OwnerWrite, RangeWrites, TransferWrite};
+
+pub use erc721::{Erc721EventCallback, Erc721Metadata, Erc721RawEventCallback, HoldingsReport, MetadataChange};
+pub use erc721_evm::{
+    Erc721ApprovalEvent, Erc721ApprovalForAllEvent, Erc721ConsecutiveTransfer, Erc721Event, Erc721MetadataUpdate,
+    LogKinds, CRYPTOKITTIES_ADDRESS, CRYPTOPUNKS_ADDRESS,
+};
+pub use erc721_stream::{erc721_event_stream, Erc721EventWithMetadata};
 
 pub use erc1155::Erc1155EventCallback;
 pub use erc1155_evm::Erc1155Event;
@@ -33,7 +78,6 @@ extern crate log;
 #[macro_use]
 extern crate async_trait;
 
-use rusqlite::Connection;
 use std::path::PathBuf;
 use web3::{transports::Http, Web3};
 
@@ -50,26 +94,30 @@ pub async fn start_tracking(
 ) -> Result<()> {
     let web3 = Web3::new(Http::new(rpc)?);
     let client = EvmClient::new(chain_name.to_owned(), web3);
+    let config = TrackerConfig::builder()
+        .start_from(start_from)
+        .step(step)
+        .build()?;
 
     // ERC721
     // ******************************************************************
     // Prepare database to store erc721 metadata
     let database_path: PathBuf = [data_dir, "erc721.db"].iter().collect();
-    let db_conn1 = Connection::open(database_path.clone())?;
-    erc721_db::create_tables_if_not_exist(&db_conn1)?;
+    // the database may have been upgraded from a version which did not save the chains
+    let store1 = SqliteStore::open_with_chain(database_path, chain_name).await?;
 
-    let t1 = erc721::track_erc721_events(&client, &db_conn1, start_from, step, None, erc721_cb);
+    let t1 = erc721::track_erc721_events_with_config(&client, &store1, &config, erc721_cb);
 
     // ERC1155
     // ******************************************************************
     // Prepare database to store erc721 metadata
     let database_path: PathBuf = [data_dir, "erc1155.db"].iter().collect();
-    let db_conn2 = Connection::open(database_path.clone())?;
-    erc1155_db::create_tables_if_not_exist(&db_conn2)?;
+    let store2 = SqliteStore::open(database_path).await?;
 
-    let t2 = erc1155::track_erc1155_events(&client, &db_conn2, start_from, step, None, erc1155_cb);
+    let t2 = erc1155::track_erc1155_events_with_config(&client, &store2, &config, erc1155_cb);
 
-    tokio::join!(t1, t2);
+    // the first terminal error stops the other tracker
+    tokio::try_join!(t1, t2)?;
 
     Ok(())
 }
@@ -0,0 +1,344 @@
+//! This module finds the marketplace sales of ERC721 tokens in the logs of the receipts of their transfers.
+//! The sales are the fulfillments of the Seaport orders, and the LooksRare and X2Y2 trades. The Wyvern matches
+//! do not tell the tokens of their orders, so they are not recognized.
+use array_bytes::hex2bytes_unchecked as bytes;
+use web3::{
+    ethabi::{self, ParamType, Token},
+    types::{Log, H160, H256, U256},
+};
+
+/// The topic of the Seaport `OrderFulfilled` event
+const SEAPORT_ORDER_FULFILLED_TOPIC: &str = "0x9d9af8e38d66c62e2c12f0225249fd9d721c54b83f48d9352c97c6cacdcb6f31";
+/// The topic of the LooksRare `TakerAsk` event, emitted when a bid is accepted
+const LOOKSRARE_TAKER_ASK_TOPIC: &str = "0x68cd251d4d267c6e2034ff0088b990352b97b2002c0476587d0c4da889c11330";
+/// The topic of the LooksRare `TakerBid` event, emitted when a listing is bought
+const LOOKSRARE_TAKER_BID_TOPIC: &str = "0x95fb6205e23ff6bda16a2d1dba56b9ad7c783f67c96fa149785052f47696f2be";
+/// The topic of the X2Y2 `EvInventory` event
+const X2Y2_INVENTORY_TOPIC: &str = "0x3cbb63f144840e5b1b0a38a7c19211d2e89de4d7c5faf8b2d3c1776c302d1d33";
+
+/// The Seaport item types of the native currency and of the ERC20 tokens
+const SEAPORT_NATIVE: u64 = 0;
+const SEAPORT_ERC20: u64 = 1;
+/// The Seaport item types of the ERC721 tokens, the criteria being resolved to the token id in the event
+const SEAPORT_ERC721: u64 = 2;
+const SEAPORT_ERC721_WITH_CRITERIA: u64 = 4;
+/// The X2Y2 delegate type of the ERC721 tokens
+const X2Y2_ERC721_DELEGATE: u64 = 1;
+
+/// The marketplace of a sale
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Marketplace {
+    /// OpenSea and the others fulfilling the Seaport orders
+    Seaport,
+    /// LooksRare
+    LooksRare,
+    /// X2Y2
+    X2Y2,
+}
+
+/// The sale of a token found in the transaction of its transfer
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Sale {
+    /// The marketplace which settled the sale
+    pub marketplace: Marketplace,
+    /// The price paid by the buyer including the fees, in the smallest unit of the currency.
+    /// The price of a bundle is the price of all of its tokens.
+    #[cfg_attr(feature = "serde", serde(with = "crate::serde_decimal"))]
+    pub price_wei: U256,
+    /// The ERC20 token of the payment, the zero address for the native currency
+    pub currency: H160,
+}
+
+/// The sale of the token `token_id` of the ERC721 contract `address` in the logs of a transaction, if any.
+/// The logs of the marketplaces which can not be decoded are ignored.
+pub fn find_sale(logs: &[Log], address: &H160, token_id: &U256) -> Option<Sale> {
+    let seaport = H256::from_slice(&bytes(SEAPORT_ORDER_FULFILLED_TOPIC));
+    let looksrare_ask = H256::from_slice(&bytes(LOOKSRARE_TAKER_ASK_TOPIC));
+    let looksrare_bid = H256::from_slice(&bytes(LOOKSRARE_TAKER_BID_TOPIC));
+    let x2y2 = H256::from_slice(&bytes(X2Y2_INVENTORY_TOPIC));
+
+    logs.iter().find_map(|log| match log.topics.first() {
+        Some(topic) if *topic == seaport => seaport_sale(&log.data.0, address, token_id),
+        Some(topic) if *topic == looksrare_ask || *topic == looksrare_bid => {
+            looksrare_sale(&log.data.0, address, token_id)
+        }
+        Some(topic) if *topic == x2y2 => x2y2_sale(&log.data.0, address, token_id),
+        _ => None,
+    })
+}
+
+/// A Seaport offer or consideration item
+struct SeaportItem {
+    item_type: u64,
+    token: H160,
+    identifier: U256,
+    amount: U256,
+}
+
+impl SeaportItem {
+    fn is_token(&self, address: &H160, token_id: &U256) -> bool {
+        (self.item_type == SEAPORT_ERC721 || self.item_type == SEAPORT_ERC721_WITH_CRITERIA)
+            && self.token == *address
+            && self.identifier == *token_id
+    }
+}
+
+/// The sale of a token offered in an order, paid with its consideration, or of a token wanted in an order,
+/// paid with its offer
+fn seaport_sale(data: &[u8], address: &H160, token_id: &U256) -> Option<Sale> {
+    let spent_item = ParamType::Tuple(vec![
+        ParamType::Uint(8),
+        ParamType::Address,
+        ParamType::Uint(256),
+        ParamType::Uint(256),
+    ]);
+    let received_item = ParamType::Tuple(vec![
+        ParamType::Uint(8),
+        ParamType::Address,
+        ParamType::Uint(256),
+        ParamType::Uint(256),
+        ParamType::Address,
+    ]);
+    let mut tokens = ethabi::decode(
+        &[
+            ParamType::FixedBytes(32),
+            ParamType::Address,
+            ParamType::Array(Box::new(spent_item)),
+            ParamType::Array(Box::new(received_item)),
+        ],
+        data,
+    )
+    .ok()?;
+    let consideration = seaport_items(tokens.pop()?)?;
+    let offer = seaport_items(tokens.pop()?)?;
+
+    let payment = if offer.iter().any(|item| item.is_token(address, token_id)) {
+        consideration
+    } else if consideration.iter().any(|item| item.is_token(address, token_id)) {
+        offer
+    } else {
+        return None;
+    };
+    // the payment is in the currency of its first item, the fees of the marketplace and of the creator included
+    let currency = payment
+        .iter()
+        .find(|item| item.item_type == SEAPORT_NATIVE || item.item_type == SEAPORT_ERC20)?;
+    let (item_type, token) = (currency.item_type, currency.token);
+    let price_wei = payment
+        .iter()
+        .filter(|item| item.item_type == item_type && item.token == token)
+        .fold(U256::zero(), |price, item| price.saturating_add(item.amount));
+    Some(Sale {
+        marketplace: Marketplace::Seaport,
+        price_wei,
+        currency: token,
+    })
+}
+
+fn seaport_items(items: Token) -> Option<Vec<SeaportItem>> {
+    items
+        .into_array()?
+        .into_iter()
+        .map(|item| match tuple(item)?.as_slice() {
+            [Token::Uint(item_type), Token::Address(token), Token::Uint(identifier), Token::Uint(amount), ..] => {
+                Some(SeaportItem {
+                    item_type: item_type.low_u64(),
+                    token: *token,
+                    identifier: *identifier,
+                    amount: *amount,
+                })
+            }
+            _ => None,
+        })
+        .collect()
+}
+
+/// The sale of a LooksRare `TakerAsk` or `TakerBid`, which tell the token, the currency and the price
+fn looksrare_sale(data: &[u8], address: &H160, token_id: &U256) -> Option<Sale> {
+    let tokens = ethabi::decode(
+        &[
+            ParamType::FixedBytes(32),
+            ParamType::Uint(256),
+            ParamType::Address,
+            ParamType::Address,
+            ParamType::Uint(256),
+            ParamType::Uint(256),
+            ParamType::Uint(256),
+        ],
+        data,
+    )
+    .ok()?;
+    match tokens.as_slice() {
+        [_, _, Token::Address(currency), Token::Address(collection), Token::Uint(id), _, Token::Uint(price)]
+            if collection == address && id == token_id =>
+        {
+            Some(Sale {
+                marketplace: Marketplace::LooksRare,
+                price_wei: *price,
+                currency: *currency,
+            })
+        }
+        _ => None,
+    }
+}
+
+/// The sale of an X2Y2 `EvInventory` of ERC721 tokens, paid at the price of its settlement
+fn x2y2_sale(data: &[u8], address: &H160, token_id: &U256) -> Option<Sale> {
+    let order_item = ParamType::Tuple(vec![ParamType::Uint(256), ParamType::Bytes]);
+    let fee = ParamType::Tuple(vec![ParamType::Uint(256), ParamType::Address]);
+    let settle_detail = ParamType::Tuple(vec![
+        ParamType::Uint(8),
+        ParamType::Uint(256),
+        ParamType::Uint(256),
+        ParamType::Uint(256),
+        ParamType::FixedBytes(32),
+        ParamType::Address,
+        ParamType::Bytes,
+        ParamType::Uint(256),
+        ParamType::Uint(256),
+        ParamType::Uint(256),
+        ParamType::Array(Box::new(fee)),
+    ]);
+    let tokens = ethabi::decode(
+        &[
+            ParamType::Address,
+            ParamType::Address,
+            ParamType::Uint(256),
+            ParamType::Uint(256),
+            ParamType::Uint(256),
+            ParamType::Uint(256),
+            ParamType::Uint(256),
+            ParamType::Address,
+            ParamType::Bytes,
+            order_item,
+            settle_detail,
+        ],
+        data,
+    )
+    .ok()?;
+    let (delegate_type, currency, item, detail) = match tokens.as_slice() {
+        [_, _, _, _, _, Token::Uint(delegate_type), _, Token::Address(currency), _, item, detail] => {
+            (delegate_type.low_u64(), *currency, item.clone(), detail.clone())
+        }
+        _ => return None,
+    };
+    if delegate_type != X2Y2_ERC721_DELEGATE {
+        return None;
+    }
+    let item_data = tuple(item)?.pop()?.into_bytes()?;
+    let price_wei = tuple(detail)?.get(3)?.clone().into_uint()?;
+
+    // the data of an ERC721 item are its (address token, uint256 tokenId) pairs
+    let pair = ParamType::Tuple(vec![ParamType::Address, ParamType::Uint(256)]);
+    let pairs = ethabi::decode(&[ParamType::Array(Box::new(pair))], &item_data).ok()?.pop()?.into_array()?;
+    let sold = pairs.into_iter().any(|pair| match tuple(pair).as_deref() {
+        Some([Token::Address(token), Token::Uint(id)]) => token == address && id == token_id,
+        _ => false,
+    });
+    if !sold {
+        return None;
+    }
+    Some(Sale {
+        marketplace: Marketplace::X2Y2,
+        price_wei,
+        currency,
+    })
+}
+
+fn tuple(token: Token) -> Option<Vec<Token>> {
+    match token {
+        Token::Tuple(tokens) => Some(tokens),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::receipt_logs;
+
+    fn collection() -> H160 {
+        H160::from_slice(&bytes("0xbc4ca0eda7647a8ab7c2061c2e118a18a936f13d"))
+    }
+
+    fn weth() -> H160 {
+        H160::from_slice(&bytes("0xc02aaa39b223fe8d0a0e5c4f27ead9083c756cc2"))
+    }
+
+    fn ether(milli: u64) -> U256 {
+        U256::from(milli) * U256::exp10(15)
+    }
+
+    #[test]
+    fn test_find_seaport_listing_sale() {
+        let logs = receipt_logs(include_str!("./fixtures/seaport_listing_receipt.json"));
+        // paid to the seller, OpenSea and the creator
+        assert_eq!(
+            Some(Sale {
+                marketplace: Marketplace::Seaport,
+                price_wei: ether(10_000),
+                currency: H160::zero(),
+            }),
+            find_sale(&logs, &collection(), &U256::from(7537))
+        );
+        assert_eq!(None, find_sale(&logs, &collection(), &U256::from(7538)));
+        assert_eq!(None, find_sale(&logs, &weth(), &U256::from(7537)));
+    }
+
+    #[test]
+    fn test_find_seaport_offer_sale() {
+        let logs = receipt_logs(include_str!("./fixtures/seaport_offer_receipt.json"));
+        // the fees are taken from the offer
+        assert_eq!(
+            Some(Sale {
+                marketplace: Marketplace::Seaport,
+                price_wei: ether(2_000),
+                currency: weth(),
+            }),
+            find_sale(&logs, &collection(), &U256::from(42))
+        );
+    }
+
+    #[test]
+    fn test_find_looksrare_sale() {
+        let logs = receipt_logs(include_str!("./fixtures/looksrare_taker_bid_receipt.json"));
+        assert_eq!(
+            Some(Sale {
+                marketplace: Marketplace::LooksRare,
+                price_wei: ether(1_500),
+                currency: weth(),
+            }),
+            find_sale(&logs, &collection(), &U256::from(77))
+        );
+        assert_eq!(None, find_sale(&logs, &collection(), &U256::from(78)));
+    }
+
+    #[test]
+    fn test_find_x2y2_sale() {
+        let logs = receipt_logs(include_str!("./fixtures/x2y2_inventory_receipt.json"));
+        assert_eq!(
+            Some(Sale {
+                marketplace: Marketplace::X2Y2,
+                price_wei: ether(800),
+                currency: H160::zero(),
+            }),
+            find_sale(&logs, &collection(), &U256::from(9))
+        );
+        assert_eq!(None, find_sale(&logs, &collection(), &U256::from(10)));
+    }
+
+    #[test]
+    fn test_find_no_sale() {
+        let mut logs = receipt_logs(include_str!("./fixtures/seaport_listing_receipt.json"));
+        // only the transfer is left
+        logs.truncate(1);
+        assert_eq!(None, find_sale(&logs, &collection(), &U256::from(7537)));
+
+        // an undecodable fulfillment is ignored
+        let mut logs = receipt_logs(include_str!("./fixtures/seaport_listing_receipt.json"));
+        logs[1].data.0.truncate(64);
+        assert_eq!(None, find_sale(&logs, &collection(), &U256::from(7537)));
+    }
+}
@@ -0,0 +1,218 @@
+//! This module contains the in-memory caches of the ERC721 metadata saved in the database,
+//! which spare the database queries of the events of the collections and tokens seen recently.
+use crate::TrackerMetrics;
+use std::{collections::HashMap, hash::Hash, ops::RangeInclusive, sync::Arc, sync::Mutex};
+use web3::types::{H160, U256};
+
+/// A map keeping at most `capacity` entries, the least recently used one is evicted first.
+/// A map without capacity keeps nothing.
+pub(crate) struct Lru<K, V> {
+    capacity: usize,
+    entries: HashMap<K, (V, u64)>,
+    /// Increased by each access, the entry accessed the longest ago has the lowest one
+    clock: u64,
+}
+
+impl<K: Eq + Hash + Clone, V: Clone> Lru<K, V> {
+    pub(crate) fn new(capacity: usize) -> Lru<K, V> {
+        Lru {
+            capacity,
+            entries: HashMap::new(),
+            clock: 0,
+        }
+    }
+
+    pub(crate) fn get(&mut self, key: &K) -> Option<V> {
+        self.clock += 1;
+        let clock = self.clock;
+        self.entries.get_mut(key).map(|(value, used_at)| {
+            *used_at = clock;
+            value.clone()
+        })
+    }
+
+    pub(crate) fn insert(&mut self, key: K, value: V) {
+        if self.capacity == 0 {
+            return;
+        }
+        if !self.entries.contains_key(&key) && self.entries.len() >= self.capacity {
+            let least_recently_used = self
+                .entries
+                .iter()
+                .min_by_key(|(_, (_, used_at))| *used_at)
+                .map(|(key, _)| key.clone());
+            if let Some(least_recently_used) = least_recently_used {
+                self.entries.remove(&least_recently_used);
+            }
+        }
+        self.clock += 1;
+        self.entries.insert(key, (value, self.clock));
+    }
+
+    pub(crate) fn remove(&mut self, key: &K) {
+        self.entries.remove(key);
+    }
+
+    /// Remove the entries whose key does not satisfy `keep`
+    pub(crate) fn retain(&mut self, mut keep: impl FnMut(&K) -> bool) {
+        self.entries.retain(|key, _| keep(key));
+    }
+}
+
+/// A collection whose metadata lookup is settled, as saved in the database
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct CachedCollection {
+    /// The database id of the collection
+    pub(crate) id: usize,
+    /// None if the contract does not provide its metadata
+    pub(crate) name_symbol: Option<(String, String)>,
+    /// Whether the contract reported supporting ERC721 with ERC165, None if it has not been checked
+    pub(crate) supports_erc721: Option<Option<bool>>,
+    pub(crate) contract_uri: Option<String>,
+    /// The EIP-1967 implementation last resolved, None if the contract is not a proxy or was never resolved
+    pub(crate) implementation: Option<H160>,
+}
+
+/// A token whose last lookup did not fail, as saved in the database
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct CachedToken {
+    pub(crate) token_uri: Option<String>,
+    /// When the token uri was fetched, in unix seconds, to refresh it as `metadata_refresh` says
+    pub(crate) fetched_at: Option<u64>,
+}
+
+/// The caches of the collections by address and of the tokens by collection and token id.
+/// An entry is replaced when its rows are written again, and removed when they are rewritten out of its sight.
+pub(crate) struct MetadataCache {
+    collections: Mutex<Lru<H160, CachedCollection>>,
+    tokens: Mutex<Lru<(H160, U256), CachedToken>>,
+    metrics: Arc<TrackerMetrics>,
+}
+
+impl MetadataCache {
+    /// Cache at most `collection_capacity` collections and `token_capacity` tokens,
+    /// the lookups are counted in `metrics`
+    pub(crate) fn new(collection_capacity: usize, token_capacity: usize, metrics: Arc<TrackerMetrics>) -> MetadataCache {
+        MetadataCache {
+            collections: Mutex::new(Lru::new(collection_capacity)),
+            tokens: Mutex::new(Lru::new(token_capacity)),
+            metrics,
+        }
+    }
+
+    pub(crate) fn collection(&self, address: &H160) -> Option<CachedCollection> {
+        self.collections.lock().unwrap().get(address)
+    }
+
+    pub(crate) fn save_collection(&self, address: H160, collection: CachedCollection) {
+        self.collections.lock().unwrap().insert(address, collection);
+    }
+
+    pub(crate) fn remove_collection(&self, address: &H160) {
+        self.collections.lock().unwrap().remove(address);
+    }
+
+    pub(crate) fn token(&self, address: &H160, token_id: &U256) -> Option<CachedToken> {
+        self.tokens.lock().unwrap().get(&(*address, *token_id))
+    }
+
+    pub(crate) fn save_token(&self, address: H160, token_id: U256, token: CachedToken) {
+        self.tokens.lock().unwrap().insert((address, token_id), token);
+    }
+
+    pub(crate) fn remove_token(&self, address: &H160, token_id: &U256) {
+        self.tokens.lock().unwrap().remove(&(*address, *token_id));
+    }
+
+    /// Remove the cached tokens of a collection whose ids are in `token_ids`
+    pub(crate) fn remove_tokens(&self, address: &H160, token_ids: &RangeInclusive<U256>) {
+        self.tokens
+            .lock()
+            .unwrap()
+            .retain(|(token_address, token_id)| token_address != address || !token_ids.contains(token_id));
+    }
+
+    /// Count a metadata lookup of an event, answered by the cache or not
+    pub(crate) fn record_lookup(&self, hit: bool) {
+        self.metrics.record_memory_cache_lookup(hit);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_lru() {
+        let mut lru = Lru::new(2);
+        lru.insert(1, "one");
+        lru.insert(2, "two");
+        assert_eq!(Some("one"), lru.get(&1));
+
+        // the entry 2 is the least recently used
+        lru.insert(3, "three");
+        assert_eq!(None, lru.get(&2));
+        assert_eq!(Some("one"), lru.get(&1));
+        assert_eq!(Some("three"), lru.get(&3));
+
+        // replacing an entry evicts nothing
+        lru.insert(1, "un");
+        assert_eq!(Some("un"), lru.get(&1));
+        assert_eq!(Some("three"), lru.get(&3));
+
+        lru.remove(&1);
+        assert_eq!(None, lru.get(&1));
+    }
+
+    #[test]
+    fn test_lru_without_capacity() {
+        let mut lru = Lru::new(0);
+        lru.insert(1, "one");
+        assert_eq!(None, lru.get(&1));
+    }
+
+    #[test]
+    fn test_metadata_cache() {
+        let metrics = Arc::new(TrackerMetrics::new());
+        let cache = MetadataCache::new(1, 1, metrics.clone());
+        let collection = CachedCollection {
+            id: 1,
+            name_symbol: Some(("Mock Collection".to_owned(), "MOCK".to_owned())),
+            supports_erc721: Some(Some(true)),
+            contract_uri: None,
+            implementation: None,
+        };
+        let address = H160::from_low_u64_be(1);
+        cache.save_collection(address, collection.clone());
+        assert_eq!(Some(collection), cache.collection(&address));
+        cache.remove_collection(&address);
+        assert_eq!(None, cache.collection(&address));
+
+        let token = CachedToken {
+            token_uri: Some("https://mock/1".to_owned()),
+            fetched_at: Some(100),
+        };
+        cache.save_token(address, U256::from(1), token.clone());
+        assert_eq!(Some(token.clone()), cache.token(&address, &U256::from(1)));
+        assert_eq!(None, cache.token(&address, &U256::from(2)));
+
+        // the tokens of a range of the collection
+        let cache = MetadataCache::new(1, 4, metrics.clone());
+        let other = H160::from_low_u64_be(2);
+        for token_id in 1..4 {
+            cache.save_token(address, U256::from(token_id), token.clone());
+        }
+        cache.save_token(other, U256::from(2), token.clone());
+        cache.remove_tokens(&address, &(U256::from(2)..=U256::from(3)));
+        assert_eq!(Some(token.clone()), cache.token(&address, &U256::from(1)));
+        assert_eq!(None, cache.token(&address, &U256::from(2)));
+        assert_eq!(None, cache.token(&address, &U256::from(3)));
+        assert_eq!(Some(token), cache.token(&other, &U256::from(2)));
+
+        cache.record_lookup(true);
+        cache.record_lookup(false);
+        cache.record_lookup(false);
+        let snapshot = metrics.snapshot();
+        assert_eq!((1, 2), (snapshot.memory_cache_hits, snapshot.memory_cache_misses));
+    }
+}
@@ -0,0 +1,258 @@
+//! This module contains the metrics updated by the tracking loops and by the EVM clients.
+//! The metrics can be shared with the host application through an `Arc` and scraped from another task.
+use std::{
+    collections::HashMap,
+    sync::atomic::{AtomicU64, Ordering},
+    sync::Mutex,
+    time::Duration,
+};
+
+/// The upper bounds of the latency buckets of `ClientMetrics`, in milliseconds.
+/// The requests slower than the last bound are counted in one more bucket.
+pub const LATENCY_BUCKETS_MS: [u64; 8] = [10, 25, 50, 100, 250, 500, 1_000, 5_000];
+
+/// The counters and gauges of a tracker
+#[derive(Debug, Default)]
+pub struct TrackerMetrics {
+    events_delivered: AtomicU64,
+    errors: AtomicU64,
+    callback_errors: AtomicU64,
+    rpc_errors: AtomicU64,
+    from: AtomicU64,
+    latest_block: AtomicU64,
+    step: AtomicU64,
+    memory_cache_hits: AtomicU64,
+    memory_cache_misses: AtomicU64,
+}
+
+/// A point-in-time copy of `TrackerMetrics`
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct MetricsSnapshot {
+    /// How many events were delivered to the callback
+    pub events_delivered: u64,
+    /// How many errors were encountered, of any kind
+    pub errors: u64,
+    /// How many times the callback returned an error
+    pub callback_errors: u64,
+    /// How many requests to the node failed
+    pub rpc_errors: u64,
+    /// The first block of the next range to scan
+    pub from: u64,
+    /// The latest block of the chain, as last seen by the tracker
+    pub latest_block: u64,
+    /// How many blocks the tracker is behind the latest block
+    pub lag: u64,
+    /// The current step
+    pub step: u64,
+    /// How many metadata lookups of the events were answered by the in-memory cache, without the database
+    pub memory_cache_hits: u64,
+    /// How many metadata lookups of the events went to the database
+    pub memory_cache_misses: u64,
+}
+
+impl TrackerMetrics {
+    /// Create the metrics of a tracker which has not started yet
+    pub fn new() -> TrackerMetrics {
+        TrackerMetrics::default()
+    }
+
+    /// Copy the current values
+    pub fn snapshot(&self) -> MetricsSnapshot {
+        let from = self.from.load(Ordering::Relaxed);
+        let latest_block = self.latest_block.load(Ordering::Relaxed);
+        MetricsSnapshot {
+            events_delivered: self.events_delivered.load(Ordering::Relaxed),
+            errors: self.errors.load(Ordering::Relaxed),
+            callback_errors: self.callback_errors.load(Ordering::Relaxed),
+            rpc_errors: self.rpc_errors.load(Ordering::Relaxed),
+            from,
+            latest_block,
+            lag: latest_block.saturating_sub(from),
+            step: self.step.load(Ordering::Relaxed),
+            memory_cache_hits: self.memory_cache_hits.load(Ordering::Relaxed),
+            memory_cache_misses: self.memory_cache_misses.load(Ordering::Relaxed),
+        }
+    }
+
+    pub(crate) fn record_events_delivered(&self, count: u64) {
+        self.events_delivered.fetch_add(count, Ordering::Relaxed);
+    }
+
+    /// Record an error which is neither a callback error nor an RPC error
+    pub(crate) fn record_error(&self) {
+        self.errors.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn record_callback_error(&self) {
+        self.record_error();
+        self.callback_errors.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn record_rpc_error(&self) {
+        self.record_error();
+        self.rpc_errors.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn record_memory_cache_lookup(&self, hit: bool) {
+        let counter = if hit {
+            &self.memory_cache_hits
+        } else {
+            &self.memory_cache_misses
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn set_position(&self, from: u64, step: u64) {
+        self.from.store(from, Ordering::Relaxed);
+        self.step.store(step, Ordering::Relaxed);
+    }
+
+    pub(crate) fn set_latest_block(&self, latest_block: u64) {
+        self.latest_block.store(latest_block, Ordering::Relaxed);
+    }
+}
+
+/// The requests an EVM client sent to its node by method, shared by the clones of the client.
+/// A method sending several requests counts each of them, the retries of a request are part of its latency.
+#[derive(Debug, Default)]
+pub struct ClientMetrics {
+    chain_name: String,
+    methods: Mutex<HashMap<&'static str, MethodMetrics>>,
+    circuits: Mutex<Vec<(String, CircuitState)>>,
+}
+
+/// The state of the circuit breaker of an endpoint of a client failing over between several endpoints
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CircuitState {
+    /// The requests are sent to the endpoint
+    Closed,
+    /// The endpoint failed too many times in a row, no request is sent to it until its cooldown is over
+    Open,
+    /// The cooldown is over, a single request probes the endpoint before the circuit closes again
+    HalfOpen,
+}
+
+/// The requests of a method of an EVM client
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct MethodMetrics {
+    /// How many requests were sent
+    pub calls: u64,
+    /// How many of them failed, including the timeouts and the reverted calls
+    pub errors: u64,
+    /// How many requests took at most each bound of `LATENCY_BUCKETS_MS`, and longer in the last bucket
+    pub latency_buckets: [u64; LATENCY_BUCKETS_MS.len() + 1],
+    /// How long all the requests took
+    pub total_latency: Duration,
+}
+
+/// A point-in-time copy of `ClientMetrics`
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ClientMetricsSnapshot {
+    /// The chain of the client, to tell the clients of a multi-chain tracker apart
+    pub chain: String,
+    /// The requests of each method
+    pub methods: HashMap<&'static str, MethodMetrics>,
+    /// The circuit breaker state of each endpoint, empty if the client has a single endpoint
+    pub circuits: Vec<(String, CircuitState)>,
+}
+
+impl ClientMetrics {
+    /// Create the metrics of a client of `chain_name` which has not sent anything yet
+    pub fn new(chain_name: &str) -> ClientMetrics {
+        ClientMetrics {
+            chain_name: chain_name.to_owned(),
+            methods: Mutex::default(),
+            circuits: Mutex::default(),
+        }
+    }
+
+    /// The chain of the client
+    pub fn chain_name(&self) -> &str {
+        &self.chain_name
+    }
+
+    /// Copy the current values
+    pub fn snapshot(&self) -> ClientMetricsSnapshot {
+        ClientMetricsSnapshot {
+            chain: self.chain_name.clone(),
+            methods: self.methods.lock().unwrap().clone(),
+            circuits: self.circuits.lock().unwrap().clone(),
+        }
+    }
+
+    pub(crate) fn set_circuit_state(&self, endpoint: &str, state: CircuitState) {
+        let mut circuits = self.circuits.lock().unwrap();
+        match circuits.iter_mut().find(|(name, _)| name == endpoint) {
+            Some((_, circuit)) => *circuit = state,
+            None => circuits.push((endpoint.to_owned(), state)),
+        }
+    }
+
+    pub(crate) fn record_request(&self, method: &'static str, latency: Duration, failed: bool) {
+        let mut methods = self.methods.lock().unwrap();
+        let metrics = methods.entry(method).or_default();
+        metrics.calls += 1;
+        if failed {
+            metrics.errors += 1;
+        }
+        let bucket = LATENCY_BUCKETS_MS
+            .iter()
+            .position(|bound| latency <= Duration::from_millis(*bound))
+            .unwrap_or(LATENCY_BUCKETS_MS.len());
+        metrics.latency_buckets[bucket] += 1;
+        metrics.total_latency += latency;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_snapshot() {
+        let metrics = TrackerMetrics::new();
+        metrics.record_events_delivered(1);
+        metrics.record_callback_error();
+        metrics.record_rpc_error();
+        metrics.record_error();
+        metrics.set_position(90, 10);
+        metrics.set_latest_block(100);
+        metrics.record_memory_cache_lookup(true);
+
+        assert_eq!(
+            MetricsSnapshot {
+                events_delivered: 1,
+                errors: 3,
+                callback_errors: 1,
+                rpc_errors: 1,
+                from: 90,
+                latest_block: 100,
+                lag: 10,
+                step: 10,
+                memory_cache_hits: 1,
+                memory_cache_misses: 0,
+            },
+            metrics.snapshot()
+        );
+    }
+
+    #[test]
+    fn test_client_metrics() {
+        let metrics = ClientMetrics::new("Mock");
+        metrics.record_request("get_logs", Duration::from_millis(5), false);
+        metrics.record_request("get_logs", Duration::from_millis(10), true);
+        metrics.record_request("get_logs", Duration::from_secs(6), false);
+
+        let snapshot = metrics.snapshot();
+        assert_eq!("Mock", snapshot.chain);
+        assert_eq!(
+            MethodMetrics {
+                calls: 3,
+                errors: 1,
+                latency_buckets: [2, 0, 0, 0, 0, 0, 0, 0, 1],
+                total_latency: Duration::from_millis(6_015),
+            },
+            snapshot.methods["get_logs"]
+        );
+    }
+}
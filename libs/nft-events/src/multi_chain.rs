@@ -0,0 +1,378 @@
+//! This module drives the ERC721 trackers of several chains concurrently,
+//! and delivers the events of all of them to a single callback.
+use crate::{
+    erc721::{spawn_erc721_tracker, Erc721EventCallback, Erc721Metadata},
+    ClientMetrics, ClientMetricsSnapshot, Erc721Event, Erc721TrackerConfig, Error, EvmClientApi, NftStore, Result,
+    ScanReport, SqliteStore, TrackerHandle, TrackerStatus,
+};
+use std::{path::Path, sync::Arc};
+use tokio::{
+    sync::{mpsc, oneshot},
+    task::JoinHandle,
+};
+use tokio_util::sync::CancellationToken;
+
+/// How many batches of events the trackers can send before waiting for the callback
+const CHANNEL_CAPACITY: usize = 16;
+
+/// Receives the ERC721 events of all the chains of a `MultiChainTracker`.
+#[async_trait]
+pub trait MultiChainErc721EventCallback: Send {
+    /// The callback function, `chain` is the name of the client which tracked the event
+    async fn on_erc721_event(
+        &mut self,
+        chain: &str,
+        event: Erc721Event,
+        name: String,
+        symbol: String,
+        total_supply: Option<u128>,
+        token_uri: String,
+    ) -> Result<()>;
+}
+
+/// A chain tracked by a `MultiChainTracker`
+struct Chain {
+    evm_client: Arc<dyn EvmClientApi>,
+    store: Arc<dyn NftStore>,
+    config: Erc721TrackerConfig,
+}
+
+/// Tracks the ERC721 events of several chains, each one with its own client, database and config.
+#[derive(Default)]
+pub struct MultiChainTracker {
+    chains: Vec<Chain>,
+}
+
+impl MultiChainTracker {
+    /// Create a tracker without any chain
+    pub fn new() -> MultiChainTracker {
+        MultiChainTracker::default()
+    }
+
+    /// Track the chain of `evm_client`, with its metadata stored in `store`
+    pub fn add_chain(
+        mut self,
+        evm_client: Arc<dyn EvmClientApi>,
+        store: Arc<dyn NftStore>,
+        config: Erc721TrackerConfig,
+    ) -> Self {
+        self.chains.push(Chain {
+            evm_client,
+            store,
+            config,
+        });
+        self
+    }
+
+    /// Track the chain of `evm_client`, with its metadata stored in the database at `db_path`
+    pub async fn add_chain_with_db_path<P: AsRef<Path>>(
+        self,
+        evm_client: Arc<dyn EvmClientApi>,
+        db_path: P,
+        config: Erc721TrackerConfig,
+    ) -> Result<Self> {
+        let store = SqliteStore::open_with_chain(db_path, evm_client.chain_name()).await?;
+        Ok(self.add_chain(evm_client, Arc::new(store), config))
+    }
+
+    /// Spawn a tracker for each chain. Their events are delivered to `callback` one batch at a time.
+    /// The trackers are independent: one of them returning an error does not stop the others,
+    /// like a tracker whose client fails `EvmClientApi::verify` since its node is for another chain.
+    /// The cancellation tokens of the configs are replaced, the trackers are stopped through the handle.
+    pub fn spawn(self, mut callback: Box<dyn MultiChainErc721EventCallback>) -> MultiChainHandle {
+        let token = CancellationToken::new();
+        let (sender, mut receiver) = mpsc::channel::<Delivery>(CHANNEL_CAPACITY);
+        let clients = self
+            .chains
+            .iter()
+            .filter_map(|chain| chain.evm_client.client_metrics())
+            .collect();
+
+        let trackers = self
+            .chains
+            .into_iter()
+            .map(|Chain { evm_client, store, mut config }| {
+                let chain = evm_client.chain_name().to_owned();
+                config.options.cancellation_token = Some(token.child_token());
+                let callback = ChainErc721EventCallback {
+                    chain: chain.clone(),
+                    sender: sender.clone(),
+                };
+                let handle = spawn_erc721_tracker(evm_client, store, config, Box::new(callback));
+                (chain, handle)
+            })
+            .collect();
+
+        // it returns once all the trackers have returned and dropped their senders
+        drop(sender);
+        let dispatcher = tokio::spawn(async move {
+            while let Some(delivery) = receiver.recv().await {
+                let result = deliver(&mut *callback, &delivery.chain, delivery.events).await;
+                let _ = delivery.reply.send(result);
+            }
+        });
+
+        MultiChainHandle {
+            trackers,
+            clients,
+            token,
+            dispatcher,
+        }
+    }
+}
+
+/// The handle of the trackers spawned by a `MultiChainTracker`
+#[derive(Debug)]
+pub struct MultiChainHandle {
+    trackers: Vec<(String, TrackerHandle)>,
+    clients: Vec<Arc<ClientMetrics>>,
+    token: CancellationToken,
+    dispatcher: JoinHandle<()>,
+}
+
+impl MultiChainHandle {
+    /// The status of the tracker of each chain, in the order the chains were added
+    pub fn status(&self) -> Vec<(String, TrackerStatus)> {
+        self.trackers
+            .iter()
+            .map(|(chain, handle)| (chain.clone(), handle.status()))
+            .collect()
+    }
+
+    /// The request metrics of the clients recording them, labelled with their chain
+    pub fn client_metrics(&self) -> Vec<ClientMetricsSnapshot> {
+        self.clients.iter().map(|metrics| metrics.snapshot()).collect()
+    }
+
+    /// Stop all the trackers once they have processed their current range
+    pub fn shutdown(&self) {
+        self.token.cancel();
+    }
+
+    /// Wait for all the trackers to return, their results are in the order the chains were added
+    pub async fn join(self) -> Vec<(String, Result<ScanReport>)> {
+        let mut results = Vec::with_capacity(self.trackers.len());
+        for (chain, handle) in self.trackers {
+            let result = handle.join().await;
+            if let Err(err) = &result {
+                error!("The {} ERC721 tracker stopped because of error: {:?}.", chain, err);
+            }
+            results.push((chain, result));
+        }
+        if let Err(err) = self.dispatcher.await {
+            error!("The multi-chain callback task failed: {}.", err);
+        }
+        results
+    }
+}
+
+/// The events of a range of a chain, sent to the task running the callback
+struct Delivery {
+    chain: String,
+    events: Vec<(Erc721Event, Erc721Metadata)>,
+    reply: oneshot::Sender<Result<()>>,
+}
+
+/// Deliver the events of a chain, the events after a failing one are still delivered and the first error is returned
+async fn deliver(
+    callback: &mut dyn MultiChainErc721EventCallback,
+    chain: &str,
+    events: Vec<(Erc721Event, Erc721Metadata)>,
+) -> Result<()> {
+    let mut result = Ok(());
+    for (event, metadata) in events {
+        let Erc721Metadata {
+            name,
+            symbol,
+            total_supply,
+            token_uri,
+            ..
+        } = metadata;
+        let delivered = callback
+            .on_erc721_event(chain, event, name, symbol, total_supply, token_uri)
+            .await;
+        if let Err(err) = delivered {
+            if result.is_ok() {
+                result = Err(err);
+            }
+        }
+    }
+    result
+}
+
+/// Forwards the events of a chain to the callback task, and returns the result of the callback
+/// so that the tracker applies its callback error policy.
+struct ChainErc721EventCallback {
+    chain: String,
+    sender: mpsc::Sender<Delivery>,
+}
+
+#[async_trait]
+impl Erc721EventCallback for ChainErc721EventCallback {
+    async fn on_erc721_event(
+        &mut self,
+        event: Erc721Event,
+        name: String,
+        symbol: String,
+        total_supply: Option<u128>,
+        token_uri: String,
+    ) -> Result<()> {
+        let metadata = Erc721Metadata {
+            name,
+            symbol,
+            total_supply,
+            token_uri,
+            owner: None,
+            royalty: None,
+            contract_uri: None,
+            implementation: None,
+        };
+        self.on_erc721_events(vec![(event, metadata)]).await
+    }
+
+    async fn on_erc721_events(&mut self, events: Vec<(Erc721Event, Erc721Metadata)>) -> Result<()> {
+        let (reply, result) = oneshot::channel();
+        let delivery = Delivery {
+            chain: self.chain.clone(),
+            events,
+            reply,
+        };
+        let gone = || Error::Other("The multi-chain callback task is gone".to_owned());
+        self.sender.send(delivery).await.map_err(|_| gone())?;
+        result.await.map_err(|_| gone())?
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{address, erc721_transfer_log, rpc_error, MockEvmClient};
+    use crate::{ScanOptions, TrackerState};
+    use std::{sync::Mutex, time::Duration};
+
+    fn tiny_intervals() -> ScanOptions {
+        ScanOptions {
+            range_interval: Duration::from_millis(1),
+            idle_interval: Duration::from_millis(1),
+            error_interval: Duration::from_millis(1),
+            ..Default::default()
+        }
+    }
+
+    fn client_with_events(chain: &str, blocks: std::ops::Range<u64>) -> MockEvmClient {
+        let collection = address(1);
+        let mut client = MockEvmClient::new(chain, 100)
+            .with_erc721_collection(collection, "Mock Collection", "MOCK");
+        for block_number in blocks {
+            client = client
+                .with_erc721_token_uri(collection, block_number, "https://mock")
+                .with_log(erc721_transfer_log(collection, address(0), address(2), block_number, block_number, 0));
+        }
+        client
+    }
+
+    async fn store() -> Arc<dyn NftStore> {
+        Arc::new(SqliteStore::open_in_memory().await.unwrap())
+    }
+
+    fn config(end_block: Option<u64>) -> Erc721TrackerConfig {
+        let builder = Erc721TrackerConfig::builder()
+            .start_from(10)
+            .step(5)
+            .options(tiny_intervals());
+        let builder = match end_block {
+            Some(end_block) => builder.end_block(end_block),
+            None => builder,
+        };
+        builder.build().unwrap()
+    }
+
+    #[derive(Clone, Default)]
+    struct TaggingCallback {
+        delivered: Arc<Mutex<Vec<(String, u64)>>>,
+    }
+
+    #[async_trait]
+    impl MultiChainErc721EventCallback for TaggingCallback {
+        async fn on_erc721_event(
+            &mut self,
+            chain: &str,
+            event: Erc721Event,
+            _name: String,
+            _symbol: String,
+            _total_supply: Option<u128>,
+            _token_uri: String,
+        ) -> Result<()> {
+            self.delivered
+                .lock()
+                .unwrap()
+                .push((chain.to_owned(), event.block_number.unwrap()));
+            Ok(())
+        }
+    }
+
+    impl TaggingCallback {
+        fn blocks_of(&self, chain: &str) -> Vec<u64> {
+            self.delivered
+                .lock()
+                .unwrap()
+                .iter()
+                .filter(|(delivered_chain, _)| delivered_chain == chain)
+                .map(|(_, block_number)| *block_number)
+                .collect()
+        }
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_multi_chain_tracker() {
+        let callback = TaggingCallback::default();
+        let handle = MultiChainTracker::new()
+            .add_chain(Arc::new(client_with_events("Ethereum", 10..30)), store().await, config(Some(29)))
+            .add_chain(Arc::new(client_with_events("BSC", 20..40)), store().await, config(Some(39)))
+            .spawn(Box::new(callback.clone()));
+        let results = handle.join().await;
+
+        assert_eq!(vec!["Ethereum", "BSC"], results.iter().map(|(chain, _)| chain.as_str()).collect::<Vec<_>>());
+        assert!(results.iter().all(|(_, result)| result.is_ok()));
+        assert_eq!((10..30).collect::<Vec<u64>>(), callback.blocks_of("Ethereum"));
+        assert_eq!((20..40).collect::<Vec<u64>>(), callback.blocks_of("BSC"));
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_multi_chain_tracker_chain_failure() {
+        let failing_client = MockEvmClient::new("Polygon", 100)
+            .fail_next_get_logs(rpc_error(-32000, "Unauthorized"));
+        let callback = TaggingCallback::default();
+        let handle = MultiChainTracker::new()
+            .add_chain(Arc::new(failing_client), store().await, config(Some(29)))
+            .add_chain(Arc::new(client_with_events("Ethereum", 10..30)), store().await, config(Some(29)))
+            .spawn(Box::new(callback.clone()));
+        let results = handle.join().await;
+
+        assert!(results[0].1.is_err());
+        assert!(results[1].1.is_ok());
+        assert_eq!((10..30).collect::<Vec<u64>>(), callback.blocks_of("Ethereum"));
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_multi_chain_tracker_shutdown() {
+        let callback = TaggingCallback::default();
+        let handle = MultiChainTracker::new()
+            .add_chain(Arc::new(client_with_events("Ethereum", 10..30)), store().await, config(None))
+            .add_chain(Arc::new(client_with_events("BSC", 10..30)), store().await, config(None))
+            .spawn(Box::new(callback.clone()));
+
+        // both trackers catch up with the confirmed blocks and keep waiting for new ones
+        while callback.blocks_of("Ethereum").len() < 20 || callback.blocks_of("BSC").len() < 20 {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        let status = handle.status();
+        assert_eq!("Ethereum", status[0].0);
+        assert_eq!("BSC", status[1].0);
+        assert!(status.iter().all(|(_, status)| status.state != TrackerState::Stopped));
+
+        handle.shutdown();
+        let results = handle.join().await;
+        assert!(results.iter().all(|(_, result)| result.is_ok()));
+    }
+}
@@ -0,0 +1,83 @@
+//! This module contains a token bucket limiting the rate of the requests sent to a node.
+use std::{
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+/// A token bucket refilled at `rate` tokens per second, up to `burst` tokens.
+/// A caller takes a token even if none is left and waits until it is refilled,
+/// so the waiting callers are served in order.
+#[derive(Debug)]
+pub(crate) struct RateLimiter {
+    rate: f64,
+    burst: f64,
+    /// The tokens left, negative when they are taken in advance, and when they were counted
+    state: Mutex<(f64, Instant)>,
+}
+
+impl RateLimiter {
+    /// Allow `max_requests_per_second` on average and `burst` at once, both are at least 1
+    pub(crate) fn new(max_requests_per_second: u32, burst: u32) -> RateLimiter {
+        let burst = f64::from(std::cmp::max(burst, 1));
+        RateLimiter {
+            rate: f64::from(std::cmp::max(max_requests_per_second, 1)),
+            burst,
+            state: Mutex::new((burst, Instant::now())),
+        }
+    }
+
+    /// Wait until a request can be sent
+    pub(crate) async fn acquire(&self) {
+        let delay = self.take_token();
+        if delay > Duration::ZERO {
+            tokio::time::sleep(delay).await;
+        }
+    }
+
+    /// Take a token, and return how long to wait until it is refilled
+    fn take_token(&self) -> Duration {
+        let mut state = self.state.lock().unwrap();
+        let (tokens, counted_at) = &mut *state;
+        let now = Instant::now();
+        let refilled = now.duration_since(*counted_at).as_secs_f64() * self.rate;
+        *tokens = (*tokens + refilled).min(self.burst) - 1.0;
+        *counted_at = now;
+        if *tokens >= 0.0 {
+            Duration::ZERO
+        } else {
+            Duration::from_secs_f64(-*tokens / self.rate)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_burst() {
+        let limiter = RateLimiter::new(10, 3);
+        for _ in 0..3 {
+            assert_eq!(Duration::ZERO, limiter.take_token());
+        }
+        let delay = limiter.take_token();
+        assert!(delay > Duration::from_millis(90) && delay <= Duration::from_millis(100));
+    }
+
+    #[tokio::test]
+    async fn test_rate() {
+        let limiter = RateLimiter::new(20, 1);
+        let started = Instant::now();
+        let mut timestamps = vec![];
+        for _ in 0..5 {
+            limiter.acquire().await;
+            timestamps.push(started.elapsed());
+        }
+
+        // one request every 50ms after the first one
+        assert!(timestamps[0] < Duration::from_millis(10));
+        for (i, timestamp) in timestamps.iter().enumerate().skip(1) {
+            assert!(*timestamp >= Duration::from_millis(50 * i as u64 - 1), "{:?}", timestamps);
+        }
+    }
+}
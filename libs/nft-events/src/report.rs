@@ -0,0 +1,67 @@
+//! This module contains the report returned by the trackers when they stop,
+//! and the progress reported after each block range.
+use std::time::Duration;
+
+/// What a tracker did before it returned
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ScanReport {
+    /// The last fully processed block, None if no block has been processed
+    pub last_processed_block: Option<u64>,
+    /// How many blocks were scanned, the rescanned blocks are counted again
+    pub blocks_scanned: u64,
+    /// How many events were found in the scanned blocks, including the denied ones
+    pub events_decoded: u64,
+    /// How many events were delivered to the callback
+    pub events_delivered: u64,
+    /// How many `Transfer` logs were skipped as they are not shaped like ERC721 ones, like the ERC20 transfers.
+    /// Only the ERC721 tracker counts them.
+    pub transfers_rejected: u64,
+    /// How many times the metadata of a token was found in the database
+    pub metadata_cache_hits: u64,
+    /// How many times the metadata of a token had to be fetched from the chain
+    pub metadata_cache_misses: u64,
+    /// How many errors were encountered and retried
+    pub errors: u64,
+    /// How many of the errors were failed requests to the node
+    pub rpc_errors: u64,
+    /// How long the tracker ran
+    pub duration: Duration,
+}
+
+impl ScanReport {
+    pub(crate) fn record_metadata_lookup(&mut self, cached: bool) {
+        if cached {
+            self.metadata_cache_hits += 1;
+        } else {
+            self.metadata_cache_misses += 1;
+        }
+    }
+
+    /// Add the metadata lookups recorded in `other`
+    pub(crate) fn add_metadata_lookups(&mut self, other: &ScanReport) {
+        self.metadata_cache_hits += other.metadata_cache_hits;
+        self.metadata_cache_misses += other.metadata_cache_misses;
+    }
+
+    pub(crate) fn record_rpc_error(&mut self) {
+        self.errors += 1;
+        self.rpc_errors += 1;
+    }
+}
+
+/// The progress of a tracker, reported after each scanned block range
+#[derive(Debug, Clone, PartialEq)]
+pub struct ScanProgress {
+    /// The blockchain name
+    pub chain: String,
+    /// The first block of the range
+    pub from: u64,
+    /// The last block of the range
+    pub to: u64,
+    /// The latest block of the chain when the range was scanned
+    pub latest_block: u64,
+    /// How many events were found in the range
+    pub events_found: usize,
+    /// How long scanning and processing the range took
+    pub elapsed: Duration,
+}
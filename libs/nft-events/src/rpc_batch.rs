@@ -0,0 +1,278 @@
+//! This module contains a transport sending the requests issued together as a single JSON-RPC batch.
+use futures::future::BoxFuture;
+use std::{
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+use tokio::sync::oneshot;
+use web3::{rpc, BatchTransport, RequestId, Transport};
+
+/// A request waiting for its batch to be sent, with the sender of its response
+type PendingRequest = (RequestId, rpc::Call, oneshot::Sender<web3::Result<rpc::Value>>);
+
+/// How the requests are batched
+#[derive(Debug, Clone, Copy)]
+struct Batching {
+    /// How long the first request of a batch waits for the next ones
+    window: Duration,
+    /// A full batch is sent right away
+    max_batch_size: usize,
+}
+
+/// A transport which collects the requests issued within a small window of time and sends them as one batch.
+/// Each response goes back to its request, so a failed request fails alone.
+/// Without batching, the requests are sent one by one by the inner transport.
+#[derive(Debug, Clone)]
+pub(crate) struct BatchingTransport<T> {
+    inner: T,
+    batching: Option<Batching>,
+    pending: Arc<Mutex<Vec<PendingRequest>>>,
+}
+
+impl<T> BatchingTransport<T>
+where
+    T: BatchTransport + Send + Sync + 'static,
+    T::Out: Send + 'static,
+    T::Batch: Send + 'static,
+{
+    /// Send the requests one by one
+    pub(crate) fn new(inner: T) -> BatchingTransport<T> {
+        BatchingTransport {
+            inner,
+            batching: None,
+            pending: Arc::new(Mutex::new(vec![])),
+        }
+    }
+
+    /// Batch the requests issued within `window`, at most `max_batch_size` of them (at least 1)
+    pub(crate) fn with_batching(mut self, window: Duration, max_batch_size: usize) -> BatchingTransport<T> {
+        self.batching = Some(Batching {
+            window,
+            max_batch_size: std::cmp::max(max_batch_size, 1),
+        });
+        self
+    }
+
+    /// Whether the requests are batched
+    pub(crate) fn is_batching(&self) -> bool {
+        self.batching.is_some()
+    }
+
+    /// Send the pending requests as a batch
+    fn flush(&self) {
+        let requests = std::mem::take(&mut *self.pending.lock().unwrap());
+        if !requests.is_empty() {
+            tokio::spawn(send_batch(self.inner.clone(), requests));
+        }
+    }
+}
+
+/// Send `requests` with the inner transport, and dispatch the responses to their requests
+async fn send_batch<T: BatchTransport>(inner: T, requests: Vec<PendingRequest>) {
+    let (calls, senders): (Vec<_>, Vec<_>) = requests
+        .into_iter()
+        .map(|(id, call, sender)| ((id, call), sender))
+        .unzip();
+    let requests_count = calls.len();
+    match inner.send_batch(calls).await {
+        // the responses are matched with the ids of the requests by the inner transport, in the order of the requests
+        Ok(responses) if responses.len() == requests_count => {
+            for (sender, response) in senders.into_iter().zip(responses) {
+                let _ = sender.send(response);
+            }
+        }
+        Ok(responses) => {
+            let message = format!("The batch of {} requests got {} responses", requests_count, responses.len());
+            for sender in senders {
+                let _ = sender.send(Err(web3::Error::Transport(message.clone())));
+            }
+        }
+        Err(err) => {
+            for sender in senders {
+                let _ = sender.send(Err(web3::Error::Transport(err.to_string())));
+            }
+        }
+    }
+}
+
+impl<T> Transport for BatchingTransport<T>
+where
+    T: BatchTransport + Send + Sync + 'static,
+    T::Out: Send + 'static,
+    T::Batch: Send + 'static,
+{
+    type Out = BoxFuture<'static, web3::Result<rpc::Value>>;
+
+    fn prepare(&self, method: &str, params: Vec<rpc::Value>) -> (RequestId, rpc::Call) {
+        self.inner.prepare(method, params)
+    }
+
+    fn send(&self, id: RequestId, request: rpc::Call) -> Self::Out {
+        let batching = match self.batching {
+            Some(batching) => batching,
+            None => return Box::pin(self.inner.send(id, request)),
+        };
+
+        let (sender, receiver) = oneshot::channel();
+        let (first, full) = {
+            let mut pending = self.pending.lock().unwrap();
+            pending.push((id, request, sender));
+            (pending.len() == 1, pending.len() >= batching.max_batch_size)
+        };
+        if full {
+            self.flush();
+        } else if first {
+            // the requests issued within the window join this one
+            let transport = self.clone();
+            tokio::spawn(async move {
+                tokio::time::sleep(batching.window).await;
+                transport.flush();
+            });
+        }
+        Box::pin(async move {
+            receiver
+                .await
+                .unwrap_or_else(|_| Err(web3::Error::Transport("The batch of the request was dropped".to_owned())))
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::future::join_all;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use web3::{
+        contract::{Contract, Options},
+        ethabi::{self, Token},
+        helpers,
+        types::{Bytes, H160, U256},
+        Web3,
+    };
+
+    /// A transport answering every `eth_call` with a token uri made of the id of the request
+    #[derive(Debug, Clone, Default)]
+    struct MockTransport {
+        next_id: Arc<AtomicUsize>,
+        /// The sizes of the requests sent, 1 for a request sent alone
+        sent: Arc<Mutex<Vec<usize>>>,
+        failing_id: Option<RequestId>,
+    }
+
+    impl MockTransport {
+        fn respond(&self, id: RequestId) -> web3::Result<rpc::Value> {
+            if Some(id) == self.failing_id {
+                return Err(web3::Error::Rpc(rpc::Error {
+                    code: rpc::ErrorCode::ServerError(-32000),
+                    message: "execution reverted".to_owned(),
+                    data: None,
+                }));
+            }
+            let token_uri = Token::String(format!("https://mock/{}", id));
+            Ok(helpers::serialize(&Bytes(ethabi::encode(&[token_uri]))))
+        }
+
+        fn sent(&self) -> Vec<usize> {
+            self.sent.lock().unwrap().clone()
+        }
+    }
+
+    impl Transport for MockTransport {
+        type Out = BoxFuture<'static, web3::Result<rpc::Value>>;
+
+        fn prepare(&self, method: &str, params: Vec<rpc::Value>) -> (RequestId, rpc::Call) {
+            let id = self.next_id.fetch_add(1, Ordering::SeqCst);
+            (id, helpers::build_request(id, method, params))
+        }
+
+        fn send(&self, id: RequestId, _request: rpc::Call) -> Self::Out {
+            self.sent.lock().unwrap().push(1);
+            let response = self.respond(id);
+            Box::pin(async move { response })
+        }
+    }
+
+    impl BatchTransport for MockTransport {
+        type Batch = BoxFuture<'static, web3::Result<Vec<web3::Result<rpc::Value>>>>;
+
+        fn send_batch<R>(&self, requests: R) -> Self::Batch
+        where
+            R: IntoIterator<Item = (RequestId, rpc::Call)>,
+        {
+            let responses: Vec<_> = requests.into_iter().map(|(id, _)| self.respond(id)).collect();
+            self.sent.lock().unwrap().push(responses.len());
+            Box::pin(async move { Ok(responses) })
+        }
+    }
+
+    /// Look up the token uris of `count` tokens concurrently
+    async fn token_uris(transport: BatchingTransport<MockTransport>, count: u64) -> Vec<web3::contract::Result<String>> {
+        let web3 = Web3::new(transport);
+        let contract = Contract::from_json(
+            web3.eth(),
+            H160::from_low_u64_be(1),
+            include_bytes!("./contracts/erc721.json"),
+        )
+        .unwrap();
+        let lookups = (0..count).map(|token_id| {
+            let contract = &contract;
+            async move {
+                let token_uri: web3::contract::Result<String> = contract
+                    .query("tokenURI", (U256::from(token_id),), None, Options::default(), None)
+                    .await;
+                token_uri
+            }
+        });
+        join_all(lookups).await
+    }
+
+    #[tokio::test]
+    async fn test_batching_transport() {
+        let transport = MockTransport::default();
+        let batching = BatchingTransport::new(transport.clone()).with_batching(Duration::from_millis(10), 100);
+
+        let token_uris = token_uris(batching, 5).await;
+
+        assert_eq!(vec![5], transport.sent());
+        let token_uris: Vec<String> = token_uris.into_iter().map(|token_uri| token_uri.unwrap()).collect();
+        let expected: Vec<String> = (0..5).map(|id| format!("https://mock/{}", id)).collect();
+        assert_eq!(expected, token_uris);
+    }
+
+    #[tokio::test]
+    async fn test_batching_transport_failed_request() {
+        let transport = MockTransport {
+            failing_id: Some(2),
+            ..Default::default()
+        };
+        let batching = BatchingTransport::new(transport.clone()).with_batching(Duration::from_millis(10), 100);
+
+        let token_uris = token_uris(batching, 5).await;
+
+        assert_eq!(vec![5], transport.sent());
+        for (id, token_uri) in token_uris.iter().enumerate() {
+            assert_eq!(id != 2, token_uri.is_ok());
+        }
+    }
+
+    #[tokio::test]
+    async fn test_batching_transport_max_batch_size() {
+        let transport = MockTransport::default();
+        let batching = BatchingTransport::new(transport.clone()).with_batching(Duration::from_millis(10), 2);
+
+        let token_uris = token_uris(batching, 5).await;
+
+        assert_eq!(vec![2, 2, 1], transport.sent());
+        assert!(token_uris.iter().all(|token_uri| token_uri.is_ok()));
+    }
+
+    #[tokio::test]
+    async fn test_without_batching() {
+        let transport = MockTransport::default();
+
+        let token_uris = token_uris(BatchingTransport::new(transport.clone()), 3).await;
+
+        assert_eq!(vec![1, 1, 1], transport.sent());
+        assert!(token_uris.iter().all(|token_uri| token_uri.is_ok()));
+    }
+}
@@ -0,0 +1,61 @@
+//! Serialize the large integers as decimal strings, as the JSON numbers lose the precision of the integers
+//! above 2^53. Use it with `#[serde(with = "crate::serde_decimal")]`.
+use serde::{Deserialize, Deserializer, Serializer};
+use web3::types::U256;
+
+/// An integer written as a decimal string
+pub(crate) trait Decimal: Sized {
+    fn to_decimal(&self) -> String;
+    fn from_decimal(decimal: &str) -> Option<Self>;
+}
+
+impl Decimal for U256 {
+    fn to_decimal(&self) -> String {
+        self.to_string()
+    }
+
+    fn from_decimal(decimal: &str) -> Option<Self> {
+        U256::from_dec_str(decimal).ok()
+    }
+}
+
+impl Decimal for u128 {
+    fn to_decimal(&self) -> String {
+        self.to_string()
+    }
+
+    fn from_decimal(decimal: &str) -> Option<Self> {
+        decimal.parse().ok()
+    }
+}
+
+pub(crate) fn serialize<T: Decimal, S: Serializer>(value: &T, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_str(&value.to_decimal())
+}
+
+pub(crate) fn deserialize<'de, T: Decimal, D: Deserializer<'de>>(deserializer: D) -> Result<T, D::Error> {
+    let decimal = String::deserialize(deserializer)?;
+    parse(&decimal)
+}
+
+fn parse<T: Decimal, E: serde::de::Error>(decimal: &str) -> Result<T, E> {
+    T::from_decimal(decimal).ok_or_else(|| E::custom(format!("{} is not a decimal integer", decimal)))
+}
+
+/// The optional integers, null when there is none
+pub(crate) mod option {
+    use super::{parse, Decimal};
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub(crate) fn serialize<T: Decimal, S: Serializer>(value: &Option<T>, serializer: S) -> Result<S::Ok, S::Error> {
+        match value {
+            Some(value) => super::serialize(value, serializer),
+            None => serializer.serialize_none(),
+        }
+    }
+
+    pub(crate) fn deserialize<'de, T: Decimal, D: Deserializer<'de>>(deserializer: D) -> Result<Option<T>, D::Error> {
+        let decimal = Option::<String>::deserialize(deserializer)?;
+        decimal.map(|decimal| parse(&decimal)).transpose()
+    }
+}
@@ -0,0 +1,139 @@
+//! This module cancels the trackers when the process receives ctrl-c.
+//! The trackers stop as they do when their cancellation token is cancelled: the ERC721 tracker finishes
+//! the range it is processing, commits it together with its progress and returns, so that it resumes
+//! from the next block on the next start.
+use tokio::task::JoinHandle;
+use tokio_util::sync::CancellationToken;
+
+/// Cancel `token` when the process receives ctrl-c.
+/// Give the same token to the trackers through `ScanOptions::cancellation_token`.
+/// The spawned task returns once the token is cancelled, by the signal or by anything else.
+pub fn cancel_on_ctrl_c(token: CancellationToken) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        tokio::select! {
+            _ = token.cancelled() => {}
+            result = tokio::signal::ctrl_c() => {
+                match result {
+                    Ok(()) => info!("Received ctrl-c, stop the trackers."),
+                    Err(err) => {
+                        // the trackers can still be stopped through the token if listening failed
+                        error!("Failed to listen for ctrl-c: {:?}.", err);
+                        return;
+                    }
+                }
+                token.cancel();
+            }
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::erc721::{track_erc721_events_with_config, Erc721EventCallback};
+    use crate::test_support::{address, erc721_transfer_log, MockEvmClient};
+    use crate::{Erc721Event, Erc721TrackerConfig, NftStore, Result, ScanOptions, SqliteStore};
+    use std::time::Duration;
+
+    fn client_with_events(blocks: std::ops::Range<u64>) -> MockEvmClient {
+        let collection = address(1);
+        let mut client = MockEvmClient::new("Mock", 100)
+            .with_erc721_collection(collection, "Mock Collection", "MOCK");
+        for block_number in blocks {
+            client = client
+                .with_erc721_token_uri(collection, block_number, "https://mock")
+                .with_log(erc721_transfer_log(collection, address(0), address(2), block_number, block_number, 0));
+        }
+        client
+    }
+
+    fn config(token: CancellationToken) -> Erc721TrackerConfig {
+        Erc721TrackerConfig::builder()
+            .start_from(10)
+            .step(5)
+            .end_block(49)
+            .options(ScanOptions {
+                range_interval: Duration::from_millis(1),
+                idle_interval: Duration::from_millis(1),
+                error_interval: Duration::from_millis(1),
+                ..Default::default()
+            })
+            .resume(true)
+            .cancellation_token(token)
+            .build()
+            .unwrap()
+    }
+
+    /// Cancels the token, as ctrl-c would, once the event of `cancel_at` is delivered
+    struct InterruptingCallback {
+        token: CancellationToken,
+        cancel_at: u64,
+        delivered_blocks: Vec<u64>,
+    }
+
+    #[async_trait]
+    impl Erc721EventCallback for InterruptingCallback {
+        async fn on_erc721_event(
+            &mut self,
+            event: Erc721Event,
+            _name: String,
+            _symbol: String,
+            _total_supply: Option<u128>,
+            _token_uri: String,
+        ) -> Result<()> {
+            let block_number = event.block_number.unwrap();
+            self.delivered_blocks.push(block_number);
+            if block_number == self.cancel_at {
+                self.token.cancel();
+            }
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_cancel_on_ctrl_c_returns_once_cancelled() {
+        let token = CancellationToken::new();
+        let handler = cancel_on_ctrl_c(token.clone());
+        token.cancel();
+        tokio::time::timeout(Duration::from_secs(1), handler)
+            .await
+            .unwrap()
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_interrupted_tracker_resumes_after_the_checkpoint() {
+        let store = SqliteStore::open_in_memory().await.unwrap();
+
+        let token = CancellationToken::new();
+        let handler = cancel_on_ctrl_c(token.clone());
+        let mut callback = InterruptingCallback {
+            token: token.clone(),
+            cancel_at: 22,
+            delivered_blocks: vec![],
+        };
+        let client = client_with_events(10..50);
+        track_erc721_events_with_config(&client, &store, &config(token), &mut callback)
+            .await
+            .unwrap();
+        handler.await.unwrap();
+
+        // the range of the interruption is finished and checkpointed
+        assert_eq!(Some(&24), callback.delivered_blocks.last());
+        assert_eq!(Some(24), store.get_scan_progress("Mock").await.unwrap());
+
+        let token = CancellationToken::new();
+        let mut callback = InterruptingCallback {
+            token: token.clone(),
+            cancel_at: 0,
+            delivered_blocks: vec![],
+        };
+        let client = client_with_events(10..50);
+        track_erc721_events_with_config(&client, &store, &config(token), &mut callback)
+            .await
+            .unwrap();
+
+        assert_eq!((25..50).collect::<Vec<u64>>(), callback.delivered_blocks);
+        assert_eq!(Some(&(25, 29)), client.scanned_ranges().first());
+    }
+}
@@ -0,0 +1,452 @@
+//! This module defines a store in the sqlite database of `erc721_db` whose connection lives on a thread of its own,
+//! so that the queries and the transactions do not block the async executor, and the RPC requests of the other
+//! tasks go on meanwhile. The operations are sent to the thread through a channel and run one by one.
+use crate::erc721_db::{self, Collection, CollectionCode, Token, Transfer};
+use crate::store::RangeWrites;
+use crate::{erc1155_db, Error, NftStore, Result};
+
+use rusqlite::Connection;
+use std::{path::Path, thread};
+use tokio::sync::{mpsc, oneshot};
+
+/// An operation run on the connection of the database thread
+type Operation = Box<dyn FnOnce(&mut Connection) + Send>;
+
+/// A sqlite database on a dedicated thread. The clones share the thread, which stops once they are all dropped.
+/// The store is `Send` and `Sync`, the tasks of any runtime thread may call it.
+#[derive(Clone)]
+pub struct SqliteStore {
+    operations: mpsc::UnboundedSender<Operation>,
+}
+
+impl SqliteStore {
+    /// Open the database at `path`, configured by `erc721_db::configure_connection`, and create or migrate the tables
+    /// of `erc721_db` and `erc1155_db`
+    pub async fn open(path: impl AsRef<Path>) -> Result<SqliteStore> {
+        let path = path.as_ref().to_owned();
+        SqliteStore::start(move || {
+            let conn = Connection::open(path)?;
+            erc721_db::configure_connection(&conn)?;
+            Ok(conn)
+        })
+        .await
+    }
+
+    /// Open the database of the tracker of `chain` at `path` as `open` does, migrated by
+    /// `erc721_db::migrate_with_chain` which assigns its rows saved before the chains were namespaced to `chain`
+    pub async fn open_with_chain(path: impl AsRef<Path>, chain: &str) -> Result<SqliteStore> {
+        let (path, chain) = (path.as_ref().to_owned(), chain.to_owned());
+        SqliteStore::start(move || {
+            let conn = Connection::open(path)?;
+            erc721_db::configure_connection(&conn)?;
+            erc721_db::migrate_with_chain(&conn, &chain)?;
+            Ok(conn)
+        })
+        .await
+    }
+
+    /// Open a database in memory, lost when the store is dropped
+    pub async fn open_in_memory() -> Result<SqliteStore> {
+        SqliteStore::start(|| Ok(Connection::open_in_memory()?)).await
+    }
+
+    async fn start(open: impl FnOnce() -> Result<Connection> + Send + 'static) -> Result<SqliteStore> {
+        let (operations, mut received) = mpsc::unbounded_channel::<Operation>();
+        let (opened_sender, opened) = oneshot::channel();
+        thread::Builder::new().name("nft-events-sqlite".to_owned()).spawn(move || {
+            let conn = open().and_then(|conn| {
+                erc721_db::create_tables_if_not_exist(&conn)?;
+                erc1155_db::create_tables_if_not_exist(&conn)?;
+                Ok(conn)
+            });
+            let mut conn = match conn {
+                Ok(conn) => {
+                    let _ = opened_sender.send(Ok(()));
+                    conn
+                }
+                Err(err) => {
+                    let _ = opened_sender.send(Err(err));
+                    return;
+                }
+            };
+            // until all the stores are dropped, the thread is outside of the runtime and may block
+            while let Some(operation) = received.blocking_recv() {
+                operation(&mut conn);
+            }
+        })?;
+        opened.await.map_err(|_| thread_stopped())??;
+        Ok(SqliteStore { operations })
+    }
+
+    /// Run `operation` on the connection of the database thread, after the operations called before it, and wait for
+    /// its result without blocking the executor. An operation may run a transaction with `Connection::transaction`,
+    /// no other operation runs before it returns.
+    pub async fn call<T, F>(&self, operation: F) -> Result<T>
+    where
+        T: Send + 'static,
+        F: FnOnce(&mut Connection) -> Result<T> + Send + 'static,
+    {
+        let (result_sender, result) = oneshot::channel();
+        self.operations
+            .send(Box::new(move |conn| {
+                // the caller may have stopped waiting
+                let _ = result_sender.send(operation(conn));
+            }))
+            .map_err(|_| thread_stopped())?;
+        result.await.map_err(|_| thread_stopped())?
+    }
+}
+
+fn thread_stopped() -> Error {
+    Error::Other("The thread of the sqlite database has stopped".to_owned())
+}
+
+/// Save the writes of a scanned range in a transaction
+fn commit_range(conn: &mut Connection, writes: &RangeWrites) -> Result<()> {
+    let tx = conn.transaction()?;
+    let chain = writes.chain.as_str();
+    for owner in &writes.owners {
+        erc721_db::save_token_transfer(
+            &tx,
+            chain,
+            &owner.address,
+            &owner.token_id,
+            &owner.owner,
+            owner.burnt,
+            owner.block_number,
+            owner.log_index,
+        )?;
+    }
+    for transfer in &writes.transfers {
+        let collection_id = match erc721_db::get_collection(&tx, chain, &transfer.address)? {
+            Some(collection) => collection.id,
+            None => erc721_db::add_collection_of_transfers(&tx, chain, transfer.address.clone())?,
+        };
+        erc721_db::save_transfer(&tx, &transfer.transfer(collection_id))?;
+    }
+    for (transaction_hash, log_index, block_number) in &writes.delivered_events {
+        erc721_db::mark_event_delivered(&tx, chain, transaction_hash, *log_index, *block_number)?;
+    }
+    if let Some(block_number) = writes.last_scanned_block {
+        erc721_db::save_scan_progress(&tx, chain, block_number)?;
+    }
+    for (block_number, block_hash) in &writes.scanned_blocks {
+        erc721_db::save_scanned_block(&tx, chain, *block_number, block_hash)?;
+    }
+    tx.commit()?;
+    Ok(())
+}
+
+/// Forget what a chain saved from `block_number` on in a transaction, with its new progress
+fn rewind(conn: &mut Connection, chain: &str, block_number: u64, last_scanned_block: Option<u64>) -> Result<()> {
+    let tx = conn.transaction()?;
+    erc721_db::remove_scanned_blocks_from(&tx, chain, block_number)?;
+    erc721_db::remove_transfers_from(&tx, chain, block_number)?;
+    erc721_db::remove_token_owners_from(&tx, chain, block_number)?;
+    if let Some(last_scanned_block) = last_scanned_block {
+        erc721_db::save_scan_progress(&tx, chain, last_scanned_block)?;
+    }
+    tx.commit()?;
+    Ok(())
+}
+
+#[async_trait]
+impl NftStore for SqliteStore {
+    async fn get_collection(&self, chain: &str, address: &str) -> Result<Option<Collection>> {
+        let (chain, address) = (chain.to_owned(), address.to_owned());
+        self.call(move |conn| erc721_db::get_collection(conn, &chain, &address)).await
+    }
+
+    async fn put_collection(&self, chain: &str, address: &str, name_symbol: Option<(String, String)>) -> Result<usize> {
+        let (chain, address) = (chain.to_owned(), address.to_owned());
+        let (name, symbol) = match name_symbol {
+            Some((name, symbol)) => (Some(name), Some(symbol)),
+            None => (None, None),
+        };
+        self.call(move |conn| erc721_db::add_collection_to_db(conn, &chain, address, name, symbol))
+            .await
+    }
+
+    async fn add_collection_with_erc721_support(
+        &self,
+        chain: &str,
+        address: &str,
+        supports_erc721: Option<bool>,
+    ) -> Result<usize> {
+        let (chain, address) = (chain.to_owned(), address.to_owned());
+        self.call(move |conn| erc721_db::add_collection_with_erc721_support(conn, &chain, address, supports_erc721))
+            .await
+    }
+
+    async fn get_collection_erc721_support(&self, collection_id: usize) -> Result<Option<Option<bool>>> {
+        self.call(move |conn| erc721_db::get_collection_erc721_support(conn, collection_id)).await
+    }
+
+    async fn save_collection_erc721_support(&self, collection_id: usize, supports_erc721: Option<bool>) -> Result<()> {
+        self.call(move |conn| erc721_db::save_collection_erc721_support(conn, collection_id, supports_erc721))
+            .await
+    }
+
+    async fn get_collection_code(&self, collection_id: usize) -> Result<Option<CollectionCode>> {
+        self.call(move |conn| erc721_db::get_collection_code(conn, collection_id)).await
+    }
+
+    async fn save_collection_code(&self, collection_id: usize, code: CollectionCode) -> Result<()> {
+        self.call(move |conn| erc721_db::save_collection_code(conn, collection_id, code)).await
+    }
+
+    async fn get_collection_creation_block(&self, chain: &str, address: &str) -> Result<Option<u64>> {
+        let (chain, address) = (chain.to_owned(), address.to_owned());
+        self.call(move |conn| erc721_db::get_collection_creation_block(conn, &chain, &address))
+            .await
+    }
+
+    async fn save_collection_creation_block(&self, collection_id: usize, block_number: u64) -> Result<()> {
+        self.call(move |conn| erc721_db::save_collection_creation_block(conn, collection_id, block_number))
+            .await
+    }
+
+    async fn get_collection_royalty(&self, chain: &str, address: &str) -> Result<Option<(String, u64)>> {
+        let (chain, address) = (chain.to_owned(), address.to_owned());
+        self.call(move |conn| erc721_db::get_collection_royalty(conn, &chain, &address)).await
+    }
+
+    async fn save_collection_royalty(&self, collection_id: usize, royalty: Option<(&str, u64)>) -> Result<()> {
+        let royalty = royalty.map(|(receiver, bps)| (receiver.to_owned(), bps));
+        self.call(move |conn| {
+            let royalty = royalty.as_ref().map(|(receiver, bps)| (receiver.as_str(), *bps));
+            erc721_db::save_collection_royalty(conn, collection_id, royalty)
+        })
+        .await
+    }
+
+    async fn get_collection_contract_uri(&self, chain: &str, address: &str) -> Result<Option<String>> {
+        let (chain, address) = (chain.to_owned(), address.to_owned());
+        self.call(move |conn| erc721_db::get_collection_contract_uri(conn, &chain, &address))
+            .await
+    }
+
+    async fn save_collection_contract_uri(&self, collection_id: usize, contract_uri: Option<&str>) -> Result<()> {
+        let contract_uri = contract_uri.map(str::to_owned);
+        self.call(move |conn| erc721_db::save_collection_contract_uri(conn, collection_id, contract_uri.as_deref()))
+            .await
+    }
+
+    async fn get_collection_implementation(&self, chain: &str, address: &str) -> Result<Option<(Option<String>, u64)>> {
+        let (chain, address) = (chain.to_owned(), address.to_owned());
+        self.call(move |conn| erc721_db::get_collection_implementation(conn, &chain, &address))
+            .await
+    }
+
+    async fn save_collection_implementation(
+        &self,
+        collection_id: usize,
+        implementation: Option<&str>,
+        block_number: u64,
+    ) -> Result<()> {
+        let implementation = implementation.map(str::to_owned);
+        self.call(move |conn| {
+            erc721_db::save_collection_implementation(conn, collection_id, implementation.as_deref(), block_number)
+        })
+        .await
+    }
+
+    async fn get_collection_snapshot(&self, chain: &str, address: &str) -> Result<Option<(u64, u64)>> {
+        let (chain, address) = (chain.to_owned(), address.to_owned());
+        self.call(move |conn| erc721_db::get_collection_snapshot(conn, &chain, &address)).await
+    }
+
+    async fn save_collection_snapshot(&self, collection_id: usize, block_number: u64, index: u64) -> Result<()> {
+        self.call(move |conn| erc721_db::save_collection_snapshot(conn, collection_id, block_number, index))
+            .await
+    }
+
+    async fn update_collection_metadata(
+        &self,
+        collection_id: usize,
+        name: Option<String>,
+        symbol: Option<String>,
+    ) -> Result<()> {
+        self.call(move |conn| erc721_db::update_collection_metadata(conn, collection_id, name, symbol))
+            .await
+    }
+
+    async fn get_collection_lookup_failures(&self, collection_id: usize) -> Result<Option<(u32, u64)>> {
+        self.call(move |conn| erc721_db::get_collection_lookup_failures(conn, collection_id)).await
+    }
+
+    async fn record_collection_lookup_failure(&self, collection_id: usize, attempted_at: u64) -> Result<()> {
+        self.call(move |conn| erc721_db::record_collection_lookup_failure(conn, collection_id, attempted_at))
+            .await
+    }
+
+    async fn get_token(&self, collection_id: usize, token_id: &str) -> Result<Option<Token>> {
+        let token_id = token_id.to_owned();
+        self.call(move |conn| erc721_db::get_token(conn, collection_id, &token_id)).await
+    }
+
+    async fn put_token(&self, collection_id: usize, token_id: &str, token_uri: Option<String>) -> Result<usize> {
+        let token_id = token_id.to_owned();
+        self.call(move |conn| erc721_db::add_token_to_db(conn, token_id, collection_id, token_uri))
+            .await
+    }
+
+    async fn get_token_fetched_at(&self, token_db_id: usize) -> Result<Option<u64>> {
+        self.call(move |conn| erc721_db::get_token_fetched_at(conn, token_db_id)).await
+    }
+
+    async fn update_token_uri(&self, token_db_id: usize, token_uri: Option<String>, fetched_at: u64) -> Result<()> {
+        self.call(move |conn| erc721_db::update_token_uri(conn, token_db_id, token_uri, fetched_at))
+            .await
+    }
+
+    async fn get_token_lookup_failures(&self, token_db_id: usize) -> Result<Option<(u32, u64)>> {
+        self.call(move |conn| erc721_db::get_token_lookup_failures(conn, token_db_id)).await
+    }
+
+    async fn record_token_lookup_failure(&self, token_db_id: usize, attempted_at: u64) -> Result<()> {
+        self.call(move |conn| erc721_db::record_token_lookup_failure(conn, token_db_id, attempted_at))
+            .await
+    }
+
+    async fn is_token_stale(&self, token_db_id: usize) -> Result<bool> {
+        self.call(move |conn| erc721_db::is_token_stale(conn, token_db_id)).await
+    }
+
+    async fn mark_tokens_stale(&self, collection_id: usize, first: &str, last: &str) -> Result<usize> {
+        let (first, last) = (first.to_owned(), last.to_owned());
+        self.call(move |conn| erc721_db::mark_tokens_stale(conn, collection_id, &first, &last))
+            .await
+    }
+
+    async fn get_token_owner(&self, chain: &str, address: &str, token_id: &str) -> Result<Option<String>> {
+        let (chain, address, token_id) = (chain.to_owned(), address.to_owned(), token_id.to_owned());
+        self.call(move |conn| erc721_db::get_token_owner(conn, &chain, &address, &token_id))
+            .await
+    }
+
+    async fn count_tokens_of_owner(&self, chain: &str, address: &str, owner: &str) -> Result<u64> {
+        let (chain, address, owner) = (chain.to_owned(), address.to_owned(), owner.to_owned());
+        self.call(move |conn| erc721_db::count_tokens_of_owner(conn, &chain, &address, &owner))
+            .await
+    }
+
+    async fn put_transfer(&self, transfer: &Transfer) -> Result<bool> {
+        let transfer = transfer.clone();
+        self.call(move |conn| erc721_db::save_transfer(conn, &transfer)).await
+    }
+
+    async fn get_token_transfers(&self, collection_id: usize, token_id: &str) -> Result<Vec<Transfer>> {
+        let token_id = token_id.to_owned();
+        self.call(move |conn| erc721_db::get_token_transfers(conn, collection_id, &token_id))
+            .await
+    }
+
+    async fn get_scan_progress(&self, chain: &str) -> Result<Option<u64>> {
+        let chain = chain.to_owned();
+        self.call(move |conn| erc721_db::get_scan_progress(conn, &chain)).await
+    }
+
+    async fn save_scan_progress(&self, chain: &str, block_number: u64) -> Result<()> {
+        let chain = chain.to_owned();
+        self.call(move |conn| erc721_db::save_scan_progress(conn, &chain, block_number))
+            .await
+    }
+
+    async fn is_event_delivered(&self, chain: &str, transaction_hash: &str, log_index: u64) -> Result<bool> {
+        let (chain, transaction_hash) = (chain.to_owned(), transaction_hash.to_owned());
+        self.call(move |conn| erc721_db::is_event_delivered(conn, &chain, &transaction_hash, log_index))
+            .await
+    }
+
+    async fn mark_event_delivered(
+        &self,
+        chain: &str,
+        transaction_hash: &str,
+        log_index: u64,
+        block_number: u64,
+    ) -> Result<()> {
+        let (chain, transaction_hash) = (chain.to_owned(), transaction_hash.to_owned());
+        self.call(move |conn| erc721_db::mark_event_delivered(conn, &chain, &transaction_hash, log_index, block_number))
+            .await
+    }
+
+    async fn get_scanned_blocks_before(&self, chain: &str, block_number: u64) -> Result<Vec<(u64, String)>> {
+        let chain = chain.to_owned();
+        self.call(move |conn| erc721_db::get_scanned_blocks_before(conn, &chain, block_number))
+            .await
+    }
+
+    async fn commit_range(&self, writes: &RangeWrites) -> Result<()> {
+        let writes = writes.clone();
+        self.call(move |conn| commit_range(conn, &writes)).await
+    }
+
+    async fn rewind(&self, chain: &str, block_number: u64, last_scanned_block: Option<u64>) -> Result<()> {
+        let chain = chain.to_owned();
+        self.call(move |conn| rewind(conn, &chain, block_number, last_scanned_block)).await
+    }
+
+    async fn get_erc1155_collection(&self, address: &str) -> Result<Option<usize>> {
+        let address = address.to_owned();
+        self.call(move |conn| Ok(erc1155_db::get_collection_from_db(conn, &address)?.map(|(id, _)| id)))
+            .await
+    }
+
+    async fn add_erc1155_collection(&self, address: &str) -> Result<usize> {
+        let address = address.to_owned();
+        self.call(move |conn| erc1155_db::add_collection_to_db(conn, address)).await
+    }
+
+    async fn get_erc1155_token_uri(&self, collection_id: usize, token_id: &str) -> Result<Option<Option<String>>> {
+        let token_id = token_id.to_owned();
+        self.call(move |conn| {
+            let token = erc1155_db::get_token_from_db(conn, collection_id, &token_id)?;
+            Ok(token.map(|(_, _, _, token_uri)| token_uri))
+        })
+        .await
+    }
+
+    async fn add_erc1155_token(
+        &self,
+        collection_id: usize,
+        token_id: &str,
+        token_uri: Option<String>,
+    ) -> Result<usize> {
+        let token_id = token_id.to_owned();
+        self.call(move |conn| erc1155_db::add_token_to_db(conn, token_id, collection_id, token_uri))
+            .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::store::tests::check_store;
+
+    #[tokio::test]
+    async fn test_sqlite_store() {
+        check_store(&SqliteStore::open_in_memory().await.unwrap()).await;
+    }
+
+    #[tokio::test]
+    async fn test_sqlite_store_on_a_file() {
+        let path = std::env::temp_dir().join("sqlite_store.db");
+        let remove_database = || {
+            for suffix in &["", "-wal", "-shm"] {
+                let _ = std::fs::remove_file(format!("{}{}", path.display(), suffix));
+            }
+        };
+        remove_database();
+        let store = SqliteStore::open(&path).await.unwrap();
+        store.save_scan_progress("Ethereum", 100).await.unwrap();
+        drop(store);
+
+        let store = SqliteStore::open(&path).await.unwrap();
+        assert_eq!(Some(100), store.get_scan_progress("Ethereum").await.unwrap());
+        let version = store.call(|conn| erc721_db::get_schema_version(conn)).await.unwrap();
+        assert_eq!(erc721_db::SCHEMA_VERSION, version);
+        drop(store);
+        remove_database();
+    }
+}
@@ -0,0 +1,1030 @@
+//! This module defines the storage of the ERC721 and ERC1155 metadata, the owners, the transfers, the scan progress,
+//! the delivered events and the scanned blocks, as a trait implemented by the sqlite database of `erc721_db` on a
+//! thread of its own which does not block the executor, see `sqlite_store`, by a store in memory, and by a PostgreSQL
+//! database with the `postgres` feature, see `postgres_store`. The stores are `Send` and `Sync`, to be shared by the
+//! tasks spawned on a multi-threaded runtime.
+//! The trackers save the metadata as they look it up, and the other writes of a scanned range together once it is
+//! processed, see `RangeWrites`.
+use crate::erc721_db::{Collection, CollectionCode, Token, Transfer};
+use crate::Result;
+
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    sync::Mutex,
+};
+
+/// The owner of a token after a transfer of a scanned range
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OwnerWrite {
+    /// The address of the collection, as formatted by `{:?}`
+    pub address: String,
+    /// The token id, in decimal
+    pub token_id: String,
+    /// The recipient of the transfer, as formatted by `{:?}`
+    pub owner: String,
+    /// Whether the transfer burnt the token, which has no owner anymore
+    pub burnt: bool,
+    /// The block of the transfer
+    pub block_number: u64,
+    /// The index of the log of the transfer in its block
+    pub log_index: u64,
+}
+
+/// A transfer of a scanned range, by the address of its collection
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TransferWrite {
+    /// The address of the collection, as formatted by `{:?}`. A collection which is not saved yet is saved without
+    /// its metadata, which is looked up by its next events.
+    pub address: String,
+    /// The token id, in decimal
+    pub token_id: String,
+    /// The sender of the token, as formatted by `{:?}`
+    pub from: String,
+    /// The recipient of the token, as formatted by `{:?}`
+    pub to: String,
+    /// The block of the transfer
+    pub block_number: u64,
+    /// The hash of the transaction of the transfer, as formatted by `{:?}`
+    pub tx_hash: String,
+    /// The index of the log of the transfer in its block
+    pub log_index: u64,
+    /// The timestamp of the block, if it was fetched
+    pub timestamp: Option<u64>,
+}
+
+impl TransferWrite {
+    /// The transfer to save with the collection `collection_id`
+    pub fn transfer(&self, collection_id: usize) -> Transfer {
+        Transfer {
+            collection_id,
+            token_id: self.token_id.clone(),
+            from: self.from.clone(),
+            to: self.to.clone(),
+            block_number: self.block_number,
+            tx_hash: self.tx_hash.clone(),
+            log_index: self.log_index,
+            timestamp: self.timestamp,
+        }
+    }
+}
+
+/// The writes of a scanned block range, which `NftStore::commit_range` saves together once the range is processed.
+/// A range which is not committed, as when the callback rejects its events, saves none of them and is scanned again.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RangeWrites {
+    /// The chain of the tracker, the key of its scan progress
+    pub chain: String,
+    /// The owners after the transfers of the range, in the order of the chain. A transfer older than the saved owner
+    /// of its token, as when a range is scanned again, changes nothing.
+    pub owners: Vec<OwnerWrite>,
+    /// The transfers of the delivered events, saved once even if the range is scanned again
+    pub transfers: Vec<TransferWrite>,
+    /// The delivered events, by transaction hash, log index and block number
+    pub delivered_events: Vec<(String, u64, u64)>,
+    /// The last block of the range, saved as the scan progress of the chain if it is some
+    pub last_scanned_block: Option<u64>,
+    /// The hashes of the blocks of the range to detect the reorgs, as formatted by `{:?}`.
+    /// Only the latest ones of the chain are kept.
+    pub scanned_blocks: Vec<(u64, String)>,
+}
+
+/// The storage of the ERC721 and ERC1155 metadata, the owners, the transfers, the scan progress, the delivered events
+/// and the scanned blocks.
+/// The addresses are formatted by `{:?}` and the token ids are in decimal, as in `erc721_db`. The ERC721 collections
+/// are saved by chain, a contract deployed at the same address on two chains is two collections. The times are in
+/// unix seconds.
+#[async_trait]
+pub trait NftStore: Send + Sync {
+    /// Get a saved contract of a chain, with its name and symbol
+    async fn get_collection(&self, chain: &str, address: &str) -> Result<Option<Collection>>;
+
+    /// Save a contract with its name and symbol, which replace the saved ones if it is saved already.
+    /// It returns the id of the collection.
+    async fn put_collection(&self, chain: &str, address: &str, name_symbol: Option<(String, String)>) -> Result<usize>;
+
+    /// Save a contract whose metadata is not looked up yet, with whether it supports ERC721. Its metadata lookup is
+    /// due right away. A contract saved already only gets whether it supports ERC721. It returns the id.
+    async fn add_collection_with_erc721_support(
+        &self,
+        chain: &str,
+        address: &str,
+        supports_erc721: Option<bool>,
+    ) -> Result<usize>;
+
+    /// Get whether a contract reported supporting ERC721 with ERC165.
+    /// None if it has not been checked yet, Some(None) if the contract does not implement ERC165.
+    async fn get_collection_erc721_support(&self, collection_id: usize) -> Result<Option<Option<bool>>>;
+
+    /// Save whether a contract reported supporting ERC721 with ERC165, None if it does not implement ERC165
+    async fn save_collection_erc721_support(&self, collection_id: usize, supports_erc721: Option<bool>) -> Result<()>;
+
+    /// Get the contract code at the address of a collection, None if it has not been checked yet
+    async fn get_collection_code(&self, collection_id: usize) -> Result<Option<CollectionCode>>;
+
+    /// Save the contract code at the address of a collection
+    async fn save_collection_code(&self, collection_id: usize, code: CollectionCode) -> Result<()>;
+
+    /// Get the block where a contract was created, if it was saved
+    async fn get_collection_creation_block(&self, chain: &str, address: &str) -> Result<Option<u64>>;
+
+    /// Save the block where a contract was created
+    async fn save_collection_creation_block(&self, collection_id: usize, block_number: u64) -> Result<()>;
+
+    /// Get the last ERC2981 royalty saved for a contract, its receiver and its basis points
+    async fn get_collection_royalty(&self, chain: &str, address: &str) -> Result<Option<(String, u64)>>;
+
+    /// Save the last ERC2981 royalty of a contract, None if it has none anymore
+    async fn save_collection_royalty(&self, collection_id: usize, royalty: Option<(&str, u64)>) -> Result<()>;
+
+    /// Get the uri of the collection-level metadata of a contract, from its `contractURI`
+    async fn get_collection_contract_uri(&self, chain: &str, address: &str) -> Result<Option<String>>;
+
+    /// Save the uri of the collection-level metadata of a contract
+    async fn save_collection_contract_uri(&self, collection_id: usize, contract_uri: Option<&str>) -> Result<()>;
+
+    /// Get the EIP-1967 implementation of a contract and the block it was last resolved at.
+    /// None if it was never resolved, the implementation is None if it is not a proxy.
+    async fn get_collection_implementation(&self, chain: &str, address: &str) -> Result<Option<(Option<String>, u64)>>;
+
+    /// Save the EIP-1967 implementation of a contract resolved at `block_number`, None if it is not a proxy
+    async fn save_collection_implementation(
+        &self,
+        collection_id: usize,
+        implementation: Option<&str>,
+        block_number: u64,
+    ) -> Result<()>;
+
+    /// Get the progress of the last snapshot of a contract: its block and the index of the next token to save
+    async fn get_collection_snapshot(&self, chain: &str, address: &str) -> Result<Option<(u64, u64)>>;
+
+    /// Save the progress of the snapshot of a contract at `block_number`
+    async fn save_collection_snapshot(&self, collection_id: usize, block_number: u64, index: u64) -> Result<()>;
+
+    /// Save the name and symbol of a contract, None if it has no metadata. It forgets the failed lookups.
+    async fn update_collection_metadata(
+        &self,
+        collection_id: usize,
+        name: Option<String>,
+        symbol: Option<String>,
+    ) -> Result<()>;
+
+    /// Get how many times looking up the metadata of a contract failed, and when it failed last.
+    /// None if no lookup failed since the last successful one.
+    async fn get_collection_lookup_failures(&self, collection_id: usize) -> Result<Option<(u32, u64)>>;
+
+    /// Record a failed lookup of the metadata of a contract at `attempted_at`
+    async fn record_collection_lookup_failure(&self, collection_id: usize, attempted_at: u64) -> Result<()>;
+
+    /// Get a saved token of a collection, with its token uri
+    async fn get_token(&self, collection_id: usize, token_id: &str) -> Result<Option<Token>>;
+
+    /// Save a token of a collection with its token uri, which replaces the saved one if it is saved already.
+    /// It returns the id of the token.
+    async fn put_token(&self, collection_id: usize, token_id: &str, token_uri: Option<String>) -> Result<usize>;
+
+    /// Get when the token uri of a token was fetched
+    async fn get_token_fetched_at(&self, token_db_id: usize) -> Result<Option<u64>>;
+
+    /// Save the token uri of a token and when it was fetched. It forgets the failed lookups, and that it was stale.
+    async fn update_token_uri(&self, token_db_id: usize, token_uri: Option<String>, fetched_at: u64) -> Result<()>;
+
+    /// Get how many times looking up the token uri of a token failed, and when it failed last.
+    /// None if no lookup failed since the last successful one.
+    async fn get_token_lookup_failures(&self, token_db_id: usize) -> Result<Option<(u32, u64)>>;
+
+    /// Record a failed lookup of the token uri of a token at `attempted_at`
+    async fn record_token_lookup_failure(&self, token_db_id: usize, attempted_at: u64) -> Result<()>;
+
+    /// Check if the token uri of a token is stale, so it has to be fetched again
+    async fn is_token_stale(&self, token_db_id: usize) -> Result<bool>;
+
+    /// Mark as stale the token uris of the saved tokens of a collection from `first` to `last`.
+    /// It returns how many tokens were marked.
+    async fn mark_tokens_stale(&self, collection_id: usize, first: &str, last: &str) -> Result<usize>;
+
+    /// Get the saved owner of a token of a chain, None if the token is burnt
+    async fn get_token_owner(&self, chain: &str, address: &str, token_id: &str) -> Result<Option<String>>;
+
+    /// Count the saved tokens of a collection held by `owner`
+    async fn count_tokens_of_owner(&self, chain: &str, address: &str, owner: &str) -> Result<u64>;
+
+    /// Save a transfer, once even if it is put again. It returns whether the transfer was saved.
+    async fn put_transfer(&self, transfer: &Transfer) -> Result<bool>;
+
+    /// Get the saved transfers of a token, in the order of the chain
+    async fn get_token_transfers(&self, collection_id: usize, token_id: &str) -> Result<Vec<Transfer>>;
+
+    /// Get the last block scanned by the tracker of a chain
+    async fn get_scan_progress(&self, chain: &str) -> Result<Option<u64>>;
+
+    /// Save the last block scanned by the tracker of a chain
+    async fn save_scan_progress(&self, chain: &str, block_number: u64) -> Result<()>;
+
+    /// Check if an event has been delivered already
+    async fn is_event_delivered(&self, chain: &str, transaction_hash: &str, log_index: u64) -> Result<bool>;
+
+    /// Record that an event has been delivered
+    async fn mark_event_delivered(
+        &self,
+        chain: &str,
+        transaction_hash: &str,
+        log_index: u64,
+        block_number: u64,
+    ) -> Result<()>;
+
+    /// Get the saved hashes of the scanned blocks of a chain before `block_number`, the latest first
+    async fn get_scanned_blocks_before(&self, chain: &str, block_number: u64) -> Result<Vec<(u64, String)>>;
+
+    /// Save the writes of a scanned range all at once, or none of them
+    async fn commit_range(&self, writes: &RangeWrites) -> Result<()>;
+
+    /// Forget the scanned blocks, the delivered events, the transfers and the owners of a chain saved from
+    /// `block_number` on, as after a reorg, and save `last_scanned_block` as its progress if it is some,
+    /// all at once or none of them
+    async fn rewind(&self, chain: &str, block_number: u64, last_scanned_block: Option<u64>) -> Result<()>;
+
+    /// Get the id of a saved ERC1155 contract
+    async fn get_erc1155_collection(&self, address: &str) -> Result<Option<usize>>;
+
+    /// Save a ERC1155 contract, it returns its id
+    async fn add_erc1155_collection(&self, address: &str) -> Result<usize>;
+
+    /// Get the token uri of a saved ERC1155 token, None if the token is not saved
+    async fn get_erc1155_token_uri(&self, collection_id: usize, token_id: &str) -> Result<Option<Option<String>>>;
+
+    /// Save a ERC1155 token with its token uri, it returns its id
+    async fn add_erc1155_token(&self, collection_id: usize, token_id: &str, token_uri: Option<String>) -> Result<usize>;
+}
+
+/// A store keeping everything in memory, lost when it is dropped, for the tests and the deployments which do not
+/// persist anything. The ids start from 1 as in the database.
+#[derive(Default)]
+pub struct MemoryStore {
+    state: Mutex<MemoryState>,
+}
+
+#[derive(Default)]
+struct MemoryState {
+    collections: Vec<MemoryCollection>,
+    /// The ids of the collections by chain and address
+    collection_ids: HashMap<(String, String), usize>,
+    tokens: Vec<MemoryToken>,
+    token_ids: HashMap<(usize, String), usize>,
+    /// The owners by chain, address and token id
+    owners: HashMap<(String, String, String), MemoryOwner>,
+    transfers: Vec<Transfer>,
+    /// The collection id, transaction hash, log index and token id of the saved transfers
+    transfer_keys: HashSet<(usize, String, u64, String)>,
+    scan_progress: HashMap<String, u64>,
+    /// The block of the delivered events, by chain, transaction hash and log index
+    delivered_events: HashMap<(String, String, u64), u64>,
+    /// The hashes of the scanned blocks by chain and block number
+    scanned_blocks: HashMap<String, BTreeMap<u64, String>>,
+    erc1155_collection_ids: HashMap<String, usize>,
+    /// The id and the token uri of the ERC1155 tokens by collection id and token id
+    erc1155_tokens: HashMap<(usize, String), (usize, Option<String>)>,
+}
+
+#[derive(Default)]
+struct MemoryCollection {
+    chain: String,
+    collection: Collection,
+    supports_erc721: Option<Option<bool>>,
+    code: Option<CollectionCode>,
+    creation_block: Option<u64>,
+    royalty: Option<(String, u64)>,
+    contract_uri: Option<String>,
+    implementation: Option<(Option<String>, u64)>,
+    snapshot: Option<(u64, u64)>,
+    lookup_failures: Option<(u32, u64)>,
+}
+
+struct MemoryToken {
+    token: Token,
+    fetched_at: Option<u64>,
+    lookup_failures: Option<(u32, u64)>,
+    stale: bool,
+}
+
+struct MemoryOwner {
+    owner: String,
+    burnt: bool,
+    block_number: u64,
+    log_index: u64,
+}
+
+/// How many scanned blocks are kept per chain, as in `erc721_db`
+const SCANNED_BLOCKS_KEPT: usize = 64;
+
+impl MemoryStore {
+    /// An empty store
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Run `f` on the collection `collection_id`, nothing happens if it is not saved
+    fn update_collection(&self, collection_id: usize, f: impl FnOnce(&mut MemoryCollection)) -> Result<()> {
+        let mut state = self.state.lock().unwrap();
+        if let Some(collection) = collection_id.checked_sub(1).and_then(|index| state.collections.get_mut(index)) {
+            f(collection);
+        }
+        Ok(())
+    }
+
+    /// Read the collection `collection_id`, None if it is not saved
+    fn read_collection<T>(
+        &self,
+        collection_id: usize,
+        f: impl FnOnce(&MemoryCollection) -> Option<T>,
+    ) -> Result<Option<T>> {
+        let state = self.state.lock().unwrap();
+        Ok(collection_id.checked_sub(1).and_then(|index| state.collections.get(index)).and_then(f))
+    }
+
+    /// Read the collection of a chain at `address`, None if it is not saved
+    fn read_collection_at<T>(
+        &self,
+        chain: &str,
+        address: &str,
+        f: impl FnOnce(&MemoryCollection) -> Option<T>,
+    ) -> Result<Option<T>> {
+        let state = self.state.lock().unwrap();
+        let key = (chain.to_owned(), address.to_owned());
+        Ok(state.collection_ids.get(&key).map(|id| &state.collections[id - 1]).and_then(f))
+    }
+
+    /// Run `f` on the token `token_db_id`, nothing happens if it is not saved
+    fn update_token(&self, token_db_id: usize, f: impl FnOnce(&mut MemoryToken)) -> Result<()> {
+        let mut state = self.state.lock().unwrap();
+        if let Some(token) = token_db_id.checked_sub(1).and_then(|index| state.tokens.get_mut(index)) {
+            f(token);
+        }
+        Ok(())
+    }
+
+    /// Read the token `token_db_id`, None if it is not saved
+    fn read_token<T>(&self, token_db_id: usize, f: impl FnOnce(&MemoryToken) -> Option<T>) -> Result<Option<T>> {
+        let state = self.state.lock().unwrap();
+        Ok(token_db_id.checked_sub(1).and_then(|index| state.tokens.get(index)).and_then(f))
+    }
+}
+
+impl MemoryState {
+    /// The id of a collection, saved first with `new_collection` if it is not saved yet
+    fn collection_id(
+        &mut self,
+        chain: &str,
+        address: &str,
+        new_collection: impl FnOnce() -> MemoryCollection,
+    ) -> usize {
+        let key = (chain.to_owned(), address.to_owned());
+        if let Some(id) = self.collection_ids.get(&key) {
+            return *id;
+        }
+        let id = self.collections.len() + 1;
+        let mut collection = new_collection();
+        collection.chain = chain.to_owned();
+        collection.collection = Collection {
+            id,
+            address: address.to_owned(),
+            name: None,
+            symbol: None,
+        };
+        self.collections.push(collection);
+        self.collection_ids.insert(key, id);
+        id
+    }
+
+    fn put_transfer(&mut self, transfer: &Transfer) -> bool {
+        let key = (transfer.collection_id, transfer.tx_hash.clone(), transfer.log_index, transfer.token_id.clone());
+        if !self.transfer_keys.insert(key) {
+            return false;
+        }
+        self.transfers.push(transfer.clone());
+        true
+    }
+
+    fn save_owner(&mut self, chain: &str, owner: &OwnerWrite) {
+        let key = (chain.to_owned(), owner.address.clone(), owner.token_id.clone());
+        if let Some(saved) = self.owners.get(&key) {
+            if (saved.block_number, saved.log_index) > (owner.block_number, owner.log_index) {
+                return;
+            }
+        }
+        let saved = MemoryOwner {
+            owner: owner.owner.clone(),
+            burnt: owner.burnt,
+            block_number: owner.block_number,
+            log_index: owner.log_index,
+        };
+        self.owners.insert(key, saved);
+    }
+
+    fn mark_event_delivered(&mut self, chain: &str, transaction_hash: &str, log_index: u64, block_number: u64) {
+        let key = (chain.to_owned(), transaction_hash.to_owned(), log_index);
+        self.delivered_events.entry(key).or_insert(block_number);
+    }
+
+    fn save_scanned_block(&mut self, chain: &str, block_number: u64, block_hash: &str) {
+        let scanned_blocks = self.scanned_blocks.entry(chain.to_owned()).or_default();
+        scanned_blocks.insert(block_number, block_hash.to_owned());
+        while scanned_blocks.len() > SCANNED_BLOCKS_KEPT {
+            let oldest = *scanned_blocks.keys().next().unwrap();
+            scanned_blocks.remove(&oldest);
+        }
+    }
+}
+
+/// The decimal token ids without leading zeros in the numeric order, by their length first
+fn token_id_order(token_id: &str) -> (usize, &str) {
+    (token_id.len(), token_id)
+}
+
+#[async_trait]
+impl NftStore for MemoryStore {
+    async fn get_collection(&self, chain: &str, address: &str) -> Result<Option<Collection>> {
+        self.read_collection_at(chain, address, |collection| Some(collection.collection.clone()))
+    }
+
+    async fn put_collection(&self, chain: &str, address: &str, name_symbol: Option<(String, String)>) -> Result<usize> {
+        let mut state = self.state.lock().unwrap();
+        let id = state.collection_id(chain, address, MemoryCollection::default);
+        if let Some((name, symbol)) = name_symbol {
+            let collection = &mut state.collections[id - 1].collection;
+            collection.name = Some(name);
+            collection.symbol = Some(symbol);
+        }
+        Ok(id)
+    }
+
+    async fn add_collection_with_erc721_support(
+        &self,
+        chain: &str,
+        address: &str,
+        supports_erc721: Option<bool>,
+    ) -> Result<usize> {
+        let mut state = self.state.lock().unwrap();
+        let id = state.collection_id(chain, address, || MemoryCollection {
+            lookup_failures: Some((0, 0)),
+            ..Default::default()
+        });
+        state.collections[id - 1].supports_erc721 = Some(supports_erc721);
+        Ok(id)
+    }
+
+    async fn get_collection_erc721_support(&self, collection_id: usize) -> Result<Option<Option<bool>>> {
+        self.read_collection(collection_id, |collection| collection.supports_erc721)
+    }
+
+    async fn save_collection_erc721_support(&self, collection_id: usize, supports_erc721: Option<bool>) -> Result<()> {
+        self.update_collection(collection_id, |collection| collection.supports_erc721 = Some(supports_erc721))
+    }
+
+    async fn get_collection_code(&self, collection_id: usize) -> Result<Option<CollectionCode>> {
+        self.read_collection(collection_id, |collection| collection.code)
+    }
+
+    async fn save_collection_code(&self, collection_id: usize, code: CollectionCode) -> Result<()> {
+        self.update_collection(collection_id, |collection| collection.code = Some(code))
+    }
+
+    async fn get_collection_creation_block(&self, chain: &str, address: &str) -> Result<Option<u64>> {
+        self.read_collection_at(chain, address, |collection| collection.creation_block)
+    }
+
+    async fn save_collection_creation_block(&self, collection_id: usize, block_number: u64) -> Result<()> {
+        self.update_collection(collection_id, |collection| collection.creation_block = Some(block_number))
+    }
+
+    async fn get_collection_royalty(&self, chain: &str, address: &str) -> Result<Option<(String, u64)>> {
+        self.read_collection_at(chain, address, |collection| collection.royalty.clone())
+    }
+
+    async fn save_collection_royalty(&self, collection_id: usize, royalty: Option<(&str, u64)>) -> Result<()> {
+        let royalty = royalty.map(|(receiver, bps)| (receiver.to_owned(), bps));
+        self.update_collection(collection_id, |collection| collection.royalty = royalty)
+    }
+
+    async fn get_collection_contract_uri(&self, chain: &str, address: &str) -> Result<Option<String>> {
+        self.read_collection_at(chain, address, |collection| collection.contract_uri.clone())
+    }
+
+    async fn save_collection_contract_uri(&self, collection_id: usize, contract_uri: Option<&str>) -> Result<()> {
+        let contract_uri = contract_uri.map(str::to_owned);
+        self.update_collection(collection_id, |collection| collection.contract_uri = contract_uri)
+    }
+
+    async fn get_collection_implementation(&self, chain: &str, address: &str) -> Result<Option<(Option<String>, u64)>> {
+        self.read_collection_at(chain, address, |collection| collection.implementation.clone())
+    }
+
+    async fn save_collection_implementation(
+        &self,
+        collection_id: usize,
+        implementation: Option<&str>,
+        block_number: u64,
+    ) -> Result<()> {
+        let implementation = (implementation.map(str::to_owned), block_number);
+        self.update_collection(collection_id, |collection| collection.implementation = Some(implementation))
+    }
+
+    async fn get_collection_snapshot(&self, chain: &str, address: &str) -> Result<Option<(u64, u64)>> {
+        self.read_collection_at(chain, address, |collection| collection.snapshot)
+    }
+
+    async fn save_collection_snapshot(&self, collection_id: usize, block_number: u64, index: u64) -> Result<()> {
+        self.update_collection(collection_id, |collection| collection.snapshot = Some((block_number, index)))
+    }
+
+    async fn update_collection_metadata(
+        &self,
+        collection_id: usize,
+        name: Option<String>,
+        symbol: Option<String>,
+    ) -> Result<()> {
+        self.update_collection(collection_id, |collection| {
+            collection.collection.name = name;
+            collection.collection.symbol = symbol;
+            collection.lookup_failures = None;
+        })
+    }
+
+    async fn get_collection_lookup_failures(&self, collection_id: usize) -> Result<Option<(u32, u64)>> {
+        self.read_collection(collection_id, |collection| collection.lookup_failures)
+    }
+
+    async fn record_collection_lookup_failure(&self, collection_id: usize, attempted_at: u64) -> Result<()> {
+        self.update_collection(collection_id, |collection| {
+            let attempts = collection.lookup_failures.map_or(0, |(attempts, _)| attempts);
+            collection.lookup_failures = Some((attempts + 1, attempted_at));
+        })
+    }
+
+    async fn get_token(&self, collection_id: usize, token_id: &str) -> Result<Option<Token>> {
+        let state = self.state.lock().unwrap();
+        let key = (collection_id, token_id.to_owned());
+        Ok(state.token_ids.get(&key).map(|id| state.tokens[id - 1].token.clone()))
+    }
+
+    async fn put_token(&self, collection_id: usize, token_id: &str, token_uri: Option<String>) -> Result<usize> {
+        let mut state = self.state.lock().unwrap();
+        let key = (collection_id, token_id.to_owned());
+        let id = match state.token_ids.get(&key) {
+            Some(id) => *id,
+            None => {
+                let id = state.tokens.len() + 1;
+                state.tokens.push(MemoryToken {
+                    token: Token {
+                        id,
+                        token_id: token_id.to_owned(),
+                        collection_id,
+                        token_uri: None,
+                    },
+                    fetched_at: None,
+                    lookup_failures: None,
+                    stale: false,
+                });
+                state.token_ids.insert(key, id);
+                id
+            }
+        };
+        if token_uri.is_some() {
+            state.tokens[id - 1].token.token_uri = token_uri;
+        }
+        Ok(id)
+    }
+
+    async fn get_token_fetched_at(&self, token_db_id: usize) -> Result<Option<u64>> {
+        self.read_token(token_db_id, |token| token.fetched_at)
+    }
+
+    async fn update_token_uri(&self, token_db_id: usize, token_uri: Option<String>, fetched_at: u64) -> Result<()> {
+        self.update_token(token_db_id, |token| {
+            token.token.token_uri = token_uri;
+            token.fetched_at = Some(fetched_at);
+            token.lookup_failures = None;
+            token.stale = false;
+        })
+    }
+
+    async fn get_token_lookup_failures(&self, token_db_id: usize) -> Result<Option<(u32, u64)>> {
+        self.read_token(token_db_id, |token| token.lookup_failures)
+    }
+
+    async fn record_token_lookup_failure(&self, token_db_id: usize, attempted_at: u64) -> Result<()> {
+        self.update_token(token_db_id, |token| {
+            let attempts = token.lookup_failures.map_or(0, |(attempts, _)| attempts);
+            token.lookup_failures = Some((attempts + 1, attempted_at));
+        })
+    }
+
+    async fn is_token_stale(&self, token_db_id: usize) -> Result<bool> {
+        Ok(self.read_token(token_db_id, |token| Some(token.stale))?.unwrap_or(false))
+    }
+
+    async fn mark_tokens_stale(&self, collection_id: usize, first: &str, last: &str) -> Result<usize> {
+        let mut state = self.state.lock().unwrap();
+        let mut marked = 0;
+        for token in state.tokens.iter_mut().filter(|token| token.token.collection_id == collection_id) {
+            let order = token_id_order(&token.token.token_id);
+            if token_id_order(first) <= order && order <= token_id_order(last) {
+                token.stale = true;
+                marked += 1;
+            }
+        }
+        Ok(marked)
+    }
+
+    async fn get_token_owner(&self, chain: &str, address: &str, token_id: &str) -> Result<Option<String>> {
+        let state = self.state.lock().unwrap();
+        let key = (chain.to_owned(), address.to_owned(), token_id.to_owned());
+        Ok(state.owners.get(&key).filter(|owner| !owner.burnt).map(|owner| owner.owner.clone()))
+    }
+
+    async fn count_tokens_of_owner(&self, chain: &str, address: &str, owner: &str) -> Result<u64> {
+        let state = self.state.lock().unwrap();
+        let count = state
+            .owners
+            .iter()
+            .filter(|((saved_chain, saved_address, _), saved)| {
+                saved_chain == chain && saved_address == address && saved.owner == owner && !saved.burnt
+            })
+            .count();
+        Ok(count as u64)
+    }
+
+    async fn put_transfer(&self, transfer: &Transfer) -> Result<bool> {
+        Ok(self.state.lock().unwrap().put_transfer(transfer))
+    }
+
+    async fn get_token_transfers(&self, collection_id: usize, token_id: &str) -> Result<Vec<Transfer>> {
+        let state = self.state.lock().unwrap();
+        let mut transfers: Vec<_> = state
+            .transfers
+            .iter()
+            .filter(|transfer| transfer.collection_id == collection_id && transfer.token_id == token_id)
+            .cloned()
+            .collect();
+        // stable, the transfers of a log stay in the order they were saved
+        transfers.sort_by_key(|transfer| (transfer.block_number, transfer.log_index));
+        Ok(transfers)
+    }
+
+    async fn get_scan_progress(&self, chain: &str) -> Result<Option<u64>> {
+        Ok(self.state.lock().unwrap().scan_progress.get(chain).copied())
+    }
+
+    async fn save_scan_progress(&self, chain: &str, block_number: u64) -> Result<()> {
+        self.state.lock().unwrap().scan_progress.insert(chain.to_owned(), block_number);
+        Ok(())
+    }
+
+    async fn is_event_delivered(&self, chain: &str, transaction_hash: &str, log_index: u64) -> Result<bool> {
+        let key = (chain.to_owned(), transaction_hash.to_owned(), log_index);
+        Ok(self.state.lock().unwrap().delivered_events.contains_key(&key))
+    }
+
+    async fn mark_event_delivered(
+        &self,
+        chain: &str,
+        transaction_hash: &str,
+        log_index: u64,
+        block_number: u64,
+    ) -> Result<()> {
+        let mut state = self.state.lock().unwrap();
+        state.mark_event_delivered(chain, transaction_hash, log_index, block_number);
+        Ok(())
+    }
+
+    async fn get_scanned_blocks_before(&self, chain: &str, block_number: u64) -> Result<Vec<(u64, String)>> {
+        let state = self.state.lock().unwrap();
+        let scanned_blocks = match state.scanned_blocks.get(chain) {
+            Some(scanned_blocks) => scanned_blocks,
+            None => return Ok(vec![]),
+        };
+        Ok(scanned_blocks
+            .range(..block_number)
+            .rev()
+            .map(|(block_number, block_hash)| (*block_number, block_hash.clone()))
+            .collect())
+    }
+
+    async fn commit_range(&self, writes: &RangeWrites) -> Result<()> {
+        let mut state = self.state.lock().unwrap();
+        let chain = writes.chain.as_str();
+        for owner in &writes.owners {
+            state.save_owner(chain, owner);
+        }
+        for transfer in &writes.transfers {
+            let collection_id = state.collection_id(chain, &transfer.address, || MemoryCollection {
+                lookup_failures: Some((0, 0)),
+                ..Default::default()
+            });
+            state.put_transfer(&transfer.transfer(collection_id));
+        }
+        for (transaction_hash, log_index, block_number) in &writes.delivered_events {
+            state.mark_event_delivered(chain, transaction_hash, *log_index, *block_number);
+        }
+        if let Some(block_number) = writes.last_scanned_block {
+            state.scan_progress.insert(chain.to_owned(), block_number);
+        }
+        for (block_number, block_hash) in &writes.scanned_blocks {
+            state.save_scanned_block(chain, *block_number, block_hash);
+        }
+        Ok(())
+    }
+
+    async fn rewind(&self, chain: &str, block_number: u64, last_scanned_block: Option<u64>) -> Result<()> {
+        let mut state = self.state.lock().unwrap();
+        if let Some(scanned_blocks) = state.scanned_blocks.get_mut(chain) {
+            scanned_blocks.split_off(&block_number);
+        }
+        state
+            .delivered_events
+            .retain(|(saved_chain, _, _), saved_block| saved_chain != chain || *saved_block < block_number);
+        let MemoryState {
+            collections,
+            transfers,
+            transfer_keys,
+            ..
+        } = &mut *state;
+        transfers.retain(|transfer| {
+            let orphaned = transfer.block_number >= block_number
+                && transfer.collection_id.checked_sub(1).and_then(|index| collections.get(index))
+                    .map_or(false, |collection| collection.chain == chain);
+            if orphaned {
+                let token_id = transfer.token_id.clone();
+                let key = (transfer.collection_id, transfer.tx_hash.clone(), transfer.log_index, token_id);
+                transfer_keys.remove(&key);
+            }
+            !orphaned
+        });
+        state
+            .owners
+            .retain(|(saved_chain, _, _), owner| saved_chain != chain || owner.block_number < block_number);
+        if let Some(last_scanned_block) = last_scanned_block {
+            state.scan_progress.insert(chain.to_owned(), last_scanned_block);
+        }
+        Ok(())
+    }
+
+    async fn get_erc1155_collection(&self, address: &str) -> Result<Option<usize>> {
+        Ok(self.state.lock().unwrap().erc1155_collection_ids.get(address).copied())
+    }
+
+    async fn add_erc1155_collection(&self, address: &str) -> Result<usize> {
+        let mut state = self.state.lock().unwrap();
+        let id = state.erc1155_collection_ids.len() + 1;
+        Ok(*state.erc1155_collection_ids.entry(address.to_owned()).or_insert(id))
+    }
+
+    async fn get_erc1155_token_uri(&self, collection_id: usize, token_id: &str) -> Result<Option<Option<String>>> {
+        let state = self.state.lock().unwrap();
+        let key = (collection_id, token_id.to_owned());
+        Ok(state.erc1155_tokens.get(&key).map(|(_, token_uri)| token_uri.clone()))
+    }
+
+    async fn add_erc1155_token(
+        &self,
+        collection_id: usize,
+        token_id: &str,
+        token_uri: Option<String>,
+    ) -> Result<usize> {
+        let mut state = self.state.lock().unwrap();
+        let id = state.erc1155_tokens.len() + 1;
+        let key = (collection_id, token_id.to_owned());
+        Ok(state.erc1155_tokens.entry(key).or_insert((id, token_uri)).0)
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    /// Save some collections, tokens, transfers, owners, progress, delivered events and scanned blocks to `store`,
+    /// which is empty
+    pub(crate) async fn check_store(store: &dyn NftStore) {
+        // 1
+        let address = "0xC5c1C9c3cEA2f4A68E540b18e63310310FD8af57";
+
+        assert_eq!(None, store.get_collection("Ethereum", address).await.unwrap());
+
+        store.put_collection("Ethereum", address, None).await.unwrap();
+        let collection = Collection {
+            id: 1,
+            address: address.to_string(),
+            name: None,
+            symbol: None,
+        };
+        assert_eq!(Some(collection), store.get_collection("Ethereum", address).await.unwrap());
+
+        // 2
+        let address = "0xa7d8d9ef8d8ce8992df33d8b8cf4aebabd5bd270";
+
+        assert_eq!(None, store.get_collection("Ethereum", address).await.unwrap());
+
+        let name_symbol = Some(("Art Blocks".to_owned(), "BLOCKS".to_owned()));
+        let collection_id = store.put_collection("Ethereum", address, name_symbol.clone()).await.unwrap();
+        let collection = Collection {
+            id: 2,
+            address: address.to_string(),
+            name: Some("Art Blocks".to_owned()),
+            symbol: Some("BLOCKS".to_owned()),
+        };
+        assert_eq!(Some(collection), store.get_collection("Ethereum", address).await.unwrap());
+        // saved already, the name and the symbol are kept
+        assert_eq!(collection_id, store.put_collection("Ethereum", address, None).await.unwrap());
+        assert_eq!(name_symbol, store.get_collection("Ethereum", address).await.unwrap().unwrap().name_symbol());
+
+        let token_db_id = store.put_token(collection_id, "129000030", None).await.unwrap();
+        let token_uri = Some("https://api.artblocks.io/token/129000030".to_owned());
+        assert_eq!(token_db_id, store.put_token(collection_id, "129000030", token_uri.clone()).await.unwrap());
+        let token = store.get_token(collection_id, "129000030").await.unwrap().unwrap();
+        assert_eq!((token_db_id, token_uri), (token.id, token.token_uri));
+        assert_eq!(None, store.get_token(collection_id, "1").await.unwrap());
+
+        let transfer = |block_number: u64, log_index: u64| Transfer {
+            collection_id,
+            token_id: "129000030".to_owned(),
+            from: "0x0000000000000000000000000000000000000000".to_owned(),
+            to: "0x0000000000000000000000000000000000000002".to_owned(),
+            block_number,
+            tx_hash: format!("0x{:064x}", block_number),
+            log_index,
+            timestamp: None,
+        };
+        assert!(store.put_transfer(&transfer(11, 0)).await.unwrap());
+        assert!(store.put_transfer(&transfer(10, 3)).await.unwrap());
+        assert!(!store.put_transfer(&transfer(10, 3)).await.unwrap());
+        assert_eq!(
+            vec![transfer(10, 3), transfer(11, 0)],
+            store.get_token_transfers(collection_id, "129000030").await.unwrap()
+        );
+        assert!(store.get_token_transfers(collection_id, "1").await.unwrap().is_empty());
+
+        // the same contract address on another chain is another collection, with its own transfers
+        assert_eq!(None, store.get_collection("Pangolin", address).await.unwrap());
+        let other_id = store.put_collection("Pangolin", address, None).await.unwrap();
+        assert_eq!(3, other_id);
+        assert_eq!(name_symbol, store.get_collection("Ethereum", address).await.unwrap().unwrap().name_symbol());
+        let other_transfer = Transfer {
+            collection_id: other_id,
+            ..transfer(10, 3)
+        };
+        assert!(store.put_transfer(&other_transfer).await.unwrap());
+        assert_eq!(vec![other_transfer], store.get_token_transfers(other_id, "129000030").await.unwrap());
+        assert_eq!(2, store.get_token_transfers(collection_id, "129000030").await.unwrap().len());
+
+        assert_eq!(None, store.get_scan_progress("Ethereum").await.unwrap());
+        store.save_scan_progress("Ethereum", 100).await.unwrap();
+        assert_eq!(Some(100), store.get_scan_progress("Ethereum").await.unwrap());
+
+        assert!(!store.is_event_delivered("Ethereum", "0x01", 0).await.unwrap());
+        store.mark_event_delivered("Ethereum", "0x01", 0, 100).await.unwrap();
+        assert!(store.is_event_delivered("Ethereum", "0x01", 0).await.unwrap());
+        assert!(!store.is_event_delivered("Pangolin", "0x01", 0).await.unwrap());
+
+        // the metadata lookups
+        let id = store.add_collection_with_erc721_support("Ethereum", address, Some(true)).await.unwrap();
+        assert_eq!(collection_id, id);
+        assert_eq!(Some(Some(true)), store.get_collection_erc721_support(id).await.unwrap());
+        store.save_collection_erc721_support(id, None).await.unwrap();
+        assert_eq!(Some(None), store.get_collection_erc721_support(id).await.unwrap());
+        assert_eq!(None, store.get_collection_erc721_support(other_id).await.unwrap());
+        assert_eq!(None, store.get_collection_code(id).await.unwrap());
+        store.save_collection_code(id, CollectionCode::Removed).await.unwrap();
+        assert_eq!(Some(CollectionCode::Removed), store.get_collection_code(id).await.unwrap());
+        store.save_collection_creation_block(id, 8).await.unwrap();
+        assert_eq!(Some(8), store.get_collection_creation_block("Ethereum", address).await.unwrap());
+        assert_eq!(None, store.get_collection_creation_block("Pangolin", address).await.unwrap());
+        store.save_collection_royalty(id, Some(("0x0000000000000000000000000000000000000003", 750))).await.unwrap();
+        let royalty = Some(("0x0000000000000000000000000000000000000003".to_owned(), 750));
+        assert_eq!(royalty, store.get_collection_royalty("Ethereum", address).await.unwrap());
+        store.save_collection_royalty(id, None).await.unwrap();
+        assert_eq!(None, store.get_collection_royalty("Ethereum", address).await.unwrap());
+        store.save_collection_contract_uri(id, Some("ipfs://contract")).await.unwrap();
+        let contract_uri = store.get_collection_contract_uri("Ethereum", address).await.unwrap();
+        assert_eq!(Some("ipfs://contract".to_owned()), contract_uri);
+        store.save_collection_implementation(id, None, 9).await.unwrap();
+        assert_eq!(Some((None, 9)), store.get_collection_implementation("Ethereum", address).await.unwrap());
+        store.save_collection_snapshot(id, 9, 2).await.unwrap();
+        assert_eq!(Some((9, 2)), store.get_collection_snapshot("Ethereum", address).await.unwrap());
+
+        let new_id = store.add_collection_with_erc721_support("Ethereum", "0x01", None).await.unwrap();
+        assert_eq!(Some((0, 0)), store.get_collection_lookup_failures(new_id).await.unwrap());
+        store.record_collection_lookup_failure(new_id, 1000).await.unwrap();
+        assert_eq!(Some((1, 1000)), store.get_collection_lookup_failures(new_id).await.unwrap());
+        store.update_collection_metadata(new_id, Some("A".to_owned()), Some("B".to_owned())).await.unwrap();
+        assert_eq!(None, store.get_collection_lookup_failures(new_id).await.unwrap());
+        let collection = store.get_collection("Ethereum", "0x01").await.unwrap().unwrap();
+        assert_eq!(Some(("A".to_owned(), "B".to_owned())), collection.name_symbol());
+
+        assert_eq!(None, store.get_token_fetched_at(token_db_id).await.unwrap());
+        store.record_token_lookup_failure(token_db_id, 1000).await.unwrap();
+        store.record_token_lookup_failure(token_db_id, 1010).await.unwrap();
+        assert_eq!(Some((2, 1010)), store.get_token_lookup_failures(token_db_id).await.unwrap());
+        for token_id in &["9", "10", "99", "100"] {
+            store.put_token(collection_id, token_id, None).await.unwrap();
+        }
+        // 10 and 99, and not 100 nor 129000030 which are greater, nor 9 which is smaller
+        assert_eq!(2, store.mark_tokens_stale(collection_id, "10", "99").await.unwrap());
+        let token = store.get_token(collection_id, "99").await.unwrap().unwrap();
+        assert!(store.is_token_stale(token.id).await.unwrap());
+        assert!(!store.is_token_stale(token_db_id).await.unwrap());
+        store.update_token_uri(token.id, Some("ipfs://99".to_owned()), 1020).await.unwrap();
+        assert!(!store.is_token_stale(token.id).await.unwrap());
+        assert_eq!(Some(1020), store.get_token_fetched_at(token.id).await.unwrap());
+        let token = store.get_token(collection_id, "99").await.unwrap().unwrap();
+        assert_eq!(Some("ipfs://99".to_owned()), token.token_uri);
+
+        // the writes of the scanned ranges
+        let owner = |token_id: &str, owner: &str, block_number: u64, log_index: u64| OwnerWrite {
+            address: address.to_owned(),
+            token_id: token_id.to_owned(),
+            owner: owner.to_owned(),
+            burnt: owner == "0x0000000000000000000000000000000000000000",
+            block_number,
+            log_index,
+        };
+        let transfer_write = |token_id: &str, block_number: u64| TransferWrite {
+            address: "0x02".to_owned(),
+            token_id: token_id.to_owned(),
+            from: "0x0000000000000000000000000000000000000000".to_owned(),
+            to: "0x0000000000000000000000000000000000000002".to_owned(),
+            block_number,
+            tx_hash: format!("0x{:064x}", block_number),
+            log_index: 0,
+            timestamp: Some(1000 + block_number),
+        };
+        let writes = RangeWrites {
+            chain: "Ethereum".to_owned(),
+            owners: vec![owner("1", "0x02", 110, 0), owner("1", "0x03", 110, 1), owner("2", "0x03", 120, 0)],
+            transfers: vec![transfer_write("1", 110), transfer_write("2", 120)],
+            delivered_events: vec![("0x110".to_owned(), 0, 110), ("0x120".to_owned(), 0, 120)],
+            last_scanned_block: Some(120),
+            scanned_blocks: vec![(110, "0x110".to_owned()), (120, "0x120".to_owned())],
+        };
+        store.commit_range(&writes).await.unwrap();
+        assert_eq!(Some("0x03".to_owned()), store.get_token_owner("Ethereum", address, "1").await.unwrap());
+        assert_eq!(None, store.get_token_owner("Pangolin", address, "1").await.unwrap());
+        assert_eq!(2, store.count_tokens_of_owner("Ethereum", address, "0x03").await.unwrap());
+        assert_eq!(Some(120), store.get_scan_progress("Ethereum").await.unwrap());
+        assert!(store.is_event_delivered("Ethereum", "0x120", 0).await.unwrap());
+        // the collection of the transfers is saved, its metadata lookup is due
+        let transfers_collection = store.get_collection("Ethereum", "0x02").await.unwrap().unwrap();
+        assert_eq!(Some((0, 0)), store.get_collection_lookup_failures(transfers_collection.id).await.unwrap());
+        let transfers = store.get_token_transfers(transfers_collection.id, "2").await.unwrap();
+        assert_eq!(vec![transfer_write("2", 120).transfer(transfers_collection.id)], transfers);
+        let blocks = vec![(120, "0x120".to_owned()), (110, "0x110".to_owned())];
+        assert_eq!(blocks, store.get_scanned_blocks_before("Ethereum", 121).await.unwrap());
+        assert_eq!(blocks[1..], store.get_scanned_blocks_before("Ethereum", 120).await.unwrap()[..]);
+
+        // scanned again, the older transfers change nothing
+        let again = RangeWrites {
+            chain: "Ethereum".to_owned(),
+            owners: vec![owner("1", "0x02", 110, 0), owner("2", "0x0000000000000000000000000000000000000000", 130, 0)],
+            last_scanned_block: None,
+            ..writes.clone()
+        };
+        store.commit_range(&again).await.unwrap();
+        assert_eq!(Some("0x03".to_owned()), store.get_token_owner("Ethereum", address, "1").await.unwrap());
+        // burnt
+        assert_eq!(None, store.get_token_owner("Ethereum", address, "2").await.unwrap());
+        assert_eq!(1, store.count_tokens_of_owner("Ethereum", address, "0x03").await.unwrap());
+        assert_eq!(1, store.get_token_transfers(transfers_collection.id, "2").await.unwrap().len());
+        assert_eq!(Some(120), store.get_scan_progress("Ethereum").await.unwrap());
+
+        // a reorg from the block 120
+        store.rewind("Ethereum", 120, Some(119)).await.unwrap();
+        assert_eq!(Some(119), store.get_scan_progress("Ethereum").await.unwrap());
+        assert_eq!(blocks[1..], store.get_scanned_blocks_before("Ethereum", 200).await.unwrap()[..]);
+        assert!(!store.is_event_delivered("Ethereum", "0x120", 0).await.unwrap());
+        assert!(store.is_event_delivered("Ethereum", "0x110", 0).await.unwrap());
+        assert!(store.get_token_transfers(transfers_collection.id, "2").await.unwrap().is_empty());
+        assert_eq!(1, store.get_token_transfers(transfers_collection.id, "1").await.unwrap().len());
+        assert_eq!(Some("0x03".to_owned()), store.get_token_owner("Ethereum", address, "1").await.unwrap());
+        assert_eq!(1, store.count_tokens_of_owner("Ethereum", address, "0x03").await.unwrap());
+        // the transfers of the other chains are kept
+        assert_eq!(1, store.get_token_transfers(other_id, "129000030").await.unwrap().len());
+        // the transfers scanned again after the reorg are saved again
+        store.commit_range(&writes).await.unwrap();
+        assert_eq!(1, store.get_token_transfers(transfers_collection.id, "2").await.unwrap().len());
+        store.rewind("Ethereum", 120, None).await.unwrap();
+        assert_eq!(Some(120), store.get_scan_progress("Ethereum").await.unwrap());
+
+        // the ERC1155 contracts
+        assert_eq!(None, store.get_erc1155_collection(address).await.unwrap());
+        let erc1155_id = store.add_erc1155_collection(address).await.unwrap();
+        assert_eq!(Some(erc1155_id), store.get_erc1155_collection(address).await.unwrap());
+        assert_eq!(None, store.get_erc1155_token_uri(erc1155_id, "1").await.unwrap());
+        store.add_erc1155_token(erc1155_id, "1", None).await.unwrap();
+        store.add_erc1155_token(erc1155_id, "2", Some("ipfs://2".to_owned())).await.unwrap();
+        assert_eq!(Some(None), store.get_erc1155_token_uri(erc1155_id, "1").await.unwrap());
+        assert_eq!(Some(Some("ipfs://2".to_owned())), store.get_erc1155_token_uri(erc1155_id, "2").await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_memory_store() {
+        check_store(&MemoryStore::new()).await;
+    }
+}
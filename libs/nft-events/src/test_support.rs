@@ -0,0 +1,1386 @@
+//! This module contains a mock EVM client used by the tests to drive the trackers offline, and a store failing
+//! some commits.
+use crate::erc721_db::{Collection, CollectionCode, Token, Transfer};
+use crate::{
+    Error, EvmClientApi, HeadStream, NftStore, RangeWrites, Result, SqliteStore, ERC721_ENUMERABLE_INTERFACE_ID,
+};
+use array_bytes::hex2bytes_unchecked as bytes;
+use futures::{future::BoxFuture, stream, StreamExt};
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::mpsc;
+use web3::types::{Bytes, Log, H160, H256, U256, U64};
+use web3::{helpers, rpc, BatchTransport, RequestId, Transport};
+
+/// The topic of the ERC721 `Transfer` event
+pub const TRANSFER_TOPIC: &str =
+    "0xddf252ad1be2c89b69c2b068fc378daa952ba7f163c4a11628f55a4df523b3ef";
+
+/// The topic of the ERC4906 `MetadataUpdate` event
+pub const METADATA_UPDATE_TOPIC: &str =
+    "0xf8e1a15aba9398e019f0b49df1a4fde98ee17ae345cb5f6b5e2c27f5033e8ce7";
+
+/// The topic of the ERC4906 `BatchMetadataUpdate` event
+pub const BATCH_METADATA_UPDATE_TOPIC: &str =
+    "0x6bd5c950a8d8df17f772f5af37cb3655737899cbf903264b9795592da439661c";
+
+/// The topic of the ERC721 and ERC20 `Approval` event
+pub const APPROVAL_TOPIC: &str =
+    "0x8c5be1e5ebec7d5bd14f71427d1e84f3dd0314c0f7b2291e5b200ac8c7c3b925";
+
+/// The topic of the ERC721 and ERC1155 `ApprovalForAll` event
+pub const APPROVAL_FOR_ALL_TOPIC: &str =
+    "0x17307eab39ab6107e8899845ad3d59bd9653f200f220920489ca2b5937696c31";
+
+/// The topic of the ERC1155 `TransferSingle` event
+pub const TRANSFER_SINGLE_TOPIC: &str =
+    "0xc3d58168c5ae7397731d063d5bbf3d657854427343f4c083240f7aacaa2d0f62";
+
+#[derive(Default, Clone)]
+struct MockCollection {
+    name_symbol: Option<(String, String)>,
+    token_uris: HashMap<U256, String>,
+    total_supply: Option<u128>,
+    /// The tokens enumerated by `tokenByIndex`
+    tokens_by_index: Vec<U256>,
+}
+
+/// A scripted EVM client serving canned blocks, logs and metadata from memory.
+#[derive(Default)]
+pub struct MockEvmClient {
+    chain_name: String,
+    latest_block_numbers: Mutex<VecDeque<u64>>,
+    logs: Vec<Log>,
+    /// The logs of the blocks replaced by `reorg`, served until then
+    orphaned_logs: Vec<Log>,
+    get_logs_errors: Mutex<VecDeque<Error>>,
+    name_symbol_errors: Mutex<VecDeque<Error>>,
+    token_uri_errors: Mutex<VecDeque<Error>>,
+    contract_uri_errors: Mutex<VecDeque<Error>>,
+    /// Whether the metadata lookups are batched, as with a Multicall3 contract
+    multicall: bool,
+    get_logs_delay: Duration,
+    max_logs_per_request: Option<usize>,
+    /// The requests for logs in flight, and the most there ever were at once
+    get_logs_in_flight: Mutex<(usize, usize)>,
+    token_uri_delay: Duration,
+    name_symbol_delay: Duration,
+    /// The requests for token uris in flight, and the most there ever were at once
+    token_uri_in_flight: Mutex<(usize, usize)>,
+    /// Run on each request for an ERC721 token uri before it is answered
+    token_uri_hook: Option<Box<dyn Fn(&H160, &U256) + Send + Sync>>,
+    erc721_collections: HashMap<H160, MockCollection>,
+    /// The owners of the ERC721 tokens, the other tokens do not exist
+    erc721_owners: HashMap<(H160, U256), H160>,
+    /// The `balanceOf` of the accounts by contract, the other contracts have no `balanceOf`
+    erc721_balances: HashMap<(H160, H160), u64>,
+    /// The blocks of the `balanceOf` calls, in order
+    balance_blocks: Mutex<Vec<Option<u64>>>,
+    /// The ERC165 answers of the contracts, the others do not implement ERC165
+    interfaces: HashMap<(H160, [u8; 4]), bool>,
+    /// The successive EIP-1967 implementations of the proxies, the last one is kept forever
+    proxy_implementations: Mutex<HashMap<H160, VecDeque<H160>>>,
+    erc1155_token_uris: HashMap<H160, HashMap<U256, String>>,
+    /// The successive token uris of the ERC721 tokens whose metadata changes
+    changing_token_uris: Mutex<HashMap<(H160, U256), VecDeque<String>>>,
+    /// The timestamp of the first block, and the seconds between two blocks
+    block_time: (u64, u64),
+    /// The ERC2981 royalty receivers of the contracts, and their royalties in basis points
+    royalties: HashMap<H160, (H160, u64)>,
+    /// The collection-level metadata uris of the contracts, the others have no `contractURI`
+    contract_uris: HashMap<H160, String>,
+    /// The senders of the transactions of each block, by transaction hash
+    transaction_senders: HashMap<u64, HashMap<H256, H160>>,
+    /// The logs of the receipts of the transactions, by transaction hash
+    receipt_logs: HashMap<H256, Vec<Log>>,
+    /// The addresses without code for `is_contract`, the others are contracts
+    without_code: Mutex<HashSet<H160>>,
+    /// The blocks where the contracts were created
+    creation_blocks: HashMap<H160, u64>,
+    reorged_from: Mutex<Option<u64>>,
+    /// The sending side of the subscription to the new blocks
+    head_sender: Mutex<Option<mpsc::UnboundedSender<Result<u64>>>>,
+    /// The subscription to the new blocks, until a tracker takes it
+    heads: Mutex<Option<HeadStream>>,
+    /// The chain id of the node, None if it does not support `eth_chainId`
+    chain_id: Option<u64>,
+    expected_chain_id: Option<u64>,
+    calls: Mutex<HashMap<&'static str, usize>>,
+    scanned_ranges: Mutex<Vec<(u64, u64)>>,
+    /// The blocks the name, symbol and token uri lookups were pinned to, in order
+    metadata_blocks: Mutex<Vec<Option<u64>>>,
+}
+
+impl MockEvmClient {
+    /// Create a mock client whose latest block is always `latest_block_number`
+    pub fn new(chain_name: &str, latest_block_number: u64) -> MockEvmClient {
+        MockEvmClient {
+            chain_name: chain_name.to_owned(),
+            latest_block_numbers: Mutex::new(vec![latest_block_number].into()),
+            ..Default::default()
+        }
+    }
+
+    /// Return these latest block numbers one by one, the last one is kept forever
+    pub fn with_latest_block_numbers(mut self, numbers: Vec<u64>) -> Self {
+        self.latest_block_numbers = Mutex::new(numbers.into());
+        self
+    }
+
+    /// Register a visual ERC721 collection
+    pub fn with_erc721_collection(mut self, address: H160, name: &str, symbol: &str) -> Self {
+        self.erc721_collections.insert(
+            address,
+            MockCollection {
+                name_symbol: Some((name.to_owned(), symbol.to_owned())),
+                ..Default::default()
+            },
+        );
+        self
+    }
+
+    /// Register the token uri of an ERC721 token
+    pub fn with_erc721_token_uri(mut self, address: H160, token_id: u64, token_uri: &str) -> Self {
+        self.erc721_collections
+            .entry(address)
+            .or_default()
+            .token_uris
+            .insert(U256::from(token_id), token_uri.to_owned());
+        self
+    }
+
+    /// Make `owner` hold `balance` tokens of an ERC721 contract, at every block
+    pub fn with_erc721_balance(mut self, address: H160, owner: H160, balance: u64) -> Self {
+        self.erc721_balances.insert((address, owner), balance);
+        self
+    }
+
+    /// The blocks of the `balanceOf` calls, in order
+    pub fn balance_blocks(&self) -> Vec<Option<u64>> {
+        self.balance_blocks.lock().unwrap().clone()
+    }
+
+    /// Give an ERC721 token an owner, at every block
+    pub fn with_erc721_owner(mut self, address: H160, token_id: u64, owner: H160) -> Self {
+        self.erc721_owners.insert((address, U256::from(token_id)), owner);
+        self
+    }
+
+    /// Make `address` an address without code, as an externally owned account
+    pub fn without_code(self, address: H160) -> Self {
+        self.remove_code(address);
+        self
+    }
+
+    /// Remove the code of a contract, as a selfdestruct does
+    pub fn remove_code(&self, address: H160) {
+        self.without_code.lock().unwrap().insert(address);
+    }
+
+    /// Make `address` answer whether it supports `interface_id` with ERC165
+    pub fn with_interface(mut self, address: H160, interface_id: [u8; 4], supported: bool) -> Self {
+        self.interfaces.insert((address, interface_id), supported);
+        self
+    }
+
+    /// Make `address` an EIP-1967 proxy of these implementations one by one, one per lookup.
+    /// The proxy answers ERC165 as its current implementation does.
+    pub fn with_proxy_implementations(self, address: H160, implementations: Vec<H160>) -> Self {
+        self.proxy_implementations.lock().unwrap().insert(address, implementations.into());
+        self
+    }
+
+    /// Make an ERC721 collection enumerable, with this total supply at every block
+    pub fn with_erc721_total_supply(mut self, address: H160, total_supply: u128) -> Self {
+        self.erc721_collections.entry(address).or_default().total_supply = Some(total_supply);
+        self
+    }
+
+    /// Make an ERC721 collection enumerable with ERC165, `tokenByIndex` and these tokens at every block
+    pub fn with_erc721_tokens_by_index(mut self, address: H160, token_ids: &[u64]) -> Self {
+        let collection = self.erc721_collections.entry(address).or_default();
+        collection.total_supply = Some(token_ids.len() as u128);
+        collection.tokens_by_index = token_ids.iter().map(|token_id| U256::from(*token_id)).collect();
+        self.with_interface(address, ERC721_ENUMERABLE_INTERFACE_ID, true)
+    }
+
+    /// Return these token uris one by one for an ERC721 token, the last one is kept forever
+    pub fn with_erc721_token_uris(self, address: H160, token_id: u64, token_uris: Vec<&str>) -> Self {
+        self.changing_token_uris.lock().unwrap().insert(
+            (address, U256::from(token_id)),
+            token_uris.into_iter().map(|token_uri| token_uri.to_owned()).collect(),
+        );
+        self
+    }
+
+    /// Register the uri of an ERC1155 token, making its contract a visual ERC1155 contract
+    pub fn with_erc1155_token_uri(mut self, address: H160, token_id: u64, token_uri: &str) -> Self {
+        self.erc1155_token_uris
+            .entry(address)
+            .or_default()
+            .insert(U256::from(token_id), token_uri.to_owned());
+        self
+    }
+
+    /// Produce a block every `seconds_per_block` from the first block at `genesis_timestamp`
+    pub fn with_block_time(mut self, genesis_timestamp: u64, seconds_per_block: u64) -> Self {
+        self.block_time = (genesis_timestamp, seconds_per_block);
+        self
+    }
+
+    /// Answer `eth_chainId` with `chain_id`, the node does not support it with None
+    pub fn with_chain_id(mut self, chain_id: Option<u64>) -> Self {
+        self.chain_id = chain_id;
+        self
+    }
+
+    /// Expect the node to be for the chain `chain_id`, as `EvmClient::with_expected_chain_id`
+    pub fn with_expected_chain_id(mut self, chain_id: u64) -> Self {
+        self.expected_chain_id = Some(chain_id);
+        self
+    }
+
+    /// Give `address` a collection-level metadata uri with `contractURI`
+    pub fn with_contract_uri(mut self, address: H160, contract_uri: &str) -> Self {
+        self.contract_uris.insert(address, contract_uri.to_owned());
+        self
+    }
+
+    /// Make `address` pay `bps` basis points of its sales to `receiver` with ERC2981
+    pub fn with_royalty(mut self, address: H160, receiver: H160, bps: u64) -> Self {
+        self.royalties.insert(address, (receiver, bps));
+        self
+    }
+
+    /// Make `sender` the sender of the transaction `transaction_hash` of the block `block_number`
+    pub fn with_transaction_sender(mut self, block_number: u64, transaction_hash: H256, sender: H160) -> Self {
+        self.transaction_senders
+            .entry(block_number)
+            .or_default()
+            .insert(transaction_hash, sender);
+        self
+    }
+
+    /// Make `logs` the logs of the receipt of the transaction `transaction_hash`
+    pub fn with_receipt_logs(mut self, transaction_hash: H256, logs: Vec<Log>) -> Self {
+        self.receipt_logs.insert(transaction_hash, logs);
+        self
+    }
+
+    /// Give `address` some code from `creation_block`
+    pub fn with_contract_created_at(mut self, address: H160, creation_block: u64) -> Self {
+        self.creation_blocks.insert(address, creation_block);
+        self
+    }
+
+    /// Add a log served by `get_logs`
+    pub fn with_log(mut self, log: Log) -> Self {
+        self.logs.push(log);
+        self
+    }
+
+    /// Add a log served by `get_logs` until `reorg` replaces its block
+    pub fn with_orphaned_log(mut self, log: Log) -> Self {
+        self.orphaned_logs.push(log);
+        self
+    }
+
+    /// Make the next request for logs fail with `err`
+    pub fn fail_next_get_logs(self, err: Error) -> Self {
+        self.get_logs_errors.lock().unwrap().push_back(err);
+        self
+    }
+
+    /// Make the next request for the name and symbol of an ERC721 collection fail with `err`
+    pub fn fail_next_erc721_name_symbol(self, err: Error) -> Self {
+        self.name_symbol_errors.lock().unwrap().push_back(err);
+        self
+    }
+
+    /// Make the next request for the token uri of an ERC721 token fail with `err`
+    pub fn fail_next_erc721_token_uri(self, err: Error) -> Self {
+        self.token_uri_errors.lock().unwrap().push_back(err);
+        self
+    }
+
+    /// Make the next request for the collection-level metadata uri of a contract fail with `err`
+    pub fn fail_next_contract_uri(self, err: Error) -> Self {
+        self.contract_uri_errors.lock().unwrap().push_back(err);
+        self
+    }
+
+    /// Batch the metadata lookups, as a client with a Multicall3 contract does
+    pub fn with_multicall(mut self) -> Self {
+        self.multicall = true;
+        self
+    }
+
+    /// Make the requests for logs take `delay`, as if the node was far away
+    pub fn with_get_logs_delay(mut self, delay: Duration) -> Self {
+        self.get_logs_delay = delay;
+        self
+    }
+
+    /// Run `hook` on each request for an ERC721 token uri before it is answered, as another process changing
+    /// the database meanwhile
+    pub fn with_token_uri_hook(mut self, hook: impl Fn(&H160, &U256) + Send + Sync + 'static) -> Self {
+        self.token_uri_hook = Some(Box::new(hook));
+        self
+    }
+
+    /// Make the requests for ERC721 token uris take `delay`
+    pub fn with_token_uri_delay(mut self, delay: Duration) -> Self {
+        self.token_uri_delay = delay;
+        self
+    }
+
+    /// Make the requests for the names and symbols of the ERC721 collections take `delay`
+    pub fn with_name_symbol_delay(mut self, delay: Duration) -> Self {
+        self.name_symbol_delay = delay;
+        self
+    }
+
+    /// Reject the requests for logs matching more than `max` logs, like the public providers do
+    pub fn with_max_logs_per_request(mut self, max: usize) -> Self {
+        self.max_logs_per_request = Some(max);
+        self
+    }
+
+    /// Support a subscription to the new blocks, which are notified with `new_head`
+    pub fn with_new_heads(self) -> Self {
+        let (sender, receiver) = mpsc::unbounded_channel();
+        let heads = stream::unfold(receiver, |mut receiver| async move {
+            receiver.recv().await.map(|head| (head, receiver))
+        });
+        *self.head_sender.lock().unwrap() = Some(sender);
+        *self.heads.lock().unwrap() = Some(heads.boxed());
+        self
+    }
+
+    /// Make `block_number` the latest block and notify it to the subscription, if there is one
+    pub fn new_head(&self, block_number: u64) {
+        *self.latest_block_numbers.lock().unwrap() = vec![block_number].into();
+        if let Some(sender) = &*self.head_sender.lock().unwrap() {
+            let _ = sender.send(Ok(block_number));
+        }
+    }
+
+    /// End the subscription to the new blocks, as if the connection was lost
+    pub fn drop_subscription(&self) {
+        self.head_sender.lock().unwrap().take();
+    }
+
+    /// Replace the blocks from `block_number` with blocks of another hash
+    pub fn reorg(&self, block_number: u64) {
+        *self.reorged_from.lock().unwrap() = Some(block_number);
+    }
+
+    /// How many times a method of this client was called
+    pub fn call_count(&self, method: &str) -> usize {
+        *self.calls.lock().unwrap().get(method).unwrap_or(&0)
+    }
+
+    /// The block ranges of the requests for logs, in order
+    pub fn scanned_ranges(&self) -> Vec<(u64, u64)> {
+        self.scanned_ranges.lock().unwrap().clone()
+    }
+
+    /// The blocks the name, symbol and token uri lookups were pinned to, in order, None for the latest block
+    pub fn metadata_blocks(&self) -> Vec<Option<u64>> {
+        self.metadata_blocks.lock().unwrap().clone()
+    }
+
+    /// The most requests for logs which were in flight at once
+    pub fn max_concurrent_get_logs(&self) -> usize {
+        self.get_logs_in_flight.lock().unwrap().1
+    }
+
+    /// The most requests for ERC721 token uris which were in flight at once
+    pub fn max_concurrent_token_uri_calls(&self) -> usize {
+        self.token_uri_in_flight.lock().unwrap().1
+    }
+
+    fn record(&self, method: &'static str) {
+        *self.calls.lock().unwrap().entry(method).or_insert(0) += 1;
+    }
+
+    fn erc721_name_symbol(&self, contract_address: &H160) -> Result<Option<(String, String)>> {
+        if let Some(err) = self.name_symbol_errors.lock().unwrap().pop_front() {
+            return Err(err);
+        }
+        Ok(self
+            .erc721_collections
+            .get(contract_address)
+            .and_then(|collection| collection.name_symbol.clone()))
+    }
+
+    fn erc721_token_uri(&self, contract_address: &H160, token_id: &U256) -> Result<Option<String>> {
+        if let Some(err) = self.token_uri_errors.lock().unwrap().pop_front() {
+            return Err(err);
+        }
+        if let Some(token_uris) = self
+            .changing_token_uris
+            .lock()
+            .unwrap()
+            .get_mut(&(*contract_address, *token_id))
+        {
+            let token_uri = if token_uris.len() > 1 {
+                token_uris.pop_front()
+            } else {
+                token_uris.front().cloned()
+            };
+            return Ok(token_uri);
+        }
+        Ok(self
+            .erc721_collections
+            .get(contract_address)
+            .and_then(|collection| collection.token_uris.get(token_id).cloned()))
+    }
+
+    async fn delay_get_logs(&self) {
+        {
+            let mut in_flight = self.get_logs_in_flight.lock().unwrap();
+            in_flight.0 += 1;
+            in_flight.1 = std::cmp::max(in_flight.0, in_flight.1);
+        }
+        if self.get_logs_delay > Duration::ZERO {
+            tokio::time::sleep(self.get_logs_delay).await;
+        }
+        self.get_logs_in_flight.lock().unwrap().0 -= 1;
+    }
+
+    async fn delay_token_uri(&self) {
+        {
+            let mut in_flight = self.token_uri_in_flight.lock().unwrap();
+            in_flight.0 += 1;
+            in_flight.1 = std::cmp::max(in_flight.0, in_flight.1);
+        }
+        if self.token_uri_delay > Duration::ZERO {
+            tokio::time::sleep(self.token_uri_delay).await;
+        }
+        self.token_uri_in_flight.lock().unwrap().0 -= 1;
+    }
+
+    /// Whether a block was replaced by `reorg`
+    fn is_reorged(&self, block_number: u64) -> bool {
+        self.reorged_from
+            .lock()
+            .unwrap()
+            .map_or(false, |reorged_from| block_number >= reorged_from)
+    }
+
+    /// The hash of a block of the canonical chain
+    fn block_hash(&self, block_number: u64) -> H256 {
+        // the hashes of the logs are built from the block number too
+        let mut block_hash = H256::from_low_u64_be(block_number);
+        if self.is_reorged(block_number) {
+            block_hash.0[0] = 0xff;
+        }
+        block_hash
+    }
+
+    /// The logs of the canonical chain
+    fn canonical_logs(&self) -> impl Iterator<Item = &Log> {
+        let orphaned_logs = self
+            .orphaned_logs
+            .iter()
+            .filter(move |log| !self.is_reorged(log.block_number.unwrap().as_u64()));
+        self.logs.iter().chain(orphaned_logs)
+    }
+
+    fn filter_logs(
+        &self,
+        contract_addresses: Option<Vec<H160>>,
+        topics: Vec<H256>,
+        from: u64,
+        to: u64,
+    ) -> Result<Vec<Log>> {
+        self.scanned_ranges.lock().unwrap().push((from, to));
+        if let Some(err) = self.get_logs_errors.lock().unwrap().pop_front() {
+            return Err(err);
+        }
+        let logs: Vec<Log> = self
+            .canonical_logs()
+            .filter(|log| {
+                let block_number = log.block_number.unwrap().as_u64();
+                block_number >= from
+                    && block_number <= to
+                    && topics.contains(&log.topics[0])
+                    && contract_addresses
+                        .as_ref()
+                        .map_or(true, |addresses| addresses.contains(&log.address))
+            })
+            .cloned()
+            .collect();
+        match self.max_logs_per_request {
+            Some(max) if logs.len() > max => Err(rpc_error(
+                -32005,
+                &format!("query returned more than {} results", max),
+            )),
+            _ => Ok(logs),
+        }
+    }
+}
+
+#[async_trait]
+impl EvmClientApi for MockEvmClient {
+    fn chain_name(&self) -> &str {
+        &self.chain_name
+    }
+
+    async fn get_logs(
+        &self,
+        contract_address: Option<H160>,
+        topics: Vec<H256>,
+        from: u64,
+        to: u64,
+    ) -> Result<Vec<Log>> {
+        self.record("get_logs");
+        self.delay_get_logs().await;
+        let contract_addresses = contract_address.map(|address| vec![address]);
+        self.filter_logs(contract_addresses, topics, from, to)
+    }
+
+    async fn get_logs_of_contracts(
+        &self,
+        contract_addresses: Vec<H160>,
+        topics: Vec<H256>,
+        from: u64,
+        to: u64,
+    ) -> Result<Vec<Log>> {
+        self.record("get_logs_of_contracts");
+        self.delay_get_logs().await;
+        self.filter_logs(Some(contract_addresses), topics, from, to)
+    }
+
+    async fn get_logs_by_block_hash(
+        &self,
+        block_hash: H256,
+        contract_addresses: Option<Vec<H160>>,
+        topics: Vec<H256>,
+    ) -> Result<Vec<Log>> {
+        self.record("get_logs_by_block_hash");
+        if let Some(err) = self.get_logs_errors.lock().unwrap().pop_front() {
+            return Err(err);
+        }
+        // the logs are the ones of the canonical chain
+        Ok(self
+            .canonical_logs()
+            .filter(|log| {
+                self.block_hash(log.block_number.unwrap().as_u64()) == block_hash
+                    && topics.contains(&log.topics[0])
+                    && contract_addresses
+                        .as_ref()
+                        .map_or(true, |addresses| addresses.contains(&log.address))
+            })
+            .cloned()
+            .collect())
+    }
+
+    async fn get_block_hash(&self, block_number: u64) -> Result<Option<H256>> {
+        self.record("get_block_hash");
+        Ok(Some(self.block_hash(block_number)))
+    }
+
+    async fn get_latest_block_number(&self) -> Result<u64> {
+        self.record("get_latest_block_number");
+        let mut numbers = self.latest_block_numbers.lock().unwrap();
+        if numbers.len() > 1 {
+            Ok(numbers.pop_front().unwrap())
+        } else {
+            Ok(numbers[0])
+        }
+    }
+
+    fn expected_chain_id(&self) -> Option<u64> {
+        self.expected_chain_id
+    }
+
+    async fn get_chain_id(&self) -> Result<Option<u64>> {
+        self.record("get_chain_id");
+        Ok(self.chain_id)
+    }
+
+    async fn get_block_timestamp(&self, block_number: u64) -> Result<Option<u64>> {
+        self.record("get_block_timestamp");
+        let latest_block_number = self.latest_block_numbers.lock().unwrap()[0];
+        if block_number > latest_block_number {
+            return Ok(None);
+        }
+        let (genesis_timestamp, seconds_per_block) = self.block_time;
+        Ok(Some(genesis_timestamp + block_number * seconds_per_block))
+    }
+
+    async fn get_transaction_senders(&self, block_number: u64) -> Result<Option<HashMap<H256, H160>>> {
+        self.record("get_transaction_senders");
+        let latest_block_number = self.latest_block_numbers.lock().unwrap()[0];
+        if block_number > latest_block_number {
+            return Ok(None);
+        }
+        Ok(Some(self.transaction_senders.get(&block_number).cloned().unwrap_or_default()))
+    }
+
+    async fn get_transaction_logs(&self, transaction_hash: H256) -> Result<Option<Vec<Log>>> {
+        self.record("get_transaction_logs");
+        Ok(self.receipt_logs.get(&transaction_hash).cloned())
+    }
+
+    async fn subscribe_new_heads(&self) -> Result<Option<HeadStream>> {
+        self.record("subscribe_new_heads");
+        Ok(self.heads.lock().unwrap().take())
+    }
+
+    async fn get_code(&self, contract_address: H160, block_number: u64) -> Result<Bytes> {
+        self.record("get_code");
+        match self.creation_blocks.get(&contract_address) {
+            Some(creation_block) if block_number >= *creation_block => Ok(Bytes(bytes("0x6080"))),
+            _ => Ok(Bytes(vec![])),
+        }
+    }
+
+    async fn is_contract(&self, address: H160) -> Result<bool> {
+        self.record("is_contract");
+        Ok(!self.without_code.lock().unwrap().contains(&address))
+    }
+
+    async fn supports_interface(&self, contract_address: H160, interface_id: [u8; 4]) -> Result<Option<bool>> {
+        self.record("supports_interface");
+        let implementation = self
+            .proxy_implementations
+            .lock()
+            .unwrap()
+            .get(&contract_address)
+            .and_then(|implementations| implementations.front().copied());
+        let contract_address = implementation.unwrap_or(contract_address);
+        Ok(self.interfaces.get(&(contract_address, interface_id)).copied())
+    }
+
+    async fn resolve_proxy_implementation(&self, address: H160, _block_number: Option<u64>) -> Result<Option<H160>> {
+        self.record("resolve_proxy_implementation");
+        let mut proxies = self.proxy_implementations.lock().unwrap();
+        Ok(match proxies.get_mut(&address) {
+            Some(implementations) if implementations.len() > 1 => implementations.pop_front(),
+            Some(implementations) => implementations.front().copied(),
+            None => None,
+        })
+    }
+
+    async fn is_visual_erc721(&self, contract_address: H160) -> Result<bool> {
+        self.record("is_visual_erc721");
+        Ok(self.erc721_collections.contains_key(&contract_address))
+    }
+
+    async fn get_erc721_name_symbol(
+        &self,
+        contract_address: &H160,
+        at_block: Option<u64>,
+    ) -> Result<Option<(String, String)>> {
+        self.record("get_erc721_name_symbol");
+        self.metadata_blocks.lock().unwrap().push(at_block);
+        if self.name_symbol_delay > Duration::ZERO {
+            tokio::time::sleep(self.name_symbol_delay).await;
+        }
+        self.erc721_name_symbol(contract_address)
+    }
+
+    async fn get_erc721_token_uri(
+        &self,
+        contract_address: &H160,
+        token_id: &U256,
+        at_block: Option<u64>,
+    ) -> Result<Option<String>> {
+        self.record("get_erc721_token_uri");
+        self.metadata_blocks.lock().unwrap().push(at_block);
+        self.delay_token_uri().await;
+        if let Some(hook) = &self.token_uri_hook {
+            hook(contract_address, token_id);
+        }
+        self.erc721_token_uri(contract_address, token_id)
+    }
+
+    async fn get_erc721_owner_of(
+        &self,
+        contract_address: &H160,
+        token_id: &U256,
+        _block_number: Option<u64>,
+    ) -> Result<Option<H160>> {
+        self.record("get_erc721_owner_of");
+        Ok(self.erc721_owners.get(&(*contract_address, *token_id)).copied())
+    }
+
+    async fn get_erc721_balance_of(
+        &self,
+        contract_address: &H160,
+        owner: &H160,
+        block_number: Option<u64>,
+    ) -> Result<Option<U256>> {
+        self.record("get_erc721_balance_of");
+        self.balance_blocks.lock().unwrap().push(block_number);
+        Ok(self.erc721_balances.get(&(*contract_address, *owner)).map(|balance| U256::from(*balance)))
+    }
+
+    async fn get_approved(
+        &self,
+        _contract_address: &H160,
+        _token_id: &U256,
+        _block_number: Option<u64>,
+    ) -> Result<Option<H160>> {
+        // no token of the mock has an approved account
+        self.record("get_approved");
+        Ok(None)
+    }
+
+    async fn is_approved_for_all(
+        &self,
+        _contract_address: &H160,
+        _owner: &H160,
+        _operator: &H160,
+        _block_number: Option<u64>,
+    ) -> Result<Option<bool>> {
+        self.record("is_approved_for_all");
+        Ok(Some(false))
+    }
+
+    async fn get_royalty_info(
+        &self,
+        contract_address: &H160,
+        _token_id: &U256,
+        sale_price: &U256,
+    ) -> Result<Option<(H160, U256)>> {
+        self.record("get_royalty_info");
+        Ok(self
+            .royalties
+            .get(contract_address)
+            .map(|(receiver, bps)| (*receiver, *sale_price * U256::from(*bps) / U256::from(10_000))))
+    }
+
+    async fn get_contract_uri(&self, contract_address: &H160) -> Result<Option<String>> {
+        self.record("get_contract_uri");
+        if let Some(err) = self.contract_uri_errors.lock().unwrap().pop_front() {
+            return Err(err);
+        }
+        Ok(self.contract_uris.get(contract_address).cloned())
+    }
+
+    fn batches_metadata_lookups(&self) -> bool {
+        self.multicall
+    }
+
+    async fn get_erc721_name_symbols(
+        &self,
+        contract_addresses: &[H160],
+    ) -> Result<Vec<Result<Option<(String, String)>>>> {
+        self.record("get_erc721_name_symbols");
+        Ok(contract_addresses
+            .iter()
+            .map(|contract_address| self.erc721_name_symbol(contract_address))
+            .collect())
+    }
+
+    async fn get_erc721_token_uris(&self, tokens: &[(H160, U256)]) -> Result<Vec<Result<Option<String>>>> {
+        self.record("get_erc721_token_uris");
+        Ok(tokens
+            .iter()
+            .map(|(contract_address, token_id)| self.erc721_token_uri(contract_address, token_id))
+            .collect())
+    }
+
+    async fn get_erc721_total_supply(
+        &self,
+        contract_address: &H160,
+        _block_number: Option<u64>,
+    ) -> Result<Option<u128>> {
+        self.record("get_erc721_total_supply");
+        Ok(self
+            .erc721_collections
+            .get(contract_address)
+            .and_then(|collection| collection.total_supply))
+    }
+
+    async fn get_erc721_token_by_index(
+        &self,
+        contract_address: &H160,
+        index: u64,
+        _block_number: Option<u64>,
+    ) -> Result<Option<U256>> {
+        self.record("get_erc721_token_by_index");
+        Ok(self
+            .erc721_collections
+            .get(contract_address)
+            .and_then(|collection| collection.tokens_by_index.get(index as usize).copied()))
+    }
+
+    async fn is_visual_erc1155(&self, contract_address: H160) -> Result<bool> {
+        self.record("is_visual_erc1155");
+        Ok(self.erc1155_token_uris.contains_key(&contract_address))
+    }
+
+    async fn get_erc1155_token_uri(
+        &self,
+        contract_address: &H160,
+        token_id: &U256,
+    ) -> Result<String> {
+        self.record("get_erc1155_token_uri");
+        self.erc1155_token_uris
+            .get(contract_address)
+            .and_then(|token_uris| token_uris.get(token_id).cloned())
+            .ok_or_else(|| Error::Other("No such ERC1155 token in the mock".to_owned()))
+    }
+}
+
+/// A scripted web3 transport answering the JSON-RPC methods from memory, to drive an `EvmClient` offline
+#[derive(Debug, Clone, Default)]
+pub struct MockTransport {
+    /// The responses of each method in turn, the last one is kept forever
+    responses: Arc<Mutex<HashMap<String, VecDeque<web3::Result<rpc::Value>>>>>,
+    /// The methods of the requests sent, in order
+    sent: Arc<Mutex<Vec<String>>>,
+    /// The logs answering `eth_getLogs`, and the widest range of blocks it accepts
+    logs: Arc<Mutex<Option<(Vec<Log>, u64)>>>,
+    /// The block ranges of the `eth_getLogs` requests, in order
+    log_ranges: Arc<Mutex<Vec<(u64, u64)>>>,
+    /// The methods and the parameters of the requests sent, in order
+    sent_params: Arc<Mutex<Vec<(String, Vec<rpc::Value>)>>>,
+}
+
+impl MockTransport {
+    /// Answer the next request of `method` with `response`, after the responses already scripted
+    pub fn with_response(self, method: &str, response: web3::Result<rpc::Value>) -> Self {
+        self.responses
+            .lock()
+            .unwrap()
+            .entry(method.to_owned())
+            .or_default()
+            .push_back(response);
+        self
+    }
+
+    /// Answer `eth_getLogs` with the `logs` of the requested blocks and addresses,
+    /// and reject the ranges of more than `max_range` blocks as Infura does
+    pub fn with_logs(self, logs: Vec<Log>, max_range: u64) -> Self {
+        *self.logs.lock().unwrap() = Some((logs, max_range));
+        self
+    }
+
+    /// How many requests of `method` were sent
+    pub fn sent_count(&self, method: &str) -> usize {
+        self.sent.lock().unwrap().iter().filter(|sent| *sent == method).count()
+    }
+
+    /// The block ranges of the `eth_getLogs` requests, in order
+    pub fn log_ranges(&self) -> Vec<(u64, u64)> {
+        self.log_ranges.lock().unwrap().clone()
+    }
+
+    /// The parameters of the requests of `method`, in order
+    pub fn sent_params(&self, method: &str) -> Vec<Vec<rpc::Value>> {
+        self.sent_params
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|(sent, _)| sent == method)
+            .map(|(_, params)| params.clone())
+            .collect()
+    }
+
+    /// The addresses of the `eth_getLogs` filters, in order, none for a filter without any
+    pub fn log_filter_addresses(&self) -> Vec<Vec<String>> {
+        self.sent_params("eth_getLogs").iter().map(|params| filter_addresses(&params[0])).collect()
+    }
+
+    /// The blocks of the `eth_call` requests, in order, as `latest` or as a hexadecimal number
+    pub fn call_blocks(&self) -> Vec<String> {
+        self.sent_params("eth_call")
+            .iter()
+            .map(|params| params.get(1).and_then(|block| block.as_str()).unwrap_or("latest").to_owned())
+            .collect()
+    }
+
+    fn respond(&self, request: &rpc::Call) -> web3::Result<rpc::Value> {
+        let (method, params) = match request {
+            rpc::Call::MethodCall(call) => (call.method.clone(), &call.params),
+            _ => return Err(web3::Error::Transport("Only method calls are mocked".to_owned())),
+        };
+        self.sent.lock().unwrap().push(method.clone());
+        if let rpc::Params::Array(params) = params {
+            self.sent_params.lock().unwrap().push((method.clone(), params.clone()));
+        }
+        if method == "eth_getLogs" {
+            if let Some((logs, max_range)) = &*self.logs.lock().unwrap() {
+                return self.respond_logs(params, logs, *max_range);
+            }
+        }
+        let mut responses = self.responses.lock().unwrap();
+        let responses = responses
+            .get_mut(&method)
+            .ok_or_else(|| web3::Error::Transport(format!("No response to {} in the mock", method)))?;
+        if responses.len() > 1 {
+            responses.pop_front().unwrap()
+        } else {
+            responses.front().cloned().unwrap()
+        }
+    }
+
+    fn respond_logs(&self, params: &rpc::Params, logs: &[Log], max_range: u64) -> web3::Result<rpc::Value> {
+        let block = |filter: &rpc::Value, field: &str| {
+            filter[field]
+                .as_str()
+                .and_then(|block| u64::from_str_radix(block.trim_start_matches("0x"), 16).ok())
+                .ok_or_else(|| web3::Error::Transport(format!("No {} in the filter", field)))
+        };
+        let filter = match params {
+            rpc::Params::Array(params) if !params.is_empty() => &params[0],
+            _ => return Err(web3::Error::Transport("No filter in eth_getLogs".to_owned())),
+        };
+        let (from, to) = (block(filter, "fromBlock")?, block(filter, "toBlock")?);
+        let addresses = filter_addresses(filter);
+        self.log_ranges.lock().unwrap().push((from, to));
+        if to - from + 1 > max_range {
+            return Err(web3::Error::Rpc(rpc::Error {
+                code: rpc::ErrorCode::ServerError(-32005),
+                message: "query returned more than 10000 results".to_owned(),
+                data: None,
+            }));
+        }
+        let logs: Vec<&Log> = logs
+            .iter()
+            .filter(|log| log.block_number.map_or(false, |block_number| (from..=to).contains(&block_number.as_u64())))
+            .filter(|log| addresses.is_empty() || addresses.contains(&format!("{:?}", log.address)))
+            .collect();
+        Ok(helpers::serialize(&logs))
+    }
+}
+
+/// The addresses of a logs filter, a single one is not in an array
+fn filter_addresses(filter: &rpc::Value) -> Vec<String> {
+    match &filter["address"] {
+        rpc::Value::String(address) => vec![address.clone()],
+        rpc::Value::Array(addresses) => addresses
+            .iter()
+            .filter_map(|address| address.as_str().map(str::to_owned))
+            .collect(),
+        _ => vec![],
+    }
+}
+
+impl Transport for MockTransport {
+    type Out = BoxFuture<'static, web3::Result<rpc::Value>>;
+
+    fn prepare(&self, method: &str, params: Vec<rpc::Value>) -> (RequestId, rpc::Call) {
+        (1, helpers::build_request(1, method, params))
+    }
+
+    fn send(&self, _id: RequestId, request: rpc::Call) -> Self::Out {
+        let response = self.respond(&request);
+        Box::pin(async move { response })
+    }
+}
+
+impl BatchTransport for MockTransport {
+    type Batch = BoxFuture<'static, web3::Result<Vec<web3::Result<rpc::Value>>>>;
+
+    fn send_batch<R>(&self, requests: R) -> Self::Batch
+    where
+        R: IntoIterator<Item = (RequestId, rpc::Call)>,
+    {
+        let responses = requests.into_iter().map(|(_, request)| self.respond(&request)).collect();
+        Box::pin(async move { Ok(responses) })
+    }
+}
+
+/// Build an ERC721 `Transfer` log
+pub fn erc721_transfer_log(
+    address: H160,
+    from: H160,
+    to: H160,
+    token_id: u64,
+    block_number: u64,
+    log_index: u64,
+) -> Log {
+    Log {
+        address,
+        topics: vec![
+            H256::from_slice(&bytes(TRANSFER_TOPIC)),
+            H256::from(from),
+            H256::from(to),
+            H256::from_low_u64_be(token_id),
+        ],
+        data: Bytes(vec![]),
+        block_hash: Some(H256::from_low_u64_be(block_number)),
+        block_number: Some(U64::from(block_number)),
+        transaction_hash: Some(H256::from_low_u64_be(block_number * 1000 + log_index)),
+        transaction_index: Some(U64::from(log_index)),
+        log_index: Some(U256::from(log_index)),
+        transaction_log_index: Some(U256::from(log_index)),
+        log_type: None,
+        removed: Some(false),
+    }
+}
+
+/// A sqlite store whose next `failing_commits` commits fail, as when the database is unavailable for a while
+pub struct FailingCommitStore {
+    /// The store the operations are run on
+    pub inner: SqliteStore,
+    failing_commits: AtomicUsize,
+}
+
+impl FailingCommitStore {
+    pub fn new(inner: SqliteStore, failing_commits: usize) -> FailingCommitStore {
+        FailingCommitStore {
+            inner,
+            failing_commits: AtomicUsize::new(failing_commits),
+        }
+    }
+}
+
+#[async_trait]
+impl NftStore for FailingCommitStore {
+    async fn get_collection(&self, chain: &str, address: &str) -> Result<Option<Collection>> {
+        self.inner.get_collection(chain, address).await
+    }
+
+    async fn put_collection(&self, chain: &str, address: &str, name_symbol: Option<(String, String)>) -> Result<usize> {
+        self.inner.put_collection(chain, address, name_symbol).await
+    }
+
+    async fn add_collection_with_erc721_support(
+        &self,
+        chain: &str,
+        address: &str,
+        supports_erc721: Option<bool>,
+    ) -> Result<usize> {
+        self.inner.add_collection_with_erc721_support(chain, address, supports_erc721).await
+    }
+
+    async fn get_collection_erc721_support(&self, collection_id: usize) -> Result<Option<Option<bool>>> {
+        self.inner.get_collection_erc721_support(collection_id).await
+    }
+
+    async fn save_collection_erc721_support(&self, collection_id: usize, supports_erc721: Option<bool>) -> Result<()> {
+        self.inner.save_collection_erc721_support(collection_id, supports_erc721).await
+    }
+
+    async fn get_collection_code(&self, collection_id: usize) -> Result<Option<CollectionCode>> {
+        self.inner.get_collection_code(collection_id).await
+    }
+
+    async fn save_collection_code(&self, collection_id: usize, code: CollectionCode) -> Result<()> {
+        self.inner.save_collection_code(collection_id, code).await
+    }
+
+    async fn get_collection_creation_block(&self, chain: &str, address: &str) -> Result<Option<u64>> {
+        self.inner.get_collection_creation_block(chain, address).await
+    }
+
+    async fn save_collection_creation_block(&self, collection_id: usize, block_number: u64) -> Result<()> {
+        self.inner.save_collection_creation_block(collection_id, block_number).await
+    }
+
+    async fn get_collection_royalty(&self, chain: &str, address: &str) -> Result<Option<(String, u64)>> {
+        self.inner.get_collection_royalty(chain, address).await
+    }
+
+    async fn save_collection_royalty(&self, collection_id: usize, royalty: Option<(&str, u64)>) -> Result<()> {
+        self.inner.save_collection_royalty(collection_id, royalty).await
+    }
+
+    async fn get_collection_contract_uri(&self, chain: &str, address: &str) -> Result<Option<String>> {
+        self.inner.get_collection_contract_uri(chain, address).await
+    }
+
+    async fn save_collection_contract_uri(&self, collection_id: usize, contract_uri: Option<&str>) -> Result<()> {
+        self.inner.save_collection_contract_uri(collection_id, contract_uri).await
+    }
+
+    async fn get_collection_implementation(&self, chain: &str, address: &str) -> Result<Option<(Option<String>, u64)>> {
+        self.inner.get_collection_implementation(chain, address).await
+    }
+
+    async fn save_collection_implementation(
+        &self,
+        collection_id: usize,
+        implementation: Option<&str>,
+        block_number: u64,
+    ) -> Result<()> {
+        self.inner.save_collection_implementation(collection_id, implementation, block_number).await
+    }
+
+    async fn get_collection_snapshot(&self, chain: &str, address: &str) -> Result<Option<(u64, u64)>> {
+        self.inner.get_collection_snapshot(chain, address).await
+    }
+
+    async fn save_collection_snapshot(&self, collection_id: usize, block_number: u64, index: u64) -> Result<()> {
+        self.inner.save_collection_snapshot(collection_id, block_number, index).await
+    }
+
+    async fn update_collection_metadata(
+        &self,
+        collection_id: usize,
+        name: Option<String>,
+        symbol: Option<String>,
+    ) -> Result<()> {
+        self.inner.update_collection_metadata(collection_id, name, symbol).await
+    }
+
+    async fn get_collection_lookup_failures(&self, collection_id: usize) -> Result<Option<(u32, u64)>> {
+        self.inner.get_collection_lookup_failures(collection_id).await
+    }
+
+    async fn record_collection_lookup_failure(&self, collection_id: usize, attempted_at: u64) -> Result<()> {
+        self.inner.record_collection_lookup_failure(collection_id, attempted_at).await
+    }
+
+    async fn get_token(&self, collection_id: usize, token_id: &str) -> Result<Option<Token>> {
+        self.inner.get_token(collection_id, token_id).await
+    }
+
+    async fn put_token(&self, collection_id: usize, token_id: &str, token_uri: Option<String>) -> Result<usize> {
+        self.inner.put_token(collection_id, token_id, token_uri).await
+    }
+
+    async fn get_token_fetched_at(&self, token_db_id: usize) -> Result<Option<u64>> {
+        self.inner.get_token_fetched_at(token_db_id).await
+    }
+
+    async fn update_token_uri(&self, token_db_id: usize, token_uri: Option<String>, fetched_at: u64) -> Result<()> {
+        self.inner.update_token_uri(token_db_id, token_uri, fetched_at).await
+    }
+
+    async fn get_token_lookup_failures(&self, token_db_id: usize) -> Result<Option<(u32, u64)>> {
+        self.inner.get_token_lookup_failures(token_db_id).await
+    }
+
+    async fn record_token_lookup_failure(&self, token_db_id: usize, attempted_at: u64) -> Result<()> {
+        self.inner.record_token_lookup_failure(token_db_id, attempted_at).await
+    }
+
+    async fn is_token_stale(&self, token_db_id: usize) -> Result<bool> {
+        self.inner.is_token_stale(token_db_id).await
+    }
+
+    async fn mark_tokens_stale(&self, collection_id: usize, first: &str, last: &str) -> Result<usize> {
+        self.inner.mark_tokens_stale(collection_id, first, last).await
+    }
+
+    async fn get_token_owner(&self, chain: &str, address: &str, token_id: &str) -> Result<Option<String>> {
+        self.inner.get_token_owner(chain, address, token_id).await
+    }
+
+    async fn count_tokens_of_owner(&self, chain: &str, address: &str, owner: &str) -> Result<u64> {
+        self.inner.count_tokens_of_owner(chain, address, owner).await
+    }
+
+    async fn put_transfer(&self, transfer: &Transfer) -> Result<bool> {
+        self.inner.put_transfer(transfer).await
+    }
+
+    async fn get_token_transfers(&self, collection_id: usize, token_id: &str) -> Result<Vec<Transfer>> {
+        self.inner.get_token_transfers(collection_id, token_id).await
+    }
+
+    async fn get_scan_progress(&self, chain: &str) -> Result<Option<u64>> {
+        self.inner.get_scan_progress(chain).await
+    }
+
+    async fn save_scan_progress(&self, chain: &str, block_number: u64) -> Result<()> {
+        self.inner.save_scan_progress(chain, block_number).await
+    }
+
+    async fn is_event_delivered(&self, chain: &str, transaction_hash: &str, log_index: u64) -> Result<bool> {
+        self.inner.is_event_delivered(chain, transaction_hash, log_index).await
+    }
+
+    async fn mark_event_delivered(
+        &self,
+        chain: &str,
+        transaction_hash: &str,
+        log_index: u64,
+        block_number: u64,
+    ) -> Result<()> {
+        self.inner.mark_event_delivered(chain, transaction_hash, log_index, block_number).await
+    }
+
+    async fn get_scanned_blocks_before(&self, chain: &str, block_number: u64) -> Result<Vec<(u64, String)>> {
+        self.inner.get_scanned_blocks_before(chain, block_number).await
+    }
+
+    async fn commit_range(&self, writes: &RangeWrites) -> Result<()> {
+        let failing = self.failing_commits.fetch_update(Ordering::SeqCst, Ordering::SeqCst, |n| n.checked_sub(1));
+        if failing.is_ok() {
+            return Err(Error::Other("mock commit failure".to_owned()));
+        }
+        self.inner.commit_range(writes).await
+    }
+
+    async fn rewind(&self, chain: &str, block_number: u64, last_scanned_block: Option<u64>) -> Result<()> {
+        self.inner.rewind(chain, block_number, last_scanned_block).await
+    }
+
+    async fn get_erc1155_collection(&self, address: &str) -> Result<Option<usize>> {
+        self.inner.get_erc1155_collection(address).await
+    }
+
+    async fn add_erc1155_collection(&self, address: &str) -> Result<usize> {
+        self.inner.add_erc1155_collection(address).await
+    }
+
+    async fn get_erc1155_token_uri(&self, collection_id: usize, token_id: &str) -> Result<Option<Option<String>>> {
+        self.inner.get_erc1155_token_uri(collection_id, token_id).await
+    }
+
+    async fn add_erc1155_token(
+        &self,
+        collection_id: usize,
+        token_id: &str,
+        token_uri: Option<String>,
+    ) -> Result<usize> {
+        self.inner.add_erc1155_token(collection_id, token_id, token_uri).await
+    }
+}
+
+/// The logs of a receipt fixture, a response to `eth_getTransactionReceipt`
+pub fn receipt_logs(receipt: &str) -> Vec<Log> {
+    let response: serde_json::Value = serde_json::from_str(receipt).unwrap();
+    serde_json::from_value(response["result"]["logs"].clone()).unwrap()
+}
+
+/// The logs of a logs fixture, a response to `eth_getLogs`
+pub fn fixture_logs(response: &str) -> Vec<Log> {
+    let response: serde_json::Value = serde_json::from_str(response).unwrap();
+    serde_json::from_value(response["result"].clone()).unwrap()
+}
+
+/// Build an ERC4906 `MetadataUpdate` log
+pub fn erc4906_metadata_update_log(address: H160, token_id: u64, block_number: u64, log_index: u64) -> Log {
+    let mut data = [0u8; 32];
+    U256::from(token_id).to_big_endian(&mut data);
+    metadata_update_log(address, METADATA_UPDATE_TOPIC, data.to_vec(), block_number, log_index)
+}
+
+/// Build an ERC4906 `BatchMetadataUpdate` log of the tokens from `from_token_id` to `to_token_id`
+pub fn erc4906_batch_metadata_update_log(
+    address: H160,
+    from_token_id: u64,
+    to_token_id: u64,
+    block_number: u64,
+    log_index: u64,
+) -> Log {
+    let mut data = [0u8; 64];
+    U256::from(from_token_id).to_big_endian(&mut data[0..32]);
+    U256::from(to_token_id).to_big_endian(&mut data[32..64]);
+    metadata_update_log(address, BATCH_METADATA_UPDATE_TOPIC, data.to_vec(), block_number, log_index)
+}
+
+fn metadata_update_log(address: H160, topic: &str, data: Vec<u8>, block_number: u64, log_index: u64) -> Log {
+    Log {
+        address,
+        topics: vec![H256::from_slice(&bytes(topic))],
+        data: Bytes(data),
+        block_hash: Some(H256::from_low_u64_be(block_number)),
+        block_number: Some(U64::from(block_number)),
+        transaction_hash: Some(H256::from_low_u64_be(block_number * 1000 + log_index)),
+        transaction_index: Some(U64::from(log_index)),
+        log_index: Some(U256::from(log_index)),
+        transaction_log_index: Some(U256::from(log_index)),
+        log_type: None,
+        removed: Some(false),
+    }
+}
+
+/// Build an ERC721 `Approval` log of a token
+pub fn erc721_approval_log(
+    address: H160,
+    owner: H160,
+    approved: H160,
+    token_id: u64,
+    block_number: u64,
+    log_index: u64,
+) -> Log {
+    let topics = vec![H256::from(owner), H256::from(approved), H256::from_low_u64_be(token_id)];
+    approval_log(address, APPROVAL_TOPIC, topics, vec![], block_number, log_index)
+}
+
+/// Build an ERC20 `Approval` log, whose value is not indexed
+pub fn erc20_approval_log(address: H160, owner: H160, spender: H160, value: u64, block_number: u64, log_index: u64) -> Log {
+    let mut data = [0u8; 32];
+    U256::from(value).to_big_endian(&mut data);
+    let topics = vec![H256::from(owner), H256::from(spender)];
+    approval_log(address, APPROVAL_TOPIC, topics, data.to_vec(), block_number, log_index)
+}
+
+/// Build an `ApprovalForAll` log, the same for ERC721 and ERC1155
+pub fn approval_for_all_log(
+    address: H160,
+    owner: H160,
+    operator: H160,
+    approved: bool,
+    block_number: u64,
+    log_index: u64,
+) -> Log {
+    let mut data = [0u8; 32];
+    data[31] = approved as u8;
+    let topics = vec![H256::from(owner), H256::from(operator)];
+    approval_log(address, APPROVAL_FOR_ALL_TOPIC, topics, data.to_vec(), block_number, log_index)
+}
+
+fn approval_log(
+    address: H160,
+    topic: &str,
+    topics: Vec<H256>,
+    data: Vec<u8>,
+    block_number: u64,
+    log_index: u64,
+) -> Log {
+    let mut log = metadata_update_log(address, topic, data, block_number, log_index);
+    log.topics.extend(topics);
+    log
+}
+
+/// Build an ERC1155 `TransferSingle` log, operated by `from`
+pub fn erc1155_transfer_single_log(
+    address: H160,
+    from: H160,
+    to: H160,
+    token_id: u64,
+    amount: u64,
+    block_number: u64,
+    log_index: u64,
+) -> Log {
+    let mut data = [0u8; 64];
+    U256::from(token_id).to_big_endian(&mut data[0..32]);
+    U256::from(amount).to_big_endian(&mut data[32..64]);
+    Log {
+        address,
+        topics: vec![
+            H256::from_slice(&bytes(TRANSFER_SINGLE_TOPIC)),
+            H256::from(from),
+            H256::from(from),
+            H256::from(to),
+        ],
+        data: Bytes(data.to_vec()),
+        block_hash: Some(H256::from_low_u64_be(block_number)),
+        block_number: Some(U64::from(block_number)),
+        transaction_hash: Some(H256::from_low_u64_be(block_number * 1000 + log_index)),
+        transaction_index: Some(U64::from(log_index)),
+        log_index: Some(U256::from(log_index)),
+        transaction_log_index: Some(U256::from(log_index)),
+        log_type: None,
+        removed: Some(false),
+    }
+}
+
+/// Build a JSON-RPC error as returned by the node
+pub fn rpc_error(code: i64, message: &str) -> Error {
+    Error::Web3Error(web3::Error::Rpc(web3::rpc::Error {
+        code: web3::rpc::ErrorCode::ServerError(code),
+        message: message.to_owned(),
+        data: None,
+    }))
+}
+
+/// A test address built from a small number
+pub fn address(n: u64) -> H160 {
+    H160::from_low_u64_be(n)
+}
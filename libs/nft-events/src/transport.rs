@@ -0,0 +1,997 @@
+//! This module contains the transports of the EVM client: HTTP, WebSocket which is reconnected
+//! when its connection drops, or several endpoints failing over to each other.
+use futures::{
+    future::BoxFuture,
+    stream::{self, BoxStream},
+    Future, FutureExt, Stream, StreamExt,
+};
+use crate::{
+    metrics::{ClientMetrics, CircuitState},
+    CircuitBreaker,
+};
+use std::{
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant},
+};
+use web3::{
+    api::SubscriptionId,
+    helpers, rpc,
+    transports::{Http, WebSocket},
+    types::U256,
+    BatchTransport, DuplexTransport, RequestId, Transport,
+};
+
+/// The transport of the EVM clients made by `EvmClient::new`, `EvmClient::connect` and
+/// `EvmClient::connect_with_failover`
+#[derive(Debug, Clone)]
+pub struct EvmTransport(pub(crate) Connection);
+
+impl Transport for EvmTransport {
+    type Out = BoxFuture<'static, web3::Result<rpc::Value>>;
+
+    fn prepare(&self, method: &str, params: Vec<rpc::Value>) -> (RequestId, rpc::Call) {
+        self.0.prepare(method, params)
+    }
+
+    fn send(&self, id: RequestId, request: rpc::Call) -> Self::Out {
+        self.0.send(id, request)
+    }
+}
+
+impl BatchTransport for EvmTransport {
+    type Batch = BoxFuture<'static, web3::Result<Vec<web3::Result<rpc::Value>>>>;
+
+    fn send_batch<I>(&self, requests: I) -> Self::Batch
+    where
+        I: IntoIterator<Item = (RequestId, rpc::Call)>,
+    {
+        self.0.send_batch(requests)
+    }
+}
+
+/// The connection of an EVM transport
+#[derive(Debug, Clone)]
+pub(crate) enum Connection {
+    /// Requests over HTTP
+    Http(Http),
+    /// Requests over a WebSocket connection
+    WebSocket(Reconnecting<WebSocket>),
+    /// Requests over the first available of several endpoints
+    Failover(Failover<Connection>),
+}
+
+impl Transport for Connection {
+    type Out = BoxFuture<'static, web3::Result<rpc::Value>>;
+
+    fn prepare(&self, method: &str, params: Vec<rpc::Value>) -> (RequestId, rpc::Call) {
+        match self {
+            Connection::Http(http) => http.prepare(method, params),
+            Connection::WebSocket(ws) => ws.prepare(method, params),
+            Connection::Failover(failover) => failover.prepare(method, params),
+        }
+    }
+
+    fn send(&self, id: RequestId, request: rpc::Call) -> Self::Out {
+        match self {
+            Connection::Http(http) => Box::pin(http.send(id, request)),
+            Connection::WebSocket(ws) => ws.send(id, request),
+            Connection::Failover(failover) => failover.send(id, request),
+        }
+    }
+}
+
+impl BatchTransport for Connection {
+    type Batch = BoxFuture<'static, web3::Result<Vec<web3::Result<rpc::Value>>>>;
+
+    fn send_batch<I>(&self, requests: I) -> Self::Batch
+    where
+        I: IntoIterator<Item = (RequestId, rpc::Call)>,
+    {
+        match self {
+            Connection::Http(http) => Box::pin(http.send_batch(requests)),
+            Connection::WebSocket(ws) => ws.send_batch(requests),
+            Connection::Failover(failover) => failover.send_batch(requests),
+        }
+    }
+}
+
+/// The ids of the requests are unique across the connections and the endpoints,
+/// so that a request sent again on another connection does not take the id of one of its requests
+static NEXT_REQUEST_ID: AtomicUsize = AtomicUsize::new(1);
+
+fn prepare_request(method: &str, params: Vec<rpc::Value>) -> (RequestId, rpc::Call) {
+    let id = NEXT_REQUEST_ID.fetch_add(1, Ordering::SeqCst);
+    (id, helpers::build_request(id, method, params))
+}
+
+/// Connects a new transport, to replace a dropped connection
+type Connect<T> = Arc<dyn Fn() -> BoxFuture<'static, web3::Result<T>> + Send + Sync>;
+
+/// A transport whose connection is replaced by a new one when it drops.
+/// The requests which failed because of the dropped connection are sent again on the new one,
+/// so the callers do not see the failure.
+#[derive(Clone)]
+pub(crate) struct Reconnecting<T> {
+    /// The current connection, and how many times the connection was replaced
+    current: Arc<Mutex<(u64, T)>>,
+    /// None if the connection can not be reestablished
+    connect: Option<Connect<T>>,
+    /// Held while reconnecting, so that the requests which failed together reconnect once
+    reconnecting: Arc<tokio::sync::Mutex<()>>,
+}
+
+impl<T> std::fmt::Debug for Reconnecting<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Reconnecting")
+            .field("reconnections", &self.current.lock().unwrap().0)
+            .field("can_reconnect", &self.connect.is_some())
+            .finish()
+    }
+}
+
+impl<T: Clone> Reconnecting<T> {
+    /// Use `transport`, which is not reconnected if it drops
+    pub(crate) fn new(transport: T) -> Reconnecting<T> {
+        Reconnecting {
+            current: Arc::new(Mutex::new((0, transport))),
+            connect: None,
+            reconnecting: Arc::new(tokio::sync::Mutex::new(())),
+        }
+    }
+
+    /// Connect with `connect`, which is called again to replace the connection when it drops
+    pub(crate) async fn connect<F, Fut>(connect: F) -> web3::Result<Reconnecting<T>>
+    where
+        T: Send + 'static,
+        F: Fn() -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = web3::Result<T>> + Send + 'static,
+    {
+        let connect: Connect<T> = Arc::new(move || connect().boxed());
+        let transport = connect().await?;
+        Ok(Reconnecting {
+            connect: Some(connect),
+            ..Reconnecting::new(transport)
+        })
+    }
+
+    /// How many times the connection was replaced
+    pub(crate) fn generation(&self) -> u64 {
+        self.current.lock().unwrap().0
+    }
+
+    fn current(&self) -> (u64, T) {
+        self.current.lock().unwrap().clone()
+    }
+
+    /// Replace the connection of `generation` which dropped.
+    /// Nothing is done if it was already replaced, by another request which failed at the same time.
+    pub(crate) async fn reconnect(&self, generation: u64) -> web3::Result<()> {
+        let connect = match &self.connect {
+            Some(connect) => connect,
+            None => {
+                return Err(web3::Error::Transport(
+                    "The connection dropped and it can not be reestablished".to_owned(),
+                ))
+            }
+        };
+        let _reconnecting = self.reconnecting.lock().await;
+        if self.generation() != generation {
+            return Ok(());
+        }
+        let transport = connect().await?;
+        *self.current.lock().unwrap() = (generation + 1, transport);
+        info!("Reconnected after the connection dropped.");
+        Ok(())
+    }
+
+    fn can_reconnect(&self, err: &web3::Error) -> bool {
+        self.connect.is_some() && is_connection_error(err)
+    }
+}
+
+impl<T> Transport for Reconnecting<T>
+where
+    T: Transport + Send + Sync + 'static,
+    T::Out: Send + 'static,
+{
+    type Out = BoxFuture<'static, web3::Result<rpc::Value>>;
+
+    fn prepare(&self, method: &str, params: Vec<rpc::Value>) -> (RequestId, rpc::Call) {
+        prepare_request(method, params)
+    }
+
+    fn send(&self, id: RequestId, request: rpc::Call) -> Self::Out {
+        let reconnecting = self.clone();
+        Box::pin(async move {
+            let (generation, transport) = reconnecting.current();
+            match transport.send(id, request.clone()).await {
+                Err(err) if reconnecting.can_reconnect(&err) => {
+                    warn!("The connection dropped: {:?}, reconnect and send the request again.", err);
+                    reconnecting.reconnect(generation).await?;
+                    reconnecting.current().1.send(id, request).await
+                }
+                result => result,
+            }
+        })
+    }
+}
+
+impl<T> BatchTransport for Reconnecting<T>
+where
+    T: BatchTransport + Send + Sync + 'static,
+    T::Out: Send + 'static,
+    T::Batch: Send + 'static,
+{
+    type Batch = BoxFuture<'static, web3::Result<Vec<web3::Result<rpc::Value>>>>;
+
+    fn send_batch<I>(&self, requests: I) -> Self::Batch
+    where
+        I: IntoIterator<Item = (RequestId, rpc::Call)>,
+    {
+        let requests: Vec<_> = requests.into_iter().collect();
+        let reconnecting = self.clone();
+        Box::pin(async move {
+            let (generation, transport) = reconnecting.current();
+            match transport.send_batch(requests.clone()).await {
+                Err(err) if reconnecting.can_reconnect(&err) => {
+                    warn!("The connection dropped: {:?}, reconnect and send the batch again.", err);
+                    reconnecting.reconnect(generation).await?;
+                    reconnecting.current().1.send_batch(requests).await
+                }
+                result => result,
+            }
+        })
+    }
+}
+
+impl<T> DuplexTransport for Reconnecting<T>
+where
+    T: DuplexTransport + Send + Sync + 'static,
+    T::Out: Send + 'static,
+{
+    type NotificationStream = T::NotificationStream;
+
+    fn subscribe(&self, id: SubscriptionId) -> web3::Result<Self::NotificationStream> {
+        self.current().1.subscribe(id)
+    }
+
+    fn unsubscribe(&self, id: SubscriptionId) -> web3::Result<()> {
+        self.current().1.unsubscribe(id)
+    }
+}
+
+/// Subscribe with `subscribe`, and subscribe again on a new connection each time the subscription ends
+/// because its connection dropped. The stream ends after an error if the connection can not be reestablished.
+pub(crate) async fn subscribe_with_reconnection<T, I, S, F, Fut>(
+    ws: Reconnecting<T>,
+    subscribe: F,
+) -> web3::Result<BoxStream<'static, web3::Result<I>>>
+where
+    T: Clone + Send + Sync + 'static,
+    I: Send + 'static,
+    S: Stream<Item = web3::Result<I>> + Send + Unpin + 'static,
+    F: Fn(Reconnecting<T>) -> Fut + Send + Sync + 'static,
+    Fut: Future<Output = web3::Result<S>> + Send,
+{
+    let generation = ws.generation();
+    let subscription = subscribe(ws.clone()).await?;
+    let state = (ws, subscribe, Some((generation, subscription)));
+    let items = stream::unfold(state, |(ws, subscribe, mut subscription)| async move {
+        loop {
+            let (generation, items) = subscription.as_mut()?;
+            let item = items.next().await;
+            if let Some(item) = item {
+                return Some((item, (ws, subscribe, subscription)));
+            }
+            let generation = *generation;
+            warn!("The subscription ended, subscribe again on a new connection.");
+            let resubscribed: web3::Result<(u64, S)> = async {
+                ws.reconnect(generation).await?;
+                let generation = ws.generation();
+                Ok((generation, subscribe(ws.clone()).await?))
+            }
+            .await;
+            match resubscribed {
+                Ok(resubscribed) => subscription = Some(resubscribed),
+                Err(err) => return Some((Err(err), (ws, subscribe, None))),
+            }
+        }
+    });
+    Ok(Box::pin(items))
+}
+
+/// How long the preferred endpoint is not used after it failed, doubled after each failed retry
+const PREFERRED_ENDPOINT_RETRY_DELAY: Duration = Duration::from_secs(30);
+
+/// The longest delay before retrying the preferred endpoint
+const MAX_PREFERRED_ENDPOINT_RETRY_DELAY: Duration = Duration::from_secs(600);
+
+/// Whether an endpoint answered with the same chain id as the other endpoints
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum ChainIdCheck {
+    Unchecked,
+    Matching,
+    Mismatching { expected: U256, chain_id: U256 },
+}
+
+/// Why an endpoint can not be used
+#[derive(Debug)]
+pub(crate) enum EndpointError {
+    /// The endpoint failed to answer
+    Failed(web3::Error),
+    /// The endpoint is for another chain than the other endpoints
+    WrongChain {
+        /// The name of the endpoint
+        endpoint: String,
+        /// The chain id of the other endpoints
+        expected: U256,
+        /// The chain id of the endpoint
+        chain_id: U256,
+    },
+}
+
+/// The circuit breaker of an endpoint, see `CircuitBreaker`
+#[derive(Debug, Clone, Copy)]
+struct Circuit {
+    state: CircuitState,
+    /// The requests which failed in a row
+    failures: u32,
+    /// When the circuit opened, or when the probe was sent if it is half-open
+    since: Instant,
+}
+
+/// Which endpoint is used, and when the preferred one is retried
+#[derive(Debug)]
+struct FailoverState {
+    /// The index of the endpoint the requests are sent to first
+    active: usize,
+    /// When the preferred endpoint can be retried, if it failed
+    retry_preferred_at: Option<Instant>,
+    /// The delay before the next retry of the preferred endpoint
+    retry_delay: Duration,
+    /// The chain id answered by the first endpoint checked
+    chain_id: Option<U256>,
+    /// The chain id check of each endpoint
+    checks: Vec<ChainIdCheck>,
+    /// The circuit breaker of each endpoint
+    circuits: Vec<Circuit>,
+}
+
+/// A transport sending the requests to the first of its endpoints, in the order of preference.
+/// A request which fails because its endpoint is unreachable, answers with a server error or times out
+/// is sent again to the next endpoint, so the callers do not see the failure, and the next requests
+/// are sent to this endpoint. The preferred endpoint is retried after a delay, which doubles each time
+/// it fails again.
+/// Each endpoint is checked to be for the same chain as the others before it is used.
+/// An endpoint failing too many times in a row is not used until it answers a probe, as its circuit breaker says.
+#[derive(Clone)]
+pub(crate) struct Failover<T> {
+    /// The endpoints with their names for the logs, the first one is the preferred one
+    endpoints: Arc<Vec<(String, T)>>,
+    state: Arc<Mutex<FailoverState>>,
+    base_retry_delay: Duration,
+    max_retry_delay: Duration,
+    breaker: CircuitBreaker,
+    /// Where the state of the circuits is published
+    metrics: Option<Arc<ClientMetrics>>,
+}
+
+impl<T> std::fmt::Debug for Failover<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let names: Vec<&String> = self.endpoints.iter().map(|(name, _)| name).collect();
+        f.debug_struct("Failover")
+            .field("endpoints", &names)
+            .field("state", &*self.state.lock().unwrap())
+            .finish()
+    }
+}
+
+impl<T> Failover<T>
+where
+    T: Transport + Send + Sync + 'static,
+    T::Out: Send + 'static,
+{
+    /// Fail over between `endpoints`, the first one is the preferred one
+    pub(crate) fn new(endpoints: Vec<(String, T)>) -> Failover<T> {
+        Failover::with_retry_delays(endpoints, PREFERRED_ENDPOINT_RETRY_DELAY, MAX_PREFERRED_ENDPOINT_RETRY_DELAY)
+    }
+
+    /// Retry the preferred endpoint `retry_delay` after it failed, doubling the delay up to `max_retry_delay`
+    pub(crate) fn with_retry_delays(
+        endpoints: Vec<(String, T)>,
+        retry_delay: Duration,
+        max_retry_delay: Duration,
+    ) -> Failover<T> {
+        let checks = vec![ChainIdCheck::Unchecked; endpoints.len()];
+        let circuit = Circuit {
+            state: CircuitState::Closed,
+            failures: 0,
+            since: Instant::now(),
+        };
+        let circuits = vec![circuit; endpoints.len()];
+        Failover {
+            endpoints: Arc::new(endpoints),
+            state: Arc::new(Mutex::new(FailoverState {
+                active: 0,
+                retry_preferred_at: None,
+                retry_delay,
+                chain_id: None,
+                checks,
+                circuits,
+            })),
+            base_retry_delay: retry_delay,
+            max_retry_delay: std::cmp::max(retry_delay, max_retry_delay),
+            breaker: CircuitBreaker::default(),
+            metrics: None,
+        }
+    }
+
+    /// Stop using the endpoints failing again and again as `breaker` says,
+    /// the state of their circuits is published in `metrics`
+    pub(crate) fn with_circuit_breaker(mut self, breaker: CircuitBreaker, metrics: Arc<ClientMetrics>) -> Failover<T> {
+        for (name, _) in self.endpoints.iter() {
+            metrics.set_circuit_state(name, CircuitState::Closed);
+        }
+        self.breaker = breaker;
+        self.metrics = Some(metrics);
+        self
+    }
+
+    fn set_circuit_state(&self, state: &mut FailoverState, index: usize, circuit_state: CircuitState) {
+        let circuit = &mut state.circuits[index];
+        circuit.state = circuit_state;
+        circuit.since = Instant::now();
+        if let Some(metrics) = &self.metrics {
+            metrics.set_circuit_state(&self.endpoints[index].0, circuit_state);
+        }
+    }
+
+    /// Check if a request can be sent to the endpoint at `index` as its circuit breaker says.
+    /// Once its cooldown is over, an open circuit lets a single request probe the endpoint, the other requests
+    /// skip it until the probe returns. A probe which never returns is replaced after another cooldown.
+    fn admit(&self, index: usize) -> bool {
+        let mut state = self.state.lock().unwrap();
+        let circuit = state.circuits[index];
+        match circuit.state {
+            CircuitState::Closed => true,
+            CircuitState::Open | CircuitState::HalfOpen if circuit.since.elapsed() >= self.breaker.cooldown => {
+                info!("Probe the endpoint {} whose circuit is open.", self.endpoints[index].0);
+                self.set_circuit_state(&mut state, index, CircuitState::HalfOpen);
+                true
+            }
+            CircuitState::Open | CircuitState::HalfOpen => false,
+        }
+    }
+
+    /// Check the chain ids of all the endpoints, the ones which can not be reached are checked before
+    /// their first use. It fails if an endpoint is for another chain, or if no endpoint can be reached.
+    pub(crate) async fn check_chain_ids(&self) -> std::result::Result<(), EndpointError> {
+        let mut reached = false;
+        let mut last_err = None;
+        for index in 0..self.endpoints.len() {
+            match self.check_chain_id(index).await {
+                Ok(()) => reached = true,
+                Err(err @ EndpointError::WrongChain { .. }) => return Err(err),
+                Err(EndpointError::Failed(err)) => {
+                    warn!("The endpoint {} can not be reached: {:?}.", self.endpoints[index].0, err);
+                    last_err = Some(err);
+                }
+            }
+        }
+        match (reached, last_err) {
+            (false, Some(err)) => Err(EndpointError::Failed(err)),
+            _ => Ok(()),
+        }
+    }
+
+    /// Check that the endpoint at `index` is for the same chain as the others, once
+    async fn check_chain_id(&self, index: usize) -> std::result::Result<(), EndpointError> {
+        let (name, transport) = &self.endpoints[index];
+        let check = self.state.lock().unwrap().checks[index];
+        match check {
+            ChainIdCheck::Matching => return Ok(()),
+            ChainIdCheck::Mismatching { expected, chain_id } => {
+                return Err(EndpointError::WrongChain {
+                    endpoint: name.clone(),
+                    expected,
+                    chain_id,
+                })
+            }
+            ChainIdCheck::Unchecked => {}
+        }
+        let chain_id = transport
+            .execute("eth_chainId", vec![])
+            .await
+            .and_then(helpers::decode::<U256>)
+            .map_err(EndpointError::Failed)?;
+
+        let mut state = self.state.lock().unwrap();
+        let expected = *state.chain_id.get_or_insert(chain_id);
+        if chain_id != expected {
+            error!(
+                "The endpoint {} is for the chain {}, not for the chain {} of the other endpoints, it is not used.",
+                name, chain_id, expected
+            );
+            state.checks[index] = ChainIdCheck::Mismatching { expected, chain_id };
+            return Err(EndpointError::WrongChain {
+                endpoint: name.clone(),
+                expected,
+                chain_id,
+            });
+        }
+        state.checks[index] = ChainIdCheck::Matching;
+        Ok(())
+    }
+
+    /// The indexes of the endpoints in the order they are tried:
+    /// from the active one, or from the preferred one when it is time to retry it
+    fn order(&self) -> Vec<usize> {
+        let state = self.state.lock().unwrap();
+        let first = match state.retry_preferred_at {
+            Some(retry_at) if Instant::now() >= retry_at => 0,
+            _ => state.active,
+        };
+        let count = self.endpoints.len();
+        (0..count).map(|offset| (first + offset) % count).collect()
+    }
+
+    fn record_failure(&self, index: usize, err: &web3::Error) {
+        let mut state = self.state.lock().unwrap();
+        let circuit = &mut state.circuits[index];
+        circuit.failures += 1;
+        let failures = circuit.failures;
+        let open = match circuit.state {
+            CircuitState::HalfOpen => true,
+            CircuitState::Closed => self.breaker.failure_threshold > 0 && failures >= self.breaker.failure_threshold,
+            CircuitState::Open => false,
+        };
+        if open {
+            warn!(
+                "The endpoint {} failed {} times in a row, it is not used for {:?}.",
+                self.endpoints[index].0, failures, self.breaker.cooldown
+            );
+            self.set_circuit_state(&mut state, index, CircuitState::Open);
+        }
+        if index == 0 {
+            let retry_delay = state.retry_delay;
+            state.retry_preferred_at = Some(Instant::now() + retry_delay);
+            state.retry_delay = std::cmp::min(retry_delay * 2, self.max_retry_delay);
+        }
+        if index == state.active && self.endpoints.len() > 1 {
+            state.active = (index + 1) % self.endpoints.len();
+            warn!(
+                "The endpoint {} failed: {:?}, switch to {}.",
+                self.endpoints[index].0, err, self.endpoints[state.active].0
+            );
+        }
+    }
+
+    fn record_success(&self, index: usize) {
+        let mut state = self.state.lock().unwrap();
+        state.circuits[index].failures = 0;
+        if state.circuits[index].state != CircuitState::Closed {
+            info!("The endpoint {} answered, its circuit is closed.", self.endpoints[index].0);
+            self.set_circuit_state(&mut state, index, CircuitState::Closed);
+        }
+        if index == 0 && state.retry_preferred_at.is_some() {
+            info!("The preferred endpoint {} is back.", self.endpoints[0].0);
+            state.retry_preferred_at = None;
+            state.retry_delay = self.base_retry_delay;
+        }
+        state.active = index;
+    }
+
+    /// Send with `send` to the endpoints in turn, until one of them answers
+    async fn send_with_failover<R, F, Fut>(&self, send: F) -> web3::Result<R>
+    where
+        F: Fn(&T) -> Fut,
+        Fut: Future<Output = web3::Result<R>>,
+    {
+        let mut last_err = None;
+        for index in self.order() {
+            if !self.admit(index) {
+                continue;
+            }
+            match self.check_chain_id(index).await {
+                Ok(()) => {}
+                Err(EndpointError::Failed(err)) => {
+                    self.record_failure(index, &err);
+                    last_err = Some(err);
+                    continue;
+                }
+                Err(EndpointError::WrongChain { .. }) => continue,
+            }
+            match send(&self.endpoints[index].1).await {
+                Err(err) if is_connection_error(&err) => {
+                    self.record_failure(index, &err);
+                    last_err = Some(err);
+                }
+                result => {
+                    self.record_success(index);
+                    return result;
+                }
+            }
+        }
+        Err(last_err.unwrap_or_else(|| {
+            web3::Error::Transport("No endpoint is for the chain or can be used as their circuit breakers say".to_owned())
+        }))
+    }
+}
+
+impl<T> Transport for Failover<T>
+where
+    T: Transport + Send + Sync + 'static,
+    T::Out: Send + 'static,
+{
+    type Out = BoxFuture<'static, web3::Result<rpc::Value>>;
+
+    fn prepare(&self, method: &str, params: Vec<rpc::Value>) -> (RequestId, rpc::Call) {
+        prepare_request(method, params)
+    }
+
+    fn send(&self, id: RequestId, request: rpc::Call) -> Self::Out {
+        let failover = self.clone();
+        Box::pin(async move {
+            failover
+                .send_with_failover(|transport| transport.send(id, request.clone()))
+                .await
+        })
+    }
+}
+
+impl<T> BatchTransport for Failover<T>
+where
+    T: BatchTransport + Send + Sync + 'static,
+    T::Out: Send + 'static,
+    T::Batch: Send + 'static,
+{
+    type Batch = BoxFuture<'static, web3::Result<Vec<web3::Result<rpc::Value>>>>;
+
+    fn send_batch<I>(&self, requests: I) -> Self::Batch
+    where
+        I: IntoIterator<Item = (RequestId, rpc::Call)>,
+    {
+        let requests: Vec<_> = requests.into_iter().collect();
+        let failover = self.clone();
+        Box::pin(async move {
+            failover
+                .send_with_failover(|transport| transport.send_batch(requests.clone()))
+                .await
+        })
+    }
+}
+
+/// Check if a request failed because its connection dropped
+fn is_connection_error(err: &web3::Error) -> bool {
+    matches!(err, web3::Error::Transport(_) | web3::Error::Unreachable | web3::Error::Io(_))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::future::join_all;
+    use std::sync::atomic::AtomicBool;
+
+    /// A connection answering every request with its number, until it is dropped
+    #[derive(Debug, Clone)]
+    struct MockConnection {
+        number: usize,
+        dropped: Arc<AtomicBool>,
+    }
+
+    impl Transport for MockConnection {
+        type Out = BoxFuture<'static, web3::Result<rpc::Value>>;
+
+        fn prepare(&self, method: &str, params: Vec<rpc::Value>) -> (RequestId, rpc::Call) {
+            (0, helpers::build_request(0, method, params))
+        }
+
+        fn send(&self, _id: RequestId, _request: rpc::Call) -> Self::Out {
+            let response = if self.dropped.load(Ordering::SeqCst) {
+                Err(web3::Error::Transport("The connection is closed".to_owned()))
+            } else {
+                Ok(rpc::Value::from(self.number))
+            };
+            Box::pin(async move { response })
+        }
+    }
+
+    /// The connections made to a mock node
+    #[derive(Clone, Default)]
+    struct MockNode {
+        connections: Arc<Mutex<Vec<MockConnection>>>,
+    }
+
+    impl MockNode {
+        async fn connect(&self) -> Reconnecting<MockConnection> {
+            let node = self.clone();
+            Reconnecting::connect(move || {
+                let mut connections = node.connections.lock().unwrap();
+                let connection = MockConnection {
+                    number: connections.len() + 1,
+                    dropped: Arc::new(AtomicBool::new(false)),
+                };
+                connections.push(connection.clone());
+                async move { Ok(connection) }
+            })
+            .await
+            .unwrap()
+        }
+
+        fn drop_connections(&self) {
+            for connection in self.connections.lock().unwrap().iter() {
+                connection.dropped.store(true, Ordering::SeqCst);
+            }
+        }
+
+        fn connections_count(&self) -> usize {
+            self.connections.lock().unwrap().len()
+        }
+    }
+
+    #[tokio::test]
+    async fn test_reconnecting_transport() {
+        let node = MockNode::default();
+        let ws = node.connect().await;
+        assert_eq!(rpc::Value::from(1), ws.execute("eth_blockNumber", vec![]).await.unwrap());
+
+        // the requests failing together reconnect once, and are sent again on the new connection
+        node.drop_connections();
+        let responses = join_all((0..3).map(|_| ws.execute("eth_blockNumber", vec![]))).await;
+
+        for response in responses {
+            assert_eq!(rpc::Value::from(2), response.unwrap());
+        }
+        assert_eq!(2, node.connections_count());
+        assert_eq!(1, ws.generation());
+    }
+
+    #[tokio::test]
+    async fn test_transport_without_reconnection() {
+        let dropped = MockConnection {
+            number: 1,
+            dropped: Arc::new(AtomicBool::new(true)),
+        };
+        let ws = Reconnecting::new(dropped);
+
+        assert!(ws.execute("eth_blockNumber", vec![]).await.is_err());
+        assert!(ws.reconnect(0).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_subscribe_with_reconnection() {
+        let node = MockNode::default();
+        let ws = node.connect().await;
+
+        // each subscription notifies two blocks before its connection drops
+        let subscribe = |ws: Reconnecting<MockConnection>| async move {
+            let number = ws.current().1.number as u64;
+            let heads: Vec<web3::Result<u64>> = vec![Ok(number * 10 + 1), Ok(number * 10 + 2)];
+            Ok::<_, web3::Error>(stream::iter(heads))
+        };
+        let heads = subscribe_with_reconnection(ws.clone(), subscribe).await.unwrap();
+        let heads: Vec<u64> = heads.take(6).map(|head| head.unwrap()).collect().await;
+
+        assert_eq!(vec![11, 12, 21, 22, 31, 32], heads);
+        assert_eq!(3, node.connections_count());
+    }
+
+    #[tokio::test]
+    async fn test_subscribe_without_reconnection() {
+        let ws = Reconnecting::new(MockConnection {
+            number: 1,
+            dropped: Arc::new(AtomicBool::new(false)),
+        });
+
+        let subscribe = |_: Reconnecting<MockConnection>| async move {
+            let heads: Vec<web3::Result<u64>> = vec![Ok(11)];
+            Ok::<_, web3::Error>(stream::iter(heads))
+        };
+        let heads: Vec<web3::Result<u64>> = subscribe_with_reconnection(ws, subscribe).await.unwrap().collect().await;
+
+        // the subscription ends with an error once its connection drops
+        assert_eq!(2, heads.len());
+        assert_eq!(11, *heads[0].as_ref().unwrap());
+        assert!(heads[1].is_err());
+    }
+
+    /// An endpoint answering `eth_chainId` with its chain id and any other request with an empty list,
+    /// or failing as a server error while it is down
+    #[derive(Debug, Clone)]
+    struct MockEndpoint {
+        chain_id: u64,
+        down: Arc<AtomicBool>,
+        /// Whether the answers take 100 milliseconds
+        slow: Arc<AtomicBool>,
+        requests: Arc<Mutex<Vec<String>>>,
+    }
+
+    impl MockEndpoint {
+        fn new(chain_id: u64, down: bool) -> MockEndpoint {
+            MockEndpoint {
+                chain_id,
+                down: Arc::new(AtomicBool::new(down)),
+                slow: Arc::new(AtomicBool::new(false)),
+                requests: Arc::new(Mutex::new(vec![])),
+            }
+        }
+
+        fn requests(&self) -> Vec<String> {
+            self.requests.lock().unwrap().clone()
+        }
+    }
+
+    impl Transport for MockEndpoint {
+        type Out = BoxFuture<'static, web3::Result<rpc::Value>>;
+
+        fn prepare(&self, method: &str, params: Vec<rpc::Value>) -> (RequestId, rpc::Call) {
+            (0, helpers::build_request(0, method, params))
+        }
+
+        fn send(&self, _id: RequestId, request: rpc::Call) -> Self::Out {
+            let method = match &request {
+                rpc::Call::MethodCall(call) => call.method.clone(),
+                _ => String::new(),
+            };
+            self.requests.lock().unwrap().push(method.clone());
+            let response = if self.down.load(Ordering::SeqCst) {
+                Err(web3::Error::Transport("Server responded with a non-success status code: 503".to_owned()))
+            } else if method == "eth_chainId" {
+                Ok(rpc::Value::String(format!("{:#x}", self.chain_id)))
+            } else {
+                Ok(rpc::Value::Array(vec![]))
+            };
+            let slow = self.slow.load(Ordering::SeqCst);
+            Box::pin(async move {
+                if slow {
+                    tokio::time::sleep(Duration::from_millis(100)).await;
+                }
+                response
+            })
+        }
+    }
+
+    fn failover(endpoints: &[&MockEndpoint], retry_delay: Duration) -> Failover<MockEndpoint> {
+        let endpoints = endpoints
+            .iter()
+            .enumerate()
+            .map(|(index, endpoint)| (format!("mock {}", index), (*endpoint).clone()))
+            .collect();
+        Failover::with_retry_delays(endpoints, retry_delay, retry_delay * 4)
+    }
+
+    /// Get the logs of a range, as a tracker does
+    async fn get_logs(failover: &Failover<MockEndpoint>) -> web3::Result<Vec<web3::types::Log>> {
+        let filter = web3::types::FilterBuilder::default()
+            .from_block(web3::types::BlockNumber::Number(web3::types::U64::from(100u64)))
+            .to_block(web3::types::BlockNumber::Number(web3::types::U64::from(199u64)))
+            .build();
+        web3::Web3::new(failover.clone()).eth().logs(filter).await
+    }
+
+    #[tokio::test]
+    async fn test_failover() {
+        let preferred = MockEndpoint::new(1, true);
+        let backup = MockEndpoint::new(1, false);
+        let failover = failover(&[&preferred, &backup], Duration::from_secs(3600));
+
+        // the range is fetched from the backup, and the next requests go there too
+        assert!(get_logs(&failover).await.unwrap().is_empty());
+        assert!(get_logs(&failover).await.unwrap().is_empty());
+        assert_eq!(vec!["eth_chainId"], preferred.requests());
+        assert_eq!(vec!["eth_chainId", "eth_getLogs", "eth_getLogs"], backup.requests());
+    }
+
+    #[tokio::test]
+    async fn test_failover_retries_the_preferred_endpoint() {
+        let preferred = MockEndpoint::new(1, false);
+        let backup = MockEndpoint::new(1, false);
+        let failover = failover(&[&preferred, &backup], Duration::ZERO);
+        failover.check_chain_ids().await.unwrap();
+
+        preferred.down.store(true, Ordering::SeqCst);
+        assert!(get_logs(&failover).await.is_ok());
+        assert_eq!(1, backup.requests().iter().filter(|method| *method == "eth_getLogs").count());
+
+        // the preferred endpoint is back
+        preferred.down.store(false, Ordering::SeqCst);
+        assert!(get_logs(&failover).await.is_ok());
+        assert!(get_logs(&failover).await.is_ok());
+        assert_eq!(vec!["eth_chainId", "eth_getLogs", "eth_getLogs", "eth_getLogs"], preferred.requests());
+        assert_eq!(1, backup.requests().iter().filter(|method| *method == "eth_getLogs").count());
+    }
+
+    #[tokio::test]
+    async fn test_failover_all_endpoints_down() {
+        let preferred = MockEndpoint::new(1, true);
+        let backup = MockEndpoint::new(1, true);
+        let failover = failover(&[&preferred, &backup], Duration::from_secs(3600));
+
+        assert!(failover.check_chain_ids().await.is_err());
+        assert!(get_logs(&failover).await.is_err());
+
+        // the backup is used once it is back
+        backup.down.store(false, Ordering::SeqCst);
+        assert!(get_logs(&failover).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_failover_chain_id_mismatch() {
+        let ethereum = MockEndpoint::new(1, false);
+        let bsc = MockEndpoint::new(56, false);
+        let failover = failover(&[&ethereum, &bsc], Duration::from_secs(3600));
+
+        match failover.check_chain_ids().await {
+            Err(EndpointError::WrongChain { expected, chain_id, .. }) => {
+                assert_eq!(U256::from(1), expected);
+                assert_eq!(U256::from(56), chain_id);
+            }
+            other => panic!("unexpected check: {:?}", other),
+        }
+
+        // the first endpoint reached sets the chain, an endpoint unreachable until then is checked before its first use
+        let ethereum_down = MockEndpoint::new(1, true);
+        let failover = self::failover(&[&ethereum_down, &bsc], Duration::from_secs(3600));
+        assert!(failover.check_chain_ids().await.is_ok());
+        ethereum_down.down.store(false, Ordering::SeqCst);
+        assert!(get_logs(&failover).await.is_ok());
+        assert_eq!(vec!["eth_chainId", "eth_chainId"], ethereum_down.requests());
+    }
+
+    #[tokio::test]
+    async fn test_failover_circuit_breaker() {
+        let endpoint = MockEndpoint::new(1, false);
+        let metrics = Arc::new(ClientMetrics::new("Mock"));
+        let breaker = CircuitBreaker {
+            failure_threshold: 2,
+            cooldown: Duration::from_millis(200),
+        };
+        let failover = failover(&[&endpoint], Duration::ZERO).with_circuit_breaker(breaker, metrics.clone());
+        failover.check_chain_ids().await.unwrap();
+        let circuit = || metrics.snapshot().circuits[0].1;
+        let sent = || endpoint.requests().iter().filter(|method| *method == "eth_getLogs").count();
+        assert_eq!(CircuitState::Closed, circuit());
+
+        // closed: the failures are sent to the endpoint until the threshold
+        endpoint.down.store(true, Ordering::SeqCst);
+        assert!(get_logs(&failover).await.is_err());
+        assert_eq!(CircuitState::Closed, circuit());
+        assert!(get_logs(&failover).await.is_err());
+        assert_eq!(CircuitState::Open, circuit());
+
+        // open: nothing is sent until the cooldown is over
+        endpoint.down.store(false, Ordering::SeqCst);
+        assert!(get_logs(&failover).await.is_err());
+        assert_eq!(2, sent());
+
+        // half-open: a single request probes the endpoint, the failed probe opens the circuit again
+        endpoint.down.store(true, Ordering::SeqCst);
+        tokio::time::sleep(Duration::from_millis(250)).await;
+        assert!(get_logs(&failover).await.is_err());
+        assert_eq!(3, sent());
+        assert_eq!(CircuitState::Open, circuit());
+
+        // while the probe is pending, the other requests skip the endpoint
+        endpoint.down.store(false, Ordering::SeqCst);
+        endpoint.slow.store(true, Ordering::SeqCst);
+        tokio::time::sleep(Duration::from_millis(250)).await;
+        let other = async {
+            tokio::task::yield_now().await;
+            (get_logs(&failover).await, circuit())
+        };
+        let (probe, (other, circuit_during_probe)) = futures::join!(get_logs(&failover), other);
+        assert!(probe.is_ok());
+        assert!(other.is_err());
+        assert_eq!(CircuitState::HalfOpen, circuit_during_probe);
+        assert_eq!(4, sent());
+
+        // closed again
+        assert_eq!(CircuitState::Closed, circuit());
+        endpoint.slow.store(false, Ordering::SeqCst);
+        assert!(get_logs(&failover).await.is_ok());
+        assert_eq!(5, sent());
+    }
+}
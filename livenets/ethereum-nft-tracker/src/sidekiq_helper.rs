@@ -1,4 +1,4 @@
-use nft_events::{Erc1155Event, Erc721Event};
+use nft_events::{Erc1155Event, Erc721Event, Erc721Metadata};
 use sidekiq::{create_redis_pool, Client, ClientOpts, Job, JobOpts};
 use serde_json;
 
@@ -13,65 +13,23 @@ pub fn send_erc721(
     total_supply: Option<u128>,
 ) {
     if event.block_number.is_some() && event.transaction_hash.is_some() {
-        let block_number = event.block_number.unwrap();
-        let address = format!("{:?}", event.address);
-        let transaction_hash = format!("{:?}", event.transaction_hash.unwrap());
-        let from = format!("{:?}", event.from);
-        let to = format!("{:?}", event.to);
-        let token_id = event.token_id.to_string();
-
-        let job = build_erc721_job(
-            blockchain, 
-            block_number, 
-            address, 
-            transaction_hash, 
-            from, 
-            to, 
-            token_id, 
-            token_uri, 
-            name, 
-            symbol, 
-            total_supply
-        );
+        let metadata = Erc721Metadata {
+            name,
+            symbol,
+            total_supply,
+            token_uri,
+            ..Default::default()
+        };
+        let job = build_erc721_job(blockchain, &event, &metadata);
 
         push(job);
     }
 }
 
-fn build_erc721_job(
-    blockchain: String,
-    block_number: u64,
-    address: String,
-    transaction_hash: String,
-    from: String,
-    to: String,
-    token_id: String,
-    token_uri: String,
-    name: String,
-    symbol: String,
-    total_supply: Option<u128>,
-) -> Job {
+fn build_erc721_job(blockchain: String, event: &Erc721Event, metadata: &Erc721Metadata) -> Job {
     let class = "ProcessErc721EventWorker".to_string();
 
-    let ts = if let Some(total_supply) = total_supply {
-        Value::from(total_supply as u64)
-    } else {
-        Value::Null
-    };
-    let value = serde_json::json!({
-        "blockchain": blockchain,
-        "block_number": block_number,
-        "address": address,
-        "transaction_hash": transaction_hash,
-        "from": from,
-        "to": to,
-        "token_id": token_id,
-        "token_uri": token_uri,
-        "name": name,
-        "symbol": symbol,
-        "total_supply": ts, 
-    });
-    let args: Vec<Value> = vec![value];
+    let args: Vec<Value> = vec![erc721_job_arg(blockchain, event, metadata)];
 
     let job_opts = JobOpts {
         queue: "erc721_events".to_string(),
@@ -80,61 +38,34 @@ fn build_erc721_job(
     Job::new(class, args, job_opts)
 }
 
+/// The event as serialized by nft-events, the addresses in hex and the integers as decimal strings,
+/// with its blockchain and the metadata the worker saves
+fn erc721_job_arg(blockchain: String, event: &Erc721Event, metadata: &Erc721Metadata) -> Value {
+    let mut value = serde_json::to_value(event).expect("an event serializes to json");
+    let metadata = serde_json::to_value(metadata).expect("the metadata serializes to json");
+    value["blockchain"] = Value::from(blockchain);
+    for key in &["name", "symbol", "total_supply", "token_uri"] {
+        value[*key] = metadata[*key].clone();
+    }
+    value
+}
+
 pub fn send_erc1155(
     blockchain: String,
     event: Erc1155Event,
     token_uri: String,
 ) {
     if event.block_number.is_some() && event.transaction_hash.is_some() {
-        let block_number = event.block_number.unwrap();
-        let address = format!("{:?}", event.address);
-        let transaction_hash = format!("{:?}", event.transaction_hash.unwrap());
-        let from = format!("{:?}", event.from);
-        let to = format!("{:?}", event.to);
-        let token_id = event.token_id.to_string();
-        let amount = event.amount.as_u128();
-
-        let job = build_erc1155_job(
-            blockchain, 
-            block_number, 
-            address, 
-            transaction_hash, 
-            from, 
-            to, 
-            token_id, 
-            amount, 
-            token_uri, 
-        );
+        let job = build_erc1155_job(blockchain, &event, token_uri);
 
         push(job);
     }
 }
 
-fn build_erc1155_job(
-    blockchain: String,
-    block_number: u64,
-    address: String,
-    transaction_hash: String,
-    from: String,
-    to: String,
-    token_id: String,
-    amount: u128,
-    token_uri: String,
-) -> Job {
+fn build_erc1155_job(blockchain: String, event: &Erc1155Event, token_uri: String) -> Job {
     let class = "ProcessErc1155EventWorker".to_string();
 
-    let value = serde_json::json!({
-        "blockchain": blockchain,
-        "block_number": block_number,
-        "address": address,
-        "transaction_hash": transaction_hash,
-        "from": from,
-        "to": to,
-        "token_id": token_id,
-        "token_uri": token_uri,
-        "amount": amount as u64,
-    });
-    let args: Vec<Value> = vec![value];
+    let args: Vec<Value> = vec![erc1155_job_arg(blockchain, event, token_uri)];
 
     let job_opts = JobOpts {
         queue: "erc1155_events".to_string(),
@@ -143,6 +74,14 @@ fn build_erc1155_job(
     Job::new(class, args, job_opts)
 }
 
+/// The event as serialized by nft-events, with its blockchain and its token uri
+fn erc1155_job_arg(blockchain: String, event: &Erc1155Event, token_uri: String) -> Value {
+    let mut value = serde_json::to_value(event).expect("an event serializes to json");
+    value["blockchain"] = Value::from(blockchain);
+    value["token_uri"] = Value::from(token_uri);
+    value
+}
+
 fn get_client() -> Client {
     let client_opts = ClientOpts {
         namespace: None,
@@ -161,38 +100,84 @@ fn push(job: Job) {
     }
 }
 
+#[cfg(test)]
+fn address(address: &str) -> web3::types::H160 {
+    address.parse().unwrap()
+}
+
+#[cfg(test)]
+fn transaction_hash() -> web3::types::H256 {
+    "42ac0589bf82ccff3358859728890c520f616ac5e184cc685113732880df0825".parse().unwrap()
+}
+
 #[test]
 fn test_build_erc721_job() {
-    let job = build_erc721_job(
-        "Ethereum".to_string(),
-        123456,
-        "0xca0d36c67a0c1bf6b28e76fb8c2188c31b87d152".to_string(),
-        "0x42ac0589bf82ccff3358859728890c520f616ac5e184cc685113732880df0825".to_string(),
-        "0x8628ff3ac814ee8937c10860b85d55e6aa67cfa2".to_string(),
-        "0xc02aaa39b223fe8d0a0e5c4f27ead9083c756cc2".to_string(),
-        "1111".to_string(),
-        "https://token_uri".to_string(),
-        "Hello".to_string(),
-        "HL".to_string(),
-        Some(1234),
-    );
-
-    push(job)
+    let event = Erc721Event {
+        block_number: Some(123456),
+        block_timestamp: None,
+        address: address("ca0d36c67a0c1bf6b28e76fb8c2188c31b87d152"),
+        transaction_hash: Some(transaction_hash()),
+        tx_sender: None,
+        transaction_index: Some(1),
+        log_index: Some(2),
+        from: address("8628ff3ac814ee8937c10860b85d55e6aa67cfa2"),
+        to: address("c02aaa39b223fe8d0a0e5c4f27ead9083c756cc2"),
+        token_id: web3::types::U256::from(1111),
+        kind: nft_events::EventKind::Transfer,
+        sale: None,
+        raw: None,
+    };
+    let metadata = Erc721Metadata {
+        name: "Hello".to_string(),
+        symbol: "HL".to_string(),
+        total_supply: Some(1234),
+        token_uri: "https://token_uri".to_string(),
+        ..Default::default()
+    };
+
+    let arg = erc721_job_arg("Ethereum".to_string(), &event, &metadata);
+    assert_eq!("Ethereum", arg["blockchain"]);
+    assert_eq!(123456, arg["block_number"]);
+    assert_eq!("0xca0d36c67a0c1bf6b28e76fb8c2188c31b87d152", arg["address"]);
+    assert_eq!("0x42ac0589bf82ccff3358859728890c520f616ac5e184cc685113732880df0825", arg["transaction_hash"]);
+    assert_eq!("0x8628ff3ac814ee8937c10860b85d55e6aa67cfa2", arg["from"]);
+    assert_eq!("0xc02aaa39b223fe8d0a0e5c4f27ead9083c756cc2", arg["to"]);
+    assert_eq!("1111", arg["token_id"]);
+    assert_eq!("https://token_uri", arg["token_uri"]);
+    assert_eq!("Hello", arg["name"]);
+    assert_eq!("HL", arg["symbol"]);
+    assert_eq!("1234", arg["total_supply"]);
+
+    push(build_erc721_job("Ethereum".to_string(), &event, &metadata))
 }
 
 #[test]
 fn test_build_erc1155_job() {
-    let job = build_erc1155_job(
-        "Ethereum".to_string(),
-        123456,
-        "0xca0d36c67a0c1bf6b28e76fb8c2188c31b87d152".to_string(),
-        "0x42ac0589bf82ccff3358859728890c520f616ac5e184cc685113732880df0825".to_string(),
-        "0x8628ff3ac814ee8937c10860b85d55e6aa67cfa2".to_string(),
-        "0xc02aaa39b223fe8d0a0e5c4f27ead9083c756cc2".to_string(),
-        "1111".to_string(),
-        1234,
-        "https://token_uri".to_string(),
-    );
-
-    push(job)
+    let event = Erc1155Event {
+        block_number: Some(123456),
+        address: address("ca0d36c67a0c1bf6b28e76fb8c2188c31b87d152"),
+        transaction_hash: Some(transaction_hash()),
+        tx_sender: None,
+        transaction_index: Some(1),
+        log_index: Some(2),
+        operator: address("8628ff3ac814ee8937c10860b85d55e6aa67cfa2"),
+        from: address("8628ff3ac814ee8937c10860b85d55e6aa67cfa2"),
+        balance_of_from: web3::types::U256::zero(),
+        to: address("c02aaa39b223fe8d0a0e5c4f27ead9083c756cc2"),
+        balance_of_to: web3::types::U256::from(1234),
+        token_id: web3::types::U256::from(1111),
+        amount: web3::types::U256::from(1234),
+    };
+
+    let arg = erc1155_job_arg("Ethereum".to_string(), &event, "https://token_uri".to_string());
+    assert_eq!("Ethereum", arg["blockchain"]);
+    assert_eq!(123456, arg["block_number"]);
+    assert_eq!("0xca0d36c67a0c1bf6b28e76fb8c2188c31b87d152", arg["address"]);
+    assert_eq!("0x8628ff3ac814ee8937c10860b85d55e6aa67cfa2", arg["from"]);
+    assert_eq!("0xc02aaa39b223fe8d0a0e5c4f27ead9083c756cc2", arg["to"]);
+    assert_eq!("1111", arg["token_id"]);
+    assert_eq!("1234", arg["amount"]);
+    assert_eq!("https://token_uri", arg["token_uri"]);
+
+    push(build_erc1155_job("Ethereum".to_string(), &event, "https://token_uri".to_string()))
 }